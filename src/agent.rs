@@ -0,0 +1,407 @@
+//! The agent for programs that embed it rather than run the command line.
+
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use rig::{message::Message, tool::ToolSet};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    audit::AuditLog,
+    auth::GoogleAuth,
+    chat::{Activity, Agent, Fallback, Turn},
+    cli::QualifyArgs,
+    config::{Config, DEFAULT_SERVER, ServerConfig},
+    error::Error,
+    injection::{self, Sanitizer},
+    mcp::{McpServers, McpTools},
+    output::OutputFormat,
+    privacy::Masker,
+    profile::Profiles,
+    provider::{Model, Provider},
+    qualify::{self, Outcome, Resources},
+    render::Terminal,
+    retention,
+    rubric::{self, Rubric},
+    sheets::{self, LOCAL_SPREADSHEET, SheetsClient, Spreadsheets, Workbook},
+    tools::{Policy, RateLimiter, Toolbox, WriteGuard},
+    transcript::Transcripts,
+    web,
+    workspace::Workspace,
+};
+
+/// An agent connected to its MCP servers, holding one conversation.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use rig_google_sheets::GsheetsAgent;
+///
+/// let mut agent = GsheetsAgent::builder()
+///     .mcp_url("http://localhost:8000/sse")
+///     .model("gpt-4o")
+///     .build()
+///     .await?;
+/// let turn = agent.chat("Which sheets does the spreadsheet `1BxiM` have?").await?;
+/// println!("{}", turn.answer);
+/// # Ok(())
+/// # }
+/// ```
+pub struct GsheetsAgent {
+    agent: Arc<Agent<Model>>,
+    resources: Arc<Resources>,
+    chat_history: Vec<Message>,
+}
+
+impl GsheetsAgent {
+    pub fn builder() -> GsheetsAgentBuilder {
+        GsheetsAgentBuilder::default()
+    }
+
+    /// Answers `prompt`, calling tools as needed, with the conversation so far
+    /// as context.
+    ///
+    /// A turn that runs into the tool-call limits ends there, with an answer
+    /// saying so.
+    pub async fn chat(&mut self, prompt: &str) -> Result<Turn, Error> {
+        match self
+            .agent
+            .call_until_response(prompt.into(), &mut self.chat_history, None, None)
+            .await
+        {
+            Err(Error::LimitExceeded(limit)) => Ok(limit.abandon(&mut self.chat_history)),
+            res => res,
+        }
+    }
+
+    /// Qualifies the leads `spec` selects, as `gsheets-agent qualify` does,
+    /// writing verdicts to the results sheet.
+    pub async fn qualify(&self, spec: &QualifyArgs) -> anyhow::Result<Outcome> {
+        let pipeline = qualify::Pipeline {
+            agent: &self.agent,
+            resources: &self.resources,
+            output: OutputFormat::Text,
+            progress: None,
+            bar: None,
+        };
+
+        qualify::run(pipeline, spec).await
+    }
+
+    /// The conversation so far, oldest message first.
+    pub fn history(&self) -> &[Message] {
+        &self.chat_history
+    }
+
+    /// Starts a new conversation.
+    pub fn clear_history(&mut self) {
+        self.chat_history.clear();
+    }
+
+    pub(crate) fn into_parts(self) -> (Arc<Agent<Model>>, Arc<Resources>) {
+        (self.agent, self.resources)
+    }
+}
+
+/// Configures a [`GsheetsAgent`]. Anything not set comes from
+/// [`Config::default`].
+pub struct GsheetsAgentBuilder {
+    config: Config,
+    import_csv: Vec<PathBuf>,
+    import_xlsx: Vec<PathBuf>,
+    interactive: bool,
+    echo: bool,
+    color: bool,
+    trace: bool,
+    instructions: String,
+    activity: Option<UnboundedSender<Activity>>,
+}
+
+impl Default for GsheetsAgentBuilder {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+            import_csv: Vec::new(),
+            import_xlsx: Vec::new(),
+            interactive: false,
+            echo: false,
+            color: true,
+            trace: false,
+            instructions: String::new(),
+            activity: None,
+        }
+    }
+}
+
+impl GsheetsAgentBuilder {
+    /// Replaces every setting, as if read from a config file.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// SSE endpoint of the Google Sheets MCP server, the only server connected to.
+    pub fn mcp_url(mut self, url: impl Into<String>) -> Self {
+        let server = ServerConfig {
+            sse_url: url.into(),
+            ..ServerConfig::default()
+        };
+        self.config.mcp = BTreeMap::from([(DEFAULT_SERVER.to_string(), server)]);
+        self
+    }
+
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.config.model.provider = provider;
+        self
+    }
+
+    /// Takes the preamble from the prompt profile `name`.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.config.profile.name = name.into();
+        self.config.preamble = None;
+        self
+    }
+
+    /// Sets a `{{name}}` variable of the profile.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.profile.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Defaults to the provider's flagship model.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.config.model.model = Some(model.into());
+        self
+    }
+
+    /// Makes a CSV file available as a sheet of the local spreadsheet.
+    pub fn import_csv(mut self, path: impl Into<PathBuf>) -> Self {
+        self.import_csv.push(path.into());
+        self
+    }
+
+    /// Makes the sheets of an Excel workbook available in the local spreadsheet.
+    pub fn import_xlsx(mut self, path: impl Into<PathBuf>) -> Self {
+        self.import_xlsx.push(path.into());
+        self
+    }
+
+    /// Asks for confirmation on the terminal before changes, rather than
+    /// refusing them unless `guard.confirm` is off.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Streams answers to stdout as they arrive.
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Renders echoed answers in color, unless `NO_COLOR` is set or stdout
+    /// isn't a terminal. On by default.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Prints a line for every tool call the agent echoes once it's done,
+    /// e.g. `→ append_rows(Sheet2, 14 rows) ✓ 230ms`, instead of a notice
+    /// with its arguments when it's made.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Reports what the agent does as it goes, e.g. to show tool calls live.
+    pub fn activity(mut self, sender: UnboundedSender<Activity>) -> Self {
+        self.activity = Some(sender);
+        self
+    }
+
+    /// Appends `instructions` to the preamble.
+    pub fn instructions(mut self, instructions: &str) -> Self {
+        self.instructions.push_str(instructions);
+        self
+    }
+
+    /// Connects to the MCP servers and gathers the tools the agent may use.
+    pub async fn build(self) -> anyhow::Result<GsheetsAgent> {
+        let config = self.config;
+        config.validate()?;
+        retention::init(&config.retention);
+
+        let profiles = Profiles::from_config(&config.profile);
+        let (profile, preamble) = match &config.preamble {
+            Some(preamble) => (None, profiles.interpolate(preamble)?),
+            None => (
+                Some(config.profile.name.clone()),
+                profiles.render(&config.profile.name)?,
+            ),
+        };
+
+        let google_auth = GoogleAuth::from_config(&config.auth).await?;
+        let spreadsheets =
+            open_spreadsheets(google_auth.clone(), &self.import_csv, &self.import_xlsx)?;
+        let mcp_servers = McpServers::connect(&config.mcp, google_auth.as_ref()).await?;
+
+        let mcp_tools = McpTools::new(mcp_servers.clone(), &config.tools).await;
+        let (mut tools, mut tooldefs) = (ToolSet::default(), Vec::new());
+        sheets::add_tools(
+            &config.sheets,
+            &spreadsheets,
+            &config.tools,
+            &mut tools,
+            &mut tooldefs,
+        )
+        .await?;
+
+        let web_client = web::client(&config.web)?;
+        if let Some(client) = &web_client {
+            web::add_tool(client, &config.tools, &mut tools, &mut tooldefs).await;
+        }
+
+        let role = match (&config.policy.file, &config.policy.role) {
+            (Some(path), Some(name)) => Some(Policy::load(path)?.role(name)?),
+            (Some(path), None) => anyhow::bail!(
+                "The policy {} applies to roles; pick one with --role",
+                path.display()
+            ),
+            (None, Some(name)) => {
+                anyhow::bail!("There's no [policy] file defining the role `{name}`")
+            }
+            (None, None) => None,
+        };
+
+        let mut instructions = String::new();
+        if !spreadsheets.local().is_empty() {
+            instructions.push_str(&format!(
+                "\nThese imported files are sheets of the spreadsheet with the ID `{LOCAL_SPREADSHEET}`: \
+                 {}. Changes to them are kept in memory only.",
+                spreadsheets.local().titles().join(", ")
+            ));
+        }
+        if let Some(path) = &config.rubric {
+            let rubric = Rubric::load(path)?;
+            if let Some(rubric_instructions) =
+                rubric::add_tool(rubric, &config.tools, &mut tools, &mut tooldefs).await
+            {
+                instructions.push_str(&rubric_instructions);
+            }
+        }
+        let injection = Sanitizer::from_config(&config.injection)?.map(Arc::new);
+        if injection.is_some() {
+            instructions.push_str(injection::INSTRUCTIONS);
+        }
+        if let Some(role) = &role {
+            let allowed: Vec<String> = role.allow.iter().map(ToString::to_string).collect();
+            instructions.push_str(&format!(
+                "\nThe user acts in the `{}` role, whose calls are limited to these operations: \
+                 {}. Other tools are refused, so don't call them.",
+                role.name,
+                allowed.join(", ")
+            ));
+        }
+        instructions.push_str(&self.instructions);
+
+        let workspace = Workspace::load().unwrap_or_else(|e| {
+            tracing::warn!("Failed to load the workspace: {e:#}");
+            Workspace::default()
+        });
+        if config.sheets.metadata {
+            let mut described: Vec<(String, String)> = workspace
+                .list()
+                .into_iter()
+                .map(|spreadsheet| (spreadsheet.alias, spreadsheet.id))
+                .collect();
+            if !spreadsheets.local().is_empty() {
+                described.push(("imported files".to_string(), LOCAL_SPREADSHEET.to_string()));
+            }
+            instructions.push_str(&sheets::describe(&spreadsheets, &described).await);
+        }
+
+        let dry_run = config.guard.dry_run;
+        let limiter = RateLimiter::per_minute(config.model.requests_per_minute);
+        let agent = Agent {
+            model: Model::from_config(&config.model),
+            fallbacks: config
+                .model
+                .fallback
+                .iter()
+                .map(|fallback| {
+                    let fallback = config.model.other(fallback);
+                    Fallback {
+                        name: fallback.model_name().to_string(),
+                        model: Model::from_config(&fallback),
+                    }
+                })
+                .collect(),
+            model_config: config.model,
+            history_config: config.history,
+            limits: config.limits,
+            preamble,
+            instructions,
+            profile,
+            profiles,
+            tools: Toolbox::new(
+                mcp_tools,
+                tools,
+                tooldefs,
+                WriteGuard::new(config.guard, self.interactive).with_role(role),
+                &config.tools,
+                AuditLog::from_config(&config.audit)?,
+            ),
+            workspace,
+            echo: self
+                .echo
+                .then(|| Terminal::new(self.color).with_trace(self.trace)),
+            transcripts: Transcripts::from_config(&config.transcript)?,
+            privacy: Masker::from_config(&config.privacy)?.map(Arc::new),
+            injection,
+            limiter,
+            activity: self.activity,
+        };
+        let resources = Resources {
+            sheets: spreadsheets,
+            config: config.qualify,
+            crm: config.crm,
+            notify: config.notify,
+            dry_run,
+            web: web_client,
+            pricing: config.pricing,
+            mcp: mcp_servers,
+        };
+
+        Ok(GsheetsAgent {
+            agent: Arc::new(agent),
+            resources: Arc::new(resources),
+            chat_history: Vec::new(),
+        })
+    }
+}
+
+/// The Google spreadsheets `auth` gives access to, and a local one with the
+/// imported files as its sheets.
+pub(crate) fn open_spreadsheets(
+    google_auth: Option<GoogleAuth>,
+    import_csv: &[PathBuf],
+    import_xlsx: &[PathBuf],
+) -> anyhow::Result<Spreadsheets> {
+    let workbook = Arc::new(Workbook::default());
+    for path in import_csv {
+        let title = workbook.import_csv(path)?;
+        eprintln!("Imported {} as the `{title}` sheet", path.display());
+    }
+    for path in import_xlsx {
+        let titles = workbook.import_xlsx(path)?;
+        eprintln!(
+            "Imported {} as the `{}` sheets",
+            path.display(),
+            titles.join("`, `")
+        );
+    }
+
+    Ok(Spreadsheets::new(
+        google_auth.map(SheetsClient::new),
+        workbook,
+    ))
+}
@@ -0,0 +1,377 @@
+//! The `gsheets-agent` command line, on top of [`crate::GsheetsAgent`].
+
+use std::{io::IsTerminal, process::ExitCode, sync::Arc};
+
+use rig::message::Message;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    agent::{GsheetsAgent, open_spreadsheets},
+    auth::GoogleAuth,
+    batch,
+    chat::{Agent, Turn},
+    cli::{Action, Cli, Frontend, ServeArgs},
+    commands,
+    config::Config,
+    doctor,
+    error::Error,
+    jobs::Jobs,
+    logging, output,
+    output::OutputFormat,
+    provider::Model,
+    qualify,
+    render::Terminal,
+    repl::Repl,
+    retention, schedule, serve,
+    session::Session,
+    sheets,
+    transcript::Transcript,
+    tui, usage, wizard,
+};
+
+/// Runs what `cli` asks for, as the `gsheets-agent` binary does.
+pub async fn run(cli: Cli) -> anyhow::Result<ExitCode> {
+    let mut config = Config::load(&cli)?;
+    // Flushes the telemetry when `run` returns.
+    let _telemetry = logging::init(&config.log, &config.telemetry)?;
+    let batch_prompts = match cli.action {
+        Some(_) => None,
+        None => batch::prompts(&cli)?,
+    };
+    let interactive = cli.action.is_none() && batch_prompts.is_none();
+
+    retention::init(&config.retention);
+    if let Some(Action::Purge(args)) = &cli.action {
+        retention::purge(&config, args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::Runs(args)) = &cli.action {
+        qualify::list_runs(args, cli.output)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::InitCriteria(args)) = &cli.action {
+        wizard::run(args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::Doctor) = &cli.action {
+        return Ok(doctor::run(&config).await);
+    }
+
+    if let Some(Action::Export(args)) = &cli.action {
+        let google_auth = GoogleAuth::from_config(&config.auth).await?;
+        let spreadsheets = open_spreadsheets(google_auth, &cli.import_csv, &cli.import_xlsx)?;
+        sheets::export(&spreadsheets, args).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::Columns(args)) = &cli.action {
+        let google_auth = GoogleAuth::from_config(&config.auth).await?;
+        let spreadsheets = open_spreadsheets(google_auth, &cli.import_csv, &cli.import_xlsx)?;
+        qualify::map_columns(&spreadsheets, args).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let schedules = std::mem::take(&mut config.schedule);
+    let slack = std::mem::take(&mut config.slack);
+    let http = std::mem::take(&mut config.http);
+    let webhook = std::mem::take(&mut config.webhook);
+    let serving = matches!(cli.action, Some(Action::Serve(_)));
+    let tui = cli.tui && interactive;
+    let mut builder = GsheetsAgent::builder()
+        .config(config)
+        // A confirmation prompt would garble the TUI.
+        .interactive(interactive && !tui)
+        // Answers go in the replies when serving, and in a pane of the TUI.
+        .echo(cli.output == OutputFormat::Text && !serving && !tui)
+        .color(!cli.no_color)
+        .trace(cli.trace);
+    let (activity, activity_rx) = tokio::sync::mpsc::unbounded_channel();
+    if tui {
+        builder = builder.activity(activity);
+    }
+    for path in &cli.import_csv {
+        builder = builder.import_csv(path);
+    }
+    for path in &cli.import_xlsx {
+        builder = builder.import_xlsx(path);
+    }
+    if let Some(Action::Serve(ServeArgs {
+        frontend: Frontend::Slack,
+    })) = &cli.action
+    {
+        builder = builder.instructions(serve::slack::INSTRUCTIONS);
+    }
+    let (mut agent, resources) = builder.build().await?.into_parts();
+    let jobs = Arc::new(Jobs::default());
+
+    if let Some(Action::Qualify(args)) = &cli.action {
+        // The answers to each chunk would scroll the progress bar away, and
+        // say less than it does.
+        let bar = std::io::stderr().is_terminal().then(|| {
+            Arc::get_mut(&mut agent)
+                .expect("nothing else holds the agent yet")
+                .echo
+                .take()
+                .unwrap_or_else(|| Terminal::new(!cli.no_color))
+        });
+        let pipeline = qualify::Pipeline {
+            agent: &agent,
+            resources: &resources,
+            output: cli.output,
+            progress: None,
+            bar,
+        };
+        return Ok(qualify::run(pipeline, args).await?.exit_code);
+    }
+
+    if let Some(Action::Eval(args)) = &cli.action {
+        let agent = Arc::get_mut(&mut agent).expect("nothing else holds the agent yet");
+        qualify::eval(agent, &resources, args, cli.output).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::Schedule(args)) = &cli.action {
+        schedule::run(&agent, &resources, &schedules, args, cli.output).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::Serve(args)) = &cli.action {
+        match &args.frontend {
+            Frontend::Slack => serve::slack::run(&agent, &slack).await?,
+            Frontend::Http(args) => {
+                let server = serve::http::Server {
+                    agent,
+                    resources,
+                    jobs,
+                    token: std::env::var(&http.token_env).ok(),
+                };
+                serve::http::run(server, args.listen.unwrap_or(http.listen)).await?;
+            }
+            Frontend::Webhook(args) => {
+                let server = serve::webhook::Server {
+                    agent,
+                    resources,
+                    token: std::env::var(&webhook.token_env).ok(),
+                    running: Default::default(),
+                };
+                serve::webhook::run(server, args.listen.unwrap_or(webhook.listen)).await?;
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut session = match &cli.resume {
+        Some(id) => Session::load(id)?,
+        None => Session::new(),
+    };
+
+    if let Some(prompts) = batch_prompts {
+        return Ok(batch::run(
+            &agent,
+            &mut session,
+            prompts,
+            cli.output,
+            &resources.pricing,
+        )
+        .await);
+    }
+
+    if tui {
+        tui::run(agent, session, activity_rx, &resources.pricing).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut repl = Repl::new()?;
+    let text_output = cli.output == OutputFormat::Text;
+
+    if text_output {
+        println!("Hi! How can I help you today? (write \"quit\" to exit)");
+        println!("End a line with \\ to continue your message on the next one.");
+        println!("Type /help to see the available commands.");
+        println!("Session: {} (continue it later with --resume)", session.id);
+        println!("------------");
+    }
+
+    let mut initial_prompts = cli.prompt.into_iter();
+    // Set from a cancelled request until the next one is answered, while what
+    // was done for it is only saved if the user wants.
+    let mut cancelled = false;
+
+    while let Some(prompt) = match initial_prompts.next() {
+        Some(prompt) => Some(prompt),
+        None => repl.read_prompt(cancelled)?,
+    } {
+        let prompt = match commands::parse(&prompt) {
+            Some(Ok(command)) => {
+                let command = commands::run(
+                    command,
+                    &mut agent,
+                    &mut session,
+                    &jobs,
+                    &resources,
+                    &mut repl,
+                );
+                match command.await {
+                    Some(prompt) => prompt,
+                    None => continue,
+                }
+            }
+            Some(Err(e)) => {
+                eprintln!("{e}");
+                continue;
+            }
+            None => prompt,
+        };
+
+        if text_output {
+            println!("------------");
+        }
+
+        // In text mode, the answer is streamed to stdout as it arrives.
+        let transcript = agent.transcript(&session.id);
+        let before = session.chat_history.clone();
+        let cancel = CancellationToken::new();
+        let res = cancel_on_ctrl_c(
+            run_turn(
+                &agent,
+                &mut repl,
+                prompt.as_str().into(),
+                &mut session.chat_history,
+                transcript.as_ref(),
+                &cancel,
+            ),
+            &cancel,
+        )
+        .await;
+        let Some(res) = res else {
+            // The request was dropped wherever it was, so it's left out.
+            session.chat_history = before;
+            cancelled = false;
+            eprintln!("Quitting without waiting for the request to stop");
+            break;
+        };
+
+        // A failed prompt leaves the history usable, so the user can carry on.
+        cancelled = matches!(res, Err(Error::Cancelled));
+        let (turn, error) = match res {
+            Ok(turn) => {
+                session.record_usage(&turn.model, turn.usage);
+                (Some(turn), None)
+            }
+            Err(Error::Cancelled) => {
+                eprintln!(
+                    "Cancelled. Press Ctrl-C again to quit, or carry on with another prompt."
+                );
+                (None, Some(Error::Cancelled.to_string()))
+            }
+            Err(e) => {
+                eprintln!("Error: {e}");
+                (None, Some(e.to_string()))
+            }
+        };
+
+        if !cancelled && let Err(e) = session.save() {
+            eprintln!("Failed to save the session: {e:#}");
+        }
+
+        if text_output {
+            println!("------------");
+        } else {
+            output::print_json(&session.id, &prompt, turn.as_ref(), error);
+        }
+    }
+
+    if cancelled
+        && repl.confirm("Save the session, with what was done for the cancelled request?")
+        && let Err(e) = session.save()
+    {
+        eprintln!("Failed to save the session: {e:#}");
+    }
+
+    let running = jobs.running();
+    if running > 0 {
+        eprintln!("Stopping {running} jobs that are still running");
+    }
+    eprintln!("{}", usage::summary(&session.usage, &resources.pricing));
+
+    if text_output {
+        println!("Thanks for using me! I am quitting now.");
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs `turn`, cancelling `cancel` on Ctrl-C, or returns `None` if Ctrl-C is
+/// pressed again before the turn stops.
+async fn cancel_on_ctrl_c<T>(
+    turn: impl Future<Output = T>,
+    cancel: &CancellationToken,
+) -> Option<T> {
+    tokio::pin!(turn);
+
+    // Without a signal handler, Ctrl-C only works at the prompt.
+    tokio::select! {
+        res = &mut turn => return Some(res),
+        Ok(()) = tokio::signal::ctrl_c() => {}
+    }
+    cancel.cancel();
+    eprintln!("Cancelling the request; press Ctrl-C again to quit without waiting");
+
+    tokio::select! {
+        res = turn => Some(res),
+        Ok(()) = tokio::signal::ctrl_c() => None,
+    }
+}
+
+/// Runs one prompt, asking whether to keep going whenever it hits the tool-call
+/// limits, and for the user's reply whenever the model stops to ask something.
+async fn run_turn(
+    agent: &Agent<Model>,
+    repl: &mut Repl,
+    prompt: Message,
+    chat_history: &mut Vec<Message>,
+    transcript: Option<&Transcript>,
+    cancel: &CancellationToken,
+) -> Result<Turn, Error> {
+    let mut res = agent
+        .call_until_response(prompt, chat_history, transcript, Some(cancel))
+        .await;
+
+    loop {
+        let limit = match res {
+            Err(Error::LimitExceeded(limit)) => limit,
+            // Only when the question was shown, rather than printed as JSON.
+            Ok(turn) if agent.echo.is_some() && turn.asks_user() => {
+                let reply = repl.ask("Your reply (leave it empty to stop here):")?;
+                match reply.filter(|reply| !reply.is_empty()) {
+                    Some(reply) => {
+                        res = agent
+                            .reply(turn, &reply, chat_history, transcript, Some(cancel))
+                            .await;
+                        continue;
+                    }
+                    None => return Ok(turn),
+                }
+            }
+            res => return res,
+        };
+
+        let question = format!("The agent {} for this request. Continue?", limit.reason);
+
+        if repl.confirm(&question) {
+            res = agent
+                .resume(*limit, chat_history, transcript, Some(cancel))
+                .await;
+        } else {
+            let turn = limit.abandon(chat_history);
+            if let Some(terminal) = &agent.echo {
+                terminal.role("Agent");
+                terminal.markdown(&turn.answer);
+            }
+            return Ok(turn);
+        }
+    }
+}
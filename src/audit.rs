@@ -0,0 +1,140 @@
+//! An append-only log of every call to a mutating tool, as JSON Lines in one
+//! file, so each change to a sheet can be traced back to the prompt and model
+//! behind it. Cell values are only logged as hashes. Each run ends with a line
+//! of its metrics, so the changes before it can be told apart by run.
+
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{config::AuditConfig, metrics::Metrics, retention, session};
+
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+/// What led to a tool call: the prompt being answered, and the model that
+/// made the call.
+#[derive(Debug, Clone, Copy)]
+pub struct Origin<'a> {
+    pub prompt: &'a str,
+    /// Empty for changes the user made, e.g. with `/undo`.
+    pub model: &'a str,
+}
+
+/// One mutating tool call.
+#[derive(Debug, Serialize)]
+pub struct Entry<'a> {
+    pub tool: &'a str,
+    pub spreadsheet: Option<&'a Value>,
+    /// The cells written, or the range as the model gave it when they aren't
+    /// known.
+    pub range: Option<&'a str>,
+    /// Hash of the cells' values before the call, if they were read.
+    pub old_values: Option<String>,
+    /// Hash of the values the call writes.
+    pub new_values: Option<String>,
+    pub prompt: &'a str,
+    pub model: &'a str,
+    pub outcome: Outcome,
+    /// The reason the call failed or was refused.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Applied,
+    Failed,
+    /// Refused by the guard, or declined by the user.
+    Refused,
+    /// Skipped in a dry run.
+    Simulated,
+}
+
+impl AuditLog {
+    /// Returns `None` unless the audit log is enabled.
+    pub fn from_config(config: &AuditConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            path: path(config)?,
+        }))
+    }
+
+    /// Appends `entry`, without its prompt unless [`retention::persists`].
+    /// Failing to do so doesn't interrupt the session, but is logged as an
+    /// error.
+    pub fn record(&self, entry: &Entry<'_>) {
+        let mut line = serde_json::json!({ "at": session::unix_now() });
+        if let (Value::Object(line), Ok(Value::Object(entry))) =
+            (&mut line, serde_json::to_value(entry))
+        {
+            line.extend(entry);
+            if !retention::persists() {
+                line.remove("prompt");
+            }
+        }
+
+        if let Err(e) = self.append(&line) {
+            tracing::error!(
+                "Failed to write to the audit log {}: {e:#}",
+                self.path.display()
+            );
+        }
+    }
+
+    /// Appends the metrics of a run: `owner` is `run` for qualification runs
+    /// and `session` for batch runs, and `id` is theirs.
+    pub fn record_metrics(&self, owner: &str, id: &str, metrics: &Metrics) {
+        let line = serde_json::json!({
+            "at": session::unix_now(),
+            owner: id,
+            "metrics": metrics,
+        });
+
+        if let Err(e) = self.append(&line) {
+            tracing::error!(
+                "Failed to write to the audit log {}: {e:#}",
+                self.path.display()
+            );
+        }
+    }
+
+    fn append(&self, line: &Value) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        // One write per line, so concurrent writers don't interleave.
+        file.write_all(format!("{line}\n").as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Where the audit log is written: `[audit] path`, or
+/// `~/.local/share/gsheets-agent/audit.jsonl`.
+pub fn path(config: &AuditConfig) -> anyhow::Result<PathBuf> {
+    match &config.path {
+        Some(path) => Ok(path.clone()),
+        None => dirs::data_dir()
+            .map(|dir| dir.join("gsheets-agent").join("audit.jsonl"))
+            .context("Could not determine the data directory to store the audit log in"),
+    }
+}
+
+/// The SHA-256 of `values` as compact JSON, e.g. `sha256:9f86d0…`.
+pub fn hash(values: &[Vec<Value>]) -> String {
+    let json = serde_json::to_string(values).unwrap_or_default();
+
+    format!("sha256:{:x}", Sha256::digest(json.as_bytes()))
+}
@@ -0,0 +1,192 @@
+//! Google credentials, shared by the built-in Sheets client and MCP servers that
+//! expect a Google access token.
+
+use std::{future::Future, path::Path, pin::Pin, sync::Arc};
+
+use anyhow::Context;
+use yup_oauth2::{
+    ApplicationDefaultCredentialsAuthenticator, ApplicationDefaultCredentialsFlowOpts,
+    AuthorizedUserAuthenticator, DeviceFlowAuthenticator, InstalledFlowAuthenticator,
+    InstalledFlowReturnMethod, ServiceAccountAuthenticator,
+    authenticator::{ApplicationDefaultCredentialsTypes, DefaultAuthenticator},
+    authenticator_delegate::{DeviceAuthResponse, DeviceFlowDelegate},
+    authorized_user::AuthorizedUserSecret,
+};
+
+use crate::config::{AuthConfig, OAuthFlow};
+
+const SCOPES: &[&str] = &["https://www.googleapis.com/auth/spreadsheets"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Failed to authenticate with Google: {0}")]
+    Google(#[from] yup_oauth2::Error),
+    #[error("Google returned an access token without a value")]
+    MissingToken,
+}
+
+/// Hands out Google access tokens, refreshing them as they expire.
+#[derive(Clone)]
+pub struct GoogleAuth {
+    authenticator: Arc<DefaultAuthenticator>,
+    scopes: &'static [&'static str],
+}
+
+impl GoogleAuth {
+    /// Loads the configured credentials, or returns `None` if there are none.
+    ///
+    /// A service-account key is used as is. With an OAuth2 client secret, the user
+    /// is asked for consent once and the tokens are cached on disk for later runs.
+    pub async fn from_config(config: &AuthConfig) -> anyhow::Result<Option<Self>> {
+        let Some(credentials) = &config.credentials else {
+            return Ok(None);
+        };
+
+        let authenticator =
+            authenticator(credentials, config.flow, &config.token_cache_path()?).await?;
+
+        Ok(Some(Self {
+            authenticator: Arc::new(authenticator),
+            scopes: SCOPES,
+        }))
+    }
+
+    /// Loads Google Cloud credentials for `scopes`: the service-account key or
+    /// `gcloud` user credentials at `credentials`, or else the application
+    /// default credentials, as the Google Cloud client libraries look them up.
+    pub async fn application_default(
+        credentials: Option<&Path>,
+        scopes: &'static [&'static str],
+    ) -> anyhow::Result<Self> {
+        let credentials = credentials
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS").map(Into::into))
+            .or_else(|| {
+                let path = dirs::config_dir()?
+                    .join("gcloud")
+                    .join("application_default_credentials.json");
+                path.exists().then_some(path)
+            });
+
+        let authenticator = match credentials {
+            Some(credentials) => cloud_authenticator(&credentials).await?,
+            // On Google Cloud, the metadata server hands out tokens for the
+            // instance's service account.
+            None => match ApplicationDefaultCredentialsAuthenticator::builder(
+                ApplicationDefaultCredentialsFlowOpts::default(),
+            )
+            .await
+            {
+                ApplicationDefaultCredentialsTypes::ServiceAccount(builder) => {
+                    builder.build().await?
+                }
+                ApplicationDefaultCredentialsTypes::InstanceMetadata(builder) => {
+                    builder.build().await?
+                }
+            },
+        };
+
+        Ok(Self {
+            authenticator: Arc::new(authenticator),
+            scopes,
+        })
+    }
+
+    pub async fn token(&self) -> Result<String, AuthError> {
+        let token = self.authenticator.token(self.scopes).await?;
+
+        token
+            .token()
+            .map(String::from)
+            .ok_or(AuthError::MissingToken)
+    }
+}
+
+async fn authenticator(
+    credentials: &Path,
+    flow: OAuthFlow,
+    token_cache: &Path,
+) -> anyhow::Result<DefaultAuthenticator> {
+    let contents = std::fs::read(credentials)
+        .with_context(|| format!("Failed to read credentials from {}", credentials.display()))?;
+    let is_service_account = serde_json::from_slice::<serde_json::Value>(&contents)
+        .with_context(|| format!("Failed to parse credentials in {}", credentials.display()))?
+        .get("type")
+        .is_some_and(|kind| kind == "service_account");
+
+    if is_service_account {
+        let key = yup_oauth2::parse_service_account_key(&contents)?;
+        return Ok(ServiceAccountAuthenticator::builder(key).build().await?);
+    }
+
+    if let Some(dir) = token_cache.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let secret = yup_oauth2::parse_application_secret(&contents)?;
+    let authenticator = match flow {
+        OAuthFlow::Browser => {
+            InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::HTTPRedirect)
+                .persist_tokens_to_disk(token_cache)
+                .build()
+                .await?
+        }
+        OAuthFlow::Device => {
+            DeviceFlowAuthenticator::builder(secret)
+                .flow_delegate(Box::new(StderrDelegate))
+                .persist_tokens_to_disk(token_cache)
+                .build()
+                .await?
+        }
+    };
+
+    Ok(authenticator)
+}
+
+/// An authenticator for the service-account key or `gcloud auth
+/// application-default login` credentials at `credentials`.
+async fn cloud_authenticator(credentials: &Path) -> anyhow::Result<DefaultAuthenticator> {
+    let contents = std::fs::read(credentials)
+        .with_context(|| format!("Failed to read credentials from {}", credentials.display()))?;
+    let kind = serde_json::from_slice::<serde_json::Value>(&contents)
+        .with_context(|| format!("Failed to parse credentials in {}", credentials.display()))?
+        .get("type")
+        .and_then(|kind| kind.as_str().map(String::from));
+
+    match kind.as_deref() {
+        Some("service_account") => {
+            let key = yup_oauth2::parse_service_account_key(&contents)?;
+            Ok(ServiceAccountAuthenticator::builder(key).build().await?)
+        }
+        Some("authorized_user") => {
+            let secret: AuthorizedUserSecret =
+                serde_json::from_slice(&contents).with_context(|| {
+                    format!("Failed to parse credentials in {}", credentials.display())
+                })?;
+            Ok(AuthorizedUserAuthenticator::builder(secret).build().await?)
+        }
+        _ => anyhow::bail!(
+            "{} holds neither a service-account key nor gcloud user credentials",
+            credentials.display()
+        ),
+    }
+}
+
+/// Shows the device code on stderr, keeping stdout free for `--output json`.
+struct StderrDelegate;
+
+impl DeviceFlowDelegate for StderrDelegate {
+    fn present_user_code<'a>(
+        &'a self,
+        response: &'a DeviceAuthResponse,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            eprintln!(
+                "To let the agent use Google Sheets, enter the code {} at {}",
+                response.user_code, response.verification_uri
+            );
+            eprintln!("Waiting until access is granted...");
+        })
+    }
+}
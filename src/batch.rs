@@ -0,0 +1,144 @@
+//! Non-interactive execution, for running the agent from scripts and cron.
+
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Read},
+    path::Path,
+    process::ExitCode,
+    time::Instant,
+};
+
+use anyhow::Context;
+
+use crate::{
+    chat::Agent,
+    cli::Cli,
+    config::PriceConfig,
+    error::Error,
+    metrics::Metrics,
+    output::{self, OutputFormat},
+    provider::Model,
+    retention,
+    session::Session,
+    store, usage,
+};
+
+/// A prompt failed with an error.
+pub const EXIT_ERROR: u8 = 1;
+/// A prompt was abandoned because it hit the configured limits.
+pub const EXIT_LIMIT_EXCEEDED: u8 = 2;
+
+/// Prompts to run without a REPL, or `None` to start an interactive session.
+///
+/// These come from `--prompts-file`, from `--prompt`s combined with
+/// `--exit-after-response`, or from stdin when it isn't a terminal.
+pub fn prompts(cli: &Cli) -> Result<Option<Vec<String>>, Error> {
+    let contents = match &cli.prompts_file {
+        Some(path) if path == Path::new("-") => read_stdin()?,
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prompts from {}", path.display()))
+            .map_err(Error::Input)?,
+        None if cli.exit_after_response => return Ok(Some(cli.prompt.clone())),
+        None if !std::io::stdin().is_terminal() => read_stdin()?,
+        None => return Ok(None),
+    };
+
+    let prompts = cli
+        .prompt
+        .iter()
+        .cloned()
+        .chain(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from),
+        )
+        .collect();
+
+    Ok(Some(prompts))
+}
+
+/// Runs `prompts` in order, stopping at the first one that doesn't complete.
+pub async fn run(
+    agent: &Agent<Model>,
+    session: &mut Session,
+    prompts: Vec<String>,
+    output: OutputFormat,
+    pricing: &BTreeMap<String, PriceConfig>,
+) -> ExitCode {
+    let mut exit_code = ExitCode::SUCCESS;
+    let transcript = agent.transcript(&session.id);
+    let started = Instant::now();
+    let mut metrics = Metrics::default();
+
+    for prompt in prompts {
+        eprintln!("> {prompt}");
+
+        let res = agent
+            .call_until_response(
+                prompt.as_str().into(),
+                &mut session.chat_history,
+                transcript.as_ref(),
+                None,
+            )
+            .await;
+
+        let (turn, error, code) = match res {
+            Ok(turn) => (Some(turn), None, None),
+            Err(Error::LimitExceeded(limit)) => {
+                let error = format!("Stopped after the agent {}", limit.reason);
+                let turn = limit.abandon(&mut session.chat_history);
+                eprintln!("{}", turn.answer);
+                (Some(turn), Some(error), Some(EXIT_LIMIT_EXCEEDED))
+            }
+            Err(e) => {
+                eprintln!("Error: {e}");
+                (None, Some(e.to_string()), Some(EXIT_ERROR))
+            }
+        };
+
+        if let Some(turn) = &turn {
+            session.record_usage(&turn.model, turn.usage);
+            metrics.add(turn);
+        }
+
+        if output == OutputFormat::Json {
+            output::print_json(&session.id, &prompt, turn.as_ref(), error);
+        }
+
+        if let Err(e) = session.save() {
+            eprintln!("Failed to save the session: {e:#}");
+        }
+
+        if let Some(code) = code {
+            eprintln!("Session: {} (continue it with --resume)", session.id);
+            exit_code = ExitCode::from(code);
+            break;
+        }
+    }
+
+    eprintln!("{}", usage::summary(&session.usage, pricing));
+
+    metrics.duration_ms = started.elapsed().as_millis() as u64;
+    eprintln!("{}", metrics.summary());
+    agent.tools.audit_metrics("session", &session.id, &metrics);
+    if retention::persists()
+        && let Err(e) =
+            store::open().and_then(|mut store| store.add_session_metrics(&session.id, metrics))
+    {
+        eprintln!("Failed to record the metrics of the run: {e:#}");
+    }
+
+    exit_code
+}
+
+fn read_stdin() -> Result<String, Error> {
+    let mut contents = String::new();
+    std::io::stdin()
+        .read_to_string(&mut contents)
+        .context("Failed to read prompts from stdin")
+        .map_err(Error::Input)?;
+
+    Ok(contents)
+}
@@ -0,0 +1,922 @@
+use std::{sync::Arc, time::Instant};
+
+use futures::{StreamExt, future::join_all};
+use rig::{
+    OneOrMany,
+    completion::{CompletionError, CompletionRequest, CompletionRequestBuilder},
+    message::{AssistantContent, Message, ToolCall, ToolFunction, ToolResultContent, UserContent},
+    streaming::{StreamingChoice, StreamingCompletionModel, StreamingResult},
+};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, info, info_span, warn};
+
+use crate::{
+    audit::Origin,
+    config::{HistoryConfig, LimitsConfig, ModelConfig},
+    error::Error,
+    history,
+    injection::{self, Sanitizer},
+    privacy::{Masker, Unmasking},
+    profile::Profiles,
+    provider::retry,
+    render::{self, Stream, Terminal},
+    sheets, telemetry,
+    tools::{RateLimiter, Toolbox},
+    transcript::{Entry, Transcript, Transcripts},
+    usage::Usage,
+    workspace::Workspace,
+};
+
+/// Returned when a single request exceeds the configured tool-call or token limits.
+///
+/// The tool loop can be continued with [`Agent::resume`], or given up on with
+/// [`LimitExceeded::abandon`].
+#[derive(Debug, thiserror::Error)]
+#[error("{reason}")]
+pub struct LimitExceeded {
+    pub reason: String,
+    /// Tool results that haven't been sent to the model yet.
+    pub pending: Message,
+    /// What was done for the request before it was stopped.
+    pub turn: Turn,
+}
+
+impl LimitExceeded {
+    /// Records the pending tool results and a note that the request was stopped,
+    /// keeping every tool call in `chat_history` paired with its result.
+    ///
+    /// Returns the turn so far, with the note as its answer.
+    pub fn abandon(self, chat_history: &mut Vec<Message>) -> Turn {
+        let note = format!("I stopped working on this request after I {}.", self.reason);
+
+        chat_history.push(self.pending);
+        chat_history.push(Message::assistant(&note));
+
+        Turn {
+            answer: note,
+            ..self.turn
+        }
+    }
+}
+
+/// What the agent did to answer one prompt.
+#[derive(Debug, Default, Serialize)]
+pub struct Turn {
+    pub answer: String,
+    /// The model that gave the answer, which is a fallback if the configured
+    /// one failed.
+    pub model: String,
+    pub tool_calls: Vec<ToolCallRecord>,
+    /// Tokens used by every completion, including history summaries.
+    pub usage: Usage,
+    /// Completions the tool loop asked for, not counting history summaries
+    /// or retries.
+    pub model_calls: usize,
+    /// Completions sent again, to the same model or a fallback, after one
+    /// failed or stalled.
+    pub retries: usize,
+    pub duration_ms: u64,
+}
+
+impl Turn {
+    /// Whether the model stopped partway through the request to ask the user
+    /// something, e.g. which sheet holds the responses, rather than answering
+    /// it: it called tools, and its answer ends with a question.
+    pub fn asks_user(&self) -> bool {
+        let last_line = self
+            .answer
+            .lines()
+            .rev()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default();
+
+        !self.tool_calls.is_empty()
+            && last_line
+                .trim_end_matches(['*', '_', '`', ')', '"'])
+                .ends_with('?')
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolCallRecord {
+    pub name: String,
+    pub arguments: Value,
+    /// Set when the call failed; the error was passed to the model as the result.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Everything needed to run the tool loop that stays fixed across turns.
+pub struct Agent<M> {
+    pub model: M,
+    pub model_config: ModelConfig,
+    /// Tried in order when `model` keeps failing.
+    pub fallbacks: Vec<Fallback<M>>,
+    pub history_config: HistoryConfig,
+    pub limits: LimitsConfig,
+    pub preamble: String,
+    /// Added to the preamble: notes on imported sheets, the rubric and the front-end.
+    pub instructions: String,
+    /// The profile `preamble` was rendered from, `None` for a preamble of the user's own.
+    pub profile: Option<String>,
+    pub profiles: Profiles,
+    pub tools: Toolbox,
+    /// Spreadsheets the user refers to by alias, described in the preamble.
+    pub workspace: Workspace,
+    /// Where answers are streamed to as they arrive, if anywhere.
+    pub echo: Option<Terminal>,
+    /// Where sessions are recorded, if anywhere.
+    pub transcripts: Option<Transcripts>,
+    /// Where the tool loop reports what it does as it goes, if anywhere.
+    pub activity: Option<UnboundedSender<Activity>>,
+    /// What keeps personal data from the model, if it's kept from it.
+    pub privacy: Option<Arc<Masker>>,
+    /// What cuts instructions hidden in sheets out of tool results, if anything.
+    pub injection: Option<Arc<Sanitizer>>,
+    /// Spaces out completions to each model, if they're limited.
+    pub limiter: Option<RateLimiter>,
+}
+
+/// A model to answer with when the ones before it keep failing.
+pub struct Fallback<M> {
+    pub name: String,
+    pub model: M,
+}
+
+/// What the tool loop is doing, for front-ends that show it live.
+#[derive(Debug, Clone)]
+pub enum Activity {
+    /// A piece of an answer, as it streams in.
+    Text(String),
+    ToolCall {
+        id: String,
+        name: String,
+        arguments: Value,
+    },
+    ToolResult {
+        id: String,
+        name: String,
+        arguments: Value,
+        /// What the tool returned, or the error it failed with.
+        result: Result<String, String>,
+        duration_ms: u64,
+    },
+}
+
+impl<M> Agent<M> {
+    fn report(&self, activity: Activity) {
+        if let Some(sender) = &self.activity {
+            // The front-end may have gone away, which doesn't stop the turn.
+            let _ = sender.send(activity);
+        }
+    }
+
+    /// `text` with the placeholders the model was sent instead of personal
+    /// data replaced by the real values.
+    pub fn unmask(&self, text: &str) -> String {
+        match &self.privacy {
+            Some(privacy) => privacy.unmask(text),
+            None => text.to_string(),
+        }
+    }
+
+    /// `text` as the model is shown it, with personal data masked.
+    fn mask(&self, text: String) -> String {
+        match &self.privacy {
+            Some(privacy) => privacy.mask(&text),
+            None => text,
+        }
+    }
+
+    fn masked(&self, prompt: Message) -> Message {
+        match &self.privacy {
+            Some(privacy) => privacy.mask_prompt(prompt),
+            None => prompt,
+        }
+    }
+
+    /// The transcript of the session `id`, if transcripts are enabled.
+    pub fn transcript(&self, id: &str) -> Option<Transcript> {
+        self.transcripts
+            .as_ref()
+            .map(|transcripts| transcripts.open(id))
+    }
+}
+
+impl<M: StreamingCompletionModel> Agent<M> {
+    /// Sends `prompt` to the model and executes the tools it calls until it answers in text.
+    ///
+    /// Unless `echo` is off, the answer is streamed to stdout as it arrives. The full
+    /// text is returned in the [`Turn`] and, together with every tool call and
+    /// result, appended to `chat_history`.
+    ///
+    /// Fails with [`Error::LimitExceeded`] when the model keeps calling tools past
+    /// the configured limits. The first completion of a call is always made, so
+    /// continuing after such an error is guaranteed to make progress. After any
+    /// other error, `chat_history` is left valid for the next prompt.
+    ///
+    /// Once `cancel` is cancelled, the request fails with [`Error::Cancelled`]
+    /// without waiting for the model or for tools that only read. A change that
+    /// is already being made is finished first, so no sheet is left half-written.
+    ///
+    /// The prompt and everything done for it is recorded in `transcript`.
+    pub async fn call_until_response(
+        &self,
+        prompt: Message,
+        chat_history: &mut Vec<Message>,
+        transcript: Option<&Transcript>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Turn, Error> {
+        self.tools.begin_run();
+        self.call_within_run(prompt, chat_history, transcript, cancel)
+            .await
+    }
+
+    /// Like [`Agent::call_until_response`], but the changes made join those
+    /// of the run going on, for requests that are part of a larger one, like
+    /// the chunks of a `qualify` run. The caller begins the run.
+    pub async fn call_within_run(
+        &self,
+        prompt: Message,
+        chat_history: &mut Vec<Message>,
+        transcript: Option<&Transcript>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Turn, Error> {
+        if let Some(transcript) = transcript {
+            transcript.record(&Entry::Prompt {
+                text: &prompt_text(&prompt),
+            });
+        }
+        self.tools.refresh().await;
+        self.run(
+            self.masked(with_links(prompt)),
+            chat_history,
+            Turn::default(),
+            transcript,
+            cancel,
+        )
+        .await
+    }
+
+    /// Continues a request that was stopped by [`LimitExceeded`], with fresh limits.
+    pub async fn resume(
+        &self,
+        limit: LimitExceeded,
+        chat_history: &mut Vec<Message>,
+        transcript: Option<&Transcript>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Turn, Error> {
+        self.run(limit.pending, chat_history, limit.turn, transcript, cancel)
+            .await
+    }
+
+    /// Sends the user's `reply` to a question the model asked in `turn`, see
+    /// [`Turn::asks_user`], and carries on with the same request.
+    ///
+    /// The returned turn includes what was done before the question, and
+    /// `/undo run` still undoes every change made for the request.
+    pub async fn reply(
+        &self,
+        turn: Turn,
+        reply: &str,
+        chat_history: &mut Vec<Message>,
+        transcript: Option<&Transcript>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Turn, Error> {
+        if let Some(transcript) = transcript {
+            transcript.record(&Entry::Prompt { text: reply });
+        }
+        self.run(
+            self.masked(with_links(reply.into())),
+            chat_history,
+            turn,
+            transcript,
+            cancel,
+        )
+        .await
+    }
+
+    async fn run(
+        &self,
+        mut prompt: Message,
+        chat_history: &mut Vec<Message>,
+        mut turn: Turn,
+        transcript: Option<&Transcript>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Turn, Error> {
+        let span = info_span!("turn", model = self.model_config.model_name());
+        let started = Instant::now();
+        let res = self
+            .tool_loop(&mut prompt, chat_history, &mut turn, transcript, cancel)
+            .instrument(span.clone())
+            .await;
+        turn.duration_ms += elapsed_ms(started);
+
+        span.in_scope(|| match &res {
+            Ok(_) => info!(
+                duration_ms = turn.duration_ms,
+                tool_calls = turn.tool_calls.len(),
+                prompt_tokens = turn.usage.prompt_tokens,
+                completion_tokens = turn.usage.completion_tokens,
+                "Turn finished"
+            ),
+            Err(e) => info!(duration_ms = turn.duration_ms, error = %e, "Turn failed"),
+        });
+
+        match res {
+            Ok(Stop::Answered) => Ok(turn),
+            Ok(Stop::LimitExceeded(reason)) => Err(LimitExceeded {
+                reason,
+                pending: prompt,
+                turn,
+            }
+            .into()),
+            Err(e) => {
+                // Tool calls in the history must be followed by their results, or
+                // providers reject every later request.
+                if is_tool_result(&prompt) {
+                    let note = match &e {
+                        Error::Cancelled => {
+                            "I stopped working on this request because it was cancelled."
+                                .to_string()
+                        }
+                        e => format!("I stopped working on this request because of an error: {e}"),
+                    };
+                    chat_history.push(prompt);
+                    chat_history.push(Message::assistant(note));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs completions and tool calls until the model answers or hits the limits,
+    /// leaving the message that still has to be sent in `prompt`.
+    async fn tool_loop(
+        &self,
+        prompt: &mut Message,
+        chat_history: &mut Vec<Message>,
+        turn: &mut Turn,
+        transcript: Option<&Transcript>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Stop, Error> {
+        let record = |entry: Entry<'_>| {
+            if let Some(transcript) = transcript {
+                transcript.record(&entry);
+            }
+        };
+        let asked = self.unmask(&asked(prompt, chat_history));
+        let mut tool_calls_made = 0;
+        let mut tokens_used = 0;
+        // Once a model has failed, the rest of the turn goes to its fallback.
+        let mut model = 0;
+
+        loop {
+            if cancel.is_some_and(CancellationToken::is_cancelled) {
+                return Err(Error::Cancelled);
+            }
+
+            turn.usage += history::compact(
+                &self.model,
+                &self.model_config,
+                chat_history,
+                &self.history_config,
+            )
+            .await?;
+
+            let request_tokens =
+                history::estimate_request_tokens(&self.request(prompt, chat_history));
+            if tool_calls_made > 0 {
+                let reason = if tool_calls_made >= self.limits.max_tool_calls {
+                    Some(format!("made {tool_calls_made} tool calls"))
+                } else if tokens_used + request_tokens > self.limits.token_budget {
+                    Some(format!("used about {tokens_used} prompt tokens"))
+                } else {
+                    None
+                };
+
+                if let Some(reason) = reason {
+                    return Ok(Stop::LimitExceeded(reason));
+                }
+            }
+            tokens_used += request_tokens;
+
+            if let Some(limiter) = &self.limiter {
+                tokio::select! {
+                    () = limiter.acquire(self.model_name(model)) => {}
+                    () = cancelled(cancel) => return Err(Error::Cancelled),
+                }
+            }
+            let call_started = Instant::now();
+            let completion = tokio::select! {
+                completion = self.complete(prompt, chat_history, request_tokens, &mut model, &mut turn.retries) => completion,
+                () = cancelled(cancel) => return Err(Error::Cancelled),
+            };
+            telemetry::record_model_call(
+                self.model_name(model),
+                elapsed_ms(call_started),
+                completion.as_ref().ok().map(|(_, _, usage)| *usage),
+            );
+            let (text, made_calls, usage) = completion?;
+            turn.model_calls += 1;
+            turn.usage += usage;
+            turn.model = self.model_name(model).to_string();
+            // The history keeps what the model said, placeholders and all;
+            // everything else gets the real values.
+            let answer = self.unmask(&text);
+            let tool_calls: Vec<ToolCall> = match &self.privacy {
+                Some(privacy) => made_calls
+                    .iter()
+                    .map(|call| privacy.unmask_call(call))
+                    .collect(),
+                None => made_calls.clone(),
+            };
+
+            if !answer.is_empty() {
+                record(Entry::Completion {
+                    model: &turn.model,
+                    text: &answer,
+                });
+            }
+
+            // keep calling tools until we get human readable answer from the model
+            if tool_calls.is_empty() {
+                chat_history.push(prompt.clone());
+                chat_history.push(Message::assistant(&text));
+
+                turn.answer = answer;
+                return Ok(Stop::Answered);
+            }
+
+            tool_calls_made += tool_calls.len();
+            for tool_call in &tool_calls {
+                if let Some(terminal) = &self.echo {
+                    terminal.tool_call(&tool_call.function.name, &tool_call.function.arguments);
+                }
+                record(Entry::ToolCall {
+                    id: &tool_call.id,
+                    tool: &tool_call.function.name,
+                    arguments: &tool_call.function.arguments,
+                });
+                self.report(Activity::ToolCall {
+                    id: tool_call.id.clone(),
+                    name: tool_call.function.name.clone(),
+                    arguments: tool_call.function.arguments.clone(),
+                });
+            }
+
+            // Reads run concurrently, but each change waits for the calls before
+            // it, as it may depend on them and may ask the user first.
+            let mut responses = Vec::with_capacity(tool_calls.len());
+            for calls in tool_calls.chunk_by(|a, b| {
+                !self.tools.is_mutating(&a.function.name)
+                    && !self.tools.is_mutating(&b.function.name)
+            }) {
+                let skipped = || calls.iter().map(|_| (Err(Error::Cancelled), 0));
+                if cancel.is_some_and(CancellationToken::is_cancelled) {
+                    responses.extend(skipped());
+                    continue;
+                }
+
+                // A spinner would get in the way of asking for confirmation.
+                let spinner = self
+                    .echo
+                    .as_ref()
+                    .filter(|_| {
+                        !calls
+                            .iter()
+                            .any(|call| self.tools.asks(&call.function.name))
+                    })
+                    .map(|terminal| terminal.spinner(calling(calls)));
+                let origin = Origin {
+                    prompt: &asked,
+                    model: self.model_name(model),
+                };
+                let calling = join_all(calls.iter().map(|call| self.call_tool(call, origin)));
+                let first = responses.len();
+                if calls
+                    .iter()
+                    .any(|call| self.tools.is_mutating(&call.function.name))
+                {
+                    responses.extend(calling.await);
+                } else {
+                    tokio::select! {
+                        done = calling => responses.extend(done),
+                        () = cancelled(cancel) => responses.extend(skipped()),
+                    }
+                }
+
+                drop(spinner);
+                if let Some(terminal) = &self.echo {
+                    for (call, (response, duration_ms)) in calls.iter().zip(&responses[first..]) {
+                        let error = response.as_ref().err().map(Error::to_string);
+                        terminal.tool_trace(
+                            &call.function.name,
+                            &call.function.arguments,
+                            error.as_deref(),
+                            *duration_ms,
+                        );
+                    }
+                }
+            }
+
+            let mut results = Vec::with_capacity(tool_calls.len());
+            for (tool_call, (tool_response, duration_ms)) in tool_calls.iter().zip(responses) {
+                turn.tool_calls.push(ToolCallRecord {
+                    name: tool_call.function.name.clone(),
+                    arguments: tool_call.function.arguments.clone(),
+                    error: tool_response.as_ref().err().map(|e| e.to_string()),
+                    duration_ms,
+                });
+
+                // A failed call is reported to the model, which may try another way.
+                let (result, error) = match tool_response {
+                    Ok(result) => (result, false),
+                    Err(e) => (e.to_string(), true),
+                };
+                // The transcript and front-ends get the result as it was; only
+                // the model is kept from what looks like an instruction in it.
+                let sent = match &self.injection {
+                    Some(injection) if !error => {
+                        let sanitized = injection.sanitize(&result);
+                        injection::warn(
+                            &format!("`{}`", tool_call.function.name),
+                            &sanitized.flagged,
+                        );
+                        sanitized.text
+                    }
+                    _ => result.clone(),
+                };
+                record(Entry::ToolResult {
+                    id: &tool_call.id,
+                    result: &result,
+                    error,
+                });
+                self.report(Activity::ToolResult {
+                    id: tool_call.id.clone(),
+                    name: tool_call.function.name.clone(),
+                    arguments: tool_call.function.arguments.clone(),
+                    result: if error {
+                        Err(result.clone())
+                    } else {
+                        Ok(result.clone())
+                    },
+                    duration_ms,
+                });
+
+                results.push(UserContent::tool_result(
+                    tool_call.id.clone(),
+                    OneOrMany::one(ToolResultContent::Text(self.mask(sent).into())),
+                ));
+            }
+
+            // add the tool calls and their results into chat history and continue the loop
+            chat_history.push(prompt.clone());
+            chat_history.push(Message::Assistant {
+                content: OneOrMany::many(made_calls.into_iter().map(AssistantContent::ToolCall))
+                    .expect("there is at least one tool call"),
+            });
+
+            *prompt = Message::User {
+                content: OneOrMany::many(results).expect("there is a result for every tool call"),
+            };
+        }
+    }
+
+    /// Calls one tool, returning its result and how long it took in milliseconds.
+    async fn call_tool(
+        &self,
+        tool_call: &ToolCall,
+        origin: Origin<'_>,
+    ) -> (Result<String, Error>, u64) {
+        // Only the size of the arguments, which hold the cells written and
+        // the values searched for, unmasked.
+        let span = info_span!(
+            "tool_call",
+            tool = tool_call.function.name,
+            arguments_len = tool_call.function.arguments.to_string().len(),
+        );
+        let call_started = Instant::now();
+        let tool_response = self
+            .tools
+            .call(tool_call, origin)
+            .instrument(span.clone())
+            .await;
+        let duration_ms = elapsed_ms(call_started);
+
+        span.in_scope(|| match &tool_response {
+            Ok(_) => info!(duration_ms, "Tool call finished"),
+            Err(e) => info!(duration_ms, error = %e, "Tool call failed"),
+        });
+        telemetry::record_tool_call(&tool_call.function.name, duration_ms, tool_response.is_ok());
+
+        (tool_response, duration_ms)
+    }
+
+    /// Streams one completion, returning its text, the tools it calls and the
+    /// tokens it used.
+    #[tracing::instrument(
+        name = "model_call",
+        skip_all,
+        fields(prompt_tokens = prompt_tokens),
+        err(level = "info")
+    )]
+    async fn complete(
+        &self,
+        prompt: &Message,
+        chat_history: &[Message],
+        prompt_tokens: usize,
+        model: &mut usize,
+        retries: &mut usize,
+    ) -> Result<(String, Vec<ToolCall>, Usage), Error> {
+        let started = Instant::now();
+        let mut thinking = self
+            .echo
+            .as_ref()
+            .map(|terminal| terminal.spinner("thinking…"));
+
+        let timeout = self.model_config.timeout();
+        let mut text = String::new();
+        let mut tool_calls = Vec::new();
+        let mut echo = self.echo.as_ref().map(Stream::new);
+        let mut unmasking = self.privacy.as_deref().map(Masker::unmasking);
+        let mut stalls = 0;
+
+        // Errors in the middle of a stream aren't retried, since part of the
+        // answer may already have been printed. A stream that stalls before
+        // any of it was is started over, though.
+        let mut stream = self
+            .open_stream(prompt, chat_history, model, retries)
+            .await?;
+        loop {
+            let chunk = match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, stream.next()).await {
+                    Ok(chunk) => chunk,
+                    Err(_) => {
+                        let stalled = CompletionError::ProviderError(format!(
+                            "Timed out after {}s in the middle of an answer",
+                            timeout.as_secs()
+                        ));
+                        if !text.is_empty() || !self.retry_stalled(&stalled, model, &mut stalls) {
+                            return Err(stalled.into());
+                        }
+                        *retries += 1;
+                        tool_calls.clear();
+                        stream = self
+                            .open_stream(prompt, chat_history, model, retries)
+                            .await?;
+                        continue;
+                    }
+                },
+                None => stream.next().await,
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+
+            match chunk? {
+                StreamingChoice::Message(chunk) => {
+                    thinking = None;
+                    let shown = match &mut unmasking {
+                        Some(unmasking) => unmasking.push(&chunk),
+                        None => chunk.clone(),
+                    };
+                    if !shown.is_empty() {
+                        if let Some(echo) = &mut echo {
+                            echo.push(&shown);
+                        }
+                        self.report(Activity::Text(shown));
+                    }
+                    text.push_str(&chunk);
+                }
+                StreamingChoice::ToolCall(name, id, arguments) => {
+                    // Some providers don't include ids in streamed tool calls, but
+                    // the tool result has to reference one.
+                    let id = if id.is_empty() {
+                        format!("call_{}_{}", chat_history.len(), tool_calls.len())
+                    } else {
+                        id
+                    };
+                    tool_calls.push(ToolCall {
+                        id,
+                        function: ToolFunction { name, arguments },
+                    });
+                }
+            }
+        }
+
+        drop(thinking);
+        let held = unmasking.map(Unmasking::finish).unwrap_or_default();
+        if let Some(mut echo) = echo {
+            if !held.is_empty() {
+                echo.push(&held);
+            }
+            echo.finish();
+        }
+        if !held.is_empty() {
+            self.report(Activity::Text(held));
+        }
+
+        let usage = Usage {
+            prompt_tokens,
+            completion_tokens: history::estimate_completion_tokens(&text, &tool_calls),
+        };
+        info!(
+            model = self.model_name(*model),
+            duration_ms = elapsed_ms(started),
+            completion_tokens = usage.completion_tokens,
+            tool_calls = tool_calls.len(),
+            "Model answered"
+        );
+
+        Ok((text, tool_calls, usage))
+    }
+
+    /// Starts streaming a completion from the `model`th of the configured model
+    /// and its fallbacks, moving on to the next one whenever a model still fails
+    /// after being retried. `model` is left at the one that answered, and
+    /// `retries` counts the requests sent again.
+    async fn open_stream(
+        &self,
+        prompt: &Message,
+        chat_history: &[Message],
+        model: &mut usize,
+        retries: &mut usize,
+    ) -> Result<StreamingResult, Error> {
+        let timeout = self.model_config.timeout();
+
+        loop {
+            let completion_model = match model.checked_sub(1) {
+                Some(fallback) => &self.fallbacks[fallback].model,
+                None => &self.model,
+            };
+            let mut attempts = 0;
+            let res = retry::with_retry(&self.model_config.retry, || {
+                attempts += 1;
+                async {
+                    let stream = completion_model.stream(self.request(prompt, chat_history));
+                    match timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, stream)
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(CompletionError::ProviderError(format!(
+                                    "Timed out after {}s without an answer",
+                                    timeout.as_secs()
+                                )))
+                            }),
+                        None => stream.await,
+                    }
+                }
+            })
+            .await;
+            *retries += attempts - 1;
+
+            match res {
+                Ok(stream) => return Ok(stream),
+                Err(e) if *model < self.fallbacks.len() => {
+                    warn!(
+                        error = %e,
+                        "{} failed, falling back to {}",
+                        self.model_name(*model),
+                        self.fallbacks[*model].name
+                    );
+                    *model += 1;
+                    *retries += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Whether to ask again after the `model`th model stalled before
+    /// answering: the same model up to `retry.max_attempts` times, and then
+    /// the next fallback, if there is one.
+    fn retry_stalled(&self, error: &CompletionError, model: &mut usize, stalls: &mut u32) -> bool {
+        *stalls += 1;
+        if *stalls < self.model_config.retry.max_attempts {
+            warn!(
+                %error,
+                "{} stalled, asking again (attempt {}/{})",
+                self.model_name(*model),
+                *stalls + 1,
+                self.model_config.retry.max_attempts
+            );
+            return true;
+        }
+        if *model == self.fallbacks.len() {
+            return false;
+        }
+
+        warn!(
+            %error,
+            "{} failed, falling back to {}",
+            self.model_name(*model),
+            self.fallbacks[*model].name
+        );
+        *model += 1;
+        *stalls = 0;
+
+        true
+    }
+
+    /// The name of the `model`th of the configured model and its fallbacks.
+    fn model_name(&self, model: usize) -> &str {
+        match model.checked_sub(1) {
+            Some(fallback) => &self.fallbacks[fallback].name,
+            None => self.model_config.model_name(),
+        }
+    }
+
+    fn request(&self, prompt: &Message, chat_history: &[Message]) -> CompletionRequest {
+        CompletionRequestBuilder::new(self.model.clone(), prompt.to_owned())
+            .preamble(format!(
+                "{}{}{}",
+                self.preamble,
+                self.instructions,
+                self.workspace.context()
+            ))
+            .messages(chat_history.to_vec())
+            .temperature(self.model_config.temperature)
+            .max_tokens(self.model_config.max_tokens)
+            .tools(self.tools.definitions())
+            .build()
+    }
+}
+
+/// What a spinner shows while `calls` run, e.g. `calling read_range(Sheet1!A1:F200)…`.
+fn calling(calls: &[ToolCall]) -> String {
+    let calls: Vec<String> = calls
+        .iter()
+        .map(|call| render::call_summary(&call.function.name, &call.function.arguments))
+        .collect();
+
+    format!("calling {}…", calls.join(", "))
+}
+
+/// How the tool loop ended, short of an error.
+enum Stop {
+    Answered,
+    LimitExceeded(String),
+}
+
+/// The text of `prompt`, without any tool results or media in it.
+fn prompt_text(prompt: &Message) -> String {
+    match prompt {
+        Message::User { content } => content
+            .iter()
+            .filter_map(|content| match content {
+                UserContent::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Message::Assistant { .. } => String::new(),
+    }
+}
+
+/// `prompt`, with the spreadsheet IDs and sheets its links point to, which
+/// models often get wrong when they pick them out of the URLs themselves.
+fn with_links(mut prompt: Message) -> Message {
+    let context = sheets::link_context(&prompt_text(&prompt));
+    if let (Message::User { content }, Some(context)) = (&mut prompt, context) {
+        content.push(UserContent::text(context));
+    }
+
+    prompt
+}
+
+/// The text of the prompt being answered: `prompt`, or the latest one in
+/// `chat_history` if it carries tool results, as when a request is resumed.
+fn asked(prompt: &Message, chat_history: &[Message]) -> String {
+    std::iter::once(prompt)
+        .chain(chat_history.iter().rev())
+        .find(|message| matches!(message, Message::User { .. }) && !is_tool_result(message))
+        .map(prompt_text)
+        .unwrap_or_default()
+}
+
+fn is_tool_result(message: &Message) -> bool {
+    match message {
+        Message::User { content } => content
+            .iter()
+            .any(|content| matches!(content, UserContent::ToolResult(_))),
+        Message::Assistant { .. } => false,
+    }
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    since.elapsed().as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Resolves once `cancel` is cancelled, or never without one.
+async fn cancelled(cancel: Option<&CancellationToken>) {
+    match cancel {
+        Some(cancel) => cancel.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,373 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
+
+use crate::{config::TransportKind, crm::CrmTarget, output::OutputFormat, provider::Provider};
+
+/// An AI agent that can interface with Google Sheets to qualify leads.
+#[derive(Debug, Parser)]
+#[command(name = "gsheets-agent", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub action: Option<Action>,
+
+    /// Path to the config file [default: ~/.config/gsheets-agent/config.toml]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// How to reach the Google Sheets MCP server
+    #[arg(long, value_enum)]
+    pub transport: Option<TransportKind>,
+
+    /// SSE endpoint of the Google Sheets MCP server
+    #[arg(long, value_name = "URL")]
+    pub sse_url: Option<String>,
+
+    /// WebSocket endpoint of the Google Sheets MCP server, for the websocket transport
+    #[arg(long, value_name = "URL", conflicts_with = "mcp_command")]
+    pub ws_url: Option<String>,
+
+    /// Program that runs the MCP server, for the stdio transport
+    #[arg(long, value_name = "PROGRAM")]
+    pub mcp_command: Option<String>,
+
+    /// Argument passed to `--mcp-command` (repeatable)
+    #[arg(
+        long = "mcp-arg",
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        requires = "mcp_command"
+    )]
+    pub mcp_args: Vec<String>,
+
+    /// Completion provider to use
+    #[arg(long, value_enum)]
+    pub provider: Option<Provider>,
+
+    /// Model name, e.g. `gpt-4o` or `claude-3-5-sonnet-latest`
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Sampling temperature
+    #[arg(long)]
+    pub temperature: Option<f64>,
+
+    /// Maximum number of tokens per completion
+    #[arg(long)]
+    pub max_tokens: Option<u64>,
+
+    /// Resume a previously saved chat session
+    #[arg(long, value_name = "SESSION_ID")]
+    pub resume: Option<String>,
+
+    /// Run a prompt before the interactive session starts (repeatable)
+    #[arg(long, value_name = "TEXT")]
+    pub prompt: Vec<String>,
+
+    /// Run each non-empty line of a file as a prompt, then exit (`-` for stdin)
+    #[arg(long, value_name = "PATH")]
+    pub prompts_file: Option<PathBuf>,
+
+    /// Exit after answering the `--prompt`s instead of starting an interactive session
+    #[arg(long, requires = "prompt")]
+    pub exit_after_response: bool,
+
+    /// Run mutating tools without asking for confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+
+    /// Load a CSV file as a sheet of the `local` spreadsheet (repeatable)
+    #[arg(long, value_name = "PATH")]
+    pub import_csv: Vec<PathBuf>,
+
+    /// Load each worksheet of an Excel file as a sheet of the `local` spreadsheet (repeatable)
+    #[arg(long, value_name = "PATH")]
+    pub import_xlsx: Vec<PathBuf>,
+
+    /// Score leads with the criteria in this YAML rubric
+    #[arg(long, value_name = "PATH")]
+    pub rubric: Option<PathBuf>,
+
+    /// Simulate mutating tool calls instead of running them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Refuse every mutating tool call, e.g. to explore a production spreadsheet safely
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Act in this role of the `[policy]` file, e.g. `analyst`
+    #[arg(long, value_name = "NAME")]
+    pub role: Option<String>,
+
+    /// Format of what is written to stdout
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    pub output: OutputFormat,
+
+    /// Chat in a full-screen UI that also shows the tool calls and the cells last read or written
+    #[arg(long, conflicts_with_all = ["prompt", "prompts_file"])]
+    pub tui: bool,
+
+    /// Print answers without colors (also set by the `NO_COLOR` environment variable)
+    #[arg(long)]
+    pub no_color: bool,
+
+    /// Print a line for every tool call once it's done, with how it went and how long it took
+    #[arg(long)]
+    pub trace: bool,
+
+    /// Endpoint of a self-hosted provider, e.g. `http://localhost:11434` for Ollama
+    #[arg(long, value_name = "URL")]
+    pub base_url: Option<String>,
+
+    /// Prompt profile to take the preamble from, e.g. `data-cleaning`
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Set a variable of the profile, e.g. `--var criteria="B2B SaaS"` (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, String)>,
+
+    /// Append JSON logs of every turn, model call and tool call to this file
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+}
+
+/// Work done without a chat session.
+#[derive(Debug, Subcommand)]
+pub enum Action {
+    /// Qualify every lead in a sheet and write the verdicts to a results sheet
+    Qualify(QualifyArgs),
+    /// Save a sheet, e.g. the results of `qualify`, as a CSV file
+    Export(ExportArgs),
+    /// Work out what the columns of a sheet hold, asking about unclear ones, for `qualify` and CRM pushes
+    Columns(ColumnsArgs),
+    /// Compare two prompt profiles or two models on leads whose verdict is known
+    Eval(EvalArgs),
+    /// Run the agent behind a front-end other than the terminal
+    Serve(ServeArgs),
+    /// Qualify the rows added to the sheets of every `[[schedule]]` when it comes up
+    Schedule(ScheduleArgs),
+    /// List earlier runs of `qualify`, newest first
+    Runs(RunsArgs),
+    /// Delete the sessions, transcripts and other saved data `[retention]` no longer keeps
+    Purge(PurgeArgs),
+    /// Ask about the leads you're after and write a scoring rubric from the answers
+    InitCriteria(InitCriteriaArgs),
+    /// Check the MCP servers, the model and the Google credentials, and say how to fix what fails
+    Doctor,
+}
+
+/// Also the body of `POST /v1/qualify`, with the same names.
+#[derive(Debug, Args, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QualifyArgs {
+    /// ID of the spreadsheet, as found in its URL, or `local` for imported files
+    #[arg(long, value_name = "ID")]
+    pub spreadsheet: String,
+
+    /// Sheet holding the leads, with column headers in its first row
+    #[arg(long, value_name = "NAME")]
+    pub sheet: String,
+
+    /// What makes a lead qualified; optional with `--rubric`
+    #[arg(long, value_name = "TEXT")]
+    pub criteria: Option<String>,
+
+    /// Rows read and sent to the model at a time [default: 20]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub chunk_size: Option<u32>,
+
+    /// Rows of the previous chunk repeated with the next one as context [default: 0]
+    #[arg(long, value_name = "N")]
+    pub overlap: Option<u32>,
+
+    /// Chunks judged at once, in parallel completions [default: 1]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub concurrency: Option<u32>,
+
+    /// Only qualify the leads from this row on, appending their results
+    #[arg(long, value_name = "ROW", value_parser = clap::value_parser!(u64).range(2..))]
+    pub from_row: Option<u64>,
+
+    /// Skip the leads that earlier runs wrote results for, appending the rest
+    #[arg(long)]
+    #[serde(default)]
+    pub incremental: bool,
+
+    /// Carry on with a run that stopped, after the last rows it got through
+    #[arg(long, value_name = "ID")]
+    pub resume_run: Option<String>,
+
+    /// Sheet to write the results to [default: "<sheet> results"]
+    #[arg(long, value_name = "NAME")]
+    pub results_sheet: Option<String>,
+
+    /// Also write the leads, as cleaned before qualifying, to this sheet
+    #[arg(long, value_name = "NAME")]
+    pub cleaned_sheet: Option<String>,
+
+    /// Name the columns of the results sheet as this named range, so dashboards
+    /// can refer to them however many rows later runs add
+    #[arg(long, value_name = "NAME")]
+    pub named_range: Option<String>,
+
+    /// Also write counts and charts of the results to "<results sheet> summary"
+    #[arg(long)]
+    #[serde(default)]
+    pub summary: bool,
+
+    /// Also save the results to this CSV file
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip)]
+    pub export_csv: Option<PathBuf>,
+
+    /// Also write a report of the run to this `.html` or `.pdf` file
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip)]
+    pub report: Option<PathBuf>,
+
+    /// Create or update a record in this CRM for every qualified lead
+    #[arg(long, value_enum, value_name = "CRM")]
+    pub push_to: Option<CrmTarget>,
+
+    /// Qualify the sheet even if another run holds the lock on it
+    #[arg(long)]
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// ID of the spreadsheet, as found in its URL, or `local` for imported files
+    #[arg(long, value_name = "ID")]
+    pub spreadsheet: String,
+
+    /// Sheet to export
+    #[arg(long, value_name = "NAME")]
+    pub sheet: String,
+
+    /// CSV file to write
+    #[arg(long, value_name = "PATH")]
+    pub to: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ColumnsArgs {
+    /// ID of the spreadsheet, as found in its URL, or `local` for imported files
+    #[arg(long, value_name = "ID")]
+    pub spreadsheet: String,
+
+    /// Sheet holding the leads, with column headers in its first row
+    #[arg(long, value_name = "NAME")]
+    pub sheet: String,
+}
+
+#[derive(Debug, Args)]
+pub struct EvalArgs {
+    /// ID of the spreadsheet, as found in its URL, or `local` for imported files
+    #[arg(long, value_name = "ID")]
+    pub spreadsheet: String,
+
+    /// Sheet holding the leads, with column headers in its first row
+    #[arg(long, value_name = "NAME")]
+    pub sheet: String,
+
+    /// Column holding the right verdict of each lead, as `yes` or `no`; the model doesn't see it
+    #[arg(long, value_name = "NAME")]
+    pub label_column: String,
+
+    /// What makes a lead qualified; optional with `--rubric`
+    #[arg(long, value_name = "TEXT")]
+    pub criteria: Option<String>,
+
+    /// Leads to judge, from the top of the sheet
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    pub sample: usize,
+
+    /// The two prompt profiles to compare
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["A", "B"],
+        required_unless_present = "models",
+        conflicts_with = "models"
+    )]
+    pub profiles: Vec<String>,
+
+    /// The two models to compare, of the configured provider
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    pub models: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct InitCriteriaArgs {
+    /// YAML file to write the rubric to
+    #[arg(long, value_name = "PATH", default_value = "rubric.yaml")]
+    pub to: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    #[command(subcommand)]
+    pub frontend: Frontend,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Frontend {
+    /// Answer mentions of a Slack bot in their thread, over Socket Mode
+    Slack,
+    /// Serve an HTTP API with `POST /v1/chat` and `POST /v1/qualify`
+    Http(HttpArgs),
+    /// Qualify form responses as an Apps Script trigger posts them to `POST /v1/submissions`
+    Webhook(WebhookArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct HttpArgs {
+    /// Address to listen on [default: 127.0.0.1:8080]
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Args)]
+pub struct WebhookArgs {
+    /// Address to listen on [default: 127.0.0.1:8081]
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Args)]
+pub struct ScheduleArgs {
+    /// Run every schedule now, once, instead of waiting for them
+    #[arg(long)]
+    pub once: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RunsArgs {
+    /// Only list the runs against this spreadsheet
+    #[arg(long, value_name = "ID")]
+    pub spreadsheet: Option<String>,
+
+    /// How many runs to list
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct PurgeArgs {
+    /// Delete all of it, however recent
+    #[arg(long)]
+    pub all: bool,
+}
+
+fn parse_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected NAME=VALUE, got `{arg}`")),
+    }
+}
@@ -0,0 +1,562 @@
+//! Slash commands typed into the REPL, which are handled locally instead of
+//! being sent to the model.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use clap::Parser;
+use rig::message::Message;
+
+use crate::{
+    chat::Agent,
+    cli::QualifyArgs,
+    history,
+    jobs::Jobs,
+    provider::Model,
+    qualify::{self, Resources},
+    repl::Repl,
+    retention,
+    session::Session,
+    sheets, usage,
+    workspace::Spreadsheet,
+};
+
+const HELP: &str = "Commands:
+  /tools          List the tools the agent can use
+  /history        Show the conversation so far
+  /clear          Forget the conversation so far
+  /retry [--model NAME] [--temperature T]
+                  Answer the latest prompt again, switching the model or temperature if given
+  /edit           Change the latest prompt and answer it instead
+  /model [NAME]   Show or switch the model, e.g. `/model gpt-4o-mini`
+  /profile [NAME] Show or switch the prompt profile, e.g. `/profile data-cleaning`
+  /save           Save the session now
+  /load ID        Continue a saved session instead
+  /fork NAME      Go on in a new branch of the conversation, keeping this one
+  /branches       List the branches of the conversation
+  /switch NAME    Switch to another branch
+  /compare NAME   Show where another branch and this one differ
+  /usage          Show the tokens used and their estimated cost
+  /resources      List the resources the MCP servers publish
+  /attach URI     Add a resource to the conversation as context
+  /prompts        List the prompt templates the MCP servers publish
+  /prompt NAME    Fill in a prompt template and send it
+  /workspace      List the spreadsheets you can refer to by alias
+  /workspace add ALIAS URL
+                  Add a spreadsheet under an alias, e.g. `/workspace add leads https://docs.google.com/...`
+  /workspace remove ALIAS
+                  Remove a spreadsheet from the workspace
+  /undo           Restore the cells changed by the agent's latest write
+  /undo run       Restore every change made while answering the latest prompt
+  /qualify ARGS   Qualify a sheet in the background, with the options of `qualify`
+  /jobs           List the background jobs
+  /jobs cancel ID Stop a background job
+  /help           Show this help";
+
+#[derive(Debug)]
+pub enum Command {
+    Tools,
+    History,
+    Clear,
+    /// Forget the answer to the latest prompt and send it again, after
+    /// switching to `model` or `temperature` if given.
+    Retry {
+        model: Option<String>,
+        temperature: Option<f64>,
+    },
+    Edit,
+    Model(Option<String>),
+    Profile(Option<String>),
+    Save,
+    Load(String),
+    Fork(String),
+    Branches,
+    Switch(String),
+    Compare(String),
+    Usage,
+    Resources,
+    Attach(String),
+    Prompts,
+    Prompt(String),
+    Workspace,
+    AddToWorkspace {
+        alias: String,
+        url: String,
+    },
+    RemoveFromWorkspace(String),
+    /// Undo the latest change, or every change of the latest run.
+    Undo {
+        run: bool,
+    },
+    Qualify(Box<QualifyArgs>),
+    Jobs,
+    CancelJob(String),
+    Help,
+}
+
+/// `/qualify`, parsed like the `qualify` subcommand.
+#[derive(Debug, Parser)]
+#[command(name = "/qualify", no_binary_name = true)]
+struct QualifyCommand {
+    #[command(flatten)]
+    args: QualifyArgs,
+}
+
+/// `/retry`, with what to change before answering again.
+#[derive(Debug, Parser)]
+#[command(name = "/retry", no_binary_name = true)]
+struct RetryCommand {
+    /// Switch to this model, e.g. `gpt-4o`
+    #[arg(long)]
+    model: Option<String>,
+    /// Switch to this sampling temperature
+    #[arg(long)]
+    temperature: Option<f64>,
+}
+
+/// Parses `input` as a command, returning `None` if it isn't one.
+///
+/// The error describes a malformed or unknown command.
+pub fn parse(input: &str) -> Option<Result<Command, String>> {
+    let input = input.trim().strip_prefix('/')?;
+    let (name, arg) = match input.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, Some(arg.trim().to_string())),
+        None => (input, None),
+    };
+
+    let command = match (name, arg) {
+        ("tools", None) => Command::Tools,
+        ("history", None) => Command::History,
+        ("clear", None) => Command::Clear,
+        ("retry", arg) => {
+            let Some(words) = shlex::split(arg.as_deref().unwrap_or_default()) else {
+                return Some(Err("/retry has an unclosed quote".to_string()));
+            };
+            match RetryCommand::try_parse_from(words) {
+                Ok(command) => Command::Retry {
+                    model: command.model,
+                    temperature: command.temperature,
+                },
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+        ("edit", None) => Command::Edit,
+        ("model", model) => Command::Model(model),
+        ("profile", profile) => Command::Profile(profile),
+        ("save", None) => Command::Save,
+        ("load", Some(id)) => Command::Load(id),
+        ("load", None) => return Some(Err("Usage: /load SESSION_ID".to_string())),
+        ("fork", Some(name)) => Command::Fork(name),
+        ("fork", None) => return Some(Err("Usage: /fork NAME".to_string())),
+        ("branches", None) => Command::Branches,
+        ("switch", Some(name)) => Command::Switch(name),
+        ("switch", None) => return Some(Err("Usage: /switch NAME".to_string())),
+        ("compare", Some(name)) => Command::Compare(name),
+        ("compare", None) => return Some(Err("Usage: /compare NAME".to_string())),
+        ("usage", None) => Command::Usage,
+        ("resources", None) => Command::Resources,
+        ("attach", Some(uri)) => Command::Attach(uri),
+        ("attach", None) => return Some(Err("Usage: /attach RESOURCE_URI".to_string())),
+        ("prompts", None) => Command::Prompts,
+        ("prompt", Some(name)) => Command::Prompt(name),
+        ("prompt", None) => return Some(Err("Usage: /prompt NAME".to_string())),
+        ("workspace", None) => Command::Workspace,
+        ("workspace", Some(arg)) => match arg.split_whitespace().collect::<Vec<_>>()[..] {
+            ["add", alias, url] => Command::AddToWorkspace {
+                alias: alias.to_string(),
+                url: url.to_string(),
+            },
+            ["remove", alias] => Command::RemoveFromWorkspace(alias.to_string()),
+            _ => {
+                return Some(Err(
+                    "Usage: /workspace [add ALIAS URL | remove ALIAS]".to_string()
+                ));
+            }
+        },
+        ("undo", None) => Command::Undo { run: false },
+        ("undo", Some(arg)) if arg == "run" => Command::Undo { run: true },
+        ("undo", Some(_)) => return Some(Err("Usage: /undo [run]".to_string())),
+        ("qualify", arg) => {
+            let Some(words) = shlex::split(arg.as_deref().unwrap_or_default()) else {
+                return Some(Err("/qualify has an unclosed quote".to_string()));
+            };
+            match QualifyCommand::try_parse_from(words) {
+                Ok(command) => Command::Qualify(Box::new(command.args)),
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+        ("jobs", None) => Command::Jobs,
+        ("jobs", Some(arg)) => match arg.split_once(char::is_whitespace) {
+            Some(("cancel", id)) => Command::CancelJob(id.trim().to_string()),
+            _ => return Some(Err("Usage: /jobs [cancel ID]".to_string())),
+        },
+        ("help", None) => Command::Help,
+        (
+            "tools" | "history" | "clear" | "edit" | "save" | "branches" | "usage" | "resources"
+            | "prompts" | "help",
+            Some(_),
+        ) => {
+            return Some(Err(format!("/{name} doesn't take arguments")));
+        }
+        _ => return Some(Err(format!("Unknown command /{name}, see /help"))),
+    };
+
+    Some(Ok(command))
+}
+
+/// Executes `command`, printing its output, and returns the prompt it wants
+/// sent to the model, if any.
+pub async fn run(
+    command: Command,
+    agent: &mut Arc<Agent<Model>>,
+    session: &mut Session,
+    jobs: &Jobs,
+    resources: &Arc<Resources>,
+    repl: &mut Repl,
+) -> Option<String> {
+    let pricing = &resources.pricing;
+
+    match command {
+        Command::Tools => {
+            for tool in agent.tools.definitions() {
+                let description = tool.description.lines().next().unwrap_or_default();
+                println!("{}: {description}", tool.name);
+            }
+        }
+        Command::History => {
+            if session.chat_history.is_empty() {
+                println!("The conversation is empty.");
+            } else {
+                print!("{}", history::transcript(&session.chat_history));
+            }
+        }
+        Command::Clear => {
+            session.chat_history.clear();
+            println!("Cleared the conversation.");
+        }
+        Command::Retry { model, temperature } => {
+            if model.is_some() || temperature.is_some() {
+                // Running jobs share the agent, as for /model.
+                let Some(agent) = Arc::get_mut(agent) else {
+                    eprintln!("Can't switch models while jobs are running, see /jobs");
+                    return None;
+                };
+                if let Some(temperature) = temperature {
+                    agent.model_config.temperature = temperature;
+                }
+                if let Some(model) = model {
+                    agent.model_config.model = Some(model);
+                    agent.model = Model::from_config(&agent.model_config);
+                }
+                println!(
+                    "Switched to {} at temperature {}",
+                    agent.model_config.model_name(),
+                    agent.model_config.temperature
+                );
+            }
+            // The history holds the prompt as the model was sent it.
+            match history::rewind(&mut session.chat_history) {
+                Some(prompt) => return Some(agent.unmask(&prompt)),
+                None => eprintln!("There's no prompt to answer again."),
+            }
+        }
+        Command::Edit => {
+            let before = session.chat_history.clone();
+            let Some(prompt) = history::rewind(&mut session.chat_history) else {
+                eprintln!("There's no prompt to edit.");
+                return None;
+            };
+            match repl.edit(&agent.unmask(&prompt)) {
+                Ok(Some(prompt)) => return Some(prompt),
+                Ok(None) => println!("Kept the prompt as it was."),
+                Err(e) => eprintln!("{e}"),
+            }
+            session.chat_history = before;
+        }
+        Command::Model(None) => println!("Using {}", agent.model_config.model_name()),
+        Command::Model(Some(model)) => {
+            // Running jobs share the agent, and keep the model they started with.
+            let Some(agent) = Arc::get_mut(agent) else {
+                eprintln!("Can't switch models while jobs are running, see /jobs");
+                return None;
+            };
+            agent.model_config.model = Some(model);
+            agent.model = Model::from_config(&agent.model_config);
+            println!("Switched to {}", agent.model_config.model_name());
+        }
+        Command::Profile(None) => {
+            match &agent.profile {
+                Some(profile) => println!("Using the {profile} profile"),
+                None => println!("Using the preamble from the config file"),
+            }
+            println!("Profiles: {}", agent.profiles.names().join(", "));
+        }
+        Command::Profile(Some(profile)) => {
+            // Running jobs share the agent, and keep the preamble they started with.
+            let Some(agent) = Arc::get_mut(agent) else {
+                eprintln!("Can't switch profiles while jobs are running, see /jobs");
+                return None;
+            };
+            match agent.profiles.render(&profile) {
+                Ok(preamble) => {
+                    agent.preamble = preamble;
+                    println!("Switched to the {profile} profile");
+                    agent.profile = Some(profile);
+                }
+                Err(e) => eprintln!("{e:#}"),
+            }
+        }
+        Command::Save if !retention::persists() => {
+            println!("Sessions aren't saved, as `[retention] persist` is off");
+        }
+        Command::Save => match session.save() {
+            Ok(()) => println!("Saved session {}", session.id),
+            Err(e) => eprintln!("Failed to save the session: {e:#}"),
+        },
+        Command::Load(id) => match Session::load(&id) {
+            Ok(loaded) => {
+                if let Err(e) = session.save() {
+                    eprintln!("Failed to save the session: {e:#}");
+                }
+                *session = loaded;
+                println!(
+                    "Loaded session {} with {} messages",
+                    session.id,
+                    session.chat_history.len()
+                );
+            }
+            Err(e) => eprintln!("{e:#}"),
+        },
+        Command::Fork(name) => {
+            let from = session.branch.clone();
+            match session.fork(&name) {
+                Ok(()) => println!("Forked `{from}` into `{name}`; /switch {from} to go back"),
+                Err(e) => eprintln!("{e:#}"),
+            }
+        }
+        Command::Branches => {
+            println!(
+                "* {} ({} messages)",
+                session.branch,
+                session.chat_history.len()
+            );
+            for (name, history) in &session.branches {
+                let shared = session.shared(name).unwrap_or_default();
+                println!(
+                    "  {name} ({} messages, {shared} of them shared with {})",
+                    history.len(),
+                    session.branch
+                );
+            }
+        }
+        Command::Switch(name) => match session.switch(&name) {
+            Ok(()) => println!(
+                "Switched to `{name}` with {} messages",
+                session.chat_history.len()
+            ),
+            Err(e) => eprintln!("{e:#}"),
+        },
+        Command::Compare(name) => {
+            let (Some(shared), Some(theirs)) = (session.shared(&name), session.branches.get(&name))
+            else {
+                eprintln!("There's no other branch `{name}`, see /branches");
+                return None;
+            };
+            let ours = &session.chat_history[shared..];
+            let theirs = &theirs[shared..];
+            if ours.is_empty() && theirs.is_empty() {
+                println!("`{name}` and `{}` are the same.", session.branch);
+                return None;
+            }
+            println!("After the {shared} messages they share:");
+            for (branch, messages) in [(&session.branch, ours), (&name, theirs)] {
+                match messages {
+                    [] => println!("\n`{branch}` has nothing more."),
+                    messages => print!("\n`{branch}`:\n{}", history::transcript(messages)),
+                }
+            }
+        }
+        Command::Usage => println!("{}", usage::summary(&session.usage, pricing)),
+        Command::Resources => {
+            let resources = resources.mcp.resources().await;
+            if resources.is_empty() {
+                println!("The MCP servers don't publish any resources.");
+            }
+            for (server, resource) in resources {
+                print!("{} ({server}): {}", resource.uri, resource.name);
+                match resource.description {
+                    Some(description) => println!(" - {description}"),
+                    None => println!(),
+                }
+            }
+        }
+        Command::Attach(uri) => match resources.mcp.read_resource(&uri).await {
+            Ok(text) => {
+                // Answered right away, so the conversation still alternates
+                // between the user and the model.
+                session.chat_history.push(Message::user(format!(
+                    "Here is the resource `{uri}`, for context:\n\n{text}"
+                )));
+                session
+                    .chat_history
+                    .push(Message::assistant(format!("I'll keep `{uri}` in mind.")));
+                println!("Attached {uri} ({} characters)", text.chars().count());
+            }
+            Err(e) => eprintln!("{e:#}"),
+        },
+        Command::Prompts => {
+            let prompts = resources.mcp.prompts().await;
+            if prompts.is_empty() {
+                println!("The MCP servers don't publish any prompts.");
+            }
+            for (name, prompt) in prompts {
+                match prompt.description {
+                    Some(description) => println!("{name}: {description}"),
+                    None => println!("{name}"),
+                }
+            }
+        }
+        Command::Prompt(name) => return use_prompt(&name, session, resources, repl).await,
+        Command::Workspace => {
+            let spreadsheets = agent.workspace.list();
+            if spreadsheets.is_empty() {
+                println!("The workspace is empty, add spreadsheets with /workspace add ALIAS URL");
+            }
+            for spreadsheet in spreadsheets {
+                print!("{}: {}", spreadsheet.alias, spreadsheet.id);
+                if let Some(title) = &spreadsheet.title {
+                    print!(" ({title})");
+                }
+                match spreadsheet.sheets.as_slice() {
+                    [] => println!(),
+                    sheets => println!(" - {}", sheets.join(", ")),
+                }
+            }
+        }
+        Command::AddToWorkspace { alias, url } => {
+            let id = sheets::spreadsheet_id(&url);
+            let spreadsheet = match Spreadsheet::read(&alias, id, &resources.sheets).await {
+                Ok(spreadsheet) => spreadsheet,
+                Err(e) => {
+                    eprintln!("Couldn't read the spreadsheet, so its sheets are left out: {e}");
+                    Spreadsheet {
+                        alias: alias.clone(),
+                        id: id.to_string(),
+                        title: None,
+                        sheets: Vec::new(),
+                    }
+                }
+            };
+            match agent.workspace.add(spreadsheet) {
+                Ok(()) => println!("Added {id} to the workspace as `{alias}`"),
+                Err(e) => eprintln!("{e:#}"),
+            }
+        }
+        Command::RemoveFromWorkspace(alias) => match agent.workspace.remove(&alias) {
+            Ok(true) => println!("Removed `{alias}` from the workspace"),
+            Ok(false) => eprintln!("There's no `{alias}` in the workspace"),
+            Err(e) => eprintln!("{e:#}"),
+        },
+        Command::Undo { run } => match agent.tools.undo(run).await {
+            Ok(restored) if restored.is_empty() => println!("There are no changes to undo."),
+            Ok(restored) => println!("Restored {}", restored.join(", ")),
+            Err(e) => eprintln!("{e}"),
+        },
+        Command::Qualify(args) => {
+            let id = qualify::start(jobs, agent.clone(), resources.clone(), *args);
+            println!("Started job {id}, see /jobs");
+        }
+        Command::Jobs => {
+            let jobs = jobs.list();
+            if jobs.is_empty() {
+                println!("No jobs have been started.");
+            }
+            for job in jobs {
+                print!(
+                    "{} {} {}%: {}",
+                    job.id, job.status, job.progress, job.description
+                );
+                match job.error {
+                    Some(error) => println!(" ({error})"),
+                    None => println!(),
+                }
+            }
+        }
+        Command::CancelJob(id) => match jobs.cancel(&id) {
+            Some(job) => println!("Job {id} is {}", job.status),
+            None => eprintln!("There's no job {id}"),
+        },
+        Command::Help => println!("{HELP}"),
+    }
+
+    None
+}
+
+/// Asks for the arguments of the MCP prompt `name` and adds its messages to
+/// the conversation, except for a last one from the user, which is returned
+/// to be sent.
+async fn use_prompt(
+    name: &str,
+    session: &mut Session,
+    resources: &Resources,
+    repl: &mut Repl,
+) -> Option<String> {
+    let (name, prompt) = match resources.mcp.prompt(name).await {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return None;
+        }
+    };
+
+    let mut arguments = BTreeMap::new();
+    for argument in prompt.arguments.unwrap_or_default() {
+        let required = argument.required.unwrap_or(false);
+        let mut question = argument.name.clone();
+        if let Some(description) = &argument.description {
+            question.push_str(&format!(" ({description})"));
+        }
+        question.push_str(if required { ":" } else { " [optional]:" });
+
+        loop {
+            let answer = match repl.ask(&question) {
+                Ok(Some(answer)) => answer,
+                Ok(None) => return None,
+                Err(e) => {
+                    eprintln!("{e}");
+                    return None;
+                }
+            };
+            if answer.is_empty() && required {
+                println!("`{}` is required.", argument.name);
+                continue;
+            }
+            if !answer.is_empty() {
+                arguments.insert(argument.name.clone(), answer);
+            }
+            break;
+        }
+    }
+
+    let mut messages = match resources.mcp.get_prompt(&name, &arguments).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return None;
+        }
+    };
+    let prompt = match messages.last() {
+        Some((role, _)) if role == "user" => messages.pop().map(|(_, text)| text),
+        _ => None,
+    };
+    for (role, text) in messages {
+        session.chat_history.push(match role.as_str() {
+            "assistant" => Message::assistant(text),
+            _ => Message::user(text),
+        });
+    }
+    if prompt.is_none() {
+        println!("Added the prompt {name} to the conversation.");
+    }
+
+    prompt
+}
@@ -0,0 +1,1270 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{cli::Cli, crm::CrmTarget, mcp, provider::Provider, schedule};
+
+/// Settings loaded from `~/.config/gsheets-agent/config.toml`.
+///
+/// Every field has a default, so the file (and any section in it) is optional.
+/// Values passed on the command line take precedence over the file.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// MCP servers to connect to, keyed by the name their tools are namespaced under.
+    pub mcp: BTreeMap<String, ServerConfig>,
+    pub model: ModelConfig,
+    pub history: HistoryConfig,
+    pub limits: LimitsConfig,
+    pub guard: GuardConfig,
+    pub tools: ToolsConfig,
+    pub sheets: SheetsConfig,
+    pub web: WebConfig,
+    pub crm: CrmConfig,
+    pub notify: NotifyConfig,
+    pub slack: SlackConfig,
+    pub http: HttpConfig,
+    pub webhook: WebhookConfig,
+    pub log: LogConfig,
+    pub telemetry: TelemetryConfig,
+    pub transcript: TranscriptConfig,
+    pub audit: AuditConfig,
+    pub privacy: PrivacyConfig,
+    pub injection: InjectionConfig,
+    pub retention: RetentionConfig,
+    /// Sheets qualified again and again by `schedule`.
+    pub schedule: Vec<ScheduleConfig>,
+    pub qualify: QualifyConfig,
+    pub auth: AuthConfig,
+    /// YAML file with the criteria to score leads by.
+    pub rubric: Option<PathBuf>,
+    pub policy: PolicyConfig,
+    pub profile: ProfileConfig,
+    /// A preamble of your own, used instead of the profile's. Variables are
+    /// filled in as in profiles.
+    pub preamble: Option<String>,
+    /// Prices of models missing from the built-in list, or overrides for them,
+    /// keyed by model name.
+    pub pricing: BTreeMap<String, PriceConfig>,
+}
+
+/// Which prompt profile the preamble comes from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// One of the built-in profiles or of the files in `dir`.
+    pub name: String,
+    /// Directory of profiles of your own, as `{name}.md` files.
+    /// Defaults to `~/.config/gsheets-agent/profiles`.
+    pub dir: Option<PathBuf>,
+    /// Values of the `{{name}}` variables in the profile.
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self {
+        Self {
+            name: "lead-qualification".to_string(),
+            dir: None,
+            vars: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub transport: TransportKind,
+    /// SSE endpoint of the server.
+    pub sse_url: String,
+    /// WebSocket endpoint of the server, e.g. `wss://mcp.example.com/ws`.
+    pub ws_url: Option<String>,
+    /// Program to spawn when using the stdio transport.
+    pub command: Option<String>,
+    /// Arguments passed to `command`.
+    pub args: Vec<String>,
+    /// Send a Google access token from `[auth]` as the bearer token, for SSE servers
+    /// that act on the user's behalf.
+    pub google_auth: bool,
+    /// Bearer token sent with every request to an SSE server, or when opening
+    /// a WebSocket, e.g. for an auth proxy in front of the server.
+    pub bearer_token: Option<Secret>,
+    /// Extra headers sent when opening the event stream or WebSocket, such as
+    /// an API key.
+    pub headers: BTreeMap<String, Secret>,
+    /// How to reconnect when the connection drops.
+    pub reconnect: BackoffConfig,
+}
+
+/// A credential, given as is or, to keep it out of the config file, as
+/// `{ env = "NAME" }` or `{ file = "/run/secrets/name" }`.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Secret {
+    Value(String),
+    Env { env: String },
+    File { file: PathBuf },
+}
+
+impl Secret {
+    pub fn resolve(&self) -> anyhow::Result<String> {
+        match self {
+            Self::Value(value) => Ok(value.clone()),
+            Self::Env { env } => std::env::var(env)
+                .with_context(|| format!("The environment variable `{env}` isn't set")),
+            Self::File { file } => std::fs::read_to_string(file)
+                .map(|contents| contents.trim_end().to_string())
+                .with_context(|| format!("Failed to read {}", file.display())),
+        }
+    }
+}
+
+// Values given as is stay out of logs.
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Value(_) => f.write_str("Value(..)"),
+            Self::Env { env } => f.debug_struct("Env").field("env", env).finish(),
+            Self::File { file } => f.debug_struct("File").field("file", file).finish(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// Connect to a running server over HTTP server-sent events.
+    Sse,
+    /// Spawn the server as a child process and talk to it over stdin/stdout.
+    Stdio,
+    /// Connect to a running server over a WebSocket.
+    Websocket,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelConfig {
+    pub provider: Provider,
+    /// Defaults to the provider's flagship model.
+    pub model: Option<String>,
+    pub temperature: f64,
+    pub max_tokens: u64,
+    /// Endpoint of a self-hosted provider such as Ollama.
+    pub base_url: Option<String>,
+    /// Runs Gemini on Vertex AI instead of Google AI Studio.
+    pub vertex: Option<VertexConfig>,
+    /// How to retry completions that fail with rate limits or server errors.
+    pub retry: BackoffConfig,
+    /// Seconds to wait for the model to start answering, or to go on with an
+    /// answer, before the attempt counts as failed; `0` waits as long as it
+    /// takes.
+    pub timeout_secs: u64,
+    /// Completions per minute to each model, shared by everything the agent
+    /// asks at once, e.g. the chunks `qualify` judges concurrently and the
+    /// facts it extracts for them. `0` doesn't limit them.
+    pub requests_per_minute: u32,
+    /// Models to turn to, in order, when the ones before them keep failing.
+    pub fallback: Vec<OtherModelConfig>,
+}
+
+/// A model used besides the one of `[model]`, from which it takes its other
+/// settings.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtherModelConfig {
+    pub provider: Provider,
+    /// Defaults to the provider's flagship model.
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+}
+
+/// Gemini on Vertex AI, billed to a Google Cloud project and authenticated
+/// with its credentials instead of an API key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VertexConfig {
+    /// Defaults to `GOOGLE_CLOUD_PROJECT`.
+    pub project: Option<String>,
+    /// Region the model runs in, or `global`.
+    pub location: String,
+    /// Service-account key or `gcloud` user credentials; the application
+    /// default credentials are used if unset.
+    pub credentials: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mcp: BTreeMap::from([(DEFAULT_SERVER.to_string(), ServerConfig::default())]),
+            model: ModelConfig::default(),
+            history: HistoryConfig::default(),
+            limits: LimitsConfig::default(),
+            guard: GuardConfig::default(),
+            tools: ToolsConfig::default(),
+            sheets: SheetsConfig::default(),
+            web: WebConfig::default(),
+            crm: CrmConfig::default(),
+            notify: NotifyConfig::default(),
+            slack: SlackConfig::default(),
+            http: HttpConfig::default(),
+            webhook: WebhookConfig::default(),
+            log: LogConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            transcript: TranscriptConfig::default(),
+            audit: AuditConfig::default(),
+            privacy: PrivacyConfig::default(),
+            injection: InjectionConfig::default(),
+            retention: RetentionConfig::default(),
+            schedule: Vec::new(),
+            qualify: QualifyConfig::default(),
+            auth: AuthConfig::default(),
+            rubric: None,
+            policy: PolicyConfig::default(),
+            profile: ProfileConfig::default(),
+            preamble: None,
+            pricing: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            transport: TransportKind::Sse,
+            sse_url: "http://127.0.0.1:3000/sse".to_string(),
+            ws_url: None,
+            command: None,
+            args: Vec::new(),
+            google_auth: false,
+            bearer_token: None,
+            headers: BTreeMap::new(),
+            reconnect: BackoffConfig::default(),
+        }
+    }
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            provider: Provider::OpenAi,
+            model: None,
+            temperature: 0.0,
+            max_tokens: 1024,
+            base_url: None,
+            vertex: None,
+            retry: BackoffConfig::default(),
+            timeout_secs: 120,
+            requests_per_minute: 0,
+            fallback: Vec::new(),
+        }
+    }
+}
+
+impl Default for VertexConfig {
+    fn default() -> Self {
+        Self {
+            project: None,
+            location: "us-central1".to_string(),
+            credentials: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file and applies command line overrides on top of it.
+    ///
+    /// An explicit `--config` path must exist; the default location is only
+    /// read if present.
+    pub fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => match default_path() {
+                Some(path) if path.exists() => Self::from_file(&path)?,
+                _ => Self::default(),
+            },
+        };
+
+        config.apply_overrides(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks what deserializing alone can't, such as that cron expressions parse.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, server) in &self.mcp {
+            mcp::validate_server_name(name)?;
+            anyhow::ensure!(
+                !(server.google_auth && server.bearer_token.is_some()),
+                "[mcp.{name}] can't have both `google_auth` and `bearer_token`"
+            );
+        }
+        anyhow::ensure!(
+            self.qualify.overlap < self.qualify.chunk_size,
+            "[qualify] overlap must be smaller than chunk_size"
+        );
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.qualify.dedupe.similarity),
+            "[qualify.dedupe] similarity must be between 0 and 1"
+        );
+        for schedule in &self.schedule {
+            schedule::parse(&schedule.cron)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    fn apply_overrides(&mut self, cli: &Cli) {
+        // Server flags on the command line always configure the Google Sheets server.
+        if cli.sse_url.is_some()
+            || cli.ws_url.is_some()
+            || cli.mcp_command.is_some()
+            || cli.transport.is_some()
+        {
+            let server = self.mcp.entry(DEFAULT_SERVER.to_string()).or_default();
+
+            if let Some(sse_url) = &cli.sse_url {
+                server.sse_url = sse_url.clone();
+            }
+            if let Some(ws_url) = &cli.ws_url {
+                // Likewise, a WebSocket URL implies the websocket transport.
+                server.transport = TransportKind::Websocket;
+                server.ws_url = Some(ws_url.clone());
+            }
+            if let Some(command) = &cli.mcp_command {
+                // Passing a command only makes sense for stdio, so imply it.
+                server.transport = TransportKind::Stdio;
+                server.command = Some(command.clone());
+                server.args = cli.mcp_args.clone();
+            }
+            if let Some(transport) = cli.transport {
+                server.transport = transport;
+            }
+        }
+        if let Some(provider) = cli.provider {
+            self.model.provider = provider;
+        }
+        if let Some(model) = &cli.model {
+            self.model.model = Some(model.clone());
+        }
+        if let Some(temperature) = cli.temperature {
+            self.model.temperature = temperature;
+        }
+        if let Some(max_tokens) = cli.max_tokens {
+            self.model.max_tokens = max_tokens;
+        }
+        if let Some(base_url) = &cli.base_url {
+            self.model.base_url = Some(base_url.clone());
+        }
+        if let Some(rubric) = &cli.rubric {
+            self.rubric = Some(rubric.clone());
+        }
+        if cli.dry_run {
+            self.guard.dry_run = true;
+        }
+        if cli.read_only {
+            self.guard.refuse_writes = true;
+        }
+        if let Some(role) = &cli.role {
+            self.policy.role = Some(role.clone());
+        }
+        if cli.yes {
+            self.guard.confirm = false;
+        }
+        if let Some(profile) = &cli.profile {
+            self.profile.name = profile.clone();
+            // Asking for a profile overrides a preamble of the config file's.
+            self.preamble = None;
+        }
+        for (name, value) in &cli.vars {
+            self.profile.vars.insert(name.clone(), value.clone());
+        }
+        if let Some(path) = &cli.log_file {
+            self.log.file = Some(path.clone());
+        }
+    }
+}
+
+impl ModelConfig {
+    /// `None` if there's no limit.
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.timeout_secs > 0).then(|| Duration::from_secs(self.timeout_secs))
+    }
+
+    pub fn model_name(&self) -> &str {
+        self.model
+            .as_deref()
+            .unwrap_or_else(|| self.provider.default_model())
+    }
+
+    /// The settings of `other`, which shares everything but the model with
+    /// this one.
+    pub fn other(&self, other: &OtherModelConfig) -> Self {
+        Self {
+            provider: other.provider,
+            model: other.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            base_url: other.base_url.clone(),
+            vertex: self.vertex.clone(),
+            retry: self.retry.clone(),
+            timeout_secs: self.timeout_secs,
+            requests_per_minute: self.requests_per_minute,
+            fallback: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Condense old turns into a summary once the history exceeds `token_budget`.
+    pub summarize: bool,
+    /// Estimated number of tokens the chat history may use.
+    pub token_budget: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            summarize: true,
+            token_budget: 60_000,
+        }
+    }
+}
+
+/// Per-request limits that stop a model from calling tools forever.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Tool calls the model may make while answering a single prompt.
+    pub max_tool_calls: usize,
+    /// Estimated prompt tokens all completions for a single prompt may use.
+    pub token_budget: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_tool_calls: 25,
+            token_budget: 500_000,
+        }
+    }
+}
+
+/// Exponential backoff between attempts, starting at `initial_delay_ms` and
+/// doubling up to `max_delay_ms`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackoffConfig {
+    /// Attempts to make before giving up.
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay_ms: 500,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// How long to wait before retry number `retry`, counting from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .initial_delay_ms
+            .saturating_mul(2u64.saturating_pow(retry))
+            .min(self.max_delay_ms);
+
+        Duration::from_millis(delay)
+    }
+}
+
+/// Which tools are advertised to the model, by namespaced name and using the
+/// same globs as [`GuardConfig`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolsConfig {
+    /// Only advertise tools matching one of these; every tool if empty.
+    pub allow: Vec<String>,
+    /// Never advertise tools matching one of these, even if allowed.
+    pub deny: Vec<String>,
+    /// Estimated tokens of a tool result sent to the model at once; longer
+    /// results are cut short, and the model pages through the rest with
+    /// `results__fetch_more`. `0` sends every result in full.
+    pub max_result_tokens: usize,
+    /// Seconds a read of a range is served from memory when the model reads
+    /// it again, unless a mutating tool runs in the meantime. `0` always
+    /// calls the tool.
+    pub cache_ttl_secs: u64,
+    /// Tool calls per minute to each spreadsheet, to stay within the quotas
+    /// of the Sheets API. `0` doesn't limit them.
+    pub requests_per_minute: u32,
+    /// Backoff for tool calls the server rate limits anyway, unless it says
+    /// how long to wait.
+    pub retry: BackoffConfig,
+    /// Seconds a tool call may take before it's given up on, and the model
+    /// told it timed out. `0` waits as long as it takes.
+    pub timeout_secs: u64,
+    /// Seconds between checks for tools the MCP servers added, removed or
+    /// changed, made before answering a prompt. `0` keeps the tools listed
+    /// at startup.
+    pub refresh_secs: u64,
+    /// Answer a read-only call made again with the same arguments within one
+    /// prompt from what it returned the first time, until a mutating tool
+    /// runs.
+    pub memoize_calls: bool,
+    /// Fix the mistakes models often make in arguments, e.g. numbers sent as
+    /// strings or ranges without a sheet, where that's safe.
+    pub repair_arguments: bool,
+    /// Check the arguments of every tool call against the tool's input
+    /// schema before it's made, so the model is told exactly what's wrong
+    /// with them rather than getting the server's error.
+    pub validate_arguments: bool,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            max_result_tokens: 8_000,
+            cache_ttl_secs: 60,
+            requests_per_minute: 60,
+            retry: BackoffConfig::default(),
+            timeout_secs: 120,
+            refresh_secs: 60,
+            memoize_calls: true,
+            repair_arguments: true,
+            validate_arguments: true,
+        }
+    }
+}
+
+/// Google credentials, used by the built-in Sheets client and by MCP servers
+/// with `google_auth` set.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Service-account key or OAuth2 client secret JSON.
+    pub credentials: Option<PathBuf>,
+    /// How to ask the user for consent when `credentials` is a client secret.
+    pub flow: OAuthFlow,
+    /// Where OAuth2 tokens are kept between runs.
+    pub token_cache: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OAuthFlow {
+    /// Open the consent page in a browser on this machine.
+    #[default]
+    Browser,
+    /// Show a code to enter on another device, for headless machines.
+    Device,
+}
+
+impl AuthConfig {
+    pub fn token_cache_path(&self) -> anyhow::Result<PathBuf> {
+        match &self.token_cache {
+            Some(path) => Ok(path.clone()),
+            None => dirs::data_dir()
+                .map(|dir| dir.join("gsheets-agent").join("google_tokens.json"))
+                .context("Could not determine the data directory to store Google tokens in"),
+        }
+    }
+}
+
+/// The built-in Google Sheets client, whose tools are advertised as `sheets__<tool>`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SheetsConfig {
+    /// Advertise the Sheets tools, authenticating with the `[auth]` credentials.
+    pub enabled: bool,
+    /// Read the sheets, row counts and headers of the workspace's spreadsheets
+    /// and the imported files when the agent starts, and tell the model.
+    pub metadata: bool,
+}
+
+/// Company lookups on the web, advertised as `web__lookup_company` and run
+/// for every lead by `qualify`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    pub enabled: bool,
+    pub provider: SearchProvider,
+    /// Environment variable holding the search API key.
+    pub api_key_env: String,
+    /// Search results summarized per company.
+    pub max_results: usize,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: SearchProvider::Homepage,
+            api_key_env: "BRAVE_API_KEY".to_string(),
+            max_results: 3,
+            timeout_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchProvider {
+    /// Read the title and description of the company's homepage; no API key needed.
+    #[default]
+    Homepage,
+    /// Summarize the top results of the Brave Search API.
+    Brave,
+}
+
+/// Where `qualify --push-to` sends qualified leads.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrmConfig {
+    pub hubspot: HubSpotConfig,
+    pub salesforce: SalesforceConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HubSpotConfig {
+    /// Environment variable holding the private app's access token.
+    pub token_env: String,
+    /// Sheet column to read each contact property from, keyed by property name.
+    /// Contacts are matched by `email`, so it has to be mapped.
+    pub properties: BTreeMap<String, String>,
+    /// Contact property that receives the lead's score.
+    pub score_property: String,
+    /// Contact property that receives the reason for the score, if any.
+    pub reason_property: Option<String>,
+}
+
+impl Default for HubSpotConfig {
+    fn default() -> Self {
+        Self {
+            token_env: "HUBSPOT_TOKEN".to_string(),
+            properties: BTreeMap::from([
+                ("email".to_string(), "Email".to_string()),
+                ("firstname".to_string(), "First name".to_string()),
+                ("lastname".to_string(), "Last name".to_string()),
+                ("company".to_string(), "Company".to_string()),
+            ]),
+            score_property: "lead_score".to_string(),
+            reason_property: None,
+        }
+    }
+}
+
+/// Where to post a summary when `qualify` finishes.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Webhook URL, such as a Slack incoming webhook. Nothing is sent without one.
+    pub webhook_url: Option<String>,
+    /// Best scoring qualified leads listed in the summary.
+    pub top_leads: usize,
+    pub timeout_secs: u64,
+    pub email: EmailConfig,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            webhook_url: None,
+            top_leads: 5,
+            timeout_secs: 10,
+            email: EmailConfig::default(),
+        }
+    }
+}
+
+/// Emailing the summary and a report of every finished `qualify` run, through
+/// an SMTP server.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    /// SMTP server, e.g. `smtp.gmail.com`. Nothing is sent without one.
+    pub smtp_host: Option<String>,
+    /// 465 connects with TLS; other ports upgrade to it with STARTTLS.
+    pub port: u16,
+    /// Sign in to the server as this user, with the password in `password_env`.
+    pub username: Option<String>,
+    pub password_env: String,
+    /// Sender address [default: the username].
+    pub from: Option<String>,
+    pub to: Vec<String>,
+    pub timeout_secs: u64,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            smtp_host: None,
+            port: 587,
+            username: None,
+            password_env: "SMTP_PASSWORD".to_string(),
+            from: None,
+            to: Vec::new(),
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// The bot run by `serve slack`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlackConfig {
+    /// Environment variable holding the app-level token, with `connections:write`.
+    pub app_token_env: String,
+    /// Environment variable holding the bot token, with `app_mentions:read` and `chat:write`.
+    pub bot_token_env: String,
+    /// How to reconnect when the Socket Mode connection fails.
+    pub reconnect: BackoffConfig,
+}
+
+impl Default for SlackConfig {
+    fn default() -> Self {
+        Self {
+            app_token_env: "SLACK_APP_TOKEN".to_string(),
+            bot_token_env: "SLACK_BOT_TOKEN".to_string(),
+            reconnect: BackoffConfig::default(),
+        }
+    }
+}
+
+/// A sheet to qualify the new rows of whenever `cron` comes up.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Cron expression in local time, e.g. `0 * * * *` for every hour.
+    pub cron: String,
+    pub spreadsheet: String,
+    pub sheet: String,
+    pub criteria: Option<String>,
+    pub results_sheet: Option<String>,
+    /// Named range to keep around the columns of the results sheet.
+    pub named_range: Option<String>,
+    pub push_to: Option<CrmTarget>,
+}
+
+/// The API run by `serve http`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub listen: SocketAddr,
+    /// Environment variable holding the bearer token requests must carry. The
+    /// API is open to anyone who can reach it when the variable isn't set.
+    pub token_env: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            token_env: "GSHEETS_AGENT_TOKEN".to_string(),
+        }
+    }
+}
+
+/// The endpoint run by `serve webhook`, which form submissions are posted to.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub listen: SocketAddr,
+    /// Environment variable holding the bearer token requests must carry. The
+    /// endpoint is open to anyone who can reach it when the variable isn't set.
+    pub token_env: String,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 8081)),
+            token_env: "GSHEETS_AGENT_WEBHOOK_TOKEN".to_string(),
+        }
+    }
+}
+
+/// Logs written to stderr are filtered by `RUST_LOG` instead, and default to warnings.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// File that JSON logs are appended to, one event per line.
+    pub file: Option<PathBuf>,
+    /// Which events go to `file`, in the syntax of `RUST_LOG`.
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            filter: "info".to_string(),
+        }
+    }
+}
+
+/// Export of traces and metrics to an OpenTelemetry collector.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Base URL of the collector's OTLP/HTTP receiver, e.g. `http://localhost:4318`.
+    /// Nothing is exported without one.
+    pub endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            service_name: "gsheets-agent".to_string(),
+        }
+    }
+}
+
+/// Logs of every prompt, tool call, tool result and completion, one file per
+/// session.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranscriptConfig {
+    pub enabled: bool,
+    /// Defaults to `~/.local/share/gsheets-agent/transcripts`.
+    pub dir: Option<PathBuf>,
+    /// Kinds of personal data masked before anything is written.
+    pub mask: Vec<Mask>,
+    /// More patterns to mask, applied after `mask`.
+    pub redact: Vec<RedactionRule>,
+}
+
+impl Default for TranscriptConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            mask: vec![Mask::Email, Mask::Phone],
+            redact: Vec::new(),
+        }
+    }
+}
+
+/// A log of every call to a mutating tool, in one file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Defaults to `~/.local/share/gsheets-agent/audit.jsonl`.
+    pub path: Option<PathBuf>,
+}
+
+/// Personal data kept from the model: it's replaced with placeholders like
+/// `[EMAIL_1]` in everything the model is sent, and the real values are put
+/// back in what it answers and in the tools it calls.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    pub enabled: bool,
+    /// Kinds of personal data replaced.
+    pub mask: Vec<Mask>,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mask: vec![Mask::Email, Mask::Phone, Mask::Address],
+        }
+    }
+}
+
+/// How long what the agent keeps on disk is kept, in days, before `purge`
+/// deletes it; `0` keeps it for good.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Save sessions, background jobs, transcripts, checkpoints and the input
+    /// history at all, and the prompts in the audit log. The state of
+    /// incremental runs and the workspace are kept either way, and so are the
+    /// audit log's other fields.
+    pub persist: bool,
+    pub sessions_days: u64,
+    pub jobs_days: u64,
+    /// Also how long the checkpoints of runs that never finished are kept.
+    pub transcripts_days: u64,
+    /// Entries of the audit log older than this are dropped from it.
+    pub audit_days: u64,
+    pub input_history_days: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            persist: true,
+            sessions_days: 0,
+            jobs_days: 0,
+            transcripts_days: 0,
+            audit_days: 0,
+            input_history_days: 0,
+        }
+    }
+}
+
+/// Instructions hidden in sheet content kept from the model: they're cut out
+/// of tool results and leads, and the rest is sent as data between delimiters.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InjectionConfig {
+    pub enabled: bool,
+    /// More regular expressions of instructions to cut out, matched ignoring case.
+    /// What their `lead` and `trail` groups match, if they have them, is kept.
+    pub patterns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mask {
+    Email,
+    Phone,
+    Address,
+}
+
+impl Mask {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Phone => "phone",
+            Self::Address => "address",
+        }
+    }
+
+    /// The regular expression matching this kind of personal data.
+    pub fn pattern(self) -> &'static str {
+        match self {
+            Self::Email => crate::transcript::EMAIL,
+            Self::Phone => crate::transcript::PHONE,
+            Self::Address => crate::transcript::ADDRESS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedactionRule {
+    /// Regular expression matching the text to mask.
+    pub pattern: String,
+    /// What matches are replaced with, `[redacted]` by default. May refer to
+    /// capture groups, e.g. `$1`.
+    pub replacement: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SalesforceConfig {
+    /// The org's My Domain URL, which the client credentials flow requires.
+    pub login_url: String,
+    /// Environment variables holding the connected app's consumer key and secret.
+    pub client_id_env: String,
+    pub client_secret_env: String,
+    pub api_version: String,
+    /// Sheet column to read each Lead field from, keyed by field name. Leads are
+    /// matched by `Email`, so it has to be mapped.
+    pub fields: BTreeMap<String, String>,
+    /// Custom Lead field that receives the lead's score.
+    pub score_field: String,
+    /// Lead field that receives the reason for the score, if any.
+    pub reason_field: Option<String>,
+}
+
+impl Default for SalesforceConfig {
+    fn default() -> Self {
+        Self {
+            login_url: "https://login.salesforce.com".to_string(),
+            client_id_env: "SALESFORCE_CLIENT_ID".to_string(),
+            client_secret_env: "SALESFORCE_CLIENT_SECRET".to_string(),
+            api_version: "v60.0".to_string(),
+            fields: BTreeMap::from([
+                ("Email".to_string(), "Email".to_string()),
+                ("FirstName".to_string(), "First name".to_string()),
+                ("LastName".to_string(), "Last name".to_string()),
+                ("Company".to_string(), "Company".to_string()),
+            ]),
+            score_field: "Lead_Score__c".to_string(),
+            reason_field: None,
+        }
+    }
+}
+
+/// How `qualify` pages through a sheet. Each chunk of rows is read and judged
+/// on its own, so sheets of any size fit in the model's context.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualifyConfig {
+    /// Rows read and sent to the model at a time.
+    pub chunk_size: u32,
+    /// Rows of the previous chunk repeated with the next one as context, e.g. to
+    /// spot duplicates across the boundary. They aren't judged again.
+    pub overlap: u32,
+    /// Times a malformed answer is sent back to the model before the chunk fails.
+    pub max_reprompts: u32,
+    /// Chunks judged at once, each in completions of its own. Limit the
+    /// completions per minute under `[model]` to stay within the provider's
+    /// quota.
+    pub concurrency: u32,
+    /// Skip the leads already qualified into the results sheet by earlier runs.
+    pub incremental: bool,
+    /// How long a run holds the lock on its sheet before another run may take
+    /// it over, renewed after every chunk. 0 doesn't lock sheets.
+    pub lock_minutes: u64,
+    /// Leads the model is less sure of than this, from 0 to 1, go to
+    /// "<sheet> needs review" instead of the results. 0 sends none there.
+    pub min_confidence: f64,
+    /// Bold and freeze the header row of new results sheets, color their
+    /// scores from red to green and add a filter view.
+    pub format_results: bool,
+    /// Write counts and charts of the results to "<results sheet> summary"
+    /// after every run, as with `qualify --summary`.
+    pub summary: bool,
+    /// A cheaper model that first boils each lead down to the facts the
+    /// criteria ask about, so the configured model judges far fewer tokens.
+    pub extraction_model: Option<OtherModelConfig>,
+    pub clean: CleanConfig,
+    pub dedupe: DedupeConfig,
+    pub enrich: EnrichConfig,
+}
+
+impl Default for QualifyConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 20,
+            overlap: 0,
+            max_reprompts: 2,
+            concurrency: 1,
+            incremental: false,
+            lock_minutes: 30,
+            min_confidence: 0.0,
+            format_results: true,
+            summary: false,
+            extraction_model: None,
+            clean: CleanConfig::default(),
+            dedupe: DedupeConfig::default(),
+            enrich: EnrichConfig::default(),
+        }
+    }
+}
+
+/// Tidies up the cells of leads before `qualify` does anything else with them,
+/// with rules that don't need a model.
+///
+/// Names, email addresses and dates are found as for deduplication, see
+/// `gsheets-agent columns`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleanConfig {
+    pub enabled: bool,
+    /// Strip whitespace around cells and collapse runs of it inside them.
+    pub trim: bool,
+    /// Capitalize names typed in all lowercase or all uppercase; mixed-case
+    /// names like `McDonald` are left alone.
+    pub title_case_names: bool,
+    /// Write dates as `2024-05-31`, or `2024-05-31 14:05:00` with a time.
+    pub normalize_dates: bool,
+    /// Read dates like `05/04/2024` as the 5th of April rather than May 4th.
+    pub day_first: bool,
+    pub lowercase_emails: bool,
+    /// More columns holding dates, besides the submission time.
+    pub date_columns: Vec<String>,
+    /// Sheet to write the cleaned rows to, lined up with the rows they came
+    /// from. Nothing is written when it's unset.
+    pub cleaned_sheet: Option<String>,
+}
+
+impl Default for CleanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trim: true,
+            title_case_names: true,
+            normalize_dates: true,
+            day_first: false,
+            lowercase_emails: true,
+            date_columns: Vec::new(),
+            cleaned_sheet: None,
+        }
+    }
+}
+
+/// How `qualify` recognizes a lead that was submitted more than once. Only the
+/// first submission is judged and written to the results.
+///
+/// Columns are found by their header, e.g. `Email` or `Company name`, unless
+/// configured here.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DedupeConfig {
+    pub strategy: DedupeStrategy,
+    pub email_column: Option<String>,
+    pub name_column: Option<String>,
+    pub company_column: Option<String>,
+    /// Jaro-Winkler similarity, from 0 to 1, above which two names or two
+    /// company names count as the same for the `fuzzy` strategy.
+    pub similarity: f64,
+}
+
+impl Default for DedupeConfig {
+    fn default() -> Self {
+        Self {
+            strategy: DedupeStrategy::Email,
+            email_column: None,
+            name_column: None,
+            company_column: None,
+            similarity: 0.92,
+        }
+    }
+}
+
+/// Checks `qualify` runs on contact details before they reach the model. The
+/// results are added to every lead as `email_valid`, `email_has_mx`,
+/// `phone_valid` and `phone_e164`, for the model and the rubric to use. With
+/// `[web]` enabled, `company_summary` describes the lead's company. None of
+/// it is done unless `enabled` is set.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnrichConfig {
+    pub enabled: bool,
+    /// Look up the MX records of email domains, to catch addresses that can't
+    /// receive mail. `email_has_mx` is left out without it.
+    pub check_mx: bool,
+    /// Country whose numbering plan applies to phone numbers without a `+`
+    /// prefix, as an ISO 3166 code like `US`.
+    pub default_region: Option<String>,
+    pub email_column: Option<String>,
+    pub phone_column: Option<String>,
+    /// Column with the company's website, looked up instead of the email domain.
+    pub website_column: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupeStrategy {
+    /// Judge every row.
+    Off,
+    /// Leads with the same email address, ignoring case, are duplicates.
+    #[default]
+    Email,
+    /// Like `email`, and leads whose name and company are both similar enough
+    /// are duplicates too.
+    Fuzzy,
+}
+
+/// What the user may have the agent do, by the role they act in.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// YAML file with the operations each role may run tools for.
+    pub file: Option<PathBuf>,
+    /// The role the user acts in, one of the file's.
+    pub role: Option<String>,
+}
+
+/// Classifies tools as read-only or mutating by their namespaced name, e.g.
+/// `gsheets__append_rows`, using globs where `*` matches anything.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GuardConfig {
+    /// Ask for confirmation before running a mutating tool.
+    pub confirm: bool,
+    /// Don't run mutating tools at all; answer them with a simulated success.
+    pub dry_run: bool,
+    /// Don't run mutating tools at all; answer them with an error saying the
+    /// session is read-only. Takes precedence over `dry_run`.
+    pub refuse_writes: bool,
+    /// Show the cells a write changes, compared with what the range holds
+    /// now, before asking for confirmation and in dry runs.
+    pub diff: bool,
+    /// Keep what mutating tools overwrite, so `/undo` can write it back.
+    pub undo: bool,
+    pub mutating: Vec<String>,
+    /// Exceptions to `mutating`.
+    pub read_only: Vec<String>,
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        let mutating = [
+            "write",
+            "update",
+            "append",
+            "insert",
+            "delete",
+            "clear",
+            "create",
+            "remove",
+            "add",
+            "batch",
+            "copy",
+            "move",
+            "rename",
+            "format",
+            "sort",
+            "share",
+            "duplicate",
+            "protect",
+        ];
+
+        Self {
+            confirm: true,
+            dry_run: false,
+            refuse_writes: false,
+            diff: true,
+            undo: true,
+            mutating: mutating
+                .into_iter()
+                .map(|verb| format!("*{verb}*"))
+                .collect(),
+            read_only: Vec::new(),
+        }
+    }
+}
+
+/// Model prices in USD per million tokens.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriceConfig {
+    /// Price of prompt tokens.
+    pub input: f64,
+    /// Price of completion tokens.
+    pub output: f64,
+}
+
+/// Name of the Google Sheets MCP server, which command line flags apply to.
+pub const DEFAULT_SERVER: &str = "gsheets";
+
+/// `~/.config/gsheets-agent/config.toml` (or the platform equivalent).
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("gsheets-agent").join("config.toml"))
+}
@@ -0,0 +1,75 @@
+use serde_json::{Value, json};
+
+use super::{CrmError, QualifiedLead, map_fields};
+use crate::{config::HubSpotConfig, qualify::Columns};
+
+const BASE_URL: &str = "https://api.hubapi.com/crm/v3/objects/contacts";
+/// The most records the batch endpoints take at once.
+const BATCH_SIZE: usize = 100;
+
+/// Client for the HubSpot contacts API, authenticated with a private app token.
+pub struct HubSpotClient {
+    http: reqwest::Client,
+    token: String,
+}
+
+impl HubSpotClient {
+    pub fn new(config: &HubSpotConfig) -> Result<Self, CrmError> {
+        let token = std::env::var(&config.token_env)
+            .map_err(|_| CrmError::MissingEnv(config.token_env.clone()))?;
+
+        Ok(Self {
+            http: reqwest::Client::new(),
+            token,
+        })
+    }
+
+    /// Creates or updates the contacts made by [`records`], matched by email address.
+    pub async fn upsert_contacts(&self, inputs: &[Value]) -> Result<(), CrmError> {
+        for batch in inputs.chunks(BATCH_SIZE) {
+            let response = self
+                .http
+                .post(format!("{BASE_URL}/batch/upsert"))
+                .bearer_auth(&self.token)
+                .json(&json!({ "inputs": batch }))
+                .send()
+                .await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let body: Value = response.json().await.unwrap_or_default();
+                let message = body["message"]
+                    .as_str()
+                    .unwrap_or("no error message")
+                    .to_string();
+
+                return Err(CrmError::Api { status, message });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A batch upsert input for each lead, skipping leads without an email address
+/// since they can't be matched.
+pub fn records(
+    config: &HubSpotConfig,
+    leads: &[QualifiedLead<'_>],
+    columns: &Columns,
+) -> Vec<Value> {
+    leads
+        .iter()
+        .filter_map(|lead| {
+            let mut properties = map_fields(&config.properties, lead.fields, columns);
+            let email = properties.get("email")?.as_str()?.trim().to_lowercase();
+
+            properties.insert(config.score_property.clone(), json!(lead.score));
+            if let Some(reason_property) = &config.reason_property {
+                properties.insert(reason_property.clone(), json!(lead.reason));
+            }
+
+            Some(json!({ "idProperty": "email", "id": email, "properties": properties }))
+        })
+        .collect()
+}
@@ -0,0 +1,131 @@
+//! Pushing qualified leads into a CRM once `qualify` has judged them.
+
+mod hubspot;
+mod salesforce;
+
+pub use hubspot::HubSpotClient;
+pub use salesforce::SalesforceClient;
+
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{config::CrmConfig, qualify::Columns};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrmTarget {
+    Hubspot,
+    Salesforce,
+}
+
+impl CrmTarget {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hubspot => "HubSpot",
+            Self::Salesforce => "Salesforce",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CrmError {
+    #[error("Set {0} to use the CRM")]
+    MissingEnv(String),
+    #[error("Request to the CRM failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("The CRM returned {status}: {message}")]
+    Api {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+}
+
+/// A qualified lead, with the sheet's cells keyed by column header.
+pub struct QualifiedLead<'a> {
+    pub fields: &'a Map<String, Value>,
+    pub score: f64,
+    pub reason: &'a str,
+}
+
+/// Creates or updates a record for each of `leads` in `target`, returning how
+/// many were sent. A configured column that the sheet doesn't have is read
+/// from the one `columns` maps in its stead.
+///
+/// With `dry_run`, the records are printed instead, without contacting the CRM.
+pub async fn push(
+    target: CrmTarget,
+    config: &CrmConfig,
+    leads: &[QualifiedLead<'_>],
+    columns: &Columns,
+    dry_run: bool,
+) -> Result<usize, CrmError> {
+    let records = match target {
+        CrmTarget::Hubspot => hubspot::records(&config.hubspot, leads, columns),
+        CrmTarget::Salesforce => salesforce::records(&config.salesforce, leads, columns),
+    };
+
+    let skipped = leads.len() - records.len();
+    if skipped > 0 {
+        eprintln!(
+            "Skipped {skipped} leads without an email address, which {} records are matched by",
+            target.name()
+        );
+    }
+
+    if dry_run {
+        eprintln!(
+            "[dry run] {} records that would be sent to {}:",
+            records.len(),
+            target.name()
+        );
+        for record in &records {
+            eprintln!("{record}");
+        }
+        return Ok(records.len());
+    }
+
+    match target {
+        CrmTarget::Hubspot => {
+            HubSpotClient::new(&config.hubspot)?
+                .upsert_contacts(&records)
+                .await?
+        }
+        CrmTarget::Salesforce => {
+            SalesforceClient::connect(&config.salesforce)
+                .await?
+                .upsert_leads(&records)
+                .await?
+        }
+    }
+
+    Ok(records.len())
+}
+
+/// The CRM properties of `lead`, mapped from its columns by `mapping`, which
+/// is keyed by property name. Headers are matched ignoring case, falling back
+/// on the column `columns` maps for the header, and empty cells are left out.
+pub fn map_fields(
+    mapping: &BTreeMap<String, String>,
+    lead: &Map<String, Value>,
+    columns: &Columns,
+) -> Map<String, Value> {
+    let find = |column: &str| {
+        lead.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(column))
+            .map(|(_, value)| value)
+    };
+
+    mapping
+        .iter()
+        .filter_map(|(property, column)| {
+            let value = find(column).or_else(|| columns.stand_in(column).and_then(find))?;
+            let is_empty =
+                value.is_null() || value.as_str().is_some_and(|text| text.trim().is_empty());
+
+            (!is_empty).then(|| (property.clone(), value.clone()))
+        })
+        .collect()
+}
@@ -0,0 +1,148 @@
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+use super::{CrmError, QualifiedLead, map_fields};
+use crate::{config::SalesforceConfig, qualify::Columns};
+
+/// Filled in when a lead has no value for a field Salesforce requires.
+const NOT_PROVIDED: &str = "[not provided]";
+
+/// Client for the Salesforce REST API, authenticated with the OAuth 2.0 client
+/// credentials flow of a connected app.
+pub struct SalesforceClient {
+    http: reqwest::Client,
+    token: String,
+    /// Base URL of the REST API, including its version.
+    api_url: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    instance_url: String,
+}
+
+impl SalesforceClient {
+    pub async fn connect(config: &SalesforceConfig) -> Result<Self, CrmError> {
+        let env =
+            |name: &String| std::env::var(name).map_err(|_| CrmError::MissingEnv(name.clone()));
+        let client_id = env(&config.client_id_env)?;
+        let client_secret = env(&config.client_secret_env)?;
+
+        let http = reqwest::Client::new();
+        let response = http
+            .post(format!(
+                "{}/services/oauth2/token",
+                config.login_url.trim_end_matches('/')
+            ))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", &client_id),
+                ("client_secret", &client_secret),
+            ])
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body: Value = response.json().await.unwrap_or_default();
+            let message = body["error_description"]
+                .as_str()
+                .or(body["error"].as_str())
+                .unwrap_or("no error message")
+                .to_string();
+
+            return Err(CrmError::Api { status, message });
+        }
+
+        let token: TokenResponse = response.json().await?;
+        Ok(Self {
+            http,
+            token: token.access_token,
+            api_url: format!(
+                "{}/services/data/{}",
+                token.instance_url.trim_end_matches('/'),
+                config.api_version
+            ),
+        })
+    }
+
+    /// Updates the Lead with the same email as each of `records`, or creates
+    /// one if there is none.
+    pub async fn upsert_leads(&self, records: &[Value]) -> Result<(), CrmError> {
+        for record in records {
+            let email = record["Email"].as_str().unwrap_or_default();
+
+            match self.find_lead(email).await? {
+                Some(id) => {
+                    let url = format!("{}/sobjects/Lead/{id}", self.api_url);
+                    self.send(self.http.patch(url).json(record)).await?;
+                }
+                None => {
+                    let url = format!("{}/sobjects/Lead", self.api_url);
+                    self.send(self.http.post(url).json(record)).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The ID of a Lead with `email`.
+    async fn find_lead(&self, email: &str) -> Result<Option<String>, CrmError> {
+        let escaped = email.replace('\\', "\\\\").replace('\'', "\\'");
+        let query = format!("SELECT Id FROM Lead WHERE Email = '{escaped}' LIMIT 1");
+        let url = format!("{}/query", self.api_url);
+
+        let body = self.send(self.http.get(url).query(&[("q", query)])).await?;
+
+        Ok(body["records"][0]["Id"].as_str().map(String::from))
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Value, CrmError> {
+        let response = request.bearer_auth(&self.token).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            // Errors come as a list of `{message, errorCode}` objects.
+            let body: Value = response.json().await.unwrap_or_default();
+            let message = body[0]["message"]
+                .as_str()
+                .unwrap_or("no error message")
+                .to_string();
+
+            return Err(CrmError::Api { status, message });
+        }
+
+        // Updates answer with 204 No Content.
+        Ok(response.json().await.unwrap_or_default())
+    }
+}
+
+/// The fields of a Lead record for each lead, skipping leads without an email
+/// address since they can't be matched.
+pub fn records(
+    config: &SalesforceConfig,
+    leads: &[QualifiedLead<'_>],
+    columns: &Columns,
+) -> Vec<Value> {
+    leads
+        .iter()
+        .filter_map(|lead| {
+            let mut fields: Map<String, Value> = map_fields(&config.fields, lead.fields, columns);
+            fields.get("Email")?.as_str()?;
+
+            for required in ["LastName", "Company"] {
+                fields
+                    .entry(required)
+                    .or_insert_with(|| json!(NOT_PROVIDED));
+            }
+            fields.insert(config.score_field.clone(), json!(lead.score));
+            if let Some(reason_field) = &config.reason_field {
+                fields.insert(reason_field.clone(), json!(lead.reason));
+            }
+
+            Some(Value::Object(fields))
+        })
+        .collect()
+}
@@ -0,0 +1,396 @@
+//! `gsheets-agent doctor`: checks everything the agent needs before it can
+//! answer, one thing at a time, and says how to fix what's broken, e.g. when
+//! the agent hangs at "Loading gsheets MCP server...".
+
+use std::{collections::BTreeMap, process::ExitCode, time::Duration};
+
+use rig::completion::{CompletionModel, CompletionRequestBuilder, ToolDefinition};
+
+use crate::{
+    auth::GoogleAuth,
+    config::{Config, ModelConfig, ServerConfig, ToolsConfig, TransportKind},
+    mcp::McpServers,
+    provider::{Model, Provider},
+};
+
+/// How long a server gets to complete the MCP handshake and list its tools.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+/// How long the model gets to answer the test prompt.
+const MODEL_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long Google gets to hand out a token, including the user's consent.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Longest tool name OpenAI and Anthropic accept.
+const MAX_TOOL_NAME: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check, with how to fix it unless it passed.
+struct Check {
+    status: Status,
+    what: String,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(what: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            what: what.into(),
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        status: Status,
+        what: impl Into<String>,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            status,
+            what: what.into(),
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn print(&self) {
+        let mark = match self.status {
+            Status::Ok => "✓",
+            Status::Warn => "!",
+            Status::Fail => "✗",
+        };
+        println!("{mark} {}: {}", self.what, self.detail);
+        if let Some(fix) = &self.fix {
+            println!("  → {fix}");
+        }
+    }
+}
+
+/// Runs every check that applies to `config`, printing each as it finishes,
+/// and fails if any of them did.
+pub async fn run(config: &Config) -> ExitCode {
+    let mut checks = Vec::new();
+    let mut report = |found: Vec<Check>| {
+        for check in &found {
+            check.print();
+        }
+        checks.extend(found);
+    };
+
+    report(vec![match config.validate() {
+        Ok(()) => Check::ok("Config", "valid"),
+        Err(e) => Check::problem(
+            Status::Fail,
+            "Config",
+            format!("{e:#}"),
+            "Fix the setting named above in config.toml",
+        ),
+    }]);
+
+    let google_auth = match check_google_auth(config).await {
+        Some((check, auth)) => {
+            report(vec![check]);
+            auth
+        }
+        None => None,
+    };
+
+    if config.mcp.is_empty() {
+        report(vec![Check::ok("MCP servers", "none configured")]);
+    }
+    for (name, server) in &config.mcp {
+        report(check_server(name, server, &config.tools, google_auth.as_ref()).await);
+    }
+
+    report(vec![check_model(&config.model).await]);
+    for fallback in &config.model.fallback {
+        report(vec![check_model(&config.model.other(fallback)).await]);
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    let warned = checks.iter().filter(|c| c.status == Status::Warn).count();
+    println!();
+    match (failed, warned) {
+        (0, 0) => println!("Everything looks fine."),
+        (0, _) => println!("No problems that stop the agent, but see the warnings above."),
+        (failed, _) => println!("{failed} of {} checks failed.", checks.len()),
+    }
+
+    match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+/// Gets a Google access token, if anything needs one.
+async fn check_google_auth(config: &Config) -> Option<(Check, Option<GoogleAuth>)> {
+    let needed_by = config
+        .mcp
+        .iter()
+        .filter(|(_, server)| server.google_auth)
+        .map(|(name, _)| format!("the `{name}` MCP server"))
+        .chain(config.sheets.enabled.then(|| "[sheets]".to_string()))
+        .collect::<Vec<_>>();
+    let Some(credentials) = &config.auth.credentials else {
+        return (!needed_by.is_empty()).then(|| {
+            let check = Check::problem(
+                Status::Fail,
+                "Google auth",
+                format!("no credentials, but {} needs them", needed_by.join(" and ")),
+                "Set `credentials` under [auth] to a service-account key or an OAuth2 desktop \
+                 client secret from the Google Cloud console",
+            );
+            (check, None)
+        });
+    };
+
+    let fix = format!(
+        "Check that {} is a service-account key or an OAuth2 desktop client secret; with a \
+         client secret, delete {} to give consent again",
+        credentials.display(),
+        config
+            .auth
+            .token_cache_path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| "the token cache".to_string())
+    );
+    let auth = match GoogleAuth::from_config(&config.auth).await {
+        Ok(Some(auth)) => auth,
+        Ok(None) => return None,
+        Err(e) => {
+            return Some((
+                Check::problem(Status::Fail, "Google auth", format!("{e:#}"), fix),
+                None,
+            ));
+        }
+    };
+
+    let check = match tokio::time::timeout(AUTH_TIMEOUT, auth.token()).await {
+        Ok(Ok(_)) => Check::ok(
+            "Google auth",
+            format!("got a token for {}", credentials.display()),
+        ),
+        Ok(Err(e)) => Check::problem(Status::Fail, "Google auth", format!("{e:#}"), fix),
+        Err(_) => Check::problem(
+            Status::Fail,
+            "Google auth",
+            format!("no token after {}s", AUTH_TIMEOUT.as_secs()),
+            "Finish giving consent in the browser, or set `flow = \"device\"` under [auth] on a \
+             machine without one",
+        ),
+    };
+
+    Some((check, Some(auth)))
+}
+
+/// Connects to the server, completing the MCP handshake, and looks over the
+/// tools it lists.
+async fn check_server(
+    name: &str,
+    server: &ServerConfig,
+    tools: &ToolsConfig,
+    auth: Option<&GoogleAuth>,
+) -> Vec<Check> {
+    let what = format!("MCP server `{name}`");
+    let (transport, address) = match server.transport {
+        TransportKind::Sse => ("SSE", server.sse_url.clone()),
+        TransportKind::Websocket => ("WebSocket", server.ws_url.clone().unwrap_or_default()),
+        TransportKind::Stdio => {
+            let command = std::iter::once(server.command.clone().unwrap_or_default())
+                .chain(server.args.iter().cloned())
+                .collect::<Vec<_>>();
+            ("stdio", command.join(" "))
+        }
+    };
+    let reach = match server.transport {
+        TransportKind::Sse => format!(
+            "Start the server, or point `sse_url` under [mcp.{name}] (or --sse-url) at its SSE \
+             endpoint, which usually ends in `/sse`"
+        ),
+        TransportKind::Websocket => format!(
+            "Start the server, or point `ws_url` under [mcp.{name}] (or --ws-url) at its \
+             WebSocket endpoint"
+        ),
+        TransportKind::Stdio => format!(
+            "Check that `{address}` is installed and on the PATH, and run it by hand to see \
+             whether it starts"
+        ),
+    };
+
+    let configs = BTreeMap::from([(name.to_string(), server.clone())]);
+    let servers = match tokio::time::timeout(CONNECT_TIMEOUT, McpServers::connect(&configs, auth))
+        .await
+    {
+        Ok(Ok(servers)) => servers,
+        Ok(Err(e)) => {
+            return vec![Check::problem(Status::Fail, what, format!("{e:#}"), reach)];
+        }
+        Err(_) => {
+            return vec![Check::problem(
+                Status::Fail,
+                what,
+                format!(
+                    "no answer to the MCP handshake over {transport} at {address} within {}s",
+                    CONNECT_TIMEOUT.as_secs()
+                ),
+                format!(
+                    "{reach}. Something that isn't an MCP server can accept the connection and never answer"
+                ),
+            )];
+        }
+    };
+
+    let (_, listed) = servers.tools(&ToolsConfig::default()).await;
+    let (_, advertised) = servers.tools(tools).await;
+    let mut checks = vec![Check::ok(
+        what.clone(),
+        format!(
+            "connected over {transport} at {address}, {} tools ({} advertised)",
+            listed.len(),
+            advertised.len()
+        ),
+    )];
+    checks.extend(check_tools(&what, &listed, &advertised));
+
+    checks
+}
+
+/// Problems with the tools a server lists that would keep the model from
+/// using them.
+fn check_tools(what: &str, listed: &[ToolDefinition], advertised: &[ToolDefinition]) -> Vec<Check> {
+    let what = format!("{what} tools");
+    if listed.is_empty() {
+        return vec![Check::problem(
+            Status::Fail,
+            what,
+            "the server lists none",
+            "Check that this is the MCP server you meant, and that it started without errors",
+        )];
+    }
+
+    let mut checks = Vec::new();
+    if advertised.is_empty() {
+        checks.push(Check::problem(
+            Status::Warn,
+            what.clone(),
+            "[tools] allow and deny leave none of them",
+            "Loosen `allow` or `deny` under [tools]; `/tools` lists what the model sees",
+        ));
+    }
+
+    let long: Vec<&str> = listed
+        .iter()
+        .filter(|tool| tool.name.len() > MAX_TOOL_NAME)
+        .map(|tool| tool.name.as_str())
+        .collect();
+    if !long.is_empty() {
+        checks.push(Check::problem(
+            Status::Fail,
+            what.clone(),
+            format!(
+                "names longer than {MAX_TOOL_NAME} characters: {}",
+                long.join(", ")
+            ),
+            "Give the server a shorter name in its [mcp.<name>] table, or deny these tools",
+        ));
+    }
+
+    let no_schema: Vec<&str> = listed
+        .iter()
+        .filter(|tool| tool.parameters.get("type").and_then(|t| t.as_str()) != Some("object"))
+        .map(|tool| tool.name.as_str())
+        .collect();
+    if !no_schema.is_empty() {
+        checks.push(Check::problem(
+            Status::Warn,
+            what.clone(),
+            format!(
+                "input schemas that aren't objects: {}",
+                no_schema.join(", ")
+            ),
+            "Model providers reject such tools; fix them on the server, or deny them under [tools]",
+        ));
+    }
+
+    let undescribed: Vec<&str> = listed
+        .iter()
+        .filter(|tool| tool.description.trim().is_empty())
+        .map(|tool| tool.name.as_str())
+        .collect();
+    if !undescribed.is_empty() {
+        checks.push(Check::problem(
+            Status::Warn,
+            what,
+            format!("no description: {}", undescribed.join(", ")),
+            "The model picks tools by their descriptions, so it may not use these",
+        ));
+    }
+
+    checks
+}
+
+/// Sends the model a one-word prompt, which only works if its credentials do.
+async fn check_model(config: &ModelConfig) -> Check {
+    let what = format!("Model {}", config.model_name());
+    let key_env = match config.provider {
+        Provider::OpenAi => Some("OPENAI_API_KEY"),
+        Provider::Anthropic => Some("ANTHROPIC_API_KEY"),
+        Provider::Gemini if config.vertex.is_none() => Some("GEMINI_API_KEY"),
+        Provider::Gemini | Provider::Ollama => None,
+    };
+    // The provider clients panic without their key, so it's checked first.
+    let has_key = |name: &str| {
+        std::env::var(name).is_ok_and(|key| !key.is_empty())
+            || name == "GEMINI_API_KEY" && std::env::var("GOOGLE_API_KEY").is_ok()
+    };
+    if let Some(name) = key_env
+        && !has_key(name)
+    {
+        return Check::problem(
+            Status::Fail,
+            what,
+            format!("{name} isn't set"),
+            format!("Export {name}, or pick another provider with --provider"),
+        );
+    }
+
+    let fix = match config.provider {
+        Provider::Ollama => format!(
+            "Start Ollama with `ollama serve`, or point --base-url at it, and pull the model \
+             with `ollama pull {}`",
+            config.model_name()
+        ),
+        Provider::Gemini if config.vertex.is_some() => "Check the project and location under \
+            [model.vertex], and that the credentials may use Vertex AI there"
+            .to_string(),
+        _ => format!(
+            "Check the key in {}, and that `{}` is a model your account can use",
+            key_env.unwrap_or_default(),
+            config.model_name()
+        ),
+    };
+
+    let model = Model::from_config(config);
+    let request = CompletionRequestBuilder::new(model.clone(), "Reply with the word OK.")
+        .max_tokens(16)
+        .build();
+    match tokio::time::timeout(MODEL_TIMEOUT, model.completion(request)).await {
+        Ok(Ok(_)) => Check::ok(what, "answered a test prompt"),
+        Ok(Err(e)) => Check::problem(Status::Fail, what, e.to_string(), fix),
+        Err(_) => Check::problem(
+            Status::Fail,
+            what,
+            format!("no answer within {}s", MODEL_TIMEOUT.as_secs()),
+            fix,
+        ),
+    }
+}
@@ -0,0 +1,40 @@
+//! Errors that can happen while the agent is running, grouped by their source, so
+//! callers can decide which ones to recover from.
+
+use rig::completion::CompletionError;
+
+use crate::chat::LimitExceeded;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// An MCP server couldn't be reached or didn't follow the protocol.
+    #[error("MCP transport error: {0:#}")]
+    Transport(anyhow::Error),
+
+    /// A tool call failed or was refused. The message is sent to the model as
+    /// the tool result, so it's written for the model.
+    #[error("{0}")]
+    Tool(String),
+
+    /// The model provider returned an error.
+    #[error("Completion error: {0}")]
+    Completion(#[from] CompletionError),
+
+    /// The prompts couldn't be read from the terminal, stdin or a file.
+    #[error("Input error: {0:#}")]
+    Input(anyhow::Error),
+
+    /// The user cancelled the request, e.g. with Ctrl-C.
+    #[error("Cancelled by the user")]
+    Cancelled,
+
+    /// A request was stopped by the configured limits.
+    #[error(transparent)]
+    LimitExceeded(Box<LimitExceeded>),
+}
+
+impl From<LimitExceeded> for Error {
+    fn from(limit: LimitExceeded) -> Self {
+        Self::LimitExceeded(Box::new(limit))
+    }
+}
@@ -0,0 +1,221 @@
+//! Keeps the chat history within the model's context window by condensing old
+//! turns into a summary.
+
+use rig::{
+    completion::{CompletionError, CompletionModel, CompletionRequest, CompletionRequestBuilder},
+    message::{AssistantContent, Message, ToolCall, ToolResultContent, UserContent},
+};
+
+use crate::{
+    config::{HistoryConfig, ModelConfig},
+    provider::retry,
+    usage::Usage,
+};
+
+/// Tool results longer than this are cut when building the summarization
+/// transcript; full-sheet reads would otherwise dominate it.
+const MAX_TOOL_RESULT_CHARS: usize = 2_000;
+
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:";
+
+const SUMMARY_PREAMBLE: &str = "You condense conversations between a user and an agent that \
+qualifies sales leads in Google Sheets. Summarize the transcript you are given in a few short \
+paragraphs. Keep every spreadsheet ID, sheet name, range, qualification criterion, decision and \
+result location, and drop raw cell data that can be re-read from the sheet.";
+
+/// Rough token count of `messages`, assuming ~4 bytes per token of their JSON form.
+///
+/// Provider tokenizers differ, so this only has to be good enough to decide
+/// when to compact.
+pub fn estimate_tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| serde_json::to_string(message).map_or(0, |json| json.len()) / 4)
+        .sum()
+}
+
+/// Rough token count of everything sent to the model for `request`.
+pub fn estimate_request_tokens(request: &CompletionRequest) -> usize {
+    let preamble = request.preamble.as_ref().map_or(0, String::len) / 4;
+    let tools = serde_json::to_string(&request.tools).map_or(0, |json| json.len()) / 4;
+
+    preamble
+        + tools
+        + estimate_tokens(&request.chat_history)
+        + estimate_tokens(std::slice::from_ref(&request.prompt))
+}
+
+/// Rough token count of a completion that wrote `text` and called `tool_calls`.
+pub fn estimate_completion_tokens(text: &str, tool_calls: &[ToolCall]) -> usize {
+    let tool_calls = serde_json::to_string(tool_calls).map_or(0, |json| json.len());
+
+    (text.len() + tool_calls) / 4
+}
+
+/// Replaces the oldest turns of `history` with a model-written summary once it
+/// grows past the configured token budget.
+///
+/// History is only cut where a user turn starts, so tool calls always stay next
+/// to their results. The most recent turns are kept verbatim, aiming for half the
+/// budget so compaction doesn't run again on the next turn.
+///
+/// Returns the usage of the summarization, if one was needed.
+pub async fn compact<M: CompletionModel>(
+    model: &M,
+    model_config: &ModelConfig,
+    history: &mut Vec<Message>,
+    config: &HistoryConfig,
+) -> Result<Usage, CompletionError> {
+    if !config.summarize || estimate_tokens(history) <= config.token_budget {
+        return Ok(Usage::default());
+    }
+
+    let turn_starts: Vec<usize> = history
+        .iter()
+        .enumerate()
+        .filter(|(idx, message)| *idx > 0 && is_user_prompt(message))
+        .map(|(idx, _)| idx)
+        .collect();
+
+    let Some(&last_turn) = turn_starts.last() else {
+        return Ok(Usage::default());
+    };
+
+    let cut = turn_starts
+        .iter()
+        .copied()
+        .find(|&idx| estimate_tokens(&history[idx..]) <= config.token_budget / 2)
+        .unwrap_or(last_turn);
+
+    let transcript = transcript(&history[..cut]);
+    let transcript_tokens = (SUMMARY_PREAMBLE.len() + transcript.len()) / 4;
+    let resp = retry::with_retry(&model_config.retry, || {
+        let request = CompletionRequestBuilder::new(model.clone(), transcript.clone())
+            .preamble(SUMMARY_PREAMBLE.to_string())
+            .temperature(0.0)
+            .max_tokens(model_config.max_tokens)
+            .build();
+
+        model.completion(request)
+    })
+    .await?;
+
+    let summary = resp
+        .choice
+        .into_iter()
+        .filter_map(|content| match content {
+            AssistantContent::Text(text) => Some(text.text),
+            AssistantContent::ToolCall(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    history.splice(
+        ..cut,
+        [
+            Message::user(format!("{SUMMARY_PREFIX}\n{summary}")),
+            Message::assistant("Understood, I'll continue from this summary."),
+        ],
+    );
+
+    eprintln!(
+        "(Condensed {cut} earlier messages into a summary to stay within the context budget.)"
+    );
+
+    Ok(Usage {
+        prompt_tokens: transcript_tokens,
+        completion_tokens: estimate_completion_tokens(&summary, &[]),
+    })
+}
+
+/// Cuts `history` back to just before the latest prompt, dropping the answer
+/// to it along with the tool calls and results that led there, and returns
+/// what the prompt said.
+///
+/// The summary left by [`compact`] isn't a prompt, so there's nothing to
+/// rewind to once the latest prompt has been condensed into it.
+pub fn rewind(history: &mut Vec<Message>) -> Option<String> {
+    let (start, text) =
+        history
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(idx, message)| match message {
+                Message::User { content } if is_user_prompt(message) => {
+                    content.iter().find_map(|content| match content {
+                        UserContent::Text(text) => Some((idx, text.text.clone())),
+                        _ => None,
+                    })
+                }
+                _ => None,
+            })?;
+    if text.starts_with(SUMMARY_PREFIX) {
+        return None;
+    }
+
+    history.truncate(start);
+    Some(text)
+}
+
+fn is_user_prompt(message: &Message) -> bool {
+    match message {
+        Message::User { content } => content
+            .iter()
+            .any(|content| matches!(content, UserContent::Text(_))),
+        Message::Assistant { .. } => false,
+    }
+}
+
+/// Renders `messages` as plain text, with long tool results cut short.
+pub fn transcript(messages: &[Message]) -> String {
+    let mut transcript = String::new();
+
+    for message in messages {
+        match message {
+            Message::User { content } => {
+                for content in content.iter() {
+                    match content {
+                        UserContent::Text(text) => {
+                            transcript.push_str(&format!("User: {}\n", text.text));
+                        }
+                        UserContent::ToolResult(result) => {
+                            for content in result.content.iter() {
+                                if let ToolResultContent::Text(text) = content {
+                                    transcript.push_str(&format!(
+                                        "Tool result: {}\n",
+                                        truncate(&text.text, MAX_TOOL_RESULT_CHARS)
+                                    ));
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Message::Assistant { content } => {
+                for content in content.iter() {
+                    match content {
+                        AssistantContent::Text(text) => {
+                            transcript.push_str(&format!("Assistant: {}\n", text.text));
+                        }
+                        AssistantContent::ToolCall(tool_call) => {
+                            transcript.push_str(&format!(
+                                "Tool call: {}({})\n",
+                                tool_call.function.name, tool_call.function.arguments
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    transcript
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}… [truncated]", &text[..idx]),
+        None => text.to_string(),
+    }
+}
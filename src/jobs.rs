@@ -0,0 +1,194 @@
+//! Work that takes minutes, like qualifying a large sheet, run in the
+//! background where it can be polled and cancelled.
+//!
+//! Jobs are also saved in the database, so they can still be looked up after
+//! the process that ran them has stopped.
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use tokio::task::AbortHandle;
+
+use crate::{retention, session::unix_now, store};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        })
+    }
+}
+
+/// What a job is doing, as reported by `GET /v1/jobs/{id}` and `/jobs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub description: String,
+    pub status: Status,
+    /// Estimated share of the work done, from 0 to 100.
+    pub progress: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Lets a job report how far along it is.
+#[derive(Clone)]
+pub struct Progress(Arc<Mutex<Job>>);
+
+impl Progress {
+    /// Records that `done` of `total` items are finished. A job only reaches
+    /// 100% once it has succeeded, since totals are estimates.
+    pub fn update(&self, done: u64, total: u64) {
+        let percent = (done.saturating_mul(100) / total.max(1)).min(99) as u8;
+        self.0.lock().unwrap().progress = percent;
+    }
+}
+
+struct Entry {
+    job: Arc<Mutex<Job>>,
+    abort: AbortHandle,
+}
+
+/// The jobs started in this process, in the order they were started.
+#[derive(Default)]
+pub struct Jobs {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl Jobs {
+    /// Runs the future made by `run` on its own task, returning the job's ID.
+    pub fn start<F, Fut>(&self, description: String, run: F) -> String
+    where
+        F: FnOnce(Progress) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+        let job = Arc::new(Mutex::new(Job {
+            id: id.clone(),
+            description,
+            status: Status::Running,
+            progress: 0,
+            error: None,
+        }));
+
+        persist(&job.lock().unwrap());
+        let work = run(Progress(job.clone()));
+        let state = job.clone();
+        let task = tokio::spawn(async move {
+            let res = work.await;
+
+            let mut job = state.lock().unwrap();
+            // Cancelled while the work was finishing, before the abort took.
+            if job.status == Status::Cancelled {
+                return;
+            }
+            match res {
+                Ok(()) => {
+                    job.status = Status::Succeeded;
+                    job.progress = 100;
+                }
+                Err(e) => {
+                    job.status = Status::Failed;
+                    job.error = Some(format!("{e:#}"));
+                }
+            }
+            persist(&job);
+        });
+
+        self.entries.lock().unwrap().push(Entry {
+            job,
+            abort: task.abort_handle(),
+        });
+
+        id
+    }
+
+    /// The job with `id`, whether it was started by this process or an
+    /// earlier one.
+    pub fn get(&self, id: &str) -> Option<Job> {
+        let job = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.job.lock().unwrap())
+            .find(|job| job.id == id)
+            .map(|job| job.clone());
+        if job.is_some() {
+            return job;
+        }
+
+        let mut job = store::open().and_then(|store| store.load_job(id)).ok()??;
+        if job.status == Status::Running {
+            job.status = Status::Failed;
+            job.error = Some("The process running the job stopped".to_string());
+        }
+
+        Some(job)
+    }
+
+    pub fn list(&self) -> Vec<Job> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.job.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Stops the job with `id` if it's still running, returning its state
+    /// afterwards, or `None` if there's no such job.
+    pub fn cancel(&self, id: &str) -> Option<Job> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .iter()
+            .find(|entry| entry.job.lock().unwrap().id == id)?;
+
+        // The task may finish before the abort takes, but it leaves a
+        // cancelled job as it is.
+        let mut job = entry.job.lock().unwrap();
+        if job.status == Status::Running {
+            entry.abort.abort();
+            job.status = Status::Cancelled;
+            persist(&job);
+        }
+
+        Some(job.clone())
+    }
+
+    /// How many jobs haven't finished yet.
+    pub fn running(&self) -> usize {
+        self.list()
+            .iter()
+            .filter(|job| job.status == Status::Running)
+            .count()
+    }
+}
+
+/// Saves `job` in the database, unless [`retention::persists`] says not to,
+/// since its description holds what was asked. Failing to only affects
+/// looking it up later, so it's reported rather than failing the job.
+fn persist(job: &Job) {
+    if !retention::persists() {
+        return;
+    }
+    if let Err(e) = store::open().and_then(|store| store.save_job(job, unix_now())) {
+        eprintln!("Failed to save job {}: {e:#}", job.id);
+    }
+}
@@ -0,0 +1,56 @@
+//! An agent that works on Google Sheets through MCP, as a library.
+//!
+//! [`GsheetsAgent`] is what the `gsheets-agent` binary runs on; [`run`] is
+//! the binary itself.
+
+mod agent;
+mod app;
+mod audit;
+mod auth;
+mod batch;
+mod chat;
+pub mod cli;
+mod commands;
+pub mod config;
+mod crm;
+mod doctor;
+mod error;
+mod history;
+mod injection;
+mod jobs;
+mod logging;
+mod mcp;
+mod metrics;
+mod notify;
+mod output;
+mod privacy;
+mod profile;
+mod provider;
+mod qualify;
+mod render;
+mod repl;
+mod retention;
+mod rubric;
+mod schedule;
+mod serve;
+mod session;
+mod sheets;
+mod store;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod tools;
+mod transcript;
+mod tui;
+mod usage;
+mod web;
+mod wizard;
+mod workspace;
+
+pub use agent::{GsheetsAgent, GsheetsAgentBuilder};
+pub use app::run;
+pub use chat::{Activity, ToolCallRecord, Turn};
+pub use error::Error;
+pub use provider::Provider;
+pub use qualify::Outcome;
+pub use usage::Usage;
@@ -0,0 +1,70 @@
+//! Diagnostics of what the agent does, through `tracing`.
+//!
+//! Every turn, model call and tool call runs in a span. Events go to stderr as
+//! filtered by `RUST_LOG`, warnings only by default, and with `[log] file` also
+//! to a file as JSON lines for later analysis. They can be exported to an
+//! OpenTelemetry collector as well, see [`crate::telemetry`].
+
+use std::{fs::OpenOptions, sync::Mutex};
+
+use anyhow::Context;
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::{
+    config::{LogConfig, TelemetryConfig},
+    telemetry::{self, Telemetry},
+};
+
+/// Installs the global subscriber, returning what has to be kept alive for as
+/// long as traces are exported.
+pub fn init(
+    config: &LogConfig,
+    telemetry_config: &TelemetryConfig,
+) -> anyhow::Result<Option<Telemetry>> {
+    let stderr_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(stderr_filter);
+
+    let file = match &config.file {
+        Some(path) => {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open the log file {}", path.display()))?;
+            let filter = EnvFilter::try_new(&config.filter)
+                .with_context(|| format!("Invalid log filter `{}`", config.filter))?;
+
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(Mutex::new(file))
+                    .with_filter(filter),
+            )
+        }
+        None => None,
+    };
+
+    let (otel, telemetry) = match telemetry::init(telemetry_config)? {
+        Some((layer, telemetry)) => (
+            Some(layer.with_filter(EnvFilter::new("info"))),
+            Some(telemetry),
+        ),
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .with(otel)
+        .try_init()
+        .context("Failed to set up logging")?;
+
+    Ok(telemetry)
+}
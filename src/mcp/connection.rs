@@ -0,0 +1,370 @@
+use std::{collections::BTreeMap, future::Future, time::Duration};
+
+use anyhow::Context;
+use mcp_core::{
+    client::ClientBuilder,
+    protocol::RequestOptions,
+    transport::Transport,
+    types::{
+        CallToolResponse, ClientCapabilities, Implementation, Prompt, PromptsListResponse,
+        Resource, Tool,
+    },
+};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use super::{McpClient, McpTransport, transport};
+use crate::{
+    auth::GoogleAuth,
+    config::{ServerConfig, TransportKind},
+};
+
+/// How long a server gets to answer the ping that checks whether it's still reachable.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to one MCP server, re-established whenever its transport fails.
+pub struct Connection {
+    name: String,
+    config: ServerConfig,
+    auth: Option<GoogleAuth>,
+    live: RwLock<Live>,
+}
+
+struct Live {
+    client: McpClient,
+    transport: McpTransport,
+    tools: Vec<Tool>,
+    /// Incremented on every reconnect, so concurrent failures only reconnect once.
+    generation: u64,
+}
+
+impl Connection {
+    /// Connects to the server and lists its tools.
+    pub async fn open(
+        name: &str,
+        config: &ServerConfig,
+        auth: Option<GoogleAuth>,
+    ) -> anyhow::Result<Self> {
+        info!("Loading {name} MCP server...");
+
+        let (client, transport, tools) = establish(config, auth.as_ref()).await?;
+
+        info!("Successfully opened.");
+
+        Ok(Self {
+            name: name.to_string(),
+            config: config.clone(),
+            auth,
+            live: RwLock::new(Live {
+                client,
+                transport,
+                tools,
+                generation: 0,
+            }),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The tools the server offered when it was last connected to.
+    pub async fn tools(&self) -> Vec<Tool> {
+        self.live.read().await.tools.clone()
+    }
+
+    /// Lists the server's tools again, for when it may have changed them.
+    pub async fn refresh_tools(&self) -> anyhow::Result<()> {
+        let tools = self
+            .with_client(|client| async move { Ok(client.list_tools(None, None).await?.tools) })
+            .await
+            .context("Failed to list tools")?;
+        self.live.write().await.tools = tools;
+
+        Ok(())
+    }
+
+    /// Calls `tool`, reconnecting and retrying once if the server became unreachable.
+    ///
+    /// Errors the server reports for the call itself are returned as is.
+    pub async fn call_tool(
+        &self,
+        tool: &str,
+        args: serde_json::Value,
+    ) -> anyhow::Result<CallToolResponse> {
+        self.with_client(|client| {
+            let args = args.clone();
+            async move { client.call_tool(tool, Some(args)).await }
+        })
+        .await
+    }
+
+    /// The resources the server publishes, like spreadsheet metadata.
+    pub async fn list_resources(&self) -> anyhow::Result<Vec<Resource>> {
+        self.with_client(
+            |client| async move { Ok(client.list_resources(None, None).await?.resources) },
+        )
+        .await
+    }
+
+    /// The contents of the resource at `uri`, as text.
+    ///
+    /// Binary contents are left out, since they can't go in a prompt.
+    pub async fn read_resource(&self, uri: &str) -> anyhow::Result<String> {
+        let params = serde_json::json!({ "uri": uri });
+        let response = self
+            .with_client(|client| {
+                let params = params.clone();
+                async move {
+                    client
+                        .request("resources/read", Some(params), RequestOptions::default())
+                        .await
+                }
+            })
+            .await?;
+
+        let contents = response["contents"]
+            .as_array()
+            .context("The server answered without `contents`")?;
+        let text: Vec<&str> = contents
+            .iter()
+            .filter_map(|content| content["text"].as_str())
+            .collect();
+        anyhow::ensure!(
+            !text.is_empty() || contents.is_empty(),
+            "`{uri}` only has binary contents"
+        );
+
+        Ok(text.join("\n"))
+    }
+
+    /// The prompt templates the server publishes.
+    pub async fn list_prompts(&self) -> anyhow::Result<Vec<Prompt>> {
+        let response = self
+            .with_client(|client| async move {
+                client
+                    .request(
+                        "prompts/list",
+                        Some(serde_json::json!({})),
+                        RequestOptions::default(),
+                    )
+                    .await
+            })
+            .await?;
+        let response: PromptsListResponse =
+            serde_json::from_value(response).context("Failed to parse the prompts")?;
+
+        Ok(response.prompts)
+    }
+
+    /// The messages of the prompt `name`, filled in with `arguments`, as
+    /// `(role, text)`.
+    pub async fn get_prompt(
+        &self,
+        name: &str,
+        arguments: &BTreeMap<String, String>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let params = serde_json::json!({ "name": name, "arguments": arguments });
+        let response = self
+            .with_client(|client| {
+                let params = params.clone();
+                async move {
+                    client
+                        .request("prompts/get", Some(params), RequestOptions::default())
+                        .await
+                }
+            })
+            .await?;
+
+        let messages = response["messages"]
+            .as_array()
+            .context("The server answered without `messages`")?;
+        let messages = messages
+            .iter()
+            .map(|message| {
+                let role = message["role"].as_str().unwrap_or("user").to_string();
+                let content = &message["content"];
+                // Embedded resources carry their text one level down.
+                let text = content["text"]
+                    .as_str()
+                    .or_else(|| content["resource"]["text"].as_str())
+                    .unwrap_or_default()
+                    .to_string();
+
+                (role, text)
+            })
+            .filter(|(_, text)| !text.is_empty())
+            .collect();
+
+        Ok(messages)
+    }
+
+    /// Runs `request` with the client, reconnecting and running it once more
+    /// if the server became unreachable.
+    async fn with_client<T, F, Fut>(&self, request: F) -> anyhow::Result<T>
+    where
+        F: Fn(McpClient) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let (client, transport, generation) = {
+            let live = self.live.read().await;
+            (live.client.clone(), live.transport.clone(), live.generation)
+        };
+
+        let err = match request(client).await {
+            Ok(response) => return Ok(response),
+            Err(err) => err,
+        };
+
+        if is_reachable(&transport).await {
+            return Err(err);
+        }
+
+        warn!(
+            "Lost the connection to the `{}` MCP server: {err:#}",
+            self.name
+        );
+        self.reconnect(generation).await?;
+
+        let client = self.live.read().await.client.clone();
+        request(client).await
+    }
+
+    /// Replaces the connection with a new one, backing off between failed attempts.
+    ///
+    /// Does nothing if another call already reconnected since `generation`.
+    async fn reconnect(&self, generation: u64) -> anyhow::Result<()> {
+        let mut live = self.live.write().await;
+        if live.generation != generation {
+            return Ok(());
+        }
+
+        // The transport's futures aren't `Sync`, which tool call futures have to be,
+        // so everything that awaits them directly runs on its own task.
+        let transport = live.transport.clone();
+        tokio::spawn(async move {
+            let _ = transport.close().await;
+        });
+
+        let backoff = &self.config.reconnect;
+        let mut attempt = 0;
+
+        loop {
+            if attempt > 0 {
+                tokio::time::sleep(backoff.delay(attempt - 1)).await;
+            }
+            attempt += 1;
+
+            warn!(
+                "Reconnecting to the `{}` MCP server (attempt {attempt}/{})...",
+                self.name, backoff.max_attempts
+            );
+
+            let config = self.config.clone();
+            let auth = self.auth.clone();
+            let established = tokio::spawn(async move { establish(&config, auth.as_ref()).await })
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|res| res);
+
+            match established {
+                Ok((client, transport, tools)) => {
+                    if tool_names(&tools) != tool_names(&live.tools) {
+                        info!(
+                            "The tools of the `{}` MCP server changed while reconnecting.",
+                            self.name
+                        );
+                    }
+
+                    *live = Live {
+                        client,
+                        transport,
+                        tools,
+                        generation: generation + 1,
+                    };
+                    info!("Reconnected.");
+
+                    return Ok(());
+                }
+                Err(err) if attempt >= backoff.max_attempts => {
+                    return Err(err.context(format!(
+                        "Failed to reconnect to the `{}` MCP server after {attempt} attempts",
+                        self.name
+                    )));
+                }
+                Err(err) => warn!("Reconnecting failed: {err:#}"),
+            }
+        }
+    }
+}
+
+/// Opens the configured transport, performs the MCP initialize handshake and
+/// lists the server's tools.
+///
+/// A fresh access token is requested every time, so reconnecting also recovers
+/// from expired ones.
+async fn establish(
+    config: &ServerConfig,
+    auth: Option<&GoogleAuth>,
+) -> anyhow::Result<(McpClient, McpTransport, Vec<Tool>)> {
+    let bearer_token = match (config.google_auth, auth, &config.bearer_token) {
+        (true, Some(auth), _) => Some(auth.token().await?),
+        (true, None, _) => anyhow::bail!("`google_auth` needs `credentials` under [auth]"),
+        (false, _, Some(token)) => Some(token.resolve().context("Failed to get `bearer_token`")?),
+        (false, _, None) => None,
+    };
+    let headers = config
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .resolve()
+                .with_context(|| format!("Failed to get the `{name}` header"))?;
+            Ok((name.clone(), value))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let transport = McpTransport::from_config(config, bearer_token.clone(), &headers)?;
+
+    let client = ClientBuilder::new(transport.clone()).build();
+
+    if let Err(err) = client.open().await {
+        if config.transport == TransportKind::Sse {
+            transport::check_authorized(&config.sse_url, bearer_token.as_deref(), &headers).await?;
+        }
+        return Err(err);
+    }
+
+    client
+        .initialize(
+            Implementation {
+                name: "echo".to_string(),
+                version: "1.0".to_string(),
+            },
+            ClientCapabilities::default(),
+        )
+        .await?;
+
+    let tools = client
+        .list_tools(None, None)
+        .await
+        .context("Failed to list tools")?
+        .tools;
+
+    Ok((client, transport, tools))
+}
+
+/// Whether the server still answers over `transport`, even if only to reject the ping.
+async fn is_reachable(transport: &McpTransport) -> bool {
+    transport
+        .request(
+            "ping",
+            None,
+            RequestOptions::default().timeout(PING_TIMEOUT),
+        )
+        .await
+        .is_ok()
+}
+
+fn tool_names(tools: &[Tool]) -> Vec<&str> {
+    tools.iter().map(|tool| tool.name.as_str()).collect()
+}
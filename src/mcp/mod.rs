@@ -0,0 +1,13 @@
+mod connection;
+mod servers;
+mod tools;
+mod transport;
+mod websocket;
+
+pub use servers::{McpServers, NAMESPACE_SEPARATOR, validate_server_name};
+pub use tools::McpTools;
+pub use transport::McpTransport;
+
+use mcp_core::client::Client;
+
+pub type McpClient = Client<McpTransport>;
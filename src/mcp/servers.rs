@@ -0,0 +1,281 @@
+use std::{collections::BTreeMap, future::Future, pin::Pin, sync::Arc};
+
+use anyhow::Context;
+use mcp_core::types::{Prompt, Resource, ResourceContents, ToolResponseContent};
+use rig::{
+    completion::ToolDefinition,
+    tool::{ToolDyn, ToolError, ToolSet},
+};
+
+use super::connection::Connection;
+use crate::{
+    auth::GoogleAuth,
+    config::{ServerConfig, ToolsConfig},
+    error::Error,
+    tools,
+};
+
+/// Separator between the server name and the tool name in advertised tool names.
+///
+/// OpenAI and Anthropic only accept `[a-zA-Z0-9_-]` in tool names, so a `.`
+/// can't be used here.
+pub const NAMESPACE_SEPARATOR: &str = "__";
+
+/// The set of MCP servers the agent is connected to.
+#[derive(Clone)]
+pub struct McpServers {
+    servers: Vec<Arc<Connection>>,
+}
+
+impl McpServers {
+    /// Connects to every configured server, in name order.
+    pub async fn connect(
+        configs: &BTreeMap<String, ServerConfig>,
+        auth: Option<&GoogleAuth>,
+    ) -> Result<Self, Error> {
+        let mut servers = Vec::with_capacity(configs.len());
+
+        for (name, config) in configs {
+            let connection = Connection::open(name, config, auth.cloned())
+                .await
+                .with_context(|| format!("Failed to connect to the `{name}` MCP server"))
+                .map_err(Error::Transport)?;
+            servers.push(Arc::new(connection));
+        }
+
+        Ok(Self { servers })
+    }
+
+    /// Merges the tools of every server that `config` allows into a single toolset.
+    ///
+    /// Tool names are prefixed with their server's name, e.g. `gsheets__read_range`,
+    /// so servers exposing tools with the same name don't collide.
+    pub async fn tools(&self, config: &ToolsConfig) -> (ToolSet, Vec<ToolDefinition>) {
+        let mut toolset = ToolSet::builder().build();
+        let mut tooldefs = Vec::new();
+
+        for connection in &self.servers {
+            for tool in connection.tools().await {
+                let tool = NamespacedTool::new(tool, connection.clone());
+                if !tools::is_allowed(config, &tool.definition.name) {
+                    continue;
+                }
+
+                tooldefs.push(tool.definition.clone());
+                toolset.add_tool(tool);
+            }
+        }
+
+        (toolset, tooldefs)
+    }
+
+    /// Lists the tools of every server again. Servers that fail to list
+    /// them keep the ones they had.
+    pub async fn refresh_tools(&self) {
+        for connection in &self.servers {
+            if let Err(e) = connection.refresh_tools().await {
+                tracing::warn!(
+                    "Failed to refresh the tools of the `{}` MCP server: {e:#}",
+                    connection.name()
+                );
+            }
+        }
+    }
+
+    /// The resources every server publishes, with the name of the server.
+    ///
+    /// Servers that fail to list theirs, usually because they have none, are
+    /// left out.
+    pub async fn resources(&self) -> Vec<(String, Resource)> {
+        let mut resources = Vec::new();
+
+        for connection in &self.servers {
+            match connection.list_resources().await {
+                Ok(listed) => resources.extend(
+                    listed
+                        .into_iter()
+                        .map(|resource| (connection.name().to_string(), resource)),
+                ),
+                Err(e) => tracing::debug!(
+                    "The `{}` MCP server didn't list resources: {e:#}",
+                    connection.name()
+                ),
+            }
+        }
+
+        resources
+    }
+
+    /// The prompt templates every server publishes, as `<server>__<prompt>`.
+    pub async fn prompts(&self) -> Vec<(String, Prompt)> {
+        let mut prompts = Vec::new();
+
+        for connection in &self.servers {
+            match connection.list_prompts().await {
+                Ok(listed) => prompts.extend(listed.into_iter().map(|prompt| {
+                    let name = format!("{}{NAMESPACE_SEPARATOR}{}", connection.name(), prompt.name);
+                    (name, prompt)
+                })),
+                Err(e) => tracing::debug!(
+                    "The `{}` MCP server didn't list prompts: {e:#}",
+                    connection.name()
+                ),
+            }
+        }
+
+        prompts
+    }
+
+    /// The prompt called `name`, either namespaced or as its server calls it
+    /// if only one server has a prompt of that name.
+    pub async fn prompt(&self, name: &str) -> anyhow::Result<(String, Prompt)> {
+        let prompts = self.prompts().await;
+        if let Some(prompt) = prompts.iter().find(|(namespaced, _)| namespaced == name) {
+            return Ok(prompt.clone());
+        }
+
+        let mut matching = prompts
+            .into_iter()
+            .filter(|(_, prompt)| prompt.name == name);
+        match (matching.next(), matching.next()) {
+            (Some(prompt), None) => Ok(prompt),
+            (Some(_), Some(_)) => anyhow::bail!(
+                "Several MCP servers have a prompt called `{name}`; use `<server>{NAMESPACE_SEPARATOR}{name}`"
+            ),
+            (None, _) => anyhow::bail!("There's no prompt called `{name}`, see /prompts"),
+        }
+    }
+
+    /// The messages of the prompt `name`, namespaced, filled in with
+    /// `arguments`, as `(role, text)`.
+    pub async fn get_prompt(
+        &self,
+        name: &str,
+        arguments: &BTreeMap<String, String>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let (server, prompt) = name
+            .split_once(NAMESPACE_SEPARATOR)
+            .with_context(|| format!("`{name}` isn't namespaced with its server"))?;
+        let connection = self
+            .servers
+            .iter()
+            .find(|connection| connection.name() == server)
+            .with_context(|| format!("There's no MCP server called `{server}`"))?;
+
+        connection
+            .get_prompt(prompt, arguments)
+            .await
+            .with_context(|| format!("Failed to get the prompt `{name}`"))
+    }
+
+    /// The text of the resource at `uri`, from the server that lists it or
+    /// else from the first one that can read it.
+    pub async fn read_resource(&self, uri: &str) -> anyhow::Result<String> {
+        for (server, resource) in self.resources().await {
+            if resource.uri.as_str() == uri
+                && let Some(connection) = self.servers.iter().find(|c| c.name() == server)
+            {
+                return connection.read_resource(uri).await;
+            }
+        }
+
+        let mut last_err = None;
+        for connection in &self.servers {
+            match connection.read_resource(uri).await {
+                Ok(text) => return Ok(text),
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("No MCP servers are configured")))
+            .with_context(|| format!("Failed to read `{uri}`"))
+    }
+}
+/// Checks that `name` can be used to namespace the server's tools.
+pub fn validate_server_name(name: &str) -> anyhow::Result<()> {
+    let valid = !name.is_empty()
+        && !name.contains(NAMESPACE_SEPARATOR)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    anyhow::ensure!(
+        valid,
+        "Invalid MCP server name `{name}`: use letters, digits, `-` and single `_` only"
+    );
+
+    Ok(())
+}
+
+/// An MCP tool advertised to the model under `<server>__<tool>`.
+struct NamespacedTool {
+    definition: ToolDefinition,
+    /// Name of the tool on its server.
+    tool: String,
+    connection: Arc<Connection>,
+}
+
+impl NamespacedTool {
+    fn new(tool: mcp_core::types::Tool, connection: Arc<Connection>) -> Self {
+        let definition = ToolDefinition {
+            name: format!("{}{NAMESPACE_SEPARATOR}{}", connection.name(), tool.name),
+            description: tool.description.unwrap_or_default(),
+            parameters: tool.input_schema,
+        };
+
+        Self {
+            definition,
+            tool: tool.name,
+            connection,
+        }
+    }
+
+    async fn call(&self, args: &str) -> anyhow::Result<String> {
+        let args = serde_json::from_str(args).unwrap_or_default();
+        let response = self.connection.call_tool(&self.tool, args).await?;
+
+        let content = response.content.into_iter().map(|content| match content {
+            ToolResponseContent::Text { text } => text,
+            ToolResponseContent::Image { data, mime_type } => {
+                format!("data:{mime_type};base64,{data}")
+            }
+            ToolResponseContent::Resource {
+                resource: ResourceContents { uri, mime_type },
+            } => match mime_type {
+                Some(mime_type) => format!("data:{mime_type};{uri}"),
+                None => uri.to_string(),
+            },
+        });
+        let content = content.collect::<Vec<_>>().join("");
+
+        if response.is_error.unwrap_or(false) {
+            anyhow::bail!("Tool returned an error: {content}");
+        }
+
+        Ok(content)
+    }
+}
+
+impl ToolDyn for NamespacedTool {
+    fn name(&self) -> String {
+        self.definition.name.clone()
+    }
+
+    fn definition(
+        &self,
+        _prompt: String,
+    ) -> Pin<Box<dyn Future<Output = ToolDefinition> + Send + Sync + '_>> {
+        Box::pin(async move { self.definition.clone() })
+    }
+
+    fn call(
+        &self,
+        args: String,
+    ) -> Pin<Box<dyn Future<Output = Result<String, ToolError>> + Send + Sync + '_>> {
+        Box::pin(async move {
+            self.call(&args)
+                .await
+                .map_err(|e| ToolError::ToolCallError(e.into()))
+        })
+    }
+}
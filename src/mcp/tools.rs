@@ -0,0 +1,154 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use rig::{
+    completion::ToolDefinition,
+    tool::{ToolSet, ToolSetError},
+};
+
+use super::McpServers;
+use crate::config::ToolsConfig;
+
+/// The tools of the MCP servers that the config allows, listed again from
+/// time to time, so tools a server adds, removes or changes are used without
+/// restarting the agent.
+pub struct McpTools {
+    servers: McpServers,
+    config: ToolsConfig,
+    /// `None` if the tools listed at startup are kept.
+    refresh: Option<Duration>,
+    checked: Mutex<Instant>,
+    listed: RwLock<Arc<Listed>>,
+}
+
+struct Listed {
+    toolset: ToolSet,
+    definitions: Vec<ToolDefinition>,
+}
+
+impl McpTools {
+    pub async fn new(servers: McpServers, config: &ToolsConfig) -> Self {
+        let (toolset, definitions) = servers.tools(config).await;
+
+        Self {
+            servers,
+            config: config.clone(),
+            refresh: (config.refresh_secs > 0).then(|| Duration::from_secs(config.refresh_secs)),
+            checked: Mutex::new(Instant::now()),
+            listed: RwLock::new(Arc::new(Listed {
+                toolset,
+                definitions,
+            })),
+        }
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.listed().definitions.clone()
+    }
+
+    pub fn definition(&self, tool: &str) -> Option<ToolDefinition> {
+        self.listed()
+            .definitions
+            .iter()
+            .find(|definition| definition.name == tool)
+            .cloned()
+    }
+
+    pub fn contains(&self, tool: &str) -> bool {
+        self.listed().toolset.contains(tool)
+    }
+
+    pub async fn call(&self, tool: &str, args: String) -> Result<String, ToolSetError> {
+        let listed = self.listed();
+
+        listed.toolset.call(tool, args).await
+    }
+
+    /// Lists the tools of every server again if it's been `refresh_secs`
+    /// since they were last listed, and uses them from now on if they
+    /// changed.
+    pub async fn refresh(&self) {
+        let Some(every) = self.refresh else {
+            return;
+        };
+        {
+            let mut checked = self
+                .checked
+                .lock()
+                .expect("the refresh lock isn't poisoned");
+            if checked.elapsed() < every {
+                return;
+            }
+            *checked = Instant::now();
+        }
+
+        self.servers.refresh_tools().await;
+        let (toolset, definitions) = self.servers.tools(&self.config).await;
+
+        let changes = changes(&self.listed().definitions, &definitions);
+        if changes.is_empty() {
+            return;
+        }
+        eprintln!(
+            "The tools of the MCP servers changed: {}",
+            changes.join("; ")
+        );
+
+        *self.listed.write().expect("the tools lock isn't poisoned") = Arc::new(Listed {
+            toolset,
+            definitions,
+        });
+    }
+
+    fn listed(&self) -> Arc<Listed> {
+        self.listed
+            .read()
+            .expect("the tools lock isn't poisoned")
+            .clone()
+    }
+}
+
+/// What changed from the tools `before` to the ones `after`, e.g.
+/// `added gsheets__sort_range`, or nothing if they're the same.
+fn changes(before: &[ToolDefinition], after: &[ToolDefinition]) -> Vec<String> {
+    let by_name = |tools: &[ToolDefinition]| -> BTreeMap<String, serde_json::Value> {
+        tools
+            .iter()
+            .map(|tool| {
+                let definition = serde_json::to_value(tool).unwrap_or_default();
+                (tool.name.clone(), definition)
+            })
+            .collect()
+    };
+    let (before, after) = (by_name(before), by_name(after));
+
+    let names = |filter: &dyn Fn(&str, &serde_json::Value) -> bool| {
+        after
+            .iter()
+            .filter(|(name, definition)| filter(name, definition))
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let added = names(&|name, _| !before.contains_key(name));
+    let changed = names(&|name, definition| {
+        before
+            .get(name)
+            .is_some_and(|previous| previous != definition)
+    });
+    let removed = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    [("added", added), ("changed", changed), ("removed", removed)]
+        .into_iter()
+        .filter(|(_, names)| !names.is_empty())
+        .map(|(change, names)| format!("{change} {names}"))
+        .collect()
+}
@@ -0,0 +1,169 @@
+use std::{future::Future, pin::Pin, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use mcp_core::{
+    protocol::RequestOptions,
+    transport::{
+        ClientSseTransport, ClientSseTransportBuilder, ClientStdioTransport, JsonRpcError,
+        JsonRpcResponse, Message, RequestId, Transport,
+    },
+};
+
+use super::websocket::ClientWsTransport;
+use crate::config::{ServerConfig, TransportKind};
+
+/// Transport used to reach an MCP server, selected at runtime from the config.
+#[derive(Clone)]
+pub enum McpTransport {
+    Sse(ClientSseTransport),
+    Stdio(ClientStdioTransport),
+    Ws(ClientWsTransport),
+}
+
+impl McpTransport {
+    /// Creates the configured transport, authenticating SSE and WebSocket
+    /// requests with `bearer_token` if given, and opening the event stream or
+    /// socket with `headers`.
+    pub fn from_config(
+        config: &ServerConfig,
+        bearer_token: Option<String>,
+        headers: &[(String, String)],
+    ) -> anyhow::Result<Self> {
+        match config.transport {
+            TransportKind::Sse => {
+                let mut builder = ClientSseTransportBuilder::new(config.sse_url.clone());
+                if let Some(token) = bearer_token {
+                    builder = builder.with_bearer_token(token);
+                }
+                for (name, value) in headers {
+                    builder = builder.with_header(name, value);
+                }
+
+                Ok(Self::Sse(builder.build()))
+            }
+            TransportKind::Websocket => {
+                let url = config
+                    .ws_url
+                    .as_deref()
+                    .context("The websocket transport requires `ws_url` to be set")?;
+
+                Ok(Self::Ws(ClientWsTransport::new(url, bearer_token, headers)))
+            }
+            TransportKind::Stdio => {
+                anyhow::ensure!(
+                    bearer_token.is_none() && headers.is_empty(),
+                    "`google_auth`, `bearer_token` and `headers` don't work with the stdio transport"
+                );
+
+                let command = config
+                    .command
+                    .as_deref()
+                    .context("The stdio transport requires `command` to be set")?;
+                let args: Vec<&str> = config.args.iter().map(String::as_str).collect();
+
+                Ok(Self::Stdio(ClientStdioTransport::new(command, &args)?))
+            }
+        }
+    }
+}
+
+/// Fails with an explanation if the SSE server at `url` turns down the
+/// credentials, which the SSE client only reports as a timeout.
+pub async fn check_authorized(
+    url: &str,
+    bearer_token: Option<&str>,
+    headers: &[(String, String)],
+) -> anyhow::Result<()> {
+    let mut request = reqwest::Client::new()
+        .get(url)
+        .timeout(Duration::from_secs(5));
+    if let Some(token) = bearer_token {
+        request = request.bearer_auth(token);
+    }
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+
+    // Anything but a refusal is left to the error the client already has.
+    match request.send().await {
+        Ok(response) => anyhow::ensure!(
+            !is_unauthorized(response.status().as_u16()),
+            "{url} answered {}: check `bearer_token`, `headers` and `google_auth` for this \
+             server",
+            response.status()
+        ),
+        Err(e) => tracing::debug!("Couldn't check the credentials for {url}: {e}"),
+    }
+
+    Ok(())
+}
+
+fn is_unauthorized(status: u16) -> bool {
+    status == 401 || status == 403
+}
+
+#[async_trait]
+impl Transport for McpTransport {
+    async fn open(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Sse(transport) => transport.open().await,
+            Self::Stdio(transport) => transport.open().await,
+            Self::Ws(transport) => transport.open().await,
+        }
+    }
+
+    async fn close(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Sse(transport) => transport.close().await,
+            Self::Stdio(transport) => transport.close().await,
+            Self::Ws(transport) => transport.close().await,
+        }
+    }
+
+    async fn poll_message(&self) -> anyhow::Result<Option<Message>> {
+        match self {
+            Self::Sse(transport) => transport.poll_message().await,
+            Self::Stdio(transport) => transport.poll_message().await,
+            Self::Ws(transport) => transport.poll_message().await,
+        }
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+        options: RequestOptions,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<JsonRpcResponse>> + Send + Sync>> {
+        match self {
+            Self::Sse(transport) => transport.request(method, params, options),
+            Self::Stdio(transport) => transport.request(method, params, options),
+            Self::Ws(transport) => transport.request(method, params, options),
+        }
+    }
+
+    async fn send_notification(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Sse(transport) => transport.send_notification(method, params).await,
+            Self::Stdio(transport) => transport.send_notification(method, params).await,
+            Self::Ws(transport) => transport.send_notification(method, params).await,
+        }
+    }
+
+    async fn send_response(
+        &self,
+        id: RequestId,
+        result: Option<serde_json::Value>,
+        error: Option<JsonRpcError>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Sse(transport) => transport.send_response(id, result, error).await,
+            Self::Stdio(transport) => transport.send_response(id, result, error).await,
+            Self::Ws(transport) => transport.send_response(id, result, error).await,
+        }
+    }
+}
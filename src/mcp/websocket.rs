@@ -0,0 +1,246 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt, stream::SplitStream};
+use mcp_core::{
+    protocol::{Protocol, ProtocolBuilder, RequestOptions},
+    transport::{
+        JsonRpcError, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, Message, RequestId,
+        Transport,
+    },
+    types::ErrorCode,
+};
+use tokio::{
+    net::TcpStream,
+    sync::{Mutex, mpsc},
+};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream,
+    tungstenite::{self, client::IntoClientRequest, http::HeaderValue},
+};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Talks to an MCP server over a WebSocket, one JSON-RPC message per text
+/// frame, for gateways that don't offer SSE.
+#[derive(Clone)]
+pub struct ClientWsTransport {
+    url: String,
+    bearer_token: Option<String>,
+    headers: Vec<(String, String)>,
+    protocol: Protocol,
+    /// Frames to send, written to the socket by a task of their own; `None`
+    /// while the socket is closed.
+    outgoing: Arc<StdMutex<Option<mpsc::UnboundedSender<String>>>>,
+    incoming: Arc<Mutex<Option<SplitStream<Socket>>>>,
+}
+
+impl ClientWsTransport {
+    pub fn new(url: &str, bearer_token: Option<String>, headers: &[(String, String)]) -> Self {
+        Self {
+            url: url.to_string(),
+            bearer_token,
+            headers: headers.to_vec(),
+            protocol: ProtocolBuilder::new().build(),
+            outgoing: Arc::default(),
+            incoming: Arc::default(),
+        }
+    }
+
+    fn send(&self, message: &impl serde::Serialize) -> anyhow::Result<()> {
+        let text = serde_json::to_string(message)?;
+        let outgoing = self
+            .outgoing
+            .lock()
+            .expect("the socket lock isn't poisoned");
+
+        outgoing
+            .as_ref()
+            .context("The WebSocket isn't open")?
+            .send(text)
+            .map_err(|_| anyhow::anyhow!("The WebSocket is closed"))
+    }
+
+    fn handshake(&self) -> anyhow::Result<tungstenite::handshake::client::Request> {
+        let mut request = self.url.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        // The subprotocol MCP servers speak over WebSockets.
+        headers.insert("Sec-WebSocket-Protocol", HeaderValue::from_static("mcp"));
+        if let Some(token) = &self.bearer_token {
+            headers.insert("Authorization", format!("Bearer {token}").parse()?);
+        }
+        for (name, value) in &self.headers {
+            let name: tungstenite::http::HeaderName = name
+                .parse()
+                .with_context(|| format!("Invalid header name `{name}`"))?;
+            headers.insert(name, value.parse()?);
+        }
+
+        Ok(request)
+    }
+}
+
+#[async_trait]
+impl Transport for ClientWsTransport {
+    async fn open(&self) -> anyhow::Result<()> {
+        let (socket, _) = match tokio_tungstenite::connect_async(self.handshake()?).await {
+            Err(tungstenite::Error::Http(response)) => anyhow::bail!(
+                "{} answered {}{}",
+                self.url,
+                response.status(),
+                match response.status().as_u16() {
+                    401 | 403 =>
+                        ": check `bearer_token`, `headers` and `google_auth` for this server",
+                    _ => "",
+                }
+            ),
+            res => res.with_context(|| format!("Failed to open a WebSocket to {}", self.url))?,
+        };
+        let (mut sink, stream) = socket.split();
+        *self.incoming.lock().await = Some(stream);
+
+        let (sender, mut frames) = mpsc::unbounded_channel::<String>();
+        *self
+            .outgoing
+            .lock()
+            .expect("the socket lock isn't poisoned") = Some(sender);
+        tokio::spawn(async move {
+            while let Some(text) = frames.recv().await {
+                if let Err(e) = sink.send(tungstenite::Message::text(text)).await {
+                    tracing::debug!("Failed to write to the WebSocket: {e}");
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+        });
+
+        let transport = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match transport.poll_message().await {
+                    Ok(Some(Message::Request(request))) => {
+                        let response = transport.protocol.handle_request(request).await;
+                        let _ = transport
+                            .send_response(response.id, response.result, response.error)
+                            .await;
+                    }
+                    Ok(Some(Message::Notification(notification))) => {
+                        transport.protocol.handle_notification(notification).await;
+                    }
+                    Ok(Some(Message::Response(response))) => {
+                        transport.protocol.handle_response(response).await;
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::debug!("Failed to read from the WebSocket: {e:#}");
+                        break;
+                    }
+                }
+            }
+            // So requests fail right away rather than time out.
+            *transport
+                .outgoing
+                .lock()
+                .expect("the socket lock isn't poisoned") = None;
+        });
+
+        Ok(())
+    }
+
+    async fn close(&self) -> anyhow::Result<()> {
+        *self
+            .outgoing
+            .lock()
+            .expect("the socket lock isn't poisoned") = None;
+        *self.incoming.lock().await = None;
+
+        Ok(())
+    }
+
+    /// The next message, skipping control frames, or `None` once the socket
+    /// is closed.
+    async fn poll_message(&self) -> anyhow::Result<Option<Message>> {
+        let mut incoming = self.incoming.lock().await;
+        let stream = incoming.as_mut().context("The WebSocket isn't open")?;
+
+        while let Some(frame) = stream.next().await {
+            let text = match frame? {
+                tungstenite::Message::Text(text) => text.to_string(),
+                tungstenite::Message::Binary(bytes) => String::from_utf8(bytes.to_vec())?,
+                tungstenite::Message::Close(_) => return Ok(None),
+                _ => continue,
+            };
+
+            return Ok(Some(serde_json::from_str(&text)?));
+        }
+
+        Ok(None)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+        options: RequestOptions,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<JsonRpcResponse>> + Send + Sync>> {
+        let transport = self.clone();
+        let method = method.to_owned();
+
+        Box::pin(async move {
+            let (id, response) = transport.protocol.create_request().await;
+            transport.send(&JsonRpcRequest {
+                id,
+                method,
+                jsonrpc: Default::default(),
+                params,
+            })?;
+
+            match tokio::time::timeout(options.timeout, response).await {
+                Ok(Ok(response)) => Ok(response),
+                Ok(Err(_)) | Err(_) => {
+                    transport.protocol.cancel_response(id).await;
+                    Ok(JsonRpcResponse {
+                        id,
+                        error: Some(JsonRpcError {
+                            code: ErrorCode::RequestTimeout as i32,
+                            message: "Request timed out".to_string(),
+                            data: None,
+                        }),
+                        ..Default::default()
+                    })
+                }
+            }
+        })
+    }
+
+    async fn send_notification(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        self.send(&JsonRpcNotification {
+            jsonrpc: Default::default(),
+            method: method.to_owned(),
+            params,
+        })
+    }
+
+    async fn send_response(
+        &self,
+        id: RequestId,
+        result: Option<serde_json::Value>,
+        error: Option<JsonRpcError>,
+    ) -> anyhow::Result<()> {
+        self.send(&JsonRpcResponse {
+            id,
+            result,
+            error,
+            jsonrpc: Default::default(),
+        })
+    }
+}
@@ -0,0 +1,90 @@
+//! What a run did, counted as it goes: model calls and the tokens they used,
+//! tool calls by name and how many of them failed, retries, and how long it
+//! took. Printed once the run is over, and kept in the database next to its
+//! usage and in the audit log.
+
+use std::{collections::BTreeMap, ops::AddAssign, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chat::Turn, usage::Usage};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Completions asked for, not counting retries.
+    pub model_calls: usize,
+    pub usage: Usage,
+    /// Calls made to each tool, by namespaced name.
+    pub tool_calls: BTreeMap<String, usize>,
+    /// Tool calls that failed or were refused.
+    pub tool_errors: usize,
+    /// Completions sent again after failing or stalling, and answers asked for
+    /// again because they were malformed.
+    pub retries: usize,
+    /// Wall-clock time of the run.
+    pub duration_ms: u64,
+}
+
+impl Metrics {
+    /// Counts what the agent did in `turn`, except for the time it took, which
+    /// overlaps with that of other turns when they run at once.
+    pub fn add(&mut self, turn: &Turn) {
+        self.model_calls += turn.model_calls;
+        self.usage += turn.usage;
+        for call in &turn.tool_calls {
+            *self.tool_calls.entry(call.name.clone()).or_default() += 1;
+            self.tool_errors += usize::from(call.error.is_some());
+        }
+        self.retries += turn.retries;
+    }
+
+    /// The metrics as the block printed at the end of a run.
+    pub fn summary(&self) -> String {
+        let plural = |n: usize, one: &str, many: &str| match n {
+            1 => format!("1 {one}"),
+            n => format!("{n} {many}"),
+        };
+        let mut summary = format!(
+            "Metrics for this run:\n  {}, {}, {} tokens",
+            plural(self.model_calls, "model call", "model calls"),
+            plural(self.retries, "retry", "retries"),
+            self.usage.prompt_tokens + self.usage.completion_tokens
+        );
+
+        let calls: usize = self.tool_calls.values().sum();
+        summary.push_str(&format!(
+            "\n  {}, {} failed",
+            plural(calls, "tool call", "tool calls"),
+            self.tool_errors
+        ));
+        if calls > 0 {
+            let by_name: Vec<String> = self
+                .tool_calls
+                .iter()
+                .map(|(tool, calls)| format!("{tool} {calls}"))
+                .collect();
+            summary.push_str(&format!(": {}", by_name.join(", ")));
+        }
+
+        let took = Duration::from_millis(self.duration_ms).as_secs();
+        summary.push_str(&match took {
+            ..60 => format!("\n  took {took}s"),
+            _ => format!("\n  took {}m {}s", took / 60, took % 60),
+        });
+
+        summary
+    }
+}
+
+impl AddAssign for Metrics {
+    fn add_assign(&mut self, other: Self) {
+        self.model_calls += other.model_calls;
+        self.usage += other.usage;
+        for (tool, calls) in other.tool_calls {
+            *self.tool_calls.entry(tool).or_default() += calls;
+        }
+        self.tool_errors += other.tool_errors;
+        self.retries += other.retries;
+        self.duration_ms += other.duration_ms;
+    }
+}
@@ -0,0 +1,204 @@
+//! Telling a webhook about finished `qualify` runs, in a shape Slack's incoming
+//! webhooks accept as is.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use anyhow::Context;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Attachment, MultiPart, SinglePart, header::ContentType},
+    transport::smtp::authentication::Credentials,
+};
+
+use crate::{
+    config::{EmailConfig, NotifyConfig},
+    sheets::LOCAL_SPREADSHEET,
+};
+
+/// The JSON body posted to the webhook. Slack shows `text` and ignores the
+/// other fields, which are there for everything else.
+#[derive(Debug, Serialize)]
+pub struct Notification<'a> {
+    text: String,
+    spreadsheet: &'a str,
+    /// Link to the spreadsheet, unless it's the in-memory `local` one.
+    spreadsheet_url: Option<String>,
+    results_sheet: &'a str,
+    qualified: usize,
+    disqualified: usize,
+    /// Leads left without a verdict, because their chunk failed.
+    failed: usize,
+    top_leads: Vec<TopLead>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopLead {
+    pub row: u64,
+    /// The lead's cell in the sheet's first column.
+    pub lead: Value,
+    pub score: f64,
+    pub reason: String,
+}
+
+impl<'a> Notification<'a> {
+    /// The summary of a run, keeping the `limit` best scoring of `top_leads`.
+    pub fn new(
+        spreadsheet: &'a str,
+        results_sheet: &'a str,
+        qualified: usize,
+        disqualified: usize,
+        failed: usize,
+        mut top_leads: Vec<TopLead>,
+        limit: usize,
+    ) -> Self {
+        top_leads.sort_by(|a, b| b.score.total_cmp(&a.score));
+        top_leads.truncate(limit);
+
+        let spreadsheet_url = (spreadsheet != LOCAL_SPREADSHEET)
+            .then(|| format!("https://docs.google.com/spreadsheets/d/{spreadsheet}"));
+        let sheet = match &spreadsheet_url {
+            Some(url) => format!("<{url}|{results_sheet}>"),
+            None => format!("`{results_sheet}`"),
+        };
+
+        let mut notification = Self {
+            text: String::new(),
+            spreadsheet,
+            spreadsheet_url,
+            results_sheet,
+            qualified,
+            disqualified,
+            failed,
+            top_leads,
+        };
+        notification.text = notification.message(&sheet);
+
+        notification
+    }
+
+    /// The summary as a message, pointing to the results as `sheet`.
+    fn message(&self, sheet: &str) -> String {
+        let mut text = format!(
+            "Qualified {} of {} leads ({} disqualified, {} without a verdict); results in {sheet}",
+            self.qualified,
+            self.qualified + self.disqualified + self.failed,
+            self.disqualified,
+            self.failed
+        );
+        for lead in &self.top_leads {
+            let name = match &lead.lead {
+                Value::String(name) => name.clone(),
+                other => other.to_string(),
+            };
+            text.push_str(&format!(
+                "\n• {name} (row {}, score {}): {}",
+                lead.row, lead.score, lead.reason
+            ));
+        }
+
+        text
+    }
+}
+
+/// Posts `notification` to the configured webhook, if there is one.
+///
+/// The results are written by the time this runs, so a failure is only
+/// reported rather than failing the run.
+pub async fn send(config: &NotifyConfig, notification: &Notification<'_>) {
+    let Some(url) = &config.webhook_url else {
+        return;
+    };
+
+    let res = reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .json(notification)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    if let Err(e) = res {
+        eprintln!("Failed to send the notification: {e}");
+    }
+}
+
+/// Emails `notification` to the recipients under `[notify.email]`, if there
+/// are any, with `report` attached as an HTML page.
+///
+/// As with the webhook, a failure is only reported.
+pub async fn email(config: &EmailConfig, notification: &Notification<'_>, report: Option<String>) {
+    let Some(host) = &config.smtp_host else {
+        return;
+    };
+    if config.to.is_empty() {
+        return;
+    }
+
+    if let Err(e) = send_email(config, host, notification, report).await {
+        eprintln!("Failed to email the summary: {e:#}");
+    }
+}
+
+async fn send_email(
+    config: &EmailConfig,
+    host: &str,
+    notification: &Notification<'_>,
+    report: Option<String>,
+) -> anyhow::Result<()> {
+    let from = config
+        .from
+        .as_ref()
+        .or(config.username.as_ref())
+        .context("Set `from` under [notify.email]")?;
+    let mut message = Message::builder()
+        .from(
+            from.parse()
+                .with_context(|| format!("Invalid sender `{from}`"))?,
+        )
+        .subject(format!(
+            "{} of {} leads qualified into {}",
+            notification.qualified,
+            notification.qualified + notification.disqualified + notification.failed,
+            notification.results_sheet
+        ));
+    for to in &config.to {
+        message = message.to(to
+            .parse()
+            .with_context(|| format!("Invalid recipient `{to}`"))?);
+    }
+
+    let sheet = match &notification.spreadsheet_url {
+        Some(url) => format!("`{}` ({url})", notification.results_sheet),
+        None => format!("`{}`", notification.results_sheet),
+    };
+    let text = SinglePart::plain(notification.message(&sheet));
+    let message = match report {
+        Some(report) => message.multipart(MultiPart::mixed().singlepart(text).singlepart(
+            Attachment::new("report.html".to_string()).body(report, ContentType::TEXT_HTML),
+        ))?,
+        None => message.singlepart(text)?,
+    };
+
+    // 465 is for TLS from the start, the other ports upgrade to it.
+    let mut transport = match config.port {
+        465 => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+    }
+    .port(config.port)
+    .timeout(Some(Duration::from_secs(config.timeout_secs)));
+    if let Some(username) = &config.username {
+        let password = std::env::var(&config.password_env)
+            .with_context(|| format!("Set {} to the SMTP password", config.password_env))?;
+        transport = transport.credentials(Credentials::new(username.clone(), password));
+    }
+    transport
+        .build()
+        .send(message)
+        .await
+        .with_context(|| format!("Failed to send the email through {host}"))?;
+
+    Ok(())
+}
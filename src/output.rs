@@ -0,0 +1,44 @@
+//! Machine-readable output, for driving the agent from other programs.
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::chat::Turn;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Stream answers as they are written
+    #[default]
+    Text,
+    /// Print one JSON record per prompt
+    Json,
+}
+
+/// A line of `--output json`, describing how one prompt was handled.
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    session_id: &'a str,
+    prompt: &'a str,
+    #[serde(flatten)]
+    turn: Option<&'a Turn>,
+    /// Why the prompt didn't complete, if it didn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Prints the record for `prompt` to stdout as a single line of JSON.
+///
+/// `turn` is missing when the prompt failed before the agent could report on it.
+pub fn print_json(session_id: &str, prompt: &str, turn: Option<&Turn>, error: Option<String>) {
+    let record = Record {
+        session_id,
+        prompt,
+        turn,
+        error,
+    };
+
+    match serde_json::to_string(&record) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("Failed to serialize the output record: {e}"),
+    }
+}
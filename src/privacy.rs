@@ -0,0 +1,190 @@
+//! Keeping personal data from the model. Emails, phone numbers and addresses
+//! are swapped for placeholders like `[EMAIL_1]` before anything is sent, and
+//! swapped back in what the model answers and in the tools it calls, so sheets
+//! and the user only ever see the real values.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::Context;
+use regex::Regex;
+use rig::message::{Message, ToolCall, UserContent};
+use serde_json::Value;
+
+use crate::config::{Mask, PrivacyConfig};
+
+/// Longest placeholder, e.g. `[ADDRESS_12345]`, held back while streaming in
+/// case the rest of it is in the next chunk.
+const MAX_PLACEHOLDER: usize = 24;
+
+/// Swaps personal data for placeholders and back. The same value gets the same
+/// placeholder for as long as the agent runs, so the model can tell leads apart
+/// and refer to them.
+pub struct Masker {
+    patterns: Vec<(Mask, Regex)>,
+    placeholder: Regex,
+    placeholders: Mutex<Placeholders>,
+}
+
+#[derive(Default)]
+struct Placeholders {
+    by_value: HashMap<String, String>,
+    by_placeholder: HashMap<String, String>,
+    counts: HashMap<Mask, usize>,
+}
+
+impl Masker {
+    /// Returns `None` unless masking is enabled.
+    pub fn from_config(config: &PrivacyConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled || config.mask.is_empty() {
+            return Ok(None);
+        }
+
+        let patterns = config
+            .mask
+            .iter()
+            .map(|mask| Ok((*mask, Regex::new(mask.pattern())?)))
+            .collect::<anyhow::Result<_>>()
+            .context("Invalid pattern for [privacy] mask")?;
+
+        Ok(Some(Self {
+            patterns,
+            placeholder: Regex::new(r"\[(?:EMAIL|PHONE|ADDRESS)_\d+\]")?,
+            placeholders: Mutex::default(),
+        }))
+    }
+
+    /// `text` with the personal data in it replaced by placeholders.
+    pub fn mask(&self, text: &str) -> String {
+        let mut placeholders = self.placeholders.lock().expect("the lock isn't poisoned");
+        let mut masked = text.to_string();
+        for (mask, regex) in &self.patterns {
+            masked = regex
+                .replace_all(&masked, |found: &regex::Captures<'_>| {
+                    placeholders.placeholder(*mask, &found[0])
+                })
+                .into_owned();
+        }
+
+        masked
+    }
+
+    /// `text` with the placeholders in it replaced by the values they stand
+    /// for. Placeholders this agent didn't hand out are left alone.
+    pub fn unmask(&self, text: &str) -> String {
+        let placeholders = self.placeholders.lock().expect("the lock isn't poisoned");
+        self.placeholder
+            .replace_all(text, |found: &regex::Captures<'_>| {
+                placeholders
+                    .by_placeholder
+                    .get(&found[0])
+                    .cloned()
+                    .unwrap_or_else(|| found[0].to_string())
+            })
+            .into_owned()
+    }
+
+    /// `value` with the placeholders in every string in it unmasked.
+    pub fn unmask_value(&self, value: &Value) -> Value {
+        match value {
+            Value::String(text) => Value::String(self.unmask(text)),
+            Value::Array(values) => values.iter().map(|v| self.unmask_value(v)).collect(),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(key, v)| (self.unmask(key), self.unmask_value(v)))
+                    .collect(),
+            ),
+            value => value.clone(),
+        }
+    }
+
+    /// `value` with the personal data in every string in it masked.
+    pub fn mask_value(&self, value: &Value) -> Value {
+        match value {
+            Value::String(text) => Value::String(self.mask(text)),
+            Value::Array(values) => values.iter().map(|v| self.mask_value(v)).collect(),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(key, v)| (key.clone(), self.mask_value(v)))
+                    .collect(),
+            ),
+            value => value.clone(),
+        }
+    }
+
+    /// `prompt` with the personal data in its text masked. Tool results are
+    /// masked as they're made, see [`Masker::mask`].
+    pub fn mask_prompt(&self, mut prompt: Message) -> Message {
+        if let Message::User { content } = &mut prompt {
+            for content in content.iter_mut() {
+                if let UserContent::Text(text) = content {
+                    text.text = self.mask(&text.text);
+                }
+            }
+        }
+
+        prompt
+    }
+
+    /// The tool call the model made, with the real values in its arguments.
+    pub fn unmask_call(&self, call: &ToolCall) -> ToolCall {
+        let mut call = call.clone();
+        call.function.arguments = self.unmask_value(&call.function.arguments);
+        call
+    }
+
+    /// Unmasks an answer as it streams in.
+    pub fn unmasking(&self) -> Unmasking<'_> {
+        Unmasking {
+            masker: self,
+            pending: String::new(),
+        }
+    }
+}
+
+impl Placeholders {
+    fn placeholder(&mut self, mask: Mask, value: &str) -> String {
+        if let Some(placeholder) = self.by_value.get(value) {
+            return placeholder.clone();
+        }
+
+        let count = self.counts.entry(mask).or_default();
+        *count += 1;
+        let placeholder = format!("[{}_{count}]", mask.name().to_uppercase());
+        self.by_value.insert(value.to_string(), placeholder.clone());
+        self.by_placeholder
+            .insert(placeholder.clone(), value.to_string());
+
+        placeholder
+    }
+}
+
+/// An answer being unmasked chunk by chunk, holding back what may be the start
+/// of a placeholder until the rest of it arrives.
+pub struct Unmasking<'a> {
+    masker: &'a Masker,
+    pending: String,
+}
+
+impl Unmasking<'_> {
+    /// The text that can be shown now that `chunk` has arrived.
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        let held = self
+            .pending
+            .rfind('[')
+            .filter(|start| {
+                !self.pending[*start..].contains(']')
+                    && self.pending.len() - start < MAX_PLACEHOLDER
+            })
+            .unwrap_or(self.pending.len());
+
+        let rest = self.pending.split_off(held);
+        let ready = std::mem::replace(&mut self.pending, rest);
+        self.masker.unmask(&ready)
+    }
+
+    /// Whatever was held back, once the answer is complete.
+    pub fn finish(self) -> String {
+        self.masker.unmask(&self.pending)
+    }
+}
@@ -0,0 +1,127 @@
+//! Prompt profiles: preambles for different kinds of work, written as
+//! templates that `{{name}}` variables are filled into.
+//!
+//! A variable may give a fallback for when it isn't set, as in
+//! `{{criteria|the user's criteria}}`.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::config::ProfileConfig;
+
+/// Profiles that come with the agent, which files of the same name replace.
+const BUILT_IN: &[(&str, &str)] = &[
+    (
+        "lead-qualification",
+        include_str!("profiles/lead-qualification.md"),
+    ),
+    ("data-cleaning", include_str!("profiles/data-cleaning.md")),
+    (
+        "report-generation",
+        include_str!("profiles/report-generation.md"),
+    ),
+];
+
+/// Where profiles are looked up, and the variables filled into them.
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    dir: Option<PathBuf>,
+    vars: BTreeMap<String, String>,
+}
+
+impl Profiles {
+    pub fn from_config(config: &ProfileConfig) -> Self {
+        Self {
+            dir: config.dir.clone().or_else(default_dir),
+            vars: config.vars.clone(),
+        }
+    }
+
+    /// The preamble of the profile `name`, with the variables filled in.
+    pub fn render(&self, name: &str) -> anyhow::Result<String> {
+        let template = match self.file(name) {
+            Some(path) if path.exists() => std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read the profile {}", path.display()))?,
+            _ => BUILT_IN
+                .iter()
+                .find(|(built_in, _)| *built_in == name)
+                .map(|(_, template)| template.to_string())
+                .with_context(|| {
+                    format!(
+                        "There's no profile `{name}`; the profiles are {}",
+                        self.names().join(", ")
+                    )
+                })?,
+        };
+
+        self.interpolate(&template)
+            .with_context(|| format!("Failed to render the profile `{name}`"))
+    }
+
+    /// Fills the variables into `template`.
+    pub fn interpolate(&self, template: &str) -> anyhow::Result<String> {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .map(|end| start + end)
+                .context("A `{{` isn't closed with `}}`")?;
+            let (name, fallback) = match rest[start + 2..end].split_once('|') {
+                Some((name, fallback)) => (name.trim(), Some(fallback)),
+                None => (rest[start + 2..end].trim(), None),
+            };
+            let value = match (self.vars.get(name), fallback) {
+                (Some(value), _) => value.as_str(),
+                (None, Some(fallback)) => fallback,
+                (None, None) => anyhow::bail!(
+                    "`{{{{{name}}}}}` isn't set; set it with `--var {name}=...` or under \
+                     [profile.vars]"
+                ),
+            };
+
+            rendered.push_str(&rest[..start]);
+            rendered.push_str(value);
+            rest = &rest[end + 2..];
+        }
+        rendered.push_str(rest);
+
+        Ok(rendered)
+    }
+
+    /// Names of the built-in profiles and of those in the profile directory.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN.iter().map(|(name, _)| name.to_string()).collect();
+
+        if let Some(entries) = self.dir.as_ref().and_then(|dir| dir.read_dir().ok()) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|ext| ext == "md")
+                    && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+                    && !names.iter().any(|known| known == name)
+                {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        names.sort();
+        names
+    }
+
+    fn file(&self, name: &str) -> Option<PathBuf> {
+        // Names can't reach outside the profile directory.
+        if name.contains(['/', '\\']) || name.starts_with('.') {
+            return None;
+        }
+
+        Some(self.dir.as_ref()?.join(format!("{name}.md")))
+    }
+}
+
+/// `~/.config/gsheets-agent/profiles` (or the platform equivalent).
+fn default_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("gsheets-agent").join("profiles"))
+}
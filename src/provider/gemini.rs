@@ -0,0 +1,536 @@
+//! Gemini, through Google AI Studio with an API key or through Vertex AI with
+//! Google Cloud credentials.
+//!
+//! rig's Gemini client only reaches AI Studio and keeps just the first part of
+//! each streamed chunk, so the agent speaks the `streamGenerateContent` API
+//! itself. Gemini pairs function responses with calls by function name rather
+//! than by id, so the names are looked up from the calls in the history.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+};
+
+use futures::{Stream, StreamExt};
+use rig::{
+    OneOrMany,
+    completion::{self, CompletionError, CompletionRequest, CompletionResponse},
+    message::{AssistantContent, Message, ToolResultContent, UserContent},
+    streaming::{StreamingChoice, StreamingCompletionModel, StreamingResult},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::OnceCell;
+
+use crate::{auth::GoogleAuth, config::ModelConfig};
+
+pub const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+
+const AI_STUDIO_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+const VERTEX_SCOPES: &[&str] = &["https://www.googleapis.com/auth/cloud-platform"];
+
+/// Environment variables AI Studio API keys are read from, in order.
+const API_KEY_ENV: &[&str] = &["GEMINI_API_KEY", "GOOGLE_API_KEY"];
+
+/// Generation stopped for one of these reasons leaves the answer incomplete
+/// or missing, so it's reported as an error.
+const FAILED_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "MALFORMED_FUNCTION_CALL",
+    "UNEXPECTED_TOOL_CALL",
+    "OTHER",
+];
+
+#[derive(Clone)]
+pub struct CompletionModel {
+    client: reqwest::Client,
+    model: String,
+    backend: Backend,
+}
+
+#[derive(Clone)]
+enum Backend {
+    AiStudio {
+        base_url: String,
+    },
+    Vertex {
+        base_url: String,
+        project: Option<String>,
+        location: String,
+        credentials: Option<PathBuf>,
+        /// Loaded on the first request, since loading can fail and has to wait.
+        auth: Arc<OnceCell<GoogleAuth>>,
+    },
+}
+
+impl CompletionModel {
+    /// `base_url` replaces the API's address, e.g. for a proxy.
+    pub fn from_config(config: &ModelConfig) -> Self {
+        let backend = match &config.vertex {
+            Some(vertex) => Backend::Vertex {
+                base_url: config.base_url.clone().unwrap_or_else(|| {
+                    match vertex.location.as_str() {
+                        "global" => "https://aiplatform.googleapis.com/v1".to_string(),
+                        location => format!("https://{location}-aiplatform.googleapis.com/v1"),
+                    }
+                }),
+                project: vertex
+                    .project
+                    .clone()
+                    .or_else(|| std::env::var("GOOGLE_CLOUD_PROJECT").ok()),
+                location: vertex.location.clone(),
+                credentials: vertex.credentials.clone(),
+                auth: Arc::default(),
+            },
+            None => Backend::AiStudio {
+                base_url: config
+                    .base_url
+                    .clone()
+                    .unwrap_or_else(|| AI_STUDIO_URL.to_string()),
+            },
+        };
+
+        Self {
+            client: reqwest::Client::new(),
+            model: config.model_name().to_string(),
+            backend,
+        }
+    }
+
+    async fn post(
+        &self,
+        body: &GenerateContentRequest,
+    ) -> Result<reqwest::Response, CompletionError> {
+        let request = match &self.backend {
+            Backend::AiStudio { base_url } => {
+                let api_key = API_KEY_ENV
+                    .iter()
+                    .find_map(|name| std::env::var(name).ok())
+                    .ok_or_else(|| {
+                        request_error(
+                            "Set GEMINI_API_KEY to use Gemini, or configure [model.vertex] to use \
+                             it through Vertex AI",
+                        )
+                    })?;
+                self.client
+                    .post(format!(
+                        "{base_url}/models/{}:streamGenerateContent?alt=sse",
+                        self.model
+                    ))
+                    .header("x-goog-api-key", api_key)
+            }
+            Backend::Vertex {
+                base_url,
+                project,
+                location,
+                credentials,
+                auth,
+            } => {
+                let project = project.as_ref().ok_or_else(|| {
+                    request_error(
+                        "Set project under [model.vertex] or GOOGLE_CLOUD_PROJECT to use Gemini \
+                         through Vertex AI",
+                    )
+                })?;
+                let auth = auth
+                    .get_or_try_init(|| {
+                        GoogleAuth::application_default(credentials.as_deref(), VERTEX_SCOPES)
+                    })
+                    .await
+                    .map_err(|e| request_error(format!("{e:#}")))?;
+                let token = auth.token().await.map_err(request_error)?;
+                self.client
+                    .post(format!(
+                        "{base_url}/projects/{project}/locations/{location}/publishers/google/models/{}:streamGenerateContent?alt=sse",
+                        self.model
+                    ))
+                    .bearer_auth(token)
+            }
+        };
+
+        let response = request.json(body).send().await?;
+        if !response.status().is_success() {
+            return Err(CompletionError::ProviderError(format!(
+                "{}: {}",
+                response.status(),
+                response.text().await?
+            )));
+        }
+
+        Ok(response)
+    }
+}
+
+impl completion::CompletionModel for CompletionModel {
+    type Response = ();
+
+    async fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse<()>, CompletionError> {
+        let mut stream = self.stream(request).await?;
+        let mut text = String::new();
+        let mut contents = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            match chunk? {
+                StreamingChoice::Message(chunk) => text.push_str(&chunk),
+                StreamingChoice::ToolCall(name, id, arguments) => {
+                    contents.push(AssistantContent::tool_call(id, name, arguments));
+                }
+            }
+        }
+        if !text.is_empty() {
+            contents.insert(0, AssistantContent::text(text));
+        }
+
+        Ok(CompletionResponse {
+            choice: OneOrMany::many(contents).map_err(|_| {
+                CompletionError::ResponseError("Gemini sent neither text nor a tool call".into())
+            })?,
+            raw_response: (),
+        })
+    }
+}
+
+impl StreamingCompletionModel for CompletionModel {
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamingResult, CompletionError> {
+        let body = request_body(&request)?;
+        let response = self.post(&body).await?;
+
+        let events = Events {
+            body: Box::pin(
+                response
+                    .bytes_stream()
+                    .map(|chunk| chunk.map(|bytes| bytes.to_vec())),
+            ),
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            done: false,
+        };
+
+        Ok(Box::pin(futures::stream::try_unfold(events, Events::next)))
+    }
+}
+
+/// The server-sent events of a streamed response, as the choices they hold.
+struct Events {
+    body: Pin<Box<dyn Stream<Item = reqwest::Result<Vec<u8>>> + Send>>,
+    buffer: Vec<u8>,
+    pending: VecDeque<StreamingChoice>,
+    done: bool,
+}
+
+impl Events {
+    async fn next(mut self) -> Result<Option<(StreamingChoice, Self)>, CompletionError> {
+        loop {
+            if let Some(choice) = self.pending.pop_front() {
+                return Ok(Some((choice, self)));
+            }
+
+            // Events can be split across chunks, so only whole lines are parsed.
+            if let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                self.parse_line(&line)?;
+                continue;
+            }
+            if self.done {
+                let line = std::mem::take(&mut self.buffer);
+                self.parse_line(&line)?;
+                return match self.pending.pop_front() {
+                    Some(choice) => Ok(Some((choice, self))),
+                    None => Ok(None),
+                };
+            }
+
+            match self.body.next().await {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk?),
+                None => self.done = true,
+            }
+        }
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> Result<(), CompletionError> {
+        let line =
+            std::str::from_utf8(line).map_err(|e| CompletionError::ResponseError(e.to_string()))?;
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            return Ok(());
+        };
+
+        let chunk: GenerateContentResponse = serde_json::from_str(data.trim())?;
+        if let Some(error) = chunk.error {
+            return Err(CompletionError::ProviderError(error.to_string()));
+        }
+        if let Some(reason) = chunk
+            .prompt_feedback
+            .and_then(|feedback| feedback.block_reason)
+        {
+            return Err(CompletionError::ResponseError(format!(
+                "Gemini refused the request: {reason}"
+            )));
+        }
+
+        let Some(candidate) = chunk.candidates.into_iter().next() else {
+            return Ok(());
+        };
+        for part in candidate
+            .content
+            .map(|content| content.parts)
+            .unwrap_or_default()
+        {
+            match part {
+                // Thoughts are the model's own notes, not part of the answer.
+                Part {
+                    thought: Some(true),
+                    ..
+                } => {}
+                Part {
+                    function_call: Some(call),
+                    ..
+                } => self.pending.push_back(StreamingChoice::ToolCall(
+                    call.name,
+                    call.id.unwrap_or_default(),
+                    call.args,
+                )),
+                Part {
+                    text: Some(text), ..
+                } if !text.is_empty() => self.pending.push_back(StreamingChoice::Message(text)),
+                _ => {}
+            }
+        }
+
+        match candidate.finish_reason {
+            Some(reason) if FAILED_FINISH_REASONS.contains(&reason.as_str()) => Err(
+                CompletionError::ResponseError(format!("Gemini stopped answering: {reason}")),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Translates `request` into Gemini's contents, with tool calls as function
+/// calls and tool results as function responses.
+fn request_body(request: &CompletionRequest) -> Result<GenerateContentRequest, CompletionError> {
+    let mut function_names: HashMap<String, String> = HashMap::new();
+    let mut contents = Vec::with_capacity(request.chat_history.len() + 1);
+    let prompt = request.prompt_with_context();
+
+    for message in request.chat_history.iter().chain([&prompt]) {
+        let (role, parts) = match message {
+            Message::User { content } => {
+                let parts = content
+                    .iter()
+                    .map(|content| match content {
+                        UserContent::Text(text) => Ok(Part::text(&text.text)),
+                        UserContent::ToolResult(result) => {
+                            let text = result
+                                .content
+                                .iter()
+                                .filter_map(|content| match content {
+                                    ToolResultContent::Text(text) => Some(text.text.as_str()),
+                                    ToolResultContent::Image(_) => None,
+                                })
+                                .collect::<Vec<_>>()
+                                .join("\n");
+                            let name = function_names
+                                .get(&result.id)
+                                .cloned()
+                                .unwrap_or_else(|| result.id.clone());
+
+                            Ok(Part {
+                                function_response: Some(FunctionResponse {
+                                    name,
+                                    response: json!({ "result": text }),
+                                }),
+                                ..Part::default()
+                            })
+                        }
+                        _ => Err(request_error(
+                            "Only text and tool results can be sent to Gemini",
+                        )),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                ("user", parts)
+            }
+            Message::Assistant { content } => {
+                let parts = content
+                    .iter()
+                    .map(|content| match content {
+                        AssistantContent::Text(text) => Part::text(&text.text),
+                        AssistantContent::ToolCall(call) => {
+                            function_names.insert(call.id.clone(), call.function.name.clone());
+                            Part {
+                                function_call: Some(FunctionCall {
+                                    id: None,
+                                    name: call.function.name.clone(),
+                                    args: call.function.arguments.clone(),
+                                }),
+                                ..Part::default()
+                            }
+                        }
+                    })
+                    .collect();
+
+                ("model", parts)
+            }
+        };
+
+        // Gemini rejects empty text, which is what answers made only of tool
+        // calls leave behind.
+        let parts: Vec<Part> = parts
+            .into_iter()
+            .filter(|part| part.text.as_ref().is_none_or(|text| !text.is_empty()))
+            .collect();
+        if !parts.is_empty() {
+            contents.push(Content { role, parts });
+        }
+    }
+
+    let tools = (!request.tools.is_empty()).then(|| {
+        vec![Tool {
+            function_declarations: request
+                .tools
+                .iter()
+                .map(|tool| FunctionDeclaration {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                    parameters_json_schema: tool.parameters.clone(),
+                })
+                .collect(),
+        }]
+    });
+
+    Ok(GenerateContentRequest {
+        contents,
+        system_instruction: request.preamble.as_ref().map(|preamble| SystemInstruction {
+            parts: vec![Part::text(preamble)],
+        }),
+        tools,
+        generation_config: GenerationConfig {
+            temperature: request.temperature,
+            max_output_tokens: request.max_tokens,
+        },
+    })
+}
+
+fn request_error(message: impl ToString) -> CompletionError {
+    CompletionError::RequestError(message.to_string().into())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest {
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<SystemInstruction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<Tool>>,
+    generation_config: GenerationConfig,
+}
+
+#[derive(Serialize)]
+struct Content {
+    role: &'static str,
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize)]
+struct SystemInstruction {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing)]
+    thought: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    function_call: Option<FunctionCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    function_response: Option<FunctionResponse>,
+}
+
+impl Part {
+    fn text(text: &str) -> Self {
+        Self {
+            text: Some(text.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FunctionResponse {
+    name: String,
+    response: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Tool {
+    function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionDeclaration {
+    name: String,
+    description: String,
+    /// Taken as JSON Schema, unlike `parameters`, which only allows a subset.
+    parameters_json_schema: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    content: Option<CandidateContent>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CandidateContent {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
@@ -0,0 +1,131 @@
+mod gemini;
+pub mod retry;
+mod tool_calls;
+
+use rig::{
+    completion::{
+        self, CompletionError, CompletionModel as _, CompletionRequest, CompletionResponse,
+    },
+    message::AssistantContent,
+    providers::{anthropic, ollama, openai},
+    streaming::{StreamingChoice, StreamingCompletionModel, StreamingResult},
+};
+use serde::Deserialize;
+
+use crate::config::ModelConfig;
+
+/// Completion provider the agent talks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// OpenAI, authenticated with `OPENAI_API_KEY`
+    #[value(name = "openai")]
+    OpenAi,
+    /// Anthropic, authenticated with `ANTHROPIC_API_KEY`
+    Anthropic,
+    /// A local Ollama server, so lead data never leaves the machine
+    Ollama,
+    /// Google's Gemini, authenticated with `GEMINI_API_KEY`, or with Google Cloud
+    /// credentials on Vertex AI
+    Gemini,
+}
+
+impl Provider {
+    /// Model used when none is configured.
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Self::OpenAi => openai::GPT_4O,
+            Self::Anthropic => anthropic::CLAUDE_3_5_SONNET,
+            Self::Ollama => "llama3.1",
+            Self::Gemini => gemini::DEFAULT_MODEL,
+        }
+    }
+}
+
+/// A completion model from any of the supported providers.
+///
+/// Lets the chat loop stay generic over [`completion::CompletionModel`] while the
+/// concrete provider is only known at runtime.
+#[derive(Clone)]
+pub enum Model {
+    OpenAi(openai::CompletionModel),
+    Anthropic(anthropic::completion::CompletionModel),
+    Ollama(ollama::CompletionModel),
+    Gemini(gemini::CompletionModel),
+}
+
+impl Model {
+    /// Builds the configured model, reading the provider's API key from the environment.
+    ///
+    /// `base_url` is used by Ollama, where it defaults to `http://localhost:11434`,
+    /// and by Gemini, in place of Google's endpoint.
+    pub fn from_config(config: &ModelConfig) -> Self {
+        let name = config.model_name();
+
+        match config.provider {
+            Provider::OpenAi => Self::OpenAi(openai::Client::from_env().completion_model(name)),
+            Provider::Anthropic => {
+                Self::Anthropic(anthropic::Client::from_env().completion_model(name))
+            }
+            Provider::Ollama => {
+                let client = match &config.base_url {
+                    Some(base_url) => ollama::Client::from_url(base_url),
+                    None => ollama::Client::new(),
+                };
+                Self::Ollama(client.completion_model(name))
+            }
+            Provider::Gemini => Self::Gemini(gemini::CompletionModel::from_config(config)),
+        }
+    }
+}
+
+impl completion::CompletionModel for Model {
+    type Response = ();
+
+    async fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse<()>, CompletionError> {
+        let choice = match self {
+            Self::OpenAi(model) => model.completion(request).await?.choice,
+            Self::Anthropic(model) => model.completion(request).await?.choice,
+            Self::Ollama(model) => {
+                let tools = request.tools.clone();
+                tool_calls::normalize(model.completion(request).await?.choice, &tools)
+            }
+            Self::Gemini(model) => model.completion(request).await?.choice,
+        };
+
+        Ok(CompletionResponse {
+            choice,
+            raw_response: (),
+        })
+    }
+}
+
+impl StreamingCompletionModel for Model {
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamingResult, CompletionError> {
+        match self {
+            Self::OpenAi(model) => model.stream(request).await,
+            Self::Anthropic(model) => model.stream(request).await,
+            Self::Gemini(model) => model.stream(request).await,
+            // Tool calls can only be normalized once the whole response is in, so
+            // Ollama answers arrive as a single chunk.
+            Self::Ollama(_) => {
+                let choice = self.completion(request).await?.choice;
+                let chunks = choice.into_iter().map(|content| {
+                    Ok(match content {
+                        AssistantContent::Text(text) => StreamingChoice::Message(text.text),
+                        AssistantContent::ToolCall(tool_call) => StreamingChoice::ToolCall(
+                            tool_call.function.name,
+                            tool_call.id,
+                            tool_call.function.arguments,
+                        ),
+                    })
+                });
+
+                Ok(Box::pin(futures::stream::iter(chunks)))
+            }
+        }
+    }
+}
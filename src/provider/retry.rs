@@ -0,0 +1,80 @@
+//! Retrying completions that fail for reasons that go away on their own, like
+//! rate limits and provider outages.
+
+use std::future::Future;
+
+use rig::completion::CompletionError;
+
+use crate::config::BackoffConfig;
+
+/// Substrings of provider error bodies that mark an error as transient.
+const RETRYABLE_MARKERS: &[&str] = &[
+    "rate_limit",
+    "rate limit",
+    "Too Many Requests",
+    "overloaded",
+    "server_error",
+    "Service Unavailable",
+    "Bad Gateway",
+    "Gateway Timeout",
+    "Timed out",
+];
+
+/// Runs `call` until it succeeds, fails with an error that isn't retryable, or
+/// `backoff.max_attempts` is reached, sleeping with jittered exponential backoff
+/// in between.
+pub async fn with_retry<T, F, Fut>(
+    backoff: &BackoffConfig,
+    mut call: F,
+) -> Result<T, CompletionError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, CompletionError>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match call().await {
+            Err(e) if attempt < backoff.max_attempts && is_retryable(&e) => {
+                let delay = backoff
+                    .delay(attempt - 1)
+                    .mul_f64(rand::random_range(0.5..=1.0));
+                tracing::warn!(
+                    error = %e,
+                    "The model provider returned an error, retrying in {:.1}s (attempt {}/{})",
+                    delay.as_secs_f64(),
+                    attempt + 1,
+                    backoff.max_attempts
+                );
+
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Whether `error` is likely to succeed when the request is sent again: network
+/// failures, rate limits and server-side errors.
+pub fn is_retryable(error: &CompletionError) -> bool {
+    match error {
+        CompletionError::HttpError(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.status()
+                    .is_some_and(|status| status.as_u16() == 429 || status.is_server_error())
+        }
+        // Some providers prefix the body with the HTTP status, others only send the body.
+        CompletionError::ProviderError(message) => {
+            message.starts_with("429")
+                || message.starts_with('5')
+                || RETRYABLE_MARKERS
+                    .iter()
+                    .any(|marker| message.contains(marker))
+        }
+        CompletionError::JsonError(_)
+        | CompletionError::RequestError(_)
+        | CompletionError::ResponseError(_) => false,
+    }
+}
@@ -0,0 +1,95 @@
+//! Normalization of tool calls from models that don't follow the native
+//! function-calling format exactly, which is common with local models.
+
+use rig::{
+    OneOrMany,
+    completion::ToolDefinition,
+    message::{AssistantContent, ToolCall, ToolFunction},
+};
+use serde_json::Value;
+
+/// Rewrites a model's choice so tool calls come through as [`AssistantContent::ToolCall`].
+///
+/// Handles:
+/// - tool calls written as JSON in the message text, optionally wrapped in a
+///   Markdown code fence or `<tool_call>` tags, using either `arguments` or
+///   `parameters` for the arguments;
+/// - arguments encoded as a JSON string instead of an object;
+/// - prose emitted alongside tool calls, which is dropped so the tool calls are
+///   acted upon instead of being mistaken for the final answer.
+///
+/// Text is only reinterpreted when it names one of the advertised `tools`.
+pub fn normalize(
+    choice: OneOrMany<AssistantContent>,
+    tools: &[ToolDefinition],
+) -> OneOrMany<AssistantContent> {
+    let mut contents: Vec<AssistantContent> = choice
+        .into_iter()
+        .enumerate()
+        .map(|(idx, content)| match content {
+            AssistantContent::Text(text) => match parse_text_tool_call(&text.text, tools) {
+                Some(function) => AssistantContent::ToolCall(ToolCall {
+                    id: format!("call_{idx}"),
+                    function,
+                }),
+                None => AssistantContent::Text(text),
+            },
+            AssistantContent::ToolCall(mut tool_call) => {
+                tool_call.function.arguments = decode_arguments(tool_call.function.arguments);
+                AssistantContent::ToolCall(tool_call)
+            }
+        })
+        .collect();
+
+    if contents
+        .iter()
+        .any(|content| matches!(content, AssistantContent::ToolCall(_)))
+    {
+        contents.retain(|content| matches!(content, AssistantContent::ToolCall(_)));
+    }
+
+    OneOrMany::many(contents).expect("normalizing never removes every content")
+}
+
+fn parse_text_tool_call(text: &str, tools: &[ToolDefinition]) -> Option<ToolFunction> {
+    let text = strip_wrappers(text.trim());
+    let Value::Object(mut object) = serde_json::from_str(text).ok()? else {
+        return None;
+    };
+
+    let name = object.remove("name")?.as_str()?.to_string();
+    if !tools.iter().any(|tool| tool.name == name) {
+        return None;
+    }
+
+    let arguments = object
+        .remove("arguments")
+        .or_else(|| object.remove("parameters"))
+        .unwrap_or_else(|| Value::Object(Default::default()));
+
+    Some(ToolFunction {
+        name,
+        arguments: decode_arguments(arguments),
+    })
+}
+
+fn strip_wrappers(text: &str) -> &str {
+    let text = text
+        .strip_prefix("<tool_call>")
+        .and_then(|text| text.strip_suffix("</tool_call>"))
+        .unwrap_or(text)
+        .trim();
+
+    text.strip_prefix("```")
+        .and_then(|text| text.strip_suffix("```"))
+        .map(|text| text.trim_start_matches("json"))
+        .unwrap_or(text)
+        .trim()
+}
+
+fn decode_arguments(arguments: Value) -> Value {
+    match arguments {
+        Value::String(encoded) => serde_json::from_str(&encoded).unwrap_or(Value::String(encoded)),
+        arguments => arguments,
+    }
+}
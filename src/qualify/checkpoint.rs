@@ -0,0 +1,236 @@
+//! Saving how far a run got after every page of leads, so that a run that
+//! dies part of the way through can be carried on with `--resume-run`
+//! instead of being started over.
+//!
+//! A checkpoint is a JSON Lines file: a [`Header`] naming the run, then one
+//! [`Partial`] per page whose verdicts are in, in the order of the rows.
+//! Lines are only ever appended, so a run killed while writing one loses that
+//! page at most; what it wrote of it is cut off when the run is resumed.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::judgment::Judgment;
+use crate::{metrics::Metrics, retention, usage::Usage};
+
+/// What a checkpoint is of, on its first line.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub run: String,
+    pub spreadsheet: String,
+    pub sheet: String,
+    pub results_sheet: String,
+    /// Unix timestamp, in seconds.
+    pub started_at: u64,
+}
+
+/// The results of the rows read so far, or of one page of them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Partial {
+    /// The last row read, or 0 before the first page.
+    pub last_row: u64,
+    /// The leads sent to the model, as cleaned and enriched.
+    pub leads: Vec<(u64, Map<String, Value>)>,
+    pub verdicts: BTreeMap<u64, Result<Judgment, String>>,
+    /// Each row as it was cleaned, for the cleaned sheet.
+    pub cleaned: BTreeMap<u64, Vec<Value>>,
+    /// Hashes of the new leads, for the ledger of incremental runs.
+    pub hashes: BTreeMap<u64, String>,
+    pub usage: Usage,
+    /// Tokens used picking out the facts of the leads, if they were.
+    pub facts_usage: Usage,
+    /// What judging the leads took, picking out their facts included.
+    pub metrics: Metrics,
+    pub duplicates: usize,
+    /// Leads skipped because they were qualified before.
+    pub processed: usize,
+}
+
+impl Partial {
+    /// Adds the results of `page`, which comes after the rows of these.
+    pub fn merge(&mut self, page: Self) {
+        self.last_row = page.last_row;
+        self.leads.extend(page.leads);
+        self.verdicts.extend(page.verdicts);
+        self.cleaned.extend(page.cleaned);
+        self.hashes.extend(page.hashes);
+        self.usage += page.usage;
+        self.facts_usage += page.facts_usage;
+        self.metrics += page.metrics;
+        self.duplicates += page.duplicates;
+        self.processed += page.processed;
+    }
+}
+
+/// The checkpoint of a run that's going on.
+pub struct Checkpoint {
+    path: PathBuf,
+    file: File,
+}
+
+impl Checkpoint {
+    /// Starts the checkpoint of a new run, unless nothing is kept on disk, see
+    /// [`retention::persists`].
+    pub fn create(header: &Header) -> anyhow::Result<Option<Self>> {
+        if !retention::persists() {
+            return Ok(None);
+        }
+
+        let path = path(&header.run)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let file =
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut checkpoint = Self { path, file };
+        checkpoint.write(header)?;
+
+        Ok(Some(checkpoint))
+    }
+
+    /// Opens the checkpoint of the run `run` to carry on with it, returning
+    /// what it's of and the results of the rows it got through.
+    pub fn resume(run: &str) -> anyhow::Result<(Self, Header, Partial)> {
+        let path = path(run)?;
+        let file = File::open(&path).with_context(|| {
+            format!("There's no checkpoint of the run `{run}`; it finished, or never started")
+        })?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        anyhow::ensure!(
+            !line.is_empty(),
+            "The checkpoint {} is empty",
+            path.display()
+        );
+        let header: Header = serde_json::from_str(&line)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let mut done = Partial::default();
+        // Where the lines that were written in full end.
+        let mut end = line.len() as u64;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            // The last line is cut short if the run died while writing it.
+            match serde_json::from_str(&line) {
+                Ok(page) if line.ends_with('\n') => done.merge(page),
+                _ => break,
+            }
+            end += line.len() as u64;
+        }
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // What comes after is lost anyway, and the next page saved must start
+        // a line of its own.
+        file.set_len(end)
+            .with_context(|| format!("Failed to cut short {}", path.display()))?;
+
+        Ok((Self { path, file }, header, done))
+    }
+
+    /// Records that the rows of `page` are done.
+    pub fn save(&mut self, page: &Partial) -> anyhow::Result<()> {
+        self.write(page)
+    }
+
+    /// Deletes the checkpoint once the run has finished, since the leads it
+    /// holds aren't needed anymore.
+    pub fn finish(self) -> anyhow::Result<()> {
+        drop(self.file);
+        retention::shred(&self.path)
+    }
+
+    fn write(&mut self, line: &impl Serialize) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(line)?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("Failed to write to {}", self.path.display()))
+    }
+}
+
+/// Whether the run `run` can be carried on with `--resume-run`.
+pub fn exists(run: &str) -> bool {
+    path(run).is_ok_and(|path| path.exists())
+}
+
+/// `~/.local/share/gsheets-agent/checkpoints` (or the platform equivalent).
+pub fn dir() -> anyhow::Result<PathBuf> {
+    // Tests keep to a directory of their own, rather than the user's.
+    if cfg!(test) {
+        return Ok(std::env::temp_dir().join("gsheets-agent-test-checkpoints"));
+    }
+    dirs::data_dir()
+        .map(|dir| dir.join("gsheets-agent").join("checkpoints"))
+        .context("Could not determine the data directory to store checkpoints in")
+}
+
+fn path(run: &str) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        !run.is_empty() && run.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+        "Invalid run id `{run}`"
+    );
+
+    Ok(dir()?.join(format!("{run}.jsonl")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A page of one lead, in `row`.
+    fn page(row: u64) -> Partial {
+        let lead = Map::from_iter([("Name".to_string(), Value::from(format!("Lead {row}")))]);
+        Partial {
+            last_row: row,
+            leads: vec![(row, lead)],
+            ..Partial::default()
+        }
+    }
+
+    #[test]
+    fn resumes_again_after_a_page_cut_short() {
+        let run = uuid::Uuid::new_v4().to_string();
+        let header = Header {
+            run: run.clone(),
+            spreadsheet: "local".to_string(),
+            sheet: "Leads".to_string(),
+            results_sheet: "Leads results".to_string(),
+            started_at: 0,
+        };
+        let mut checkpoint = Checkpoint::create(&header).unwrap().unwrap();
+        checkpoint.save(&page(2)).unwrap();
+        // The run dies while saving the next page.
+        let cut = &serde_json::to_string(&page(3)).unwrap()[..20];
+        checkpoint.file.write_all(cut.as_bytes()).unwrap();
+        drop(checkpoint);
+
+        let (mut checkpoint, _, done) = Checkpoint::resume(&run).unwrap();
+        assert_eq!(done.last_row, 2);
+        checkpoint.save(&page(3)).unwrap();
+        checkpoint.save(&page(4)).unwrap();
+        drop(checkpoint);
+        let (checkpoint, _, done) = Checkpoint::resume(&run).unwrap();
+        checkpoint.finish().unwrap();
+
+        assert_eq!(done.last_row, 4);
+        let rows: Vec<u64> = done.leads.iter().map(|(row, _)| *row).collect();
+        assert_eq!(rows, [2, 3, 4]);
+    }
+}
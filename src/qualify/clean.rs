@@ -0,0 +1,134 @@
+//! Tidying up what forms collect, by rules rather than a model, before the
+//! leads are deduplicated and judged.
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::{Map, Value};
+
+use super::{ColumnKind, Columns, find_column, is_email_header};
+use crate::config::CleanConfig;
+
+/// Formats of dates with a time, tried in order; `%m/%d` is swapped for
+/// `%d/%m` when days come first.
+const DATE_TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M:%SZ",
+    "%m/%d/%Y %H:%M:%S",
+    "%m/%d/%Y %H:%M",
+    "%d.%m.%Y %H:%M:%S",
+    "%d.%m.%Y %H:%M",
+];
+const DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%d",
+    "%Y/%m/%d",
+    "%m/%d/%Y",
+    "%m/%d/%y",
+    "%d.%m.%Y",
+    "%B %d, %Y",
+    "%b %d, %Y",
+    "%d %B %Y",
+    "%d %b %Y",
+];
+
+/// Applies the rules of [`CleanConfig`] to the cells of leads.
+pub struct Cleaner<'a> {
+    config: &'a CleanConfig,
+    name_column: Option<String>,
+    email_column: Option<String>,
+    date_columns: Vec<String>,
+}
+
+impl<'a> Cleaner<'a> {
+    pub fn new(config: &'a CleanConfig, headers: &[String], columns: &Columns) -> Self {
+        let date_columns = columns
+            .get(ColumnKind::Timestamp)
+            .into_iter()
+            .chain(&config.date_columns)
+            .cloned()
+            .collect();
+
+        Self {
+            config,
+            name_column: find_column(headers, columns.get(ColumnKind::Name), |header| {
+                header.contains("name") && !header.contains("company")
+            }),
+            email_column: find_column(headers, columns.get(ColumnKind::Email), is_email_header),
+            date_columns,
+        }
+    }
+
+    /// Cleans the cells of `lead` in place.
+    pub fn clean(&self, lead: &mut Map<String, Value>) {
+        if !self.config.enabled {
+            return;
+        }
+
+        for (header, cell) in lead.iter_mut() {
+            let Value::String(text) = cell else {
+                continue;
+            };
+            let mut cleaned = if self.config.trim {
+                text.split_whitespace().collect::<Vec<_>>().join(" ")
+            } else {
+                text.clone()
+            };
+
+            if self.config.title_case_names && self.name_column.as_ref() == Some(header) {
+                cleaned = title_case(&cleaned);
+            }
+            if self.config.lowercase_emails && self.email_column.as_ref() == Some(header) {
+                cleaned = cleaned.to_lowercase();
+            }
+            if self.config.normalize_dates
+                && self.date_columns.contains(header)
+                && let Some(date) = normalize_date(&cleaned, self.config.day_first)
+            {
+                cleaned = date;
+            }
+
+            *text = cleaned;
+        }
+    }
+}
+
+/// `name` with each word capitalized, if it was typed in all lowercase or all
+/// uppercase, e.g. `ada lovelace` or `O'BRIEN-SMITH`.
+fn title_case(name: &str) -> String {
+    let letters = || name.chars().filter(|c| c.is_alphabetic());
+    if !letters().all(char::is_lowercase) && !letters().all(char::is_uppercase) {
+        return name.to_string();
+    }
+
+    let mut titled = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        if word_start {
+            titled.extend(c.to_uppercase());
+        } else {
+            titled.extend(c.to_lowercase());
+        }
+        word_start = matches!(c, ' ' | '-' | '\'');
+    }
+
+    titled
+}
+
+/// `date` in ISO 8601, or `None` if it isn't in a format that's recognized.
+fn normalize_date(date: &str, day_first: bool) -> Option<String> {
+    let format = |format: &str| match day_first {
+        true => format.replace("%m/%d", "%d/%m"),
+        false => format.to_string(),
+    };
+
+    DATE_TIME_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(date, &format(f)).ok())
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+        .or_else(|| {
+            DATE_FORMATS
+                .iter()
+                .find_map(|f| NaiveDate::parse_from_str(date, &format(f)).ok())
+                .map(|date| date.format("%Y-%m-%d").to_string())
+        })
+}
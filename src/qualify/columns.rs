@@ -0,0 +1,384 @@
+//! Working out what the columns of a sheet hold, from their headers and a
+//! sample of rows, so deduplication, enrichment and CRM pushes find the email
+//! address or company however a form named them.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    cli::ColumnsArgs,
+    repl::Repl,
+    sheets::{self, Spreadsheets, cell_text, quote_sheet},
+    store,
+};
+
+/// Rows below the headers that are looked at to infer the columns.
+const SAMPLE_ROWS: usize = 20;
+/// Lowest score at which a column is offered for a kind at all.
+const MIN_SCORE: f64 = 0.3;
+/// Lowest score at which the best column is taken without asking.
+const CONFIDENT_SCORE: f64 = 0.6;
+/// How far ahead of the next one the best column has to be to be taken
+/// without asking.
+const CONFIDENT_MARGIN: f64 = 0.2;
+
+/// What a column holds, as far as the pipeline cares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnKind {
+    Email,
+    Name,
+    Company,
+    Phone,
+    Website,
+    Budget,
+    Timestamp,
+}
+
+impl ColumnKind {
+    const ALL: [Self; 7] = [
+        Self::Email,
+        Self::Name,
+        Self::Company,
+        Self::Phone,
+        Self::Website,
+        Self::Budget,
+        Self::Timestamp,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Name => "name",
+            Self::Company => "company",
+            Self::Phone => "phone",
+            Self::Website => "website",
+            Self::Budget => "budget",
+            Self::Timestamp => "timestamp",
+        }
+    }
+
+    /// What the model is told the column holds.
+    fn description(self) -> &'static str {
+        match self {
+            Self::Email => "the lead's email address",
+            Self::Name => "the lead's name",
+            Self::Company => "the lead's company",
+            Self::Phone => "the lead's phone number",
+            Self::Website => "the company's website",
+            Self::Budget => "the lead's budget",
+            Self::Timestamp => "when the lead was submitted",
+        }
+    }
+
+    /// Words in the headers of columns of this kind, in lowercase.
+    fn keywords(self) -> &'static [&'static str] {
+        match self {
+            Self::Email => &["email", "e-mail", "mail"],
+            Self::Name => &["name", "contact", "person"],
+            Self::Company => &[
+                "company",
+                "organization",
+                "organisation",
+                "business",
+                "employer",
+            ],
+            Self::Phone => &["phone", "mobile", "tel", "cell"],
+            Self::Website => &["website", "domain", "url", "site"],
+            Self::Budget => &["budget", "spend", "price", "amount", "revenue"],
+            Self::Timestamp => &["timestamp", "date", "time", "submitted", "created"],
+        }
+    }
+
+    /// How well `header`, in lowercase, names a column of this kind, from 0 to 1.
+    fn header_score(self, header: &str) -> f64 {
+        if header == self.name() {
+            return 1.0;
+        }
+        let Some(keyword) = self
+            .keywords()
+            .iter()
+            .find(|keyword| header.contains(*keyword))
+        else {
+            return 0.0;
+        };
+
+        // "Name" qualifies other headers too, as in "Company name" or
+        // "Domain name", which aren't the lead's name.
+        let other_kind = Self::ALL
+            .into_iter()
+            .filter(|kind| *kind != self && *kind != Self::Name)
+            .any(|kind| kind.keywords().iter().any(|other| header.contains(other)));
+        match (self, other_kind) {
+            (Self::Name, true) => 0.0,
+            (_, true) => 0.4,
+            _ if *keyword == self.keywords()[0] => 0.9,
+            _ => 0.7,
+        }
+    }
+
+    /// Whether `cell` looks like a value of this kind, or `None` if the kind
+    /// can't be told by its values.
+    fn matches(self, cell: &str) -> Option<bool> {
+        let digits = cell.chars().filter(char::is_ascii_digit).count();
+        let result = match self {
+            Self::Email => cell.contains('@') && cell.contains('.') && !cell.contains(' '),
+            Self::Phone => {
+                (7..=15).contains(&digits)
+                    && cell
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || " +-().".contains(c))
+            }
+            Self::Website => {
+                !cell.contains('@')
+                    && !cell.contains(' ')
+                    && (cell.starts_with("http") || cell.starts_with("www.") || {
+                        cell.rsplit_once('.')
+                            .is_some_and(|(_, tld)| (2..=6).contains(&tld.len()))
+                    })
+            }
+            Self::Budget => {
+                let number: String = cell.chars().filter(|c| !" ,$€£kK".contains(*c)).collect();
+                digits > 0 && number.parse::<f64>().is_ok()
+            }
+            Self::Timestamp => {
+                digits >= 6 && cell.chars().any(|c| "-/.:".contains(c)) && !cell.contains('@')
+            }
+            Self::Name => {
+                let words: Vec<&str> = cell.split_whitespace().collect();
+                (1..=4).contains(&words.len())
+                    && digits == 0
+                    && words
+                        .iter()
+                        .all(|word| word.chars().next().is_some_and(char::is_uppercase))
+            }
+            Self::Company => return None,
+        };
+
+        Some(result)
+    }
+}
+
+/// The columns that might hold `kind`, best first.
+#[derive(Debug)]
+pub struct Guess {
+    pub kind: ColumnKind,
+    pub candidates: Vec<(String, f64)>,
+}
+
+impl Guess {
+    /// The column to take without asking, if one stands out.
+    fn confident(&self) -> Option<&str> {
+        let (best, score) = self.candidates.first()?;
+        let runner_up = self.candidates.get(1).map_or(0.0, |(_, score)| *score);
+
+        (*score >= CONFIDENT_SCORE && score - runner_up >= CONFIDENT_MARGIN)
+            .then_some(best.as_str())
+    }
+}
+
+/// Scores every column of `headers` for every kind, from the header and how
+/// many of the cells of `rows` look like the kind.
+pub fn infer(headers: &[String], rows: &[Vec<Value>]) -> Vec<Guess> {
+    ColumnKind::ALL
+        .into_iter()
+        .map(|kind| {
+            let mut candidates: Vec<(String, f64)> = headers
+                .iter()
+                .enumerate()
+                .filter(|(_, header)| !header.trim().is_empty())
+                .map(|(column, header)| {
+                    let header_score = kind.header_score(&header.trim().to_lowercase());
+                    let cells: Vec<String> = rows
+                        .iter()
+                        .filter_map(|row| row.get(column))
+                        .map(cell_text)
+                        .map(|cell| cell.trim().to_string())
+                        .filter(|cell| !cell.is_empty())
+                        .collect();
+                    let judged: Vec<bool> =
+                        cells.iter().filter_map(|cell| kind.matches(cell)).collect();
+                    let score = if judged.is_empty() {
+                        header_score
+                    } else {
+                        let matching = judged.iter().filter(|matches| **matches).count();
+                        0.6 * header_score + 0.4 * matching as f64 / judged.len() as f64
+                    };
+
+                    (header.clone(), score)
+                })
+                .filter(|(_, score)| *score >= MIN_SCORE)
+                .collect();
+            candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+            Guess { kind, candidates }
+        })
+        .collect()
+}
+
+/// Which column holds what, keyed by kind.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Columns(BTreeMap<ColumnKind, String>);
+
+impl Columns {
+    /// The mapping saved for `sheet` by `gsheets-agent columns`, or else the
+    /// columns that can be inferred from its first rows without asking.
+    /// Columns that are no longer in `headers` are left out.
+    pub async fn for_sheet(
+        sheets: &Spreadsheets,
+        spreadsheet: &str,
+        sheet: &str,
+        headers: &[String],
+    ) -> Self {
+        let saved = store::open().and_then(|store| store.column_mapping(spreadsheet, sheet));
+        let mut columns = match saved {
+            Ok(Some(columns)) => columns,
+            Ok(None) => {
+                let rows = read_sample(sheets, spreadsheet, sheet)
+                    .await
+                    .unwrap_or_default();
+                Self::confident(&infer(headers, rows.get(1..).unwrap_or_default()))
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load the column mapping of `{sheet}`: {e:#}");
+                Self::default()
+            }
+        };
+        columns.0.retain(|_, header| headers.contains(header));
+
+        columns
+    }
+
+    /// The columns `guesses` are sure about, each used for one kind at most.
+    fn confident(guesses: &[Guess]) -> Self {
+        let mut columns = Self::default();
+        for guess in guesses {
+            if let Some(header) = guess.confident()
+                && !columns.0.values().any(|taken| taken == header)
+            {
+                columns.0.insert(guess.kind, header.to_string());
+            }
+        }
+
+        columns
+    }
+
+    /// The header of the column holding `kind`, if it's known.
+    pub fn get(&self, kind: ColumnKind) -> Option<&String> {
+        self.0.get(&kind)
+    }
+
+    /// The header of the column that stands in for `column` when the sheet has
+    /// no column of that name, e.g. `E-mail address` for a CRM field mapped
+    /// from `Email`.
+    pub fn stand_in(&self, column: &str) -> Option<&str> {
+        ColumnKind::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(column.trim()))
+            .and_then(|kind| self.get(kind))
+            .map(String::as_str)
+    }
+
+    /// What the model is told about the columns, or nothing if none are known.
+    pub fn describe(&self) -> String {
+        if self.0.is_empty() {
+            return String::new();
+        }
+        let columns: Vec<String> = self
+            .0
+            .iter()
+            .map(|(kind, header)| format!("`{header}` is {}", kind.description()))
+            .collect();
+
+        format!("Of the columns, {}.", columns.join(", "))
+    }
+}
+
+/// The header row and up to [`SAMPLE_ROWS`] rows below it.
+async fn read_sample(
+    sheets: &Spreadsheets,
+    spreadsheet: &str,
+    sheet: &str,
+) -> anyhow::Result<Vec<Vec<Value>>> {
+    let range = format!("{}!1:{}", quote_sheet(sheet), SAMPLE_ROWS + 1);
+    let values = sheets
+        .read_range(spreadsheet, &range)
+        .await
+        .with_context(|| format!("Failed to read the first rows of `{sheet}`"))?;
+
+    Ok(sheets::rows(values))
+}
+
+/// `columns`: infers what the columns of a sheet hold, asks about the ones
+/// that could be either of several, and saves the mapping for `qualify`.
+pub async fn run(sheets: &Spreadsheets, args: &ColumnsArgs) -> anyhow::Result<()> {
+    let rows = read_sample(sheets, &args.spreadsheet, &args.sheet).await?;
+    let headers: Vec<String> = rows
+        .first()
+        .map(|row| row.iter().map(cell_text).collect())
+        .unwrap_or_default();
+    anyhow::ensure!(
+        !headers.is_empty(),
+        "`{}` has no column headers",
+        args.sheet
+    );
+
+    let guesses = infer(&headers, rows.get(1..).unwrap_or_default());
+    let mut columns = Columns::confident(&guesses);
+    let mut repl = Repl::new()?;
+    for guess in &guesses {
+        if columns.get(guess.kind).is_some() {
+            continue;
+        }
+        let candidates: Vec<&str> = guess
+            .candidates
+            .iter()
+            .map(|(header, _)| header.as_str())
+            .filter(|header| !columns.0.values().any(|taken| taken == header))
+            .collect();
+        if candidates.is_empty() {
+            continue;
+        }
+
+        println!("Which column holds {}?", guess.kind.description());
+        for (i, header) in candidates.iter().enumerate() {
+            println!("  {}) {header}", i + 1);
+        }
+        loop {
+            let Some(answer) = repl.ask("Number, or leave it empty for none:")? else {
+                eprintln!("Stopped without saving the column mapping");
+                return Ok(());
+            };
+            if answer.is_empty() {
+                break;
+            }
+            match answer.parse::<usize>() {
+                Ok(n) if (1..=candidates.len()).contains(&n) => {
+                    columns.0.insert(guess.kind, candidates[n - 1].to_string());
+                    break;
+                }
+                _ => println!("Answer with a number from 1 to {}.", candidates.len()),
+            }
+        }
+    }
+
+    if columns.0.is_empty() {
+        println!("None of the columns could be told apart.");
+    }
+    for (kind, header) in &columns.0 {
+        println!("{}: {header}", kind.name());
+    }
+    if !repl.confirm(&format!(
+        "Use this mapping when qualifying `{}`?",
+        args.sheet
+    )) {
+        return Ok(());
+    }
+    store::open()?.save_column_mapping(&args.spreadsheet, &args.sheet, &columns)?;
+    eprintln!("Saved. Run `columns` again to change it.");
+
+    Ok(())
+}
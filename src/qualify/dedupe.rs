@@ -0,0 +1,107 @@
+//! Recognizing leads that were submitted more than once, before they cost a
+//! model call.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use super::{ColumnKind, Columns, find_column, is_email_header};
+use crate::config::{DedupeConfig, DedupeStrategy};
+
+/// Remembers the leads seen so far and finds earlier submissions of new ones.
+pub struct Deduper<'a> {
+    config: &'a DedupeConfig,
+    email_column: Option<String>,
+    name_column: Option<String>,
+    company_column: Option<String>,
+    /// Row of the first lead seen with each normalized email address.
+    emails: HashMap<String, u64>,
+    /// Normalized name and company of every lead that had both, with its row.
+    people: Vec<(String, String, u64)>,
+}
+
+impl<'a> Deduper<'a> {
+    pub fn new(config: &'a DedupeConfig, headers: &[String], columns: &Columns) -> Self {
+        let configured = |column: &'a Option<String>, kind| column.as_ref().or(columns.get(kind));
+
+        Self {
+            config,
+            email_column: find_column(
+                headers,
+                configured(&config.email_column, ColumnKind::Email),
+                is_email_header,
+            ),
+            name_column: find_column(
+                headers,
+                configured(&config.name_column, ColumnKind::Name),
+                |header| header.contains("name") && !is_company(header),
+            ),
+            company_column: find_column(
+                headers,
+                configured(&config.company_column, ColumnKind::Company),
+                is_company,
+            ),
+            emails: HashMap::new(),
+            people: Vec::new(),
+        }
+    }
+
+    /// The row of an earlier submission of `lead`, or `None` after remembering
+    /// it as the first one.
+    pub fn duplicate_of(&mut self, row: u64, lead: &Map<String, Value>) -> Option<u64> {
+        if self.config.strategy == DedupeStrategy::Off {
+            return None;
+        }
+
+        let email = self.normalized(&self.email_column, lead);
+        if let Some(original) = email.as_ref().and_then(|email| self.emails.get(email)) {
+            return Some(*original);
+        }
+
+        let person = match (
+            self.normalized(&self.name_column, lead),
+            self.normalized(&self.company_column, lead),
+        ) {
+            (Some(name), Some(company)) if self.config.strategy == DedupeStrategy::Fuzzy => {
+                Some((name, company))
+            }
+            _ => None,
+        };
+        if let Some((name, company)) = &person {
+            let similar = |a: &str, b: &str| strsim::jaro_winkler(a, b) >= self.config.similarity;
+            let original = self.people.iter().find(|(other_name, other_company, _)| {
+                similar(name, other_name) && similar(company, other_company)
+            });
+            if let Some((_, _, original)) = original {
+                return Some(*original);
+            }
+        }
+
+        if let Some(email) = email {
+            self.emails.insert(email, row);
+        }
+        if let Some((name, company)) = person {
+            self.people.push((name, company, row));
+        }
+
+        None
+    }
+
+    /// The value of `column` in lowercase with whitespace collapsed, if it has one.
+    fn normalized(&self, column: &Option<String>, lead: &Map<String, Value>) -> Option<String> {
+        let value = lead.get(column.as_ref()?)?.as_str()?;
+        let value = value
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+
+        (!value.is_empty()).then_some(value)
+    }
+}
+
+fn is_company(header: &str) -> bool {
+    ["company", "organization", "organisation", "business"]
+        .iter()
+        .any(|word| header.contains(word))
+}
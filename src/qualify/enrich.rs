@@ -0,0 +1,218 @@
+//! Local checks of a lead's contact details, added to the lead as extra fields
+//! before it's judged.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use hickory_resolver::TokioResolver;
+use phonenumber::{Mode, country};
+use serde_json::{Map, Value};
+
+use super::{ColumnKind, Columns, find_column, is_email_header};
+use crate::{
+    config::EnrichConfig,
+    web::{self, WebClient},
+};
+
+/// Fields added to enriched leads, in the order they're written to the results.
+pub const FIELDS: [&str; 5] = [
+    "email_valid",
+    "email_has_mx",
+    "phone_valid",
+    "phone_e164",
+    "company_summary",
+];
+
+pub struct Enricher<'a> {
+    config: &'a EnrichConfig,
+    email_column: Option<String>,
+    phone_column: Option<String>,
+    website_column: Option<String>,
+    region: Option<country::Id>,
+    resolver: Option<TokioResolver>,
+    /// Whether each email domain looked up so far has MX records, or `None`
+    /// when the lookup failed.
+    mx: HashMap<String, Option<bool>>,
+    web: Option<WebClient>,
+    /// Summary of each company domain looked up so far, or `None` when the
+    /// lookup failed.
+    companies: HashMap<String, Option<String>>,
+}
+
+impl<'a> Enricher<'a> {
+    pub fn new(
+        config: &'a EnrichConfig,
+        headers: &[String],
+        columns: &Columns,
+        web: Option<WebClient>,
+    ) -> anyhow::Result<Self> {
+        let configured = |column: &'a Option<String>, kind| column.as_ref().or(columns.get(kind));
+        let region = config
+            .default_region
+            .as_deref()
+            .map(|region| {
+                region
+                    .to_uppercase()
+                    .parse()
+                    .with_context(|| format!("`{region}` isn't a country code"))
+            })
+            .transpose()?;
+
+        let resolver = if config.enabled && config.check_mx {
+            let resolver = TokioResolver::builder_tokio()
+                .and_then(|builder| builder.build())
+                .context("Failed to set up DNS lookups for the MX check")?;
+            Some(resolver)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            config,
+            email_column: find_column(
+                headers,
+                configured(&config.email_column, ColumnKind::Email),
+                is_email_header,
+            ),
+            phone_column: find_column(
+                headers,
+                configured(&config.phone_column, ColumnKind::Phone),
+                |header| ["phone", "mobile"].iter().any(|word| header.contains(word)),
+            ),
+            website_column: find_column(
+                headers,
+                configured(&config.website_column, ColumnKind::Website),
+                |header| {
+                    ["website", "domain", "url"]
+                        .iter()
+                        .any(|word| header.contains(word))
+                },
+            ),
+            region,
+            resolver,
+            mx: HashMap::new(),
+            web,
+            companies: HashMap::new(),
+        })
+    }
+
+    /// Adds the results of the checks to `lead`. Fields whose column is empty,
+    /// and MX checks that couldn't be made, are left out.
+    pub async fn enrich(&mut self, lead: &mut Map<String, Value>) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let email = text(lead, &self.email_column);
+        if let Some(email) = &email {
+            let domain = valid_email_domain(email);
+            lead.insert("email_valid".to_string(), domain.is_some().into());
+
+            if let Some(domain) = domain
+                && let Some(has_mx) = self.has_mx(&domain).await
+            {
+                lead.insert("email_has_mx".to_string(), has_mx.into());
+            }
+        }
+
+        let domain = text(lead, &self.website_column)
+            .or_else(|| email.clone())
+            .and_then(|text| web::company_domain(&text));
+        if let Some(domain) = domain
+            && let Some(summary) = self.company_summary(&domain).await
+        {
+            lead.insert("company_summary".to_string(), summary.into());
+        }
+
+        let phone = text(lead, &self.phone_column);
+        if let Some(phone) = phone {
+            let number = phonenumber::parse(self.region, &phone)
+                .ok()
+                .filter(|number| number.is_valid());
+            lead.insert("phone_valid".to_string(), number.is_some().into());
+
+            if let Some(number) = number {
+                let e164 = number.format().mode(Mode::E164).to_string();
+                lead.insert("phone_e164".to_string(), e164.into());
+            }
+        }
+    }
+
+    async fn has_mx(&mut self, domain: &str) -> Option<bool> {
+        let resolver = self.resolver.as_ref()?;
+        if let Some(has_mx) = self.mx.get(domain) {
+            return *has_mx;
+        }
+
+        // The trailing dot makes the name fully qualified, skipping search domains.
+        let has_mx = match resolver.mx_lookup(format!("{domain}.")).await {
+            Ok(lookup) => Some(!lookup.answers().is_empty()),
+            Err(e) if e.is_no_records_found() => Some(false),
+            Err(e) => {
+                eprintln!("Failed to look up the MX records of {domain}: {e}");
+                None
+            }
+        };
+        self.mx.insert(domain.to_string(), has_mx);
+
+        has_mx
+    }
+
+    async fn company_summary(&mut self, domain: &str) -> Option<String> {
+        let web = self.web.as_ref()?;
+        if let Some(summary) = self.companies.get(domain) {
+            return summary.clone();
+        }
+
+        let summary = match web.lookup(domain).await {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                eprintln!("{e}");
+                None
+            }
+        };
+        self.companies.insert(domain.to_string(), summary.clone());
+
+        summary
+    }
+}
+
+fn text(lead: &Map<String, Value>, column: &Option<String>) -> Option<String> {
+    let text = match lead.get(column.as_ref()?)? {
+        Value::String(text) => text.trim().to_string(),
+        Value::Number(number) => number.to_string(),
+        _ => return None,
+    };
+
+    (!text.is_empty()).then_some(text)
+}
+
+/// The lowercased domain of `email`, if it's a syntactically valid address.
+///
+/// Only the common dot-atom form is accepted; quoted local parts and IP
+/// literals are rare enough in lead forms to count as mistakes.
+fn valid_email_domain(email: &str) -> Option<String> {
+    let (local, domain) = email.rsplit_once('@')?;
+
+    let local_valid = !local.is_empty()
+        && local.len() <= 64
+        && local.split('.').all(|atom| {
+            !atom.is_empty()
+                && atom
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c))
+        });
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_valid = domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    (local_valid && domain_valid).then(|| domain.to_lowercase())
+}
@@ -0,0 +1,258 @@
+//! `eval`: judging leads whose verdict is known with two prompt profiles or two
+//! models, to tell which one agrees with people more often.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use rig::streaming::StreamingCompletionModel;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::{Columns, Resources, judge, lead};
+use crate::{
+    chat::Agent,
+    cli::EvalArgs,
+    config::QualifyConfig,
+    output::OutputFormat,
+    provider::Model,
+    sheets::{self, cell_text, quote_sheet},
+    usage::{self, Usage},
+};
+
+/// A lead's row number and its cells, keyed by their column's header.
+type Lead = (u64, Map<String, Value>);
+
+/// How the verdicts of one variant compare with the labels.
+#[derive(Debug, Default, Serialize)]
+pub struct Metrics {
+    pub variant: String,
+    /// Leads with a label.
+    pub leads: usize,
+    /// Labeled leads the variant gave a verdict for.
+    pub judged: usize,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub true_negatives: usize,
+    pub false_negatives: usize,
+    pub accuracy: f64,
+    pub precision: f64,
+    pub recall: f64,
+    pub usage: Usage,
+    /// Estimated, in USD; `None` if the model has no known price.
+    pub cost: Option<f64>,
+}
+
+impl Metrics {
+    fn add(&mut self, qualified: bool, label: bool) {
+        self.judged += 1;
+        match (qualified, label) {
+            (true, true) => self.true_positives += 1,
+            (true, false) => self.false_positives += 1,
+            (false, false) => self.true_negatives += 1,
+            (false, true) => self.false_negatives += 1,
+        }
+    }
+
+    /// Works out the ratios from the counts. Each is 0 when there's nothing to
+    /// divide by.
+    fn finish(&mut self) {
+        let ratio = |part: usize, whole: usize| match whole {
+            0 => 0.0,
+            whole => part as f64 / whole as f64,
+        };
+        self.accuracy = ratio(self.true_positives + self.true_negatives, self.judged);
+        self.precision = ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        );
+        self.recall = ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        );
+    }
+}
+
+/// Judges the sample of `args.sheet` with each variant in turn, then prints
+/// how each did against the labels.
+pub async fn run(
+    agent: &mut Agent<Model>,
+    resources: &Resources,
+    args: &EvalArgs,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let sheets = &resources.sheets;
+    let rows = sheets::rows(
+        sheets
+            .read_range(
+                &args.spreadsheet,
+                &format!("{}!1:{}", quote_sheet(&args.sheet), args.sample + 1),
+            )
+            .await
+            .with_context(|| format!("Failed to read the leads in `{}`", args.sheet))?,
+    );
+    let headers: Vec<String> = rows
+        .first()
+        .map(|row| row.iter().map(cell_text).collect())
+        .unwrap_or_default();
+    anyhow::ensure!(
+        headers.contains(&args.label_column),
+        "`{}` has no column `{}`",
+        args.sheet,
+        args.label_column
+    );
+
+    let (leads, labels) = labeled_leads(&headers, &rows[1..], &args.label_column);
+    anyhow::ensure!(
+        !leads.is_empty(),
+        "None of the first {} leads is labeled `yes` or `no` in `{}`",
+        args.sample,
+        args.label_column
+    );
+    eprintln!("Judging {} labeled leads with each variant", leads.len());
+
+    let mut headers = headers;
+    headers.retain(|header| *header != args.label_column);
+    let columns = Columns::for_sheet(sheets, &args.spreadsheet, &args.sheet, &headers).await;
+    let criteria = args.criteria.as_deref().unwrap_or(
+        "the scoring rubric; a lead is qualified when the rubric says so, and its score is the \
+         rubric's score",
+    );
+
+    let variants: Vec<(String, &str)> = match args.models.as_slice() {
+        [] => args
+            .profiles
+            .iter()
+            .map(|profile| (format!("profile {profile}"), profile.as_str()))
+            .collect(),
+        models => models
+            .iter()
+            .map(|model| (format!("model {model}"), model.as_str()))
+            .collect(),
+    };
+    let mut results = Vec::new();
+    for (variant, name) in variants {
+        if args.models.is_empty() {
+            agent.preamble = agent.profiles.render(name)?;
+            agent.profile = Some(name.to_string());
+        } else {
+            agent.model_config.model = Some(name.to_string());
+            agent.model = Model::from_config(&agent.model_config);
+        }
+        eprintln!("Judging with {variant}");
+
+        let mut metrics = evaluate(
+            agent,
+            &resources.config,
+            criteria,
+            &leads,
+            &labels,
+            &columns,
+        )
+        .await;
+        metrics.variant = variant;
+        metrics.cost = usage::price(agent.model_config.model_name(), &resources.pricing)
+            .map(|price| usage::cost(metrics.usage, &price));
+        results.push(metrics);
+    }
+
+    match output {
+        OutputFormat::Json => {
+            for metrics in &results {
+                println!("{}", serde_json::to_string(metrics)?);
+            }
+        }
+        OutputFormat::Text => {
+            let width = results
+                .iter()
+                .map(|metrics| metrics.variant.len())
+                .max()
+                .unwrap_or_default();
+            println!(
+                "{:width$}  accuracy  precision  recall  judged      cost",
+                "variant"
+            );
+            for metrics in &results {
+                let cost = metrics
+                    .cost
+                    .map_or("unknown".to_string(), |cost| format!("${cost:.4}"));
+                println!(
+                    "{:width$}  {:>8.3}  {:>9.3}  {:>6.3}  {:>6}  {cost:>8}",
+                    metrics.variant,
+                    metrics.accuracy,
+                    metrics.precision,
+                    metrics.recall,
+                    format!("{}/{}", metrics.judged, metrics.leads),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Judges `leads` a chunk at a time, as `qualify` does, and compares the
+/// verdicts with `labels`. Leads of chunks that fail count as not judged.
+pub(crate) async fn evaluate<M: StreamingCompletionModel>(
+    agent: &Agent<M>,
+    config: &QualifyConfig,
+    criteria: &str,
+    leads: &[Lead],
+    labels: &BTreeMap<u64, bool>,
+    columns: &Columns,
+) -> Metrics {
+    let mut metrics = Metrics {
+        leads: leads.len(),
+        ..Metrics::default()
+    };
+
+    for chunk in leads.chunks(config.chunk_size as usize) {
+        let (res, took) = judge(agent, config, criteria, &[], chunk, Some(columns), None).await;
+        metrics.usage += took.usage;
+        match res {
+            Ok(judgments) => {
+                for (row, judgment) in judgments {
+                    metrics.add(judgment.qualified, labels[&row]);
+                }
+            }
+            Err((e, _)) => eprintln!("Error: {e}"),
+        }
+    }
+    metrics.finish();
+
+    metrics
+}
+
+/// The leads in `rows`, below the headers, that `label_column` gives a verdict
+/// for, without that column, and the verdicts keyed by row number.
+pub(crate) fn labeled_leads(
+    headers: &[String],
+    rows: &[Vec<Value>],
+    label_column: &str,
+) -> (Vec<Lead>, BTreeMap<u64, bool>) {
+    let mut leads = Vec::new();
+    let mut labels = BTreeMap::new();
+    // The headers are row 1, so the first lead is row 2.
+    for (row, cells) in (2..).zip(rows) {
+        let mut lead = lead(headers, cells);
+        let label = lead
+            .remove(label_column)
+            .and_then(|label| parse_label(&cell_text(&label)));
+        if let Some(label) = label
+            && !lead.is_empty()
+        {
+            labels.insert(row, label);
+            leads.push((row, lead));
+        }
+    }
+
+    (leads, labels)
+}
+
+/// The verdict a label cell gives, or `None` if it doesn't give one.
+fn parse_label(label: &str) -> Option<bool> {
+    match label.trim().to_lowercase().as_str() {
+        "yes" | "y" | "true" | "1" | "qualified" => Some(true),
+        "no" | "n" | "false" | "0" | "not qualified" | "disqualified" => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,194 @@
+//! The first tier of two-tier qualification: a cheap model reads the leads in
+//! full and keeps only the facts the criteria ask about, so the model that
+//! judges them reads a fraction of the tokens.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use rig::{
+    completion::{CompletionModel, CompletionRequestBuilder},
+    message::AssistantContent,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    config::ModelConfig,
+    history,
+    injection::{self, Sanitizer},
+    privacy::Masker,
+    provider::{Model, retry},
+    tools::RateLimiter,
+    usage::Usage,
+};
+
+const PREAMBLE: &str = "You extract facts about sales leads from spreadsheet rows. You never \
+                        judge the leads, and you never make facts up.";
+
+/// Room for the facts of one lead in the answer, in tokens.
+const TOKENS_PER_LEAD: u64 = 100;
+
+pub struct Extractor<'a> {
+    model: Model,
+    config: ModelConfig,
+    /// What keeps personal data in the leads from the model, as for the agent.
+    privacy: Option<Arc<Masker>>,
+    /// What keeps instructions hidden in the leads from it, as for the agent.
+    injection: Option<Arc<Sanitizer>>,
+    /// The agent's, so the calls of both count towards each model's quota.
+    limiter: Option<&'a RateLimiter>,
+}
+
+impl<'a> Extractor<'a> {
+    pub fn new(
+        config: ModelConfig,
+        privacy: Option<Arc<Masker>>,
+        injection: Option<Arc<Sanitizer>>,
+        limiter: Option<&'a RateLimiter>,
+    ) -> Self {
+        Self {
+            model: Model::from_config(&config),
+            config,
+            privacy,
+            injection,
+            limiter,
+        }
+    }
+
+    pub fn model_name(&self) -> &str {
+        self.config.model_name()
+    }
+
+    /// The facts of each of `leads` that bear on `criteria`, in the same order.
+    ///
+    /// A lead the model left out is passed on in full, as are all of them when
+    /// the model fails or its answer can't be read, so the judgment goes on
+    /// either way.
+    pub async fn extract(
+        &self,
+        criteria: &str,
+        leads: &[(u64, Map<String, Value>)],
+    ) -> (Vec<(u64, Map<String, Value>)>, Usage) {
+        if leads.is_empty() {
+            return (Vec::new(), Usage::default());
+        }
+
+        let rows: Vec<Value> = leads
+            .iter()
+            .map(|(row, lead)| {
+                let mut lead = lead.clone();
+                lead.insert("lead_id".to_string(), Value::from(*row));
+                match &self.privacy {
+                    Some(privacy) => privacy.mask_value(&Value::Object(lead)),
+                    None => Value::Object(lead),
+                }
+            })
+            .collect();
+        let prompt = format!(
+            "For each lead below, extract only the facts that bear on these criteria: \
+             {criteria}\n\n\
+             Keep each fact short: numbers as numbers, text in a few words. Leave out what the \
+             row doesn't say. Answer with only a JSON object that maps each lead's `lead_id` to \
+             an object of its facts, keyed by short snake_case names.\n\nLeads:\n{}",
+            self.untrusted(Value::Array(rows))
+        );
+        let preamble = match &self.injection {
+            Some(_) => format!("{PREAMBLE}{}", injection::INSTRUCTIONS),
+            None => PREAMBLE.to_string(),
+        };
+
+        let res = retry::with_retry(&self.config.retry, || {
+            let request = CompletionRequestBuilder::new(self.model.clone(), prompt.clone())
+                .preamble(preamble.clone())
+                .temperature(0.0)
+                .max_tokens(
+                    self.config
+                        .max_tokens
+                        .max(TOKENS_PER_LEAD * leads.len() as u64),
+                )
+                .build();
+
+            async move {
+                if let Some(limiter) = self.limiter {
+                    limiter.acquire(self.model_name()).await;
+                }
+                self.model.completion(request).await
+            }
+        })
+        .await;
+        let prompt_tokens = (preamble.len() + prompt.len()) / 4;
+
+        let answer = match res {
+            Ok(response) => response
+                .choice
+                .into_iter()
+                .filter_map(|content| match content {
+                    AssistantContent::Text(text) => Some(text.text),
+                    AssistantContent::ToolCall(_) => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(e) => {
+                eprintln!(
+                    "{} couldn't extract the facts of the leads, so they're judged in full: {e}",
+                    self.model_name()
+                );
+                return (leads.to_vec(), Usage::default());
+            }
+        };
+        let usage = Usage {
+            prompt_tokens,
+            completion_tokens: history::estimate_completion_tokens(&answer, &[]),
+        };
+
+        let Some(mut facts) = parse(&answer) else {
+            eprintln!(
+                "{} answered with facts that aren't valid JSON, so the leads are judged in full",
+                self.model_name()
+            );
+            return (leads.to_vec(), usage);
+        };
+
+        let extracted = leads
+            .iter()
+            .map(|(row, lead)| match facts.remove(&row.to_string()) {
+                Some(facts) if !facts.is_empty() => match &self.privacy {
+                    Some(privacy) => (*row, unmask(privacy, &facts)),
+                    None => (*row, facts),
+                },
+                _ => (*row, lead.clone()),
+            })
+            .collect();
+
+        (extracted, usage)
+    }
+
+    /// `rows` as the model is sent them: as they are, or with the instructions
+    /// hidden in them cut out and between the delimiters that mark them as
+    /// data.
+    fn untrusted(&self, rows: Value) -> String {
+        let Some(injection) = &self.injection else {
+            return rows.to_string();
+        };
+
+        let mut flagged = Vec::new();
+        let rows = injection.sanitize_value(&rows, &mut flagged);
+        injection::warn("a lead", &flagged);
+        injection::wrap(&rows.to_string())
+    }
+}
+
+/// `facts` with the real values of the placeholders the model was sent.
+fn unmask(privacy: &Masker, facts: &Map<String, Value>) -> Map<String, Value> {
+    facts
+        .iter()
+        .map(|(fact, value)| (fact.clone(), privacy.unmask_value(value)))
+        .collect()
+}
+
+/// The facts in `answer`, keyed by lead ID, ignoring text or a code fence
+/// around the JSON object.
+fn parse(answer: &str) -> Option<BTreeMap<String, Map<String, Value>>> {
+    let start = answer.find('{')?;
+    let end = answer.rfind('}')? + 1;
+
+    serde_json::from_str(answer.get(start..end)?).ok()
+}
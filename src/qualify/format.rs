@@ -0,0 +1,121 @@
+//! Formatting for the results sheets `qualify` creates, so they read as a
+//! report rather than a dump of values.
+
+use anyhow::Context;
+use serde_json::{Value, json};
+
+use crate::sheets::{LOCAL_SPREADSHEET, Spreadsheets};
+
+/// The column of the results holding the score, from 0.
+const SCORE_COLUMN: usize = 3;
+
+/// Bolds and freezes the header row of `sheet`, colors the scores from red
+/// for the lowest to green for the highest, and adds a filter view over its
+/// `width` columns.
+///
+/// Rules and filter views stack up when they're added twice, so this is only
+/// done for sheets that were just created.
+pub async fn results(
+    sheets: &Spreadsheets,
+    spreadsheet: &str,
+    sheet: &str,
+    width: usize,
+) -> anyhow::Result<()> {
+    if spreadsheet == LOCAL_SPREADSHEET {
+        return Ok(());
+    }
+    let sheet_id = sheet_id(sheets, spreadsheet, sheet).await?;
+    let columns = |first: usize, last: usize| {
+        json!({
+            "sheetId": sheet_id,
+            "startColumnIndex": first,
+            "endColumnIndex": last,
+        })
+    };
+    let mut header = columns(0, width);
+    header["startRowIndex"] = json!(0);
+    header["endRowIndex"] = json!(1);
+    let mut scores = columns(SCORE_COLUMN, SCORE_COLUMN + 1);
+    scores["startRowIndex"] = json!(1);
+
+    let requests = vec![
+        json!({
+            "repeatCell": {
+                "range": header,
+                "cell": {
+                    "userEnteredFormat": {
+                        "textFormat": { "bold": true },
+                        "backgroundColor": color(0.93, 0.93, 0.93),
+                    }
+                },
+                "fields": "userEnteredFormat(textFormat,backgroundColor)",
+            }
+        }),
+        json!({
+            "updateSheetProperties": {
+                "properties": {
+                    "sheetId": sheet_id,
+                    "gridProperties": { "frozenRowCount": 1 },
+                },
+                "fields": "gridProperties.frozenRowCount",
+            }
+        }),
+        json!({
+            "addConditionalFormatRule": {
+                "rule": {
+                    "ranges": [scores],
+                    "gradientRule": {
+                        "minpoint": { "type": "MIN", "color": color(0.9, 0.49, 0.45) },
+                        "midpoint": {
+                            "type": "PERCENTILE",
+                            "value": "50",
+                            "color": color(1.0, 0.84, 0.4),
+                        },
+                        "maxpoint": { "type": "MAX", "color": color(0.34, 0.73, 0.54) },
+                    },
+                },
+                "index": 0,
+            }
+        }),
+        json!({
+            "addFilterView": {
+                "filter": {
+                    "title": "Leads",
+                    "range": columns(0, width),
+                }
+            }
+        }),
+    ];
+
+    sheets
+        .format(spreadsheet, requests)
+        .await
+        .with_context(|| format!("Failed to format `{sheet}`"))?;
+
+    Ok(())
+}
+
+/// The numeric ID of `sheet`, which formatting requests take instead of its title.
+pub(super) async fn sheet_id(
+    sheets: &Spreadsheets,
+    spreadsheet: &str,
+    sheet: &str,
+) -> anyhow::Result<u64> {
+    let list = sheets
+        .list_sheets(spreadsheet)
+        .await
+        .context("Failed to list the sheets")?;
+
+    list["sheets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|sheet| &sheet["properties"])
+        .find(|properties| properties["title"] == sheet)
+        .and_then(|properties| properties["sheetId"].as_u64())
+        .with_context(|| format!("`{sheet}` isn't in the spreadsheet"))
+}
+
+pub(super) fn color(red: f64, green: f64, blue: f64) -> Value {
+    json!({ "red": red, "green": green, "blue": blue })
+}
@@ -0,0 +1,105 @@
+//! The structured answer `qualify` expects from the model, validated locally
+//! so that only well-formed results are written to the sheet.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+/// The model's judgement of one lead, identified by its row number.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Judgment {
+    pub lead_id: u64,
+    pub score: f64,
+    pub reason: String,
+    /// The keys of the lead, usually column headers, whose answers the verdict rests on.
+    pub evidence: Vec<String>,
+    /// How sure the model is of the verdict, from 0 to 1.
+    pub confidence: f64,
+    pub qualified: bool,
+}
+
+/// JSON schema of the answer, shown to the model. Matches [`Judgment`].
+pub fn schema() -> Value {
+    json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "lead_id": { "type": "integer", "description": "The lead's `lead_id`" },
+                "score": { "type": "number" },
+                "reason": { "type": "string", "description": "One sentence" },
+                "evidence": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "The keys of the lead whose values justify the verdict, as written in the lead"
+                },
+                "confidence": {
+                    "type": "number",
+                    "minimum": 0,
+                    "maximum": 1,
+                    "description": "How sure you are of the verdict, from 0 to 1"
+                },
+                "qualified": { "type": "boolean" }
+            },
+            "required": ["lead_id", "score", "reason", "evidence", "confidence", "qualified"],
+            "additionalProperties": false
+        }
+    })
+}
+
+/// The judgments in `answer`, keyed by lead ID, if there is exactly one valid
+/// judgment for each of `lead_ids`.
+///
+/// Text or a code fence around the JSON array is ignored. Errors describe the
+/// problem in a way the model can fix when it's asked again.
+pub fn parse(answer: &str, lead_ids: &[u64]) -> Result<BTreeMap<u64, Judgment>, String> {
+    let start = answer.find('[').unwrap_or(0);
+    let end = answer.rfind(']').map_or(answer.len(), |end| end + 1);
+    let json = answer.get(start..end).unwrap_or(answer);
+
+    let judgments: Vec<Judgment> =
+        serde_json::from_str(json).map_err(|e| format!("Your answer isn't valid: {e}"))?;
+
+    let expected: BTreeSet<u64> = lead_ids.iter().copied().collect();
+    let mut by_id = BTreeMap::new();
+
+    for judgment in judgments {
+        let id = judgment.lead_id;
+        if !expected.contains(&id) {
+            return Err(format!("There is no lead with `lead_id` {id}"));
+        }
+        if !judgment.score.is_finite() {
+            return Err(format!("The score of lead {id} isn't a number"));
+        }
+        if !(0.0..=1.0).contains(&judgment.confidence) {
+            return Err(format!("The confidence for lead {id} isn't from 0 to 1"));
+        }
+        if judgment.reason.trim().is_empty() {
+            return Err(format!("The reason for lead {id} is empty"));
+        }
+        if judgment.evidence.iter().all(|key| key.trim().is_empty()) {
+            return Err(format!(
+                "The evidence for lead {id} is empty; list the keys it was judged on"
+            ));
+        }
+        if by_id.insert(id, judgment).is_some() {
+            return Err(format!("Lead {id} was judged more than once"));
+        }
+    }
+
+    let missing: Vec<String> = expected
+        .iter()
+        .filter(|id| !by_id.contains_key(id))
+        .map(u64::to_string)
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Your answer has no judgment for the leads with `lead_id` {}",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(by_id)
+}
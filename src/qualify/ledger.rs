@@ -0,0 +1,82 @@
+//! Remembering which leads have been qualified, so incremental runs skip them
+//! and never write their results twice.
+//!
+//! Leads are identified by a hash of their cells rather than by row number, so
+//! sorting the sheet or inserting rows doesn't make them look new.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::{Map, Value};
+
+use crate::store;
+
+/// The leads qualified into one results sheet.
+pub struct Ledger {
+    key: String,
+    processed: BTreeSet<String>,
+    /// Hashes of the new leads read in this run, by row number. They're taken
+    /// before enrichment adds to the leads.
+    new: BTreeMap<u64, String>,
+    /// Leads qualified in this run, recorded by [`Ledger::save`].
+    added: BTreeSet<String>,
+}
+
+impl Ledger {
+    pub fn load(spreadsheet: &str, sheet: &str, results_sheet: &str) -> anyhow::Result<Self> {
+        let key = format!("{spreadsheet}/{sheet}/{results_sheet}");
+        let processed = store::open()?.ledger(&key)?.into_iter().collect();
+
+        Ok(Self {
+            key,
+            processed,
+            new: BTreeMap::new(),
+            added: BTreeSet::new(),
+        })
+    }
+
+    /// Whether the lead in `row` hasn't been qualified before.
+    pub fn is_new(&mut self, row: u64, lead: &Map<String, Value>) -> bool {
+        let hash = hash(lead);
+        if self.processed.contains(&hash) {
+            return false;
+        }
+
+        self.new.insert(row, hash);
+        true
+    }
+
+    /// The hash of the lead in `row`, if [`Ledger::is_new`] accepted it.
+    pub fn hash_of(&self, row: u64) -> Option<&String> {
+        self.new.get(&row)
+    }
+
+    /// Takes the lead in `row`, with the hash an earlier attempt at this run
+    /// got from [`Ledger::hash_of`], as accepted.
+    pub fn restore(&mut self, row: u64, hash: String) {
+        self.new.insert(row, hash);
+    }
+
+    /// Records that the lead in `row`, which [`Ledger::is_new`] accepted, has
+    /// been qualified.
+    pub fn add(&mut self, row: u64) {
+        if let Some(hash) = self.new.remove(&row) {
+            self.added.insert(hash);
+        }
+    }
+
+    /// Records the leads added in this run.
+    pub fn save(self) -> anyhow::Result<()> {
+        store::open()?.add_to_ledger(&self.key, &self.added)
+    }
+}
+
+/// 64-bit FNV-1a of the lead's cells, which stays the same across builds,
+/// unlike the standard library's hasher.
+fn hash(lead: &Map<String, Value>) -> String {
+    let bytes = Value::Object(lead.clone()).to_string();
+    let hash = bytes.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    format!("{hash:016x}")
+}
@@ -0,0 +1,170 @@
+//! A soft lock on the sheet a run qualifies, so two people running `qualify`
+//! on it at once don't both write its results.
+//!
+//! The lock is a row in a sheet of its own in the spreadsheet, naming who holds
+//! it and until when, so it's seen by every copy of the agent. It expires on
+//! its own if a run is killed before letting go of it.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use super::{create_sheet, writer::Writer};
+use crate::sheets::{self, LOCAL_SPREADSHEET, Spreadsheets, cell_text, quote_sheet};
+
+/// The sheet holding the locks of a spreadsheet: one row per locked sheet,
+/// with the sheet, the holder, the expiry and the run.
+const LOCK_SHEET: &str = "gsheets-agent locks";
+/// How long to wait before reading a lock back, for a run that took it at the
+/// same moment to have written it.
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// A lock held by this run, until [`SheetLock::release`].
+pub struct SheetLock<'a> {
+    writer: Writer<'a>,
+    sheet: String,
+    run: String,
+    holder: String,
+    ttl: Duration,
+    /// The row of the lock in [`LOCK_SHEET`], from 1.
+    row: usize,
+}
+
+impl<'a> SheetLock<'a> {
+    /// Takes the lock on `sheet` for the run `run`, failing if another run holds
+    /// it, unless `force` is set. Imported files aren't shared, so they
+    /// aren't locked.
+    pub async fn acquire(
+        writer: Writer<'a>,
+        sheet: &str,
+        run: &str,
+        ttl: Duration,
+        force: bool,
+    ) -> anyhow::Result<Option<Self>> {
+        let (sheets, spreadsheet) = (writer.sheets, writer.spreadsheet_id);
+        if spreadsheet == LOCAL_SPREADSHEET {
+            return Ok(None);
+        }
+        create_sheet(sheets, spreadsheet, LOCK_SHEET).await?;
+
+        let locks = read_locks(sheets, spreadsheet).await?;
+        let held = locks
+            .iter()
+            .position(|lock| lock.first().is_some_and(|locked| locked == sheet));
+        if let Some(lock) = held.map(|row| &locks[row])
+            && let Some(until) = lock.get(2).and_then(|until| expiry(until))
+            && until > Utc::now()
+            && lock.get(3).is_some_and(|other| other != run)
+        {
+            let holder = lock.get(1).map_or("another run", String::as_str);
+            let until = until.with_timezone(&chrono::Local).format("%H:%M");
+            anyhow::ensure!(
+                force,
+                "`{sheet}` is being qualified by {holder} until {until}, and two runs would \
+                 write its results twice. Wait for it to finish, or pass --force if it was \
+                 stopped without letting go of the sheet."
+            );
+            eprintln!("Taking over the lock {holder} holds on `{sheet}`");
+        }
+
+        let lock = Self {
+            writer,
+            sheet: sheet.to_string(),
+            run: run.to_string(),
+            holder: holder(),
+            ttl,
+            row: held.unwrap_or(locks.len()) + 1,
+        };
+        lock.write().await?;
+
+        // Another run that took the lock just now may have written over it.
+        tokio::time::sleep(SETTLE).await;
+        let locks = read_locks(sheets, spreadsheet).await?;
+        let winner = locks.get(lock.row - 1).and_then(|lock| lock.get(3));
+        anyhow::ensure!(
+            force || winner.is_some_and(|winner| *winner == lock.run),
+            "Another run started qualifying `{sheet}` at the same time; try again once it's done"
+        );
+
+        Ok(Some(lock))
+    }
+
+    /// Pushes the expiry back, for runs that take longer than the lock lasts.
+    pub async fn renew(&self) {
+        if let Err(e) = self.write().await {
+            tracing::warn!("Failed to renew the lock on `{}`: {e:#}", self.sheet);
+        }
+    }
+
+    /// Lets go of the lock, so other runs can qualify the sheet.
+    pub async fn release(self) {
+        let res = self
+            .writer
+            .range(&self.range(), vec![vec![json!(""); 4]])
+            .await;
+        if let Err(e) = res {
+            eprintln!(
+                "Failed to release the lock on `{}`, which expires on its own: {e}",
+                self.sheet
+            );
+        }
+    }
+
+    async fn write(&self) -> anyhow::Result<()> {
+        let until = Utc::now() + self.ttl;
+        let row = vec![
+            json!(self.sheet),
+            json!(self.holder),
+            json!(until.to_rfc3339()),
+            json!(self.run),
+        ];
+
+        self.writer
+            .range(&self.range(), vec![row])
+            .await
+            .with_context(|| format!("Failed to lock `{}`", self.sheet))?;
+
+        Ok(())
+    }
+
+    fn range(&self) -> String {
+        format!("{}!A{}:D{}", quote_sheet(LOCK_SHEET), self.row, self.row)
+    }
+}
+
+/// Every row of [`LOCK_SHEET`], as text.
+async fn read_locks(sheets: &Spreadsheets, spreadsheet: &str) -> anyhow::Result<Vec<Vec<String>>> {
+    let values = sheets
+        .read_range(spreadsheet, &format!("{}!A:D", quote_sheet(LOCK_SHEET)))
+        .await
+        .context("Failed to read the locks")?;
+
+    Ok(sheets::rows(values)
+        .iter()
+        .map(|row| row.iter().map(cell_text).collect())
+        .collect())
+}
+
+fn expiry(until: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(until)
+        .ok()
+        .map(|until| until.with_timezone(&Utc))
+}
+
+/// Who is running the agent, e.g. `ada on laptop`, as far as the environment says.
+fn holder() -> String {
+    let env = |names: &[&str]| names.iter().find_map(|name| std::env::var(name).ok());
+    let user = env(&["USER", "USERNAME"]).unwrap_or_else(|| "someone".to_string());
+    let host = env(&["HOSTNAME", "COMPUTERNAME"]).or_else(|| {
+        std::fs::read_to_string("/etc/hostname")
+            .ok()
+            .map(|host| host.trim().to_string())
+    });
+
+    match host {
+        Some(host) if !host.is_empty() => format!("{user} on {host}"),
+        _ => user,
+    }
+}
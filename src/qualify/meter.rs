@@ -0,0 +1,102 @@
+//! The progress bar of a run in the terminal: how many rows are done, which
+//! ones are being judged, the tokens a row has taken lately and what the
+//! rest should cost at that rate.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use indicatif::ProgressBar;
+
+use crate::render::Terminal;
+
+/// How many of the latest pages the rates per row are taken over, so they
+/// follow changes in how long the leads are.
+const WINDOW: usize = 5;
+
+pub struct Meter {
+    bar: ProgressBar,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The rows being judged, e.g. `41-60`.
+    judging: Option<String>,
+    /// The rows read in each of the latest pages, with the tokens they took
+    /// and what those cost, if the models' prices are known.
+    recent: VecDeque<(u64, usize, Option<f64>)>,
+}
+
+impl Meter {
+    /// A bar over `total` rows, `done` of them done before this run started,
+    /// e.g. by an earlier attempt at it.
+    pub fn new(terminal: &Terminal, total: u64, done: u64) -> Self {
+        Self {
+            bar: terminal.progress_bar(total, done),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Shows that the leads from row `first` to `last` are being judged.
+    pub fn judging(&self, first: u64, last: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.judging = Some(format!("{first}-{last}"));
+        self.show(&state);
+    }
+
+    /// Moves the bar on to `done` rows, after a page of `rows` rows whose
+    /// leads took `tokens`, costing `cost`.
+    pub fn advance(&self, done: u64, rows: u64, tokens: usize, cost: Option<f64>) {
+        // Rows are counted before the pages are read, and trailing blank ones
+        // aren't.
+        if done > self.bar.length().unwrap_or_default() {
+            self.bar.set_length(done);
+        }
+        self.bar.set_position(done);
+
+        let mut state = self.state.lock().unwrap();
+        state.recent.push_back((rows, tokens, cost));
+        if state.recent.len() > WINDOW {
+            state.recent.pop_front();
+        }
+        self.show(&state);
+    }
+
+    /// Prints `line` above the bar, rather than over it.
+    pub fn println(&self, line: impl AsRef<str>) {
+        self.bar.println(line);
+    }
+
+    fn show(&self, state: &State) {
+        let mut parts = Vec::new();
+        if let Some(judging) = &state.judging {
+            parts.push(format!("judging {judging}"));
+        }
+
+        let rows: u64 = state.recent.iter().map(|(rows, _, _)| rows).sum();
+        let tokens: usize = state.recent.iter().map(|(_, tokens, _)| tokens).sum();
+        if let Some(per_row) = (tokens as u64).checked_div(rows) {
+            parts.push(format!("{per_row} tokens/row"));
+
+            let cost: Option<f64> = state.recent.iter().map(|(_, _, cost)| *cost).sum();
+            let left = self
+                .bar
+                .length()
+                .unwrap_or_default()
+                .saturating_sub(self.bar.position());
+            if let Some(cost) = cost {
+                parts.push(format!("~${:.2} to go", cost / rows as f64 * left as f64));
+            }
+        }
+
+        self.bar.set_message(match parts.is_empty() {
+            true => String::new(),
+            false => format!("· {}", parts.join(", ")),
+        });
+    }
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}
@@ -0,0 +1,1204 @@
+//! Lead qualification as a pipeline: the agent reads the rows and writes the
+//! results itself, and the model only judges one chunk of leads at a time.
+
+pub(crate) mod checkpoint;
+mod clean;
+mod columns;
+mod dedupe;
+mod enrich;
+pub(crate) mod eval;
+mod extract;
+mod format;
+mod judgment;
+mod ledger;
+mod lock;
+mod meter;
+mod overview;
+mod report;
+mod writer;
+
+use std::{
+    collections::BTreeMap,
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use futures::{StreamExt, stream::FuturesOrdered};
+use rig::{message::Message, streaming::StreamingCompletionModel, tool::Tool};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::{
+    audit::Origin,
+    batch::{EXIT_ERROR, EXIT_LIMIT_EXCEEDED},
+    chat::Agent,
+    cli::{QualifyArgs, RunsArgs},
+    config::{CrmConfig, NotifyConfig, PriceConfig, QualifyConfig},
+    crm::{self, QualifiedLead},
+    error::Error,
+    injection,
+    jobs::{Jobs, Progress},
+    mcp::McpServers,
+    metrics::Metrics,
+    notify::{self, Notification, TopLead},
+    output::OutputFormat,
+    provider::Model,
+    render::Terminal,
+    session::unix_now,
+    sheets::{
+        self, AppendRows, CreateNamedRange, CreateSheet, NumberedRow, PageReader, SheetsError,
+        Spreadsheets, WriteRange, cell_text, column_name, quote_sheet,
+    },
+    store,
+    transcript::Transcript,
+    usage::{self, Usage},
+    web::WebClient,
+};
+
+pub use self::{
+    columns::{Columns, run as map_columns},
+    eval::run as eval,
+};
+
+use self::{
+    checkpoint::{Checkpoint, Header, Partial},
+    clean::Cleaner,
+    columns::ColumnKind,
+    dedupe::Deduper,
+    enrich::Enricher,
+    extract::Extractor,
+    judgment::Judgment,
+    ledger::Ledger,
+    lock::SheetLock,
+    meter::Meter,
+    report::Report,
+    writer::Writer,
+};
+
+/// What `--output json` prints once the results are written.
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    spreadsheet: &'a str,
+    results_sheet: &'a str,
+    leads: usize,
+    qualified: usize,
+    /// Leads left without a verdict, because their chunk failed.
+    failed: usize,
+    /// Leads written to the review sheet, because the model wasn't sure of them.
+    needs_review: usize,
+    /// Rows skipped because they repeat an earlier lead.
+    duplicates: usize,
+    usage: Usage,
+}
+
+/// What qualification runs share besides the agent, owned so that runs in the
+/// background can hold on to it.
+pub struct Resources {
+    pub sheets: Spreadsheets,
+    pub config: QualifyConfig,
+    pub crm: CrmConfig,
+    pub notify: NotifyConfig,
+    /// Print what would be written to the spreadsheet and sent to the CRM,
+    /// instead of writing and sending it.
+    pub dry_run: bool,
+    pub web: Option<WebClient>,
+    pub pricing: BTreeMap<String, PriceConfig>,
+    /// For the resources the servers publish, which the REPL can attach.
+    pub mcp: McpServers,
+}
+
+/// How a run went, for the callers that carry on from where it stopped.
+pub struct Outcome {
+    pub exit_code: ExitCode,
+    /// The last row that was read with cells in it, if there were any.
+    pub last_row: Option<u64>,
+}
+
+/// Everything `qualify` needs besides its command line arguments.
+pub struct Pipeline<'a, M = Model> {
+    pub agent: &'a Agent<M>,
+    pub resources: &'a Resources,
+    pub output: OutputFormat,
+    /// Where to report how many rows are done, for runs in the background.
+    pub progress: Option<Progress>,
+    /// Where to show a progress bar, for runs in the foreground of a terminal.
+    pub bar: Option<Terminal>,
+}
+
+/// Qualifies every lead in `args.sheet`, then writes one row per lead to the
+/// results sheet, creating it if needed.
+///
+/// With `--from-row`, only the leads from that row on are qualified, and their
+/// results are appended to the results sheet instead of replacing it. Finding
+/// no leads there isn't an error. The same goes for incremental runs, which
+/// skip the leads they've written results for before.
+///
+/// A chunk that fails doesn't stop the others; its leads are written with the
+/// error instead of a verdict, and the exit status reports the failure.
+///
+/// The run is recorded in the database, for `gsheets-agent runs`, and how far
+/// it got is checkpointed after every page, so that `--resume-run` can carry
+/// on with it if it dies.
+///
+/// What the results and other sheets held before is kept, so `/undo run`
+/// writes it back; the lock and the named range aren't undone.
+///
+/// The run is refused in read-only sessions, and if the user's role may not
+/// write, append or create sheets. In dry runs, the rows it would write are
+/// printed instead, and the sheet isn't locked.
+pub async fn run<M: StreamingCompletionModel>(
+    pipeline: Pipeline<'_, M>,
+    args: &QualifyArgs,
+) -> anyhow::Result<Outcome> {
+    let mut writes = vec![WriteRange::NAME, AppendRows::NAME, CreateSheet::NAME];
+    if args.named_range.is_some() {
+        writes.push(CreateNamedRange::NAME);
+    }
+    pipeline.agent.tools.check_writes(&writes)?;
+
+    let (mut checkpoint, header, done) = match &args.resume_run {
+        Some(run) => {
+            let (checkpoint, header, done) = Checkpoint::resume(run)?;
+            anyhow::ensure!(
+                (&header.spreadsheet, &header.sheet, &header.results_sheet)
+                    == (&args.spreadsheet, &args.sheet, &results_sheet(args)),
+                "The run `{run}` qualified `{}` of {} into `{}`; resume it with the same \
+                 --spreadsheet, --sheet and --results-sheet",
+                header.sheet,
+                header.spreadsheet,
+                header.results_sheet
+            );
+            match done.last_row {
+                0 => eprintln!("Resuming the run `{run}` from its first row"),
+                row => eprintln!("Resuming the run `{run}` after row {row}"),
+            }
+            (Some(checkpoint), header, done)
+        }
+        None => {
+            let header = Header {
+                run: uuid::Uuid::new_v4().to_string(),
+                spreadsheet: args.spreadsheet.clone(),
+                sheet: args.sheet.clone(),
+                results_sheet: results_sheet(args),
+                started_at: unix_now(),
+            };
+            // Without a checkpoint, the run can't be resumed, but it still runs.
+            let checkpoint = Checkpoint::create(&header).unwrap_or_else(|e| {
+                eprintln!("Failed to checkpoint the run: {e:#}");
+                None
+            });
+            (checkpoint, header, Partial::default())
+        }
+    };
+    let mut record = store::Run {
+        id: header.run,
+        spreadsheet: header.spreadsheet,
+        sheet: header.sheet,
+        results_sheet: header.results_sheet,
+        started_at: header.started_at,
+        finished_at: None,
+        leads: 0,
+        qualified: 0,
+        failed: 0,
+        duplicates: 0,
+        error: None,
+        usage: BTreeMap::new(),
+        metrics: None,
+    };
+    save_run(&record);
+    let started = Instant::now();
+    let agent = pipeline.agent;
+    // The run's writes are undone together.
+    agent.tools.begin_run();
+    let prompt = format!("qualify run {}", record.id);
+    let writer = Writer::new(
+        &pipeline.resources.sheets,
+        &args.spreadsheet,
+        &agent.tools,
+        Origin {
+            prompt: &prompt,
+            model: agent.model_config.model_name(),
+        },
+    );
+
+    let lock = match pipeline.resources.config.lock_minutes {
+        0 => Ok(None),
+        _ if pipeline.resources.dry_run => Ok(None),
+        minutes => {
+            SheetLock::acquire(
+                writer,
+                &args.sheet,
+                &record.id,
+                Duration::from_secs(minutes * 60),
+                args.force,
+            )
+            .await
+        }
+    };
+    let res = match lock {
+        Ok(lock) => {
+            let res = qualify(
+                pipeline,
+                args,
+                &writer,
+                &mut record,
+                lock.as_ref(),
+                checkpoint.as_mut(),
+                done,
+            )
+            .await;
+            if let Some(lock) = lock {
+                lock.release().await;
+            }
+            res
+        }
+        Err(e) => Err(e),
+    };
+    record.finished_at = Some(unix_now());
+    if let Some(metrics) = &mut record.metrics {
+        metrics.duration_ms = started.elapsed().as_millis() as u64;
+        eprintln!("{}", metrics.summary());
+        agent.tools.audit_metrics("run", &record.id, metrics);
+    }
+    if res.is_ok()
+        && let Some(checkpoint) = checkpoint
+        && let Err(e) = checkpoint.finish()
+    {
+        eprintln!("Failed to delete the checkpoint of the run: {e:#}");
+    }
+    if let Err(e) = &res {
+        record.error = Some(format!("{e:#}"));
+        if checkpoint::exists(&record.id) {
+            eprintln!(
+                "Carry on with the run where it stopped by passing --resume-run {}",
+                record.id
+            );
+        }
+    }
+    save_run(&record);
+
+    res
+}
+
+/// Prints the runs recorded in the database, one per line.
+pub fn list_runs(args: &RunsArgs, output: OutputFormat) -> anyhow::Result<()> {
+    let runs = store::open()?.runs(args.spreadsheet.as_deref(), args.limit)?;
+
+    for run in runs {
+        match output {
+            OutputFormat::Json => println!("{}", serde_json::to_string(&run)?),
+            OutputFormat::Text => {
+                let started = chrono::DateTime::from_timestamp(run.started_at as i64, 0)
+                    .map(|at| at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"))
+                    .map(|at| at.to_string())
+                    .unwrap_or_default();
+                let outcome = match (&run.error, run.finished_at) {
+                    (Some(error), _) if checkpoint::exists(&run.id) => {
+                        format!("failed: {error} (resume it with --resume-run {})", run.id)
+                    }
+                    (Some(error), _) => format!("failed: {error}"),
+                    (None, None) if checkpoint::exists(&run.id) => format!(
+                        "not finished (resume it with --resume-run {} if it was stopped)",
+                        run.id
+                    ),
+                    (None, None) => "running".to_string(),
+                    (None, Some(_)) => format!(
+                        "{} of {} leads qualified, {} without a verdict",
+                        run.qualified, run.leads, run.failed
+                    ),
+                };
+                println!(
+                    "{started} {} `{}` -> `{}`: {outcome}",
+                    run.spreadsheet, run.sheet, run.results_sheet
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Failing to record a run doesn't undo it, so it's only reported.
+fn save_run(record: &store::Run) {
+    if let Err(e) = store::open().and_then(|mut store| store.save_run(record)) {
+        eprintln!("Failed to record the run: {e:#}");
+    }
+}
+
+fn results_sheet(args: &QualifyArgs) -> String {
+    args.results_sheet
+        .clone()
+        .unwrap_or_else(|| format!("{} results", args.sheet))
+}
+
+async fn qualify<M: StreamingCompletionModel>(
+    pipeline: Pipeline<'_, M>,
+    args: &QualifyArgs,
+    writer: &Writer<'_>,
+    record: &mut store::Run,
+    lock: Option<&SheetLock<'_>>,
+    mut checkpoint: Option<&mut Checkpoint>,
+    mut done: Partial,
+) -> anyhow::Result<Outcome> {
+    let Pipeline {
+        agent,
+        resources,
+        output,
+        progress,
+        bar,
+    } = pipeline;
+    let Resources {
+        sheets,
+        config,
+        crm,
+        notify,
+        dry_run,
+        web,
+        pricing,
+        mcp: _,
+    } = resources;
+    let dry_run = *dry_run;
+    // Each run gets a transcript of its own, next to those of chat sessions.
+    let transcript = agent.transcript(&format!("run-{}", record.id));
+    let criteria = args.criteria.as_deref().unwrap_or(
+        "the scoring rubric; a lead is qualified when the rubric says so, and its score is the \
+         rubric's score",
+    );
+    let chunk_size = args.chunk_size.unwrap_or(config.chunk_size);
+    let overlap = args.overlap.unwrap_or(config.overlap);
+    anyhow::ensure!(
+        overlap < chunk_size,
+        "The overlap must be smaller than the chunk size"
+    );
+    let results_sheet = results_sheet(args);
+
+    let headers: Vec<String> = sheets::rows(
+        sheets
+            .read_range(
+                &args.spreadsheet,
+                &format!("{}!1:1", quote_sheet(&args.sheet)),
+            )
+            .await
+            .with_context(|| format!("Failed to read the headers of `{}`", args.sheet))?,
+    )
+    .into_iter()
+    .next()
+    .unwrap_or_default()
+    .iter()
+    .map(cell_text)
+    .collect();
+    anyhow::ensure!(
+        !headers.is_empty(),
+        "`{}` has no column headers",
+        args.sheet
+    );
+
+    let columns = Columns::for_sheet(sheets, &args.spreadsheet, &args.sheet, &headers).await;
+    let cleaner = Cleaner::new(&config.clean, &headers, &columns);
+    let cleaned_sheet = args
+        .cleaned_sheet
+        .as_ref()
+        .or(config.clean.cleaned_sheet.as_ref());
+    let mut deduper = Deduper::new(&config.dedupe, &headers, &columns);
+    let mut ledger = if args.incremental || config.incremental {
+        Some(Ledger::load(
+            &args.spreadsheet,
+            &args.sheet,
+            &results_sheet,
+        )?)
+    } else {
+        None
+    };
+    let mut enricher = Enricher::new(&config.enrich, &headers, &columns, web.clone())?;
+    let extractor = config.extraction_model.as_ref().map(|model| {
+        Extractor::new(
+            agent.model_config.other(model),
+            agent.privacy.clone(),
+            agent.injection.clone(),
+            agent.limiter.as_ref(),
+        )
+    });
+    let mut exit_code = ExitCode::SUCCESS;
+
+    // What an earlier attempt at this run got through, if it's being resumed:
+    // the leads it read are remembered again, so that later rows repeating
+    // them are still skipped.
+    for (row, lead) in &done.leads {
+        deduper.duplicate_of(*row, lead);
+    }
+    if let Some(ledger) = &mut ledger {
+        for (row, hash) in &done.hashes {
+            ledger.restore(*row, hash.clone());
+        }
+    }
+    if done.verdicts.values().any(Result::is_err) {
+        exit_code = ExitCode::from(EXIT_ERROR);
+    }
+
+    // The headers are row 1, so the first lead is row 2.
+    let first_row = args.from_row.unwrap_or(2);
+    let mut last_row = (done.last_row > 0).then_some(done.last_row);
+
+    // Only counted for the progress, since the pages are read one at a time.
+    let total = match progress.is_some() || bar.is_some() {
+        true => count_leads(sheets, &args.spreadsheet, &args.sheet)
+            .await
+            .saturating_sub(first_row - 2),
+        false => 0,
+    };
+    let meter = bar.map(|terminal| {
+        Meter::new(
+            &terminal,
+            total,
+            done.last_row.saturating_sub(first_row - 1),
+        )
+    });
+    // Lines printed while the bar is shown go above it.
+    let note = |line: String| match &meter {
+        Some(meter) => meter.println(line),
+        None => eprintln!("{line}"),
+    };
+    // What the tokens of a page cost, if the prices of the models are known.
+    let cost = |page: &Partial| {
+        let cost =
+            |usage, model| usage::price(model, pricing).map(|price| usage::cost(usage, &price));
+        let judging = cost(page.usage, agent.model_config.model_name());
+        match &extractor {
+            Some(extractor) => judging
+                .zip(cost(page.facts_usage, extractor.model_name()))
+                .map(|(a, b)| a + b),
+            None => judging,
+        }
+    };
+
+    let concurrency = args.concurrency.unwrap_or(config.concurrency) as usize;
+    // Borrowed by the chunks being judged.
+    let (extractor, columns, transcript) = (&extractor, &columns, transcript.as_ref());
+    let mut judging = FuturesOrdered::new();
+    // Takes in the verdicts on a page, once the pages before it are in.
+    let mut take = async |Judged { mut page, res }: Judged| {
+        let mut res = res.map_err(|(e, code)| {
+            note(format!("Error: {e}"));
+            exit_code = ExitCode::from(code);
+            e
+        });
+        for (row, _) in &page.leads {
+            let verdict = match &mut res {
+                Ok(res) => res
+                    .remove(row)
+                    .ok_or_else(|| "The model didn't judge this lead".to_string()),
+                Err(e) => Err(e.clone()),
+            };
+            page.verdicts.insert(*row, verdict);
+        }
+        if let Some(progress) = &progress {
+            progress.update(page.last_row + 1 - first_row, total);
+        }
+        if let Some(meter) = &meter {
+            let rows = page.last_row - done.last_row.max(first_row - 1);
+            let tokens = page.metrics.usage.prompt_tokens + page.metrics.usage.completion_tokens;
+            meter.advance(page.last_row + 1 - first_row, rows, tokens, cost(&page));
+        }
+        if let Some(lock) = lock {
+            lock.renew().await;
+        }
+        if let Some(checkpoint) = checkpoint.as_deref_mut()
+            && let Err(e) = checkpoint.save(&page)
+        {
+            tracing::warn!("Failed to checkpoint the run: {e:#}");
+        }
+        done.merge(page);
+    };
+
+    let mut pages = PageReader::new(
+        sheets,
+        &args.spreadsheet,
+        &args.sheet,
+        last_row.map_or(first_row, |row| row + 1),
+        chunk_size,
+        overlap,
+    );
+    while let Some(page) = pages
+        .next_page()
+        .await
+        .with_context(|| format!("Failed to read the leads in `{}`", args.sheet))?
+    {
+        let mut results = Partial {
+            last_row: page.last_row(),
+            ..Partial::default()
+        };
+        last_row = Some(results.last_row);
+        let (context, rows) = page.numbered();
+        let mut context = to_leads(&headers, context);
+        let mut chunk = to_leads(&headers, rows);
+        for (_, lead) in context.iter_mut().chain(&mut chunk) {
+            cleaner.clean(lead);
+        }
+        if cleaned_sheet.is_some() {
+            for (row, lead) in &chunk {
+                let cells = headers
+                    .iter()
+                    .map(|header| lead.get(header).cloned().unwrap_or_else(|| json!("")))
+                    .collect();
+                results.cleaned.insert(*row, cells);
+            }
+        }
+        let mut chunk: Vec<_> = chunk
+            .into_iter()
+            .filter(|(row, lead)| match deduper.duplicate_of(*row, lead) {
+                Some(original) => {
+                    note(format!("Skipping row {row}, a duplicate of row {original}"));
+                    results.duplicates += 1;
+                    false
+                }
+                None => true,
+            })
+            .filter(|(row, lead)| {
+                let seen = ledger
+                    .as_mut()
+                    .is_some_and(|ledger| !ledger.is_new(*row, lead));
+                results.processed += usize::from(seen);
+                !seen
+            })
+            .collect();
+        if let Some(ledger) = &ledger {
+            results.hashes = chunk
+                .iter()
+                .filter_map(|(row, _)| Some((*row, ledger.hash_of(*row)?.clone())))
+                .collect();
+        }
+        for (_, lead) in context.iter_mut().chain(&mut chunk) {
+            enricher.enrich(lead).await;
+        }
+        if let (Some((first, _)), Some((last, _))) = (chunk.first(), chunk.last()) {
+            match &meter {
+                Some(meter) => meter.judging(*first, *last),
+                None => eprintln!("Qualifying the leads in rows {first}-{last}"),
+            }
+        }
+        let mut page = results;
+
+        judging.push_back(async move {
+            // Pages without new leads are still taken in, for the checkpoint.
+            if chunk.is_empty() {
+                return Judged {
+                    page,
+                    res: Ok(BTreeMap::new()),
+                };
+            }
+            let (res, metrics) = match extractor {
+                Some(extractor) => {
+                    let (facts, facts_usage) = extractor
+                        .extract(criteria, &[context.as_slice(), &chunk].concat())
+                        .await;
+                    page.facts_usage = facts_usage;
+                    page.metrics.model_calls += 1;
+                    page.metrics.usage += facts_usage;
+                    let (context, facts) = facts.split_at(context.len());
+                    judge(agent, config, criteria, context, facts, None, transcript).await
+                }
+                None => {
+                    judge(
+                        agent,
+                        config,
+                        criteria,
+                        &context,
+                        &chunk,
+                        Some(columns),
+                        transcript,
+                    )
+                    .await
+                }
+            };
+            page.usage = metrics.usage;
+            page.metrics += metrics;
+            page.leads = chunk;
+            Judged { page, res }
+        });
+        // Chunks are judged `concurrency` at a time, and taken in order.
+        while judging.len() >= concurrency
+            && let Some(judged) = judging.next().await
+        {
+            take(judged).await;
+        }
+    }
+    while let Some(judged) = judging.next().await {
+        take(judged).await;
+    }
+    drop(meter);
+    let Partial {
+        leads,
+        verdicts,
+        cleaned,
+        usage,
+        facts_usage: extraction_usage,
+        metrics,
+        duplicates,
+        processed,
+        ..
+    } = done;
+    // Kept even if writing the results fails.
+    record.metrics = Some(metrics);
+    if processed > 0 {
+        eprintln!("Skipped {processed} leads that were qualified before");
+    }
+    if leads.is_empty() && (args.from_row.is_some() || ledger.is_some()) {
+        eprintln!("There are no new leads in `{}`", args.sheet);
+        return Ok(Outcome {
+            exit_code,
+            last_row,
+        });
+    }
+    anyhow::ensure!(
+        !leads.is_empty(),
+        "`{}` has no leads to qualify",
+        args.sheet
+    );
+
+    let first_column = headers.first().cloned().unwrap_or_default();
+    let mut results = vec![vec![
+        json!("Row"),
+        json!(first_column),
+        json!("Qualified"),
+        json!("Score"),
+        json!("Reason"),
+        json!("Evidence"),
+        json!("Confidence"),
+    ]];
+    if config.enrich.enabled {
+        results[0].extend(enrich::FIELDS.map(Value::from));
+    }
+    // The leads the model wasn't sure enough about go to a sheet of their own,
+    // under the same headers, rather than being taken as qualified or not.
+    let unsure = |verdict: &Judgment| verdict.confidence < config.min_confidence;
+    let mut review = vec![results[0].clone()];
+    let review_sheet = format!("{} needs review", args.sheet);
+    let mut retried = 0;
+    for (row, lead) in &leads {
+        let id = lead.get(&first_column).cloned().unwrap_or_default();
+        let mut result = match &verdicts[row] {
+            Ok(verdict) => vec![
+                json!(row),
+                id,
+                json!(if verdict.qualified { "yes" } else { "no" }),
+                json!(verdict.score),
+                json!(verdict.reason),
+                json!(evidence(&headers, *row, &verdict.evidence)),
+                json!(verdict.confidence),
+            ],
+            Err(e) => vec![
+                json!(row),
+                id,
+                json!(""),
+                json!(""),
+                json!(e),
+                json!(""),
+                json!(""),
+            ],
+        };
+        if config.enrich.enabled {
+            result.extend(
+                enrich::FIELDS.map(|field| lead.get(field).cloned().unwrap_or_else(|| json!(""))),
+            );
+        }
+        match &verdicts[row] {
+            Ok(verdict) if unsure(verdict) => review.push(result),
+            // The ledger has the lead tried again by the next run, which
+            // writes its row then.
+            Err(_) if ledger.is_some() => retried += 1,
+            _ => results.push(result),
+        }
+    }
+    if retried > 0 {
+        eprintln!("{retried} leads without a verdict are left out, and tried again next run");
+    }
+
+    if let Some(path) = &args.export_csv {
+        sheets::export_csv(path, &results)?;
+        eprintln!("Exported the results to {}", path.display());
+    }
+
+    let append = args.from_row.is_some() || ledger.is_some();
+    let width = results.first().map_or(0, Vec::len);
+    if dry_run {
+        preview(&results_sheet, &results);
+    } else {
+        write_results(
+            writer,
+            &results_sheet,
+            results,
+            append,
+            config.format_results,
+        )
+        .await?;
+    }
+    if let Some(name) = &args.named_range {
+        // Whole columns, so the range takes in the rows later runs append.
+        let range = format!(
+            "{}!A:{}",
+            quote_sheet(&results_sheet),
+            sheets::column_name(width.max(1) - 1)
+        );
+        if dry_run {
+            eprintln!("[dry run] The results would be named `{name}` ({range})");
+        } else {
+            match writer.name(name, &range).await {
+                Ok(_) => eprintln!("Named the results `{name}` ({range})"),
+                // The results are written either way, so a failure is only reported.
+                Err(e) => eprintln!("Failed to name the results `{name}`: {e}"),
+            }
+        }
+    }
+    let needs_review = review.len() - 1;
+    if needs_review > 0 {
+        eprintln!("{needs_review} leads need a review, in `{review_sheet}`");
+        if dry_run {
+            preview(&review_sheet, &review);
+        } else {
+            write_results(writer, &review_sheet, review, append, config.format_results).await?;
+        }
+    }
+
+    if (args.summary || config.summary) && !dry_run {
+        let scores: Vec<f64> = verdicts
+            .values()
+            .filter_map(|verdict| verdict.as_ref().ok())
+            .map(|verdict| verdict.score)
+            .collect();
+        let summary_sheet = overview::write(writer, &results_sheet, &scores)
+            .await
+            .with_context(|| format!("Failed to summarize `{results_sheet}`"))?;
+        if let Some(summary_sheet) = summary_sheet {
+            eprintln!("Wrote a summary of the results to `{summary_sheet}`");
+        }
+    }
+
+    match cleaned_sheet {
+        Some(cleaned_sheet) if dry_run => {
+            preview(cleaned_sheet, &cleaned.into_values().collect::<Vec<_>>());
+        }
+        Some(cleaned_sheet) => {
+            write_cleaned(writer, cleaned_sheet, &headers, cleaned).await?;
+            eprintln!("Wrote the cleaned leads to `{cleaned_sheet}`");
+        }
+        None => {}
+    }
+
+    if let Some(mut ledger) = ledger
+        && !dry_run
+    {
+        // Leads without a verdict are tried again on the next run.
+        for (row, verdict) in &verdicts {
+            if verdict.is_ok() {
+                ledger.add(*row);
+            }
+        }
+        ledger.save()?;
+    }
+
+    if let Some(target) = args.push_to {
+        let qualified: Vec<QualifiedLead> = leads
+            .iter()
+            .filter_map(|(row, lead)| match &verdicts[row] {
+                Ok(judgment) if judgment.qualified && !unsure(judgment) => Some(QualifiedLead {
+                    fields: lead,
+                    score: judgment.score,
+                    reason: &judgment.reason,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let pushed = crm::push(target, crm, &qualified, columns, dry_run)
+            .await
+            .with_context(|| format!("Failed to push the qualified leads to {}", target.name()))?;
+        if !dry_run {
+            eprintln!("Pushed {pushed} qualified leads to {}", target.name());
+        }
+    }
+
+    let summary = Summary {
+        spreadsheet: &args.spreadsheet,
+        results_sheet: &results_sheet,
+        leads: leads.len(),
+        qualified: verdicts
+            .values()
+            .filter(|verdict| {
+                verdict
+                    .as_ref()
+                    .is_ok_and(|verdict| verdict.qualified && !unsure(verdict))
+            })
+            .count(),
+        failed: verdicts.values().filter(|verdict| verdict.is_err()).count(),
+        needs_review,
+        duplicates,
+        usage: {
+            let mut total = usage;
+            total += extraction_usage;
+            total
+        },
+    };
+    record.leads = summary.leads;
+    record.qualified = summary.qualified;
+    record.failed = summary.failed;
+    record.duplicates = duplicates;
+    record.usage = BTreeMap::from([(agent.model_config.model_name().to_string(), usage)]);
+    if let Some(extractor) = &extractor {
+        *record
+            .usage
+            .entry(extractor.model_name().to_string())
+            .or_default() += extraction_usage;
+    }
+
+    let top_leads: Vec<TopLead> = leads
+        .iter()
+        .filter_map(|(row, lead)| match &verdicts[row] {
+            Ok(judgment) if judgment.qualified && !unsure(judgment) => Some(TopLead {
+                row: *row,
+                lead: lead.get(&first_column).cloned().unwrap_or_default(),
+                score: judgment.score,
+                reason: judgment.reason.clone(),
+            }),
+            _ => None,
+        })
+        .collect();
+    if let Some(path) = &args.report {
+        let report = Report::new(record, needs_review, criteria, &top_leads, pricing);
+        report
+            .write(path)
+            .with_context(|| format!("Failed to write the report to {}", path.display()))?;
+        eprintln!("Wrote a report of the run to {}", path.display());
+    }
+
+    let notification = Notification::new(
+        &args.spreadsheet,
+        &results_sheet,
+        summary.qualified,
+        summary.leads - summary.qualified - summary.failed - needs_review,
+        summary.failed,
+        top_leads.clone(),
+        notify.top_leads,
+    );
+    notify::send(notify, &notification).await;
+    let report = (notify.email.smtp_host.is_some() && !notify.email.to.is_empty())
+        .then(|| Report::new(record, needs_review, criteria, &top_leads, pricing).html());
+    notify::email(&notify.email, &notification, report).await;
+
+    let written = match dry_run {
+        true => "would be written to",
+        false => "written to",
+    };
+    match output {
+        OutputFormat::Text => println!(
+            "{} of {} leads qualified ({} without a verdict, {} to review, {} duplicates \
+             skipped); results {written} `{}`",
+            summary.qualified,
+            summary.leads,
+            summary.failed,
+            needs_review,
+            summary.duplicates,
+            results_sheet
+        ),
+        OutputFormat::Json => println!("{}", serde_json::to_string(&summary)?),
+    }
+
+    eprintln!("{}", usage::summary(&record.usage, pricing));
+
+    Ok(Outcome {
+        exit_code,
+        last_row,
+    })
+}
+
+/// Runs `qualify` as a job of `jobs`, returning its ID.
+pub fn start(
+    jobs: &Jobs,
+    agent: Arc<Agent<Model>>,
+    resources: Arc<Resources>,
+    args: QualifyArgs,
+) -> String {
+    let description = format!("Qualify `{}` of {}", args.sheet, args.spreadsheet);
+
+    jobs.start(description, move |progress| async move {
+        let pipeline = Pipeline {
+            agent: &agent,
+            resources: &resources,
+            output: OutputFormat::Json,
+            progress: Some(progress),
+            bar: None,
+        };
+
+        let outcome = run(pipeline, &args).await?;
+        anyhow::ensure!(
+            outcome.exit_code == ExitCode::SUCCESS,
+            "Some leads were left without a verdict; see the results sheet"
+        );
+
+        Ok(())
+    })
+}
+
+/// Roughly how many leads `sheet` holds: the rows below the headers with a
+/// value in the first column.
+async fn count_leads(sheets: &Spreadsheets, spreadsheet: &str, sheet: &str) -> u64 {
+    let range = format!("{}!A2:A", quote_sheet(sheet));
+
+    match sheets.read_range(spreadsheet, &range).await {
+        Ok(values) => sheets::rows(values).len() as u64,
+        Err(_) => 0,
+    }
+}
+
+/// The verdicts on one page of leads.
+struct Judged {
+    /// The page's leads, and what went into judging them, without the
+    /// verdicts yet.
+    page: Partial,
+    res: Result<BTreeMap<u64, Judgment>, (String, u8)>,
+}
+
+/// Asks the model for a judgment on every lead in `chunk`, keyed by row number.
+/// `columns` says what the sheet's columns hold, and is `None` when the leads
+/// hold the facts picked out of their rows instead of the rows themselves.
+///
+/// Each chunk starts with an empty history, so the prompts stay the same size
+/// however many leads there are. A malformed answer is sent back with what's
+/// wrong with it, up to `max_reprompts` times.
+async fn judge<M: StreamingCompletionModel>(
+    agent: &Agent<M>,
+    config: &QualifyConfig,
+    criteria: &str,
+    context: &[(u64, Map<String, Value>)],
+    chunk: &[(u64, Map<String, Value>)],
+    columns: Option<&Columns>,
+    transcript: Option<&Transcript>,
+) -> (Result<BTreeMap<u64, Judgment>, (String, u8)>, Metrics) {
+    let leads = match columns {
+        None => "a JSON object of the facts of its row that bear on the criteria",
+        Some(_) => "a JSON object keyed by the sheet's column headers",
+    };
+    let columns = match columns.map(Columns::describe) {
+        Some(columns) if !columns.is_empty() => format!("{columns} "),
+        _ => String::new(),
+    };
+    let mut prompt = format!(
+        "Qualify the leads below against these criteria: {criteria}\n\n\
+         Each lead is {leads}, plus its `lead_id`. {columns}\
+         Don't change the spreadsheet. Answer with only a JSON array holding one judgment per \
+         lead, scoring leads from 0 to 10 unless the criteria say otherwise. The answer must \
+         match this JSON schema:\n{}\n",
+        judgment::schema()
+    );
+    if !context.is_empty() {
+        prompt.push_str(&format!(
+            "\nThese leads were qualified already; they are only here for context, so leave \
+             them out of your answer:\n{}\n",
+            untrusted(agent, numbered(context))
+        ));
+    }
+    prompt.push_str(&format!("\nLeads:\n{}", untrusted(agent, numbered(chunk))));
+
+    let lead_ids: Vec<u64> = chunk.iter().map(|(row, _)| *row).collect();
+    let mut prompt = Message::from(prompt);
+    let mut chat_history = Vec::new();
+    let mut metrics = Metrics::default();
+    let mut reprompts = 0;
+
+    loop {
+        let turn = match agent
+            .call_within_run(prompt, &mut chat_history, transcript, None)
+            .await
+        {
+            Ok(turn) => turn,
+            Err(Error::LimitExceeded(limit)) => {
+                let error = format!("Stopped after the agent {}", limit.reason);
+                metrics.add(&limit.abandon(&mut chat_history));
+                return (Err((error, EXIT_LIMIT_EXCEEDED)), metrics);
+            }
+            Err(e) => return (Err((e.to_string(), EXIT_ERROR)), metrics),
+        };
+        metrics.add(&turn);
+
+        match judgment::parse(&turn.answer, &lead_ids) {
+            Ok(judgments) => return (Ok(judgments), metrics),
+            Err(problem) if reprompts < config.max_reprompts => {
+                reprompts += 1;
+                metrics.retries += 1;
+                eprintln!("The model's answer was malformed, asking again: {problem}");
+                prompt = format!(
+                    "{problem}. Answer again with only the JSON array, matching the schema."
+                )
+                .into();
+            }
+            Err(problem) => {
+                let error = format!("The model's answer was malformed: {problem}");
+                return (Err((error, EXIT_ERROR)), metrics);
+            }
+        }
+    }
+}
+
+/// Writes `rows`, headers first, to `sheet`, creating and formatting it if
+/// it's new. With `append`, the rows go below those of earlier runs instead
+/// of replacing them, and without it no row of earlier runs is left below.
+async fn write_results(
+    writer: &Writer<'_>,
+    sheet: &str,
+    mut rows: Vec<Vec<Value>>,
+    append: bool,
+    format: bool,
+) -> anyhow::Result<()> {
+    let (sheets, spreadsheet_id) = (writer.sheets, writer.spreadsheet_id);
+    let width = rows.first().map_or(0, Vec::len);
+    let created = create_sheet(sheets, spreadsheet_id, sheet).await?;
+    // The sheet has its header row from an earlier run.
+    let append = append && !created;
+    if append {
+        rows.remove(0);
+        if rows.is_empty() {
+            return Ok(());
+        }
+    }
+    let written = match append {
+        true => writer.rows(sheet, rows, true).await,
+        false => writer.replace(sheet, rows).await,
+    };
+    written.with_context(|| format!("Failed to write the results to `{sheet}`"))?;
+
+    if created && format {
+        // The results are written either way, so a failure is only reported.
+        if let Err(e) = format::results(sheets, spreadsheet_id, sheet, width).await {
+            tracing::warn!("Failed to format `{sheet}`: {e:#}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the `rows` a dry run would write to `sheet`, a line each.
+fn preview(sheet: &str, rows: &[Vec<Value>]) {
+    eprintln!(
+        "[dry run] {} rows that would be written to `{sheet}`:",
+        rows.len()
+    );
+    for row in rows {
+        let cells: Vec<String> = row.iter().map(cell_text).collect();
+        eprintln!("{}", cells.join("\t"));
+    }
+}
+
+/// Adds `title` to the spreadsheet unless it's already there, returning
+/// whether it was added.
+async fn create_sheet(
+    sheets: &Spreadsheets,
+    spreadsheet_id: &str,
+    title: &str,
+) -> anyhow::Result<bool> {
+    match sheets.create_sheet(spreadsheet_id, title).await {
+        Ok(_) => Ok(true),
+        Err(SheetsError::SheetExists(_)) => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to create the sheet `{title}`")),
+    }
+}
+
+/// Writes the `cleaned` rows to `sheet` under `headers`, each on the row it was
+/// read from, leaving the rows in between as they are.
+async fn write_cleaned(
+    writer: &Writer<'_>,
+    sheet: &str,
+    headers: &[String],
+    mut cleaned: BTreeMap<u64, Vec<Value>>,
+) -> anyhow::Result<()> {
+    create_sheet(writer.sheets, writer.spreadsheet_id, sheet).await?;
+
+    let last_row = cleaned.keys().last().copied().unwrap_or(1);
+    let mut rows = vec![headers.iter().map(|header| json!(header)).collect()];
+    // An empty row leaves the row in the sheet unchanged.
+    rows.extend((2..=last_row).map(|row| cleaned.remove(&row).unwrap_or_default()));
+    writer
+        .rows(sheet, rows, false)
+        .await
+        .with_context(|| format!("Failed to write the cleaned leads to `{sheet}`"))?;
+
+    Ok(())
+}
+
+/// The cells of row `row` holding `evidence`, e.g. `C12, F12`. Keys that
+/// aren't columns, like the facts of extracted leads or enriched fields, are
+/// cited by name.
+fn evidence(headers: &[String], row: u64, evidence: &[String]) -> String {
+    let cells: Vec<String> = evidence
+        .iter()
+        .map(
+            |key| match headers.iter().position(|header| header == key) {
+                Some(column) => format!("{}{row}", column_name(column)),
+                None => key.clone(),
+            },
+        )
+        .collect();
+
+    cells.join(", ")
+}
+
+/// The configured column, or else the first header `matches` accepts in lowercase.
+fn find_column(
+    headers: &[String],
+    configured: Option<&String>,
+    matches: fn(&str) -> bool,
+) -> Option<String> {
+    configured.cloned().or_else(|| {
+        headers
+            .iter()
+            .find(|header| matches(&header.to_lowercase()))
+            .cloned()
+    })
+}
+
+fn is_email_header(header: &str) -> bool {
+    header.contains("email") || header.contains("e-mail")
+}
+
+/// `leads` as a JSON array, each with its row number as `lead_id`.
+fn numbered(leads: &[(u64, Map<String, Value>)]) -> Value {
+    leads
+        .iter()
+        .map(|(row, lead)| {
+            let mut lead = lead.clone();
+            lead.insert("lead_id".to_string(), json!(row));
+            Value::Object(lead)
+        })
+        .collect()
+}
+
+/// `leads` as the model is sent them: as they are, or with the instructions
+/// hidden in them cut out and between the delimiters that mark them as data.
+fn untrusted<M>(agent: &Agent<M>, leads: Value) -> String {
+    let Some(injection) = &agent.injection else {
+        return leads.to_string();
+    };
+
+    let mut flagged = Vec::new();
+    let leads = injection.sanitize_value(&leads, &mut flagged);
+    injection::warn("a lead", &flagged);
+    injection::wrap(&leads.to_string())
+}
+
+/// The rows with at least one non-empty cell, as leads keyed by row number.
+fn to_leads(headers: &[String], rows: Vec<NumberedRow>) -> Vec<(u64, Map<String, Value>)> {
+    rows.into_iter()
+        .map(|(row, cells)| (row, lead(headers, cells)))
+        .filter(|(_, lead)| !lead.is_empty())
+        .collect()
+}
+
+/// The non-empty cells of `row`, keyed by their column's header.
+fn lead(headers: &[String], cells: &[Value]) -> Map<String, Value> {
+    headers
+        .iter()
+        .zip(cells.iter().cloned())
+        .filter(|(_, cell)| cell.as_str().is_none_or(|text| !text.trim().is_empty()))
+        .map(|(header, cell)| (header.clone(), cell))
+        .collect()
+}
@@ -0,0 +1,180 @@
+//! A summary sheet next to the results, with how many leads qualified and how
+//! their scores are spread, for people who won't read the rows.
+//!
+//! The counts are formulas over the results sheet, so they stay right as later
+//! runs append to it.
+
+use serde_json::{Value, json};
+
+use super::{
+    create_sheet,
+    format::{color, sheet_id},
+    writer::Writer,
+};
+use crate::sheets::{LOCAL_SPREADSHEET, quote_sheet};
+
+/// Ranges of scores counted in the summary.
+const BUCKETS: usize = 5;
+
+/// Writes the summary of `results_sheet` to `<results_sheet> summary`, adding
+/// its charts if the sheet is new, and returns its name. Imported files get no
+/// summary, since it's made of formulas and charts the local workbook doesn't
+/// have.
+///
+/// The score ranges are picked to fit `scores`, those of this run.
+pub async fn write(
+    writer: &Writer<'_>,
+    results_sheet: &str,
+    scores: &[f64],
+) -> anyhow::Result<Option<String>> {
+    let (sheets, spreadsheet) = (writer.sheets, writer.spreadsheet_id);
+    if spreadsheet == LOCAL_SPREADSHEET {
+        eprintln!("Imported files get no summary sheet");
+        return Ok(None);
+    }
+    let sheet = format!("{results_sheet} summary");
+    let created = create_sheet(sheets, spreadsheet, &sheet).await?;
+
+    let results = quote_sheet(results_sheet);
+    let verdicts = [
+        ("Qualified", format!("=COUNTIF({results}!C2:C, \"yes\")")),
+        ("Not qualified", format!("=COUNTIF({results}!C2:C, \"no\")")),
+        (
+            "Without a verdict",
+            format!("=COUNTIFS({results}!A2:A, \"<>\", {results}!C2:C, \"\")"),
+        ),
+    ];
+    let mut rows = vec![vec![json!("Verdict"), json!("Leads")]];
+    rows.extend(
+        verdicts
+            .iter()
+            .map(|(verdict, count)| vec![json!(verdict), json!(count)]),
+    );
+    rows.push(Vec::new());
+    rows.push(vec![json!("Score"), json!("Leads")]);
+
+    let scale = scale(scores);
+    let width = scale / BUCKETS as f64;
+    // Rounded, so that 3 * 0.2 reads as 0.6.
+    let bound = |bucket: usize| (width * bucket as f64 * 100.0).round() / 100.0;
+    for bucket in 0..BUCKETS {
+        let (low, high) = (bound(bucket), bound(bucket + 1));
+        // The top range takes the highest score in, and the bottom one anything lower.
+        let count = match bucket {
+            0 => format!("=COUNTIF({results}!D2:D, \"<{high}\")"),
+            _ if bucket == BUCKETS - 1 => format!("=COUNTIF({results}!D2:D, \">={low}\")"),
+            _ => format!("=COUNTIFS({results}!D2:D, \">={low}\", {results}!D2:D, \"<{high}\")"),
+        };
+        rows.push(vec![json!(format!("{low}–{high}")), json!(count)]);
+    }
+
+    writer.rows(&sheet, rows, false).await?;
+
+    if created {
+        let sheet_id = sheet_id(sheets, spreadsheet, &sheet).await?;
+        // Pie charts take no header row.
+        let verdicts_rows = (1, verdicts.len() + 1);
+        let scores_rows = (verdicts.len() + 2, verdicts.len() + 3 + BUCKETS);
+        let requests = vec![
+            bold_row(sheet_id, 0),
+            bold_row(sheet_id, scores_rows.0),
+            chart(sheet_id, pie(sheet_id, verdicts_rows), "Verdicts", 0),
+            chart(sheet_id, columns(sheet_id, scores_rows), "Scores", 20),
+        ];
+        sheets.format(spreadsheet, requests).await?;
+    }
+
+    Ok(Some(sheet))
+}
+
+/// The top of the range scores are counted in: 1, 10 or 100, whichever holds
+/// the highest of `scores`, or a power of ten above it.
+fn scale(scores: &[f64]) -> f64 {
+    let highest = scores.iter().copied().fold(0.0, f64::max);
+    let mut scale = 1.0;
+    while scale < highest {
+        scale *= 10.0;
+    }
+
+    scale
+}
+
+fn bold_row(sheet_id: u64, row: usize) -> Value {
+    json!({
+        "repeatCell": {
+            "range": {
+                "sheetId": sheet_id,
+                "startRowIndex": row,
+                "endRowIndex": row + 1,
+                "startColumnIndex": 0,
+                "endColumnIndex": 2,
+            },
+            "cell": {
+                "userEnteredFormat": {
+                    "textFormat": { "bold": true },
+                    "backgroundColor": color(0.93, 0.93, 0.93),
+                }
+            },
+            "fields": "userEnteredFormat(textFormat,backgroundColor)",
+        }
+    })
+}
+
+/// Column `column` of the rows from `first` up to `end`, from 0.
+fn source(sheet_id: u64, (first, end): (usize, usize), column: usize) -> Value {
+    json!({
+        "sourceRange": {
+            "sources": [{
+                "sheetId": sheet_id,
+                "startRowIndex": first,
+                "endRowIndex": end,
+                "startColumnIndex": column,
+                "endColumnIndex": column + 1,
+            }]
+        }
+    })
+}
+
+fn pie(sheet_id: u64, rows: (usize, usize)) -> Value {
+    json!({
+        "pieChart": {
+            "legendPosition": "RIGHT_LEGEND",
+            "domain": source(sheet_id, rows, 0),
+            "series": source(sheet_id, rows, 1),
+        }
+    })
+}
+
+fn columns(sheet_id: u64, rows: (usize, usize)) -> Value {
+    json!({
+        "basicChart": {
+            "chartType": "COLUMN",
+            "legendPosition": "NO_LEGEND",
+            "headerCount": 1,
+            "axis": [
+                { "position": "BOTTOM_AXIS", "title": "Score" },
+                { "position": "LEFT_AXIS", "title": "Leads" },
+            ],
+            "domains": [{ "domain": source(sheet_id, rows, 0) }],
+            "series": [{ "series": source(sheet_id, rows, 1), "targetAxis": "LEFT_AXIS" }],
+        }
+    })
+}
+
+/// `spec` as a chart titled `title`, right of the counts, `row` rows down.
+fn chart(sheet_id: u64, mut spec: Value, title: &str, row: usize) -> Value {
+    spec["title"] = json!(title);
+
+    json!({
+        "addChart": {
+            "chart": {
+                "spec": spec,
+                "position": {
+                    "overlayPosition": {
+                        "anchorCell": { "sheetId": sheet_id, "rowIndex": row, "columnIndex": 3 },
+                    }
+                },
+            }
+        }
+    })
+}
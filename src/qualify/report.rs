@@ -0,0 +1,487 @@
+//! A report of a run for people who won't open the spreadsheet: what the leads
+//! were judged on, how many qualified, the best of them and what it cost, as
+//! an HTML page or a PDF.
+
+use std::{collections::BTreeMap, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    config::PriceConfig,
+    notify::TopLead,
+    sheets::LOCAL_SPREADSHEET,
+    store,
+    usage::{self, Usage},
+};
+
+/// The best scoring qualified leads shown.
+const TOP_LEADS: usize = 10;
+
+pub struct Report<'a> {
+    run: &'a store::Run,
+    /// Leads sent for review, which count as neither qualified nor not.
+    needs_review: usize,
+    criteria: &'a str,
+    /// Best first.
+    top_leads: Vec<TopLead>,
+    pricing: &'a BTreeMap<String, PriceConfig>,
+}
+
+impl<'a> Report<'a> {
+    /// The report of `run`, keeping the [`TOP_LEADS`] best scoring of `top_leads`.
+    pub fn new(
+        run: &'a store::Run,
+        needs_review: usize,
+        criteria: &'a str,
+        top_leads: &[TopLead],
+        pricing: &'a BTreeMap<String, PriceConfig>,
+    ) -> Self {
+        let mut top_leads = top_leads.to_vec();
+        top_leads.sort_by(|a, b| b.score.total_cmp(&a.score));
+        top_leads.truncate(TOP_LEADS);
+
+        Self {
+            run,
+            needs_review,
+            criteria,
+            top_leads,
+            pricing,
+        }
+    }
+
+    /// Writes the report to `path`, as HTML or PDF depending on its extension.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+        let contents = match extension.as_deref() {
+            Some("html" | "htm") => self.html().into_bytes(),
+            Some("pdf") => self.pdf(),
+            _ => anyhow::bail!("Reports are written as .html or .pdf files"),
+        };
+        std::fs::write(path, contents)?;
+
+        Ok(())
+    }
+
+    fn title(&self) -> String {
+        format!("Qualification of {}", self.run.sheet)
+    }
+
+    /// When the run started, in local time.
+    fn started(&self) -> String {
+        chrono::DateTime::from_timestamp(self.run.started_at as i64, 0)
+            .map(|at| at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"))
+            .map(|at| at.to_string())
+            .unwrap_or_default()
+    }
+
+    fn spreadsheet_url(&self) -> Option<String> {
+        (self.run.spreadsheet != LOCAL_SPREADSHEET).then(|| {
+            format!(
+                "https://docs.google.com/spreadsheets/d/{}",
+                self.run.spreadsheet
+            )
+        })
+    }
+
+    /// Label and number of each count of leads.
+    fn counts(&self) -> [(&'static str, usize); 6] {
+        let run = self.run;
+        [
+            ("Leads", run.leads),
+            ("Qualified", run.qualified),
+            (
+                "Not qualified",
+                run.leads - run.qualified - run.failed - self.needs_review,
+            ),
+            ("Needing a review", self.needs_review),
+            ("Without a verdict", run.failed),
+            ("Duplicates skipped", run.duplicates),
+        ]
+    }
+
+    fn cost(&self) -> String {
+        let tokens = self
+            .run
+            .usage
+            .values()
+            .fold(Usage::default(), |mut total, usage| {
+                total += *usage;
+                total
+            });
+        let (cost, unpriced) = usage::total_cost(&self.run.usage, self.pricing);
+        let mut text = format!(
+            "About ${cost:.2}, for {} prompt and {} completion tokens",
+            tokens.prompt_tokens, tokens.completion_tokens
+        );
+        if unpriced {
+            text.push_str(", not counting models without a known price");
+        }
+
+        text
+    }
+
+    pub fn html(&self) -> String {
+        let sheet = match self.spreadsheet_url() {
+            Some(url) => format!(
+                "<a href=\"{}\">{}</a>",
+                escape(&url),
+                escape(&self.run.sheet)
+            ),
+            None => escape(&self.run.sheet),
+        };
+        let counts: String = self
+            .counts()
+            .iter()
+            .map(|(label, count)| format!("<tr><th>{label}</th><td>{count}</td></tr>\n"))
+            .collect();
+        let leads: String = match self.top_leads.as_slice() {
+            [] => "<p>No lead qualified.</p>".to_string(),
+            leads => {
+                let rows: String = leads
+                    .iter()
+                    .map(|lead| {
+                        format!(
+                            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                            lead.row,
+                            escape(&name(&lead.lead)),
+                            lead.score,
+                            escape(&lead.reason)
+                        )
+                    })
+                    .collect();
+                format!(
+                    "<table>\n<tr><th>Row</th><th>Lead</th><th>Score</th><th>Reason</th></tr>\n\
+                     {rows}</table>"
+                )
+            }
+        };
+
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 50rem; margin: 2rem auto; color: #222; }}
+table {{ border-collapse: collapse; margin-bottom: 1rem; }}
+th, td {{ text-align: left; padding: 0.3rem 0.8rem; border-bottom: 1px solid #ddd; vertical-align: top; }}
+.meta {{ color: #666; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p class="meta">{sheet}, started {started}; results in {results_sheet}</p>
+<h2>Criteria</h2>
+<p>{criteria}</p>
+<h2>Outcome</h2>
+<table>
+{counts}</table>
+<h2>Top leads</h2>
+{leads}
+<h2>Cost</h2>
+<p>{cost}</p>
+</body>
+</html>
+"#,
+            title = escape(&self.title()),
+            started = self.started(),
+            results_sheet = escape(&self.run.results_sheet),
+            criteria = escape(self.criteria),
+            cost = escape(&self.cost()),
+        )
+    }
+
+    fn pdf(&self) -> Vec<u8> {
+        let mut pdf = Pdf::default();
+        pdf.text(&self.title(), Style::Title);
+        let sheet = match self.spreadsheet_url() {
+            Some(url) => format!("{} ({url})", self.run.sheet),
+            None => self.run.sheet.clone(),
+        };
+        pdf.text(
+            &format!(
+                "{sheet}, started {}; results in {}",
+                self.started(),
+                self.run.results_sheet
+            ),
+            Style::Body,
+        );
+        pdf.text("Criteria", Style::Heading);
+        pdf.text(self.criteria, Style::Body);
+        pdf.text("Outcome", Style::Heading);
+        for (label, count) in self.counts() {
+            pdf.text(&format!("{label}: {count}"), Style::Body);
+        }
+        pdf.text("Top leads", Style::Heading);
+        if self.top_leads.is_empty() {
+            pdf.text("No lead qualified.", Style::Body);
+        }
+        for lead in &self.top_leads {
+            pdf.text(
+                &format!(
+                    "{} (row {}, score {}): {}",
+                    name(&lead.lead),
+                    lead.row,
+                    lead.score,
+                    lead.reason
+                ),
+                Style::Body,
+            );
+        }
+        pdf.text("Cost", Style::Heading);
+        pdf.text(&self.cost(), Style::Body);
+
+        pdf.finish()
+    }
+}
+
+fn name(lead: &Value) -> String {
+    match lead {
+        Value::String(name) => name.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// An A4 page, in points.
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 56.0;
+
+#[derive(Clone, Copy)]
+enum Style {
+    Title,
+    Heading,
+    Body,
+}
+
+impl Style {
+    /// The font resource and size.
+    fn font(self) -> (&'static str, f64) {
+        match self {
+            Self::Title => ("F2", 18.0),
+            Self::Heading => ("F2", 13.0),
+            Self::Body => ("F1", 10.0),
+        }
+    }
+
+    /// Space above a paragraph in this style.
+    fn space_before(self) -> f64 {
+        match self {
+            Self::Title => 0.0,
+            Self::Heading => 12.0,
+            Self::Body => 3.0,
+        }
+    }
+}
+
+/// Just enough of a PDF writer for paragraphs of text in Helvetica, wrapped
+/// and broken into pages.
+#[derive(Default)]
+struct Pdf {
+    /// The content stream of each finished page.
+    pages: Vec<Vec<u8>>,
+    page: Vec<u8>,
+    /// How far down the page the next line goes, from the top margin.
+    y: f64,
+}
+
+impl Pdf {
+    fn text(&mut self, text: &str, style: Style) {
+        let (font, size) = style.font();
+        let leading = size * 1.3;
+        // Helvetica's letters are about half as wide as they're high.
+        let per_line = ((PAGE_WIDTH - 2.0 * MARGIN) / (size * 0.5)) as usize;
+
+        self.y += style.space_before();
+        for line in wrap(text, per_line) {
+            if self.y + leading > PAGE_HEIGHT - 2.0 * MARGIN {
+                self.pages.push(std::mem::take(&mut self.page));
+                self.y = 0.0;
+            }
+            self.y += leading;
+            let baseline = PAGE_HEIGHT - MARGIN - self.y;
+            self.page
+                .extend(format!("BT /{font} {size} Tf {MARGIN} {baseline:.1} Td (").bytes());
+            self.page.extend(encode(&line));
+            self.page.extend(b") Tj ET\n");
+        }
+    }
+
+    /// The document, with the page being written as its last page.
+    fn finish(mut self) -> Vec<u8> {
+        self.pages.push(std::mem::take(&mut self.page));
+
+        // 1 is the catalog, 2 the page tree, 3 and 4 the fonts, then each page
+        // and its contents.
+        let kids: Vec<String> = (0..self.pages.len())
+            .map(|page| format!("{} 0 R", 5 + 2 * page))
+            .collect();
+        let mut objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                self.pages.len()
+            )
+            .into_bytes(),
+            font("Helvetica"),
+            font("Helvetica-Bold"),
+        ];
+        for (page, contents) in self.pages.iter().enumerate() {
+            objects.push(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+                     /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                    6 + 2 * page
+                )
+                .into_bytes(),
+            );
+            let mut stream = format!("<< /Length {} >>\nstream\n", contents.len()).into_bytes();
+            stream.extend(contents);
+            stream.extend(b"endstream");
+            objects.push(stream);
+        }
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n", i + 1).bytes());
+            pdf.extend(object);
+            pdf.extend(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+        for offset in offsets {
+            pdf.extend(format!("{offset:010} 00000 n \n").bytes());
+        }
+        pdf.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .bytes(),
+        );
+
+        pdf
+    }
+}
+
+fn font(name: &str) -> Vec<u8> {
+    format!("<< /Type /Font /Subtype /Type1 /BaseFont /{name} /Encoding /WinAnsiEncoding >>")
+        .into_bytes()
+}
+
+/// `text` broken into lines of at most `width` characters, between words.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+
+    lines
+}
+
+/// `line` in the fonts' WinAnsi encoding, escaped for a PDF string. What the
+/// encoding doesn't have becomes `?`.
+fn encode(line: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(line.len());
+    for c in line.chars() {
+        let byte = match c {
+            '(' | ')' | '\\' => {
+                bytes.push(b'\\');
+                c as u8
+            }
+            ' '..='~' => c as u8,
+            // WinAnsi matches Latin-1 from here on.
+            '\u{a0}'..='\u{ff}' => c as u8,
+            '€' => 0x80,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            _ => b'?',
+        };
+        bytes.push(byte);
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text after `needle` in `pdf`, up to the end of the line.
+    fn after<'a>(pdf: &'a str, needle: &str) -> &'a str {
+        let start = pdf.rfind(needle).unwrap() + needle.len();
+        pdf[start..].lines().next().unwrap()
+    }
+
+    #[test]
+    fn points_the_xref_at_each_object() {
+        let mut pdf = Pdf::default();
+        pdf.text("Qualification report", Style::Title);
+        pdf.text("Ada Lovelace, Analytical Engines", Style::Body);
+        let pdf = String::from_utf8(pdf.finish()).unwrap();
+
+        let xref: usize = after(&pdf, "startxref\n").parse().unwrap();
+        assert!(pdf[xref..].starts_with("xref\n0 7\n"));
+        let offsets: Vec<usize> = pdf[xref..]
+            .lines()
+            .skip(3)
+            .take(6)
+            .map(|entry| entry[..10].parse().unwrap())
+            .collect();
+        for (i, offset) in offsets.iter().enumerate() {
+            assert!(pdf[*offset..].starts_with(&format!("{} 0 obj\n", i + 1)));
+        }
+    }
+
+    #[test]
+    fn escapes_the_delimiters_of_strings() {
+        assert_eq!(
+            encode(r"Rand (Remington) \ Sperry"),
+            br"Rand \(Remington\) \\ Sperry"
+        );
+    }
+
+    #[test]
+    fn breaks_long_text_onto_new_pages() {
+        let mut pdf = Pdf::default();
+        pdf.text(&"word ".repeat(10_000), Style::Body);
+        let pages = pdf.pages.len() + 1;
+        let pdf = String::from_utf8(pdf.finish()).unwrap();
+
+        assert!(pages > 1);
+        assert!(pdf.contains(&format!("/Count {pages}")));
+        assert_eq!(pdf.matches("/Type /Page ").count(), pages);
+        // Every line is above the bottom margin.
+        for line in pdf.lines().filter(|line| line.starts_with("BT ")) {
+            let baseline: f64 = line.split(' ').nth(5).unwrap().parse().unwrap();
+            assert!(baseline >= MARGIN, "{line}");
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! A run's writes to its spreadsheet, made as the built-in tools would make
+//! them: what the rows replace is kept for `/undo`, and every write goes in
+//! the audit log, with hashes of the cells before and after.
+
+use rig::tool::Tool;
+use serde_json::{Value, json};
+
+use crate::{
+    audit::{self, Origin, Outcome},
+    sheets::{
+        self, AppendRows, CreateNamedRange, SheetsError, Spreadsheets, WriteRange, quote_sheet,
+    },
+    tools::Toolbox,
+};
+
+/// Writes to one spreadsheet for one run.
+#[derive(Clone, Copy)]
+pub struct Writer<'a> {
+    pub sheets: &'a Spreadsheets,
+    pub spreadsheet_id: &'a str,
+    tools: &'a Toolbox,
+    /// The run, logged as the prompt the writes were made for.
+    origin: Origin<'a>,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(
+        sheets: &'a Spreadsheets,
+        spreadsheet_id: &'a str,
+        tools: &'a Toolbox,
+        origin: Origin<'a>,
+    ) -> Self {
+        Self {
+            sheets,
+            spreadsheet_id,
+            tools,
+            origin,
+        }
+    }
+
+    /// Writes `rows` to `sheet` from A1, or appends them below its rows with
+    /// `append`, keeping what they replace for `/undo`.
+    pub async fn rows(
+        &self,
+        sheet: &str,
+        rows: Vec<Vec<Value>>,
+        append: bool,
+    ) -> Result<(), SheetsError> {
+        let covered = self
+            .tools
+            .before_write(self.sheets, self.spreadsheet_id, sheet, &rows, append)
+            .await;
+        let new_values = self.tools.audits().then(|| audit::hash(&rows));
+        let range = format!("{}!A1", quote_sheet(sheet));
+
+        let (tool, res) = match append {
+            true => (
+                AppendRows::NAME,
+                self.sheets
+                    .append_rows(self.spreadsheet_id, &range, rows)
+                    .await,
+            ),
+            false => (
+                WriteRange::NAME,
+                self.sheets
+                    .write_range(self.spreadsheet_id, &range, rows)
+                    .await,
+            ),
+        };
+        let (range, old_values) = match &covered {
+            Some((range, values)) => (range.as_str(), Some(audit::hash(values))),
+            None => (range.as_str(), None),
+        };
+        self.audit(tool, range, old_values, new_values, res.as_ref().err());
+
+        res.map(drop)
+    }
+
+    /// Writes `rows` to `sheet` from A1 in place of everything it holds, with
+    /// the cells beyond them cleared, so none of an earlier write is left
+    /// below or beside them.
+    pub async fn replace(&self, sheet: &str, mut rows: Vec<Vec<Value>>) -> Result<(), SheetsError> {
+        let current = self
+            .sheets
+            .read_range(self.spreadsheet_id, &quote_sheet(sheet))
+            .await?;
+        let current = sheets::rows(current);
+        let width = rows.iter().chain(&current).map(Vec::len).max().unwrap_or(0);
+        rows.resize(rows.len().max(current.len()), Vec::new());
+        for row in &mut rows {
+            row.resize(width, json!(""));
+        }
+
+        self.rows(sheet, rows, false).await
+    }
+
+    /// Writes `rows` to exactly `range`, for bookkeeping like the lock on a
+    /// sheet, which isn't undone.
+    pub async fn range(&self, range: &str, rows: Vec<Vec<Value>>) -> Result<(), SheetsError> {
+        let new_values = self.tools.audits().then(|| audit::hash(&rows));
+        let res = self
+            .sheets
+            .write_range(self.spreadsheet_id, range, rows)
+            .await;
+        self.audit(
+            WriteRange::NAME,
+            range,
+            None,
+            new_values,
+            res.as_ref().err(),
+        );
+
+        res.map(drop)
+    }
+
+    /// Gives `range` the name `name`.
+    pub async fn name(&self, name: &str, range: &str) -> Result<(), SheetsError> {
+        let res = self
+            .sheets
+            .set_named_range(self.spreadsheet_id, name, range)
+            .await;
+        self.audit(
+            CreateNamedRange::NAME,
+            range,
+            None,
+            None,
+            res.as_ref().err(),
+        );
+
+        res.map(drop)
+    }
+
+    fn audit(
+        &self,
+        tool: &str,
+        range: &str,
+        old_values: Option<String>,
+        new_values: Option<String>,
+        error: Option<&SheetsError>,
+    ) {
+        if !self.tools.audits() {
+            return;
+        }
+
+        self.tools.record(&audit::Entry {
+            tool,
+            spreadsheet: Some(&json!(self.spreadsheet_id)),
+            range: Some(range),
+            old_values,
+            new_values,
+            prompt: self.origin.prompt,
+            model: self.origin.model,
+            outcome: match error {
+                Some(_) => Outcome::Failed,
+                None => Outcome::Applied,
+            },
+            error: error.map(ToString::to_string),
+        });
+    }
+}
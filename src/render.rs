@@ -0,0 +1,269 @@
+//! How answers look in the terminal: markdown rendered with termimad, colors
+//! setting apart who is speaking and what the agent is doing, spinners while
+//! it waits and progress bars while it qualifies.
+
+use std::{
+    borrow::Cow,
+    io::{IsTerminal, Write},
+    time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use termimad::{
+    MadSkin,
+    crossterm::style::{StyledContent, Stylize},
+};
+
+/// Longest tool arguments shown in full in a tool-call notice.
+const MAX_NOTICE_ARGS: usize = 120;
+/// Longest error shown in a trace line.
+const MAX_TRACE_ERROR: usize = 100;
+
+/// Styles text written to stdout.
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    /// `None` when stdout isn't a terminal, so markdown is printed as written.
+    skin: Option<MadSkin>,
+    color: bool,
+    /// Print a line for every tool call once it's done, instead of a notice
+    /// with its arguments when it's made.
+    trace: bool,
+}
+
+impl Terminal {
+    /// Colors are left out if `color` is off, `NO_COLOR` is set or stdout
+    /// isn't a terminal.
+    pub fn new(color: bool) -> Self {
+        let terminal = std::io::stdout().is_terminal();
+        let color = color && terminal && std::env::var_os("NO_COLOR").is_none();
+
+        Self {
+            skin: terminal.then(|| {
+                if color {
+                    MadSkin::default()
+                } else {
+                    MadSkin::no_style()
+                }
+            }),
+            color,
+            trace: false,
+        }
+    }
+
+    /// Prints each tool call once it's done, with its outcome and time taken.
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Prints the name of who is about to speak, e.g. `Agent`.
+    pub fn role(&self, name: &str) {
+        println!(
+            "{}",
+            self.style(format!("{name}:"), |text| text.bold().cyan())
+        );
+    }
+
+    /// Notes on stdout that the agent is calling `tool`, set apart from
+    /// answers, unless tool calls are traced instead.
+    pub fn tool_call(&self, tool: &str, arguments: &serde_json::Value) {
+        if self.trace {
+            return;
+        }
+
+        let mut arguments = arguments.to_string();
+        if arguments.len() > MAX_NOTICE_ARGS {
+            let end = arguments.floor_char_boundary(MAX_NOTICE_ARGS);
+            arguments.truncate(end);
+            arguments.push('…');
+        }
+
+        println!(
+            "{}",
+            self.style(format!("→ {tool} {arguments}"), |text| text
+                .dark_grey()
+                .italic())
+        );
+    }
+
+    /// Prints one line about a finished call to `tool`, if tool calls are
+    /// traced, e.g. `→ append_rows(Sheet2, 14 rows) ✓ 230ms`.
+    pub fn tool_trace(
+        &self,
+        tool: &str,
+        arguments: &serde_json::Value,
+        error: Option<&str>,
+        duration_ms: u64,
+    ) {
+        if !self.trace {
+            return;
+        }
+
+        let took = match duration_ms {
+            ..1_000 => format!("{duration_ms}ms"),
+            _ => format!("{:.1}s", duration_ms as f64 / 1_000.0),
+        };
+        let call = format!("→ {}", call_summary(tool, arguments));
+        let line = match error {
+            None => format!(
+                "{} {} {}",
+                self.style(call, |text| text.dark_grey()),
+                self.style("✓".to_string(), |text| text.green()),
+                self.style(took, |text| text.dark_grey()),
+            ),
+            Some(error) => {
+                let mut error = error.lines().next().unwrap_or_default().to_string();
+                if error.len() > MAX_TRACE_ERROR {
+                    let end = error.floor_char_boundary(MAX_TRACE_ERROR);
+                    error.truncate(end);
+                    error.push('…');
+                }
+                format!(
+                    "{} {} {}",
+                    self.style(call, |text| text.dark_grey()),
+                    self.style("✗".to_string(), |text| text.red()),
+                    self.style(format!("{took}: {error}"), |text| text.dark_grey()),
+                )
+            }
+        };
+
+        println!("{line}");
+    }
+
+    /// Shows `message` on stderr with a spinner and the time it's been
+    /// showing, until the spinner is dropped.
+    pub fn spinner(&self, message: impl Into<Cow<'static, str>>) -> Spinner {
+        let template = if self.color {
+            "{spinner:.cyan} {msg:.dim} {elapsed:.dim}"
+        } else {
+            "{spinner} {msg} {elapsed}"
+        };
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template(template).expect("the template is valid"))
+            .with_message(message);
+        spinner.enable_steady_tick(Duration::from_millis(100));
+
+        Spinner(spinner)
+    }
+
+    /// A bar on stderr for `total` rows, `done` of them done already, with how
+    /// long the rest should take and a message beside it. It's hidden if
+    /// stderr isn't a terminal.
+    pub fn progress_bar(&self, total: u64, done: u64) -> ProgressBar {
+        let (template, chars) = if self.color {
+            (
+                "{bar:30.cyan/dim} {pos}/{len} rows, ETA {eta} {msg:.dim}",
+                "━╸━",
+            )
+        } else {
+            ("[{bar:30}] {pos}/{len} rows, ETA {eta} {msg}", "=> ")
+        };
+        let bar = ProgressBar::new(total.max(done))
+            .with_style(
+                ProgressStyle::with_template(template)
+                    .expect("the template is valid")
+                    .progress_chars(chars),
+            )
+            .with_position(done);
+        bar.enable_steady_tick(Duration::from_secs(1));
+
+        bar
+    }
+
+    /// Prints `markdown`, rendered if stdout is a terminal.
+    pub fn markdown(&self, markdown: &str) {
+        match &self.skin {
+            Some(skin) => print!("{}", skin.term_text(markdown)),
+            None => println!("{markdown}"),
+        }
+    }
+
+    fn style(&self, text: String, style: impl FnOnce(String) -> StyledContent<String>) -> String {
+        if self.color {
+            style(text).to_string()
+        } else {
+            text
+        }
+    }
+}
+
+/// A call to `tool` in a few words, e.g. `append_rows(Sheet2, 14 rows)`: the
+/// tool's name without its server, the range or sheet it works on, and how
+/// many rows it's given.
+pub fn call_summary(tool: &str, arguments: &serde_json::Value) -> String {
+    let name = tool
+        .split_once(crate::mcp::NAMESPACE_SEPARATOR)
+        .map_or(tool, |(_, name)| name);
+    let mut parts = Vec::new();
+    if let Some(target) = ["range", "sheet", "title"]
+        .iter()
+        .find_map(|key| arguments.get(key).and_then(serde_json::Value::as_str))
+    {
+        parts.push(target.to_string());
+    }
+    if let Some(rows) = ["values", "rows"]
+        .iter()
+        .find_map(|key| arguments.get(key).and_then(serde_json::Value::as_array))
+    {
+        parts.push(match rows.len() {
+            1 => "1 row".to_string(),
+            rows => format!("{rows} rows"),
+        });
+    }
+
+    format!("{name}({})", parts.join(", "))
+}
+
+/// Cleared from the terminal when dropped, leaving no trace.
+pub struct Spinner(ProgressBar);
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+    }
+}
+
+/// Prints an answer as it streams in, a block at a time, since markdown
+/// can't be rendered before a paragraph or table is complete.
+pub struct Stream<'a> {
+    terminal: &'a Terminal,
+    pending: String,
+    started: bool,
+}
+
+impl<'a> Stream<'a> {
+    pub fn new(terminal: &'a Terminal) -> Self {
+        Self {
+            terminal,
+            pending: String::new(),
+            started: false,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &str) {
+        if !self.started {
+            self.terminal.role("Agent");
+            self.started = true;
+        }
+        self.pending.push_str(chunk);
+
+        // Blank lines end blocks, except inside code fences.
+        if let Some(end) = self.pending.rfind("\n\n") {
+            let blocks = &self.pending[..end];
+            if blocks.matches("```").count().is_multiple_of(2) {
+                self.terminal.markdown(blocks);
+                println!();
+                let _ = std::io::stdout().flush();
+                self.pending.drain(..end + 2);
+            }
+        }
+    }
+
+    /// Prints what is left of the answer.
+    pub fn finish(self) {
+        if !self.pending.trim().is_empty() {
+            self.terminal.markdown(&self.pending);
+        }
+    }
+}
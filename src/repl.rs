@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+use crate::{error::Error, retention};
+
+/// `~/.local/share/gsheets-agent/input_history.txt` (or the platform
+/// equivalent), where what was typed at the prompt is kept.
+pub fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("gsheets-agent").join("input_history.txt"))
+}
+
+/// Line editor for the chat prompt, with input history persisted across runs.
+pub struct Repl {
+    editor: DefaultEditor,
+    history_path: Option<PathBuf>,
+}
+
+impl Repl {
+    pub fn new() -> Result<Self, Error> {
+        let mut editor = DefaultEditor::new()
+            .context("Failed to set up the terminal")
+            .map_err(Error::Input)?;
+        let history_path = history_path().filter(|_| retention::persists());
+
+        if let Some(path) = &history_path {
+            // There is nothing to load on the first run.
+            let _ = editor.load_history(path);
+        }
+
+        Ok(Self {
+            editor,
+            history_path,
+        })
+    }
+
+    /// Reads the next prompt, returning `None` once the user wants to quit.
+    ///
+    /// A line ending in `\` continues on the next line. Ctrl-C discards the
+    /// prompt being typed, or exits if `quit_on_interrupt` is set, and Ctrl-D or
+    /// `quit` exits.
+    pub fn read_prompt(&mut self, quit_on_interrupt: bool) -> Result<Option<String>, Error> {
+        let mut lines: Vec<String> = Vec::new();
+
+        loop {
+            let prompt = if lines.is_empty() { "> " } else { "… " };
+
+            match self.editor.readline(prompt) {
+                Ok(line) => match line.strip_suffix('\\') {
+                    Some(line) => lines.push(line.to_string()),
+                    None => {
+                        lines.push(line);
+
+                        let input = lines.join("\n");
+                        lines.clear();
+
+                        if input.trim().is_empty() {
+                            continue;
+                        }
+                        if input.trim() == "quit" {
+                            return Ok(None);
+                        }
+
+                        self.remember(&input);
+                        return Ok(Some(input));
+                    }
+                },
+                Err(ReadlineError::Interrupted) if quit_on_interrupt => return Ok(None),
+                Err(ReadlineError::Interrupted) => {
+                    if lines.is_empty() {
+                        println!("(Type \"quit\" or press Ctrl-D to exit)");
+                    }
+                    lines.clear();
+                }
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(e) => return Err(Error::Input(e.into())),
+            }
+        }
+    }
+
+    /// Asks a yes/no question, defaulting to no.
+    pub fn confirm(&mut self, question: &str) -> bool {
+        match self.editor.readline(&format!("{question} [y/N] ")) {
+            Ok(answer) => matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"),
+            Err(_) => false,
+        }
+    }
+
+    /// Asks for a line of text, returning `None` once the user gives up with
+    /// Ctrl-C or Ctrl-D.
+    pub fn ask(&mut self, question: &str) -> Result<Option<String>, Error> {
+        match self.editor.readline(&format!("{question} ")) {
+            Ok(answer) => Ok(Some(answer.trim().to_string())),
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(Error::Input(e.into())),
+        }
+    }
+
+    /// Lets the user change `text`, returning `None` once they give up with
+    /// Ctrl-C or Ctrl-D. Lines are edited joined by spaces, as the editor only
+    /// takes one.
+    pub fn edit(&mut self, text: &str) -> Result<Option<String>, Error> {
+        let text = text.lines().collect::<Vec<_>>().join(" ");
+        match self.editor.readline_with_initial("> ", (&text, "")) {
+            Ok(edited) if edited.trim().is_empty() => Ok(None),
+            Ok(edited) => {
+                self.remember(&edited);
+                Ok(Some(edited))
+            }
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(Error::Input(e.into())),
+        }
+    }
+
+    fn remember(&mut self, input: &str) {
+        let _ = self.editor.add_history_entry(input);
+
+        if let Some(path) = &self.history_path {
+            if let Some(dir) = path.parent() {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            if let Err(e) = self.editor.save_history(path) {
+                eprintln!("Failed to save input history to {}: {e}", path.display());
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! How long what the agent keeps on disk is kept: sessions and background
+//! jobs in the database, transcripts, the checkpoints of unfinished runs, the
+//! audit log and the input history, which all hold what was asked and read.
+//! `purge` deletes what's older than `[retention]` allows, overwriting it
+//! first, and `persist = false` keeps it from being written at all, but for
+//! the audit log, which is written without the prompts.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use serde_json::Value;
+
+use crate::{
+    audit,
+    cli::PurgeArgs,
+    config::{Config, RetentionConfig},
+    qualify::checkpoint,
+    repl, session, store, transcript,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Whether sessions, jobs, transcripts, the input history and the prompts in
+/// the audit log are written, set from `[retention] persist` before any of
+/// them are.
+static PERSIST: AtomicBool = AtomicBool::new(true);
+
+pub fn init(config: &RetentionConfig) {
+    PERSIST.store(config.persist, Ordering::Relaxed);
+}
+
+/// Whether anything that holds what was asked and read is kept on disk.
+pub fn persists() -> bool {
+    PERSIST.load(Ordering::Relaxed)
+}
+
+/// `purge`: deletes the saved data that's been kept for longer than
+/// `[retention]` allows, or all of it with `--all`.
+pub fn purge(config: &Config, args: &PurgeArgs) -> anyhow::Result<()> {
+    let retention = &config.retention;
+    let now = session::unix_now();
+    // The Unix timestamp before which data kept for `days` has expired.
+    let cutoff = |days: u64| match (args.all, days) {
+        // The latest timestamp SQLite can compare with.
+        (true, _) => Some(i64::MAX as u64),
+        (false, 0) => None,
+        (false, days) => Some(now.saturating_sub(days * DAY)),
+    };
+    let mut purged = Vec::new();
+
+    if let Some(before) = cutoff(retention.sessions_days) {
+        let mut sessions = store::open()?.purge_sessions(before)?;
+        // Sessions saved as files by earlier versions.
+        if let Ok(dir) = session::sessions_dir() {
+            sessions += purge_files(&dir, before)?;
+        }
+        purged.push(format!("{sessions} sessions"));
+    }
+    if let Some(before) = cutoff(retention.jobs_days) {
+        let jobs = store::open()?.purge_jobs(before)?;
+        purged.push(format!("{jobs} background jobs"));
+    }
+    if let Some(before) = cutoff(retention.transcripts_days) {
+        let transcripts = purge_files(&transcript::dir(&config.transcript)?, before)?;
+        purged.push(format!("{transcripts} transcripts"));
+        // Those of runs that never finished, which hold the leads they read.
+        let checkpoints = purge_files(&checkpoint::dir()?, before)?;
+        purged.push(format!("{checkpoints} checkpoints"));
+    }
+    if let Some(before) = cutoff(retention.audit_days) {
+        let entries = purge_lines(&audit::path(&config.audit)?, before)?;
+        purged.push(format!("{entries} audit log entries"));
+    }
+    if let Some(before) = cutoff(retention.input_history_days)
+        && let Some(path) = repl::history_path()
+        && path.exists()
+        && modified(&path)? < before
+    {
+        shred(&path)?;
+        purged.push("the input history".to_string());
+    }
+
+    if purged.is_empty() {
+        println!(
+            "Nothing is set to expire. Set how many days to keep each kind of data under \
+             [retention], or pass --all to delete all of it."
+        );
+    } else {
+        println!("Deleted {}.", purged.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Shreds the files in `dir` last written to before `before`, returning how
+/// many there were.
+fn purge_files(dir: &Path, before: u64) -> anyhow::Result<usize> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(0);
+    };
+
+    let mut purged = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() && modified(&path)? < before {
+            shred(&path)?;
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
+
+/// Drops the lines of the JSON Lines file `path` whose `at` is before
+/// `before`, overwriting them, and returns how many there were.
+fn purge_lines(path: &Path, before: u64) -> anyhow::Result<usize> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Ok(0);
+    };
+
+    let (expired, kept): (Vec<&str>, Vec<&str>) = contents.lines().partition(|line| {
+        serde_json::from_str::<Value>(line)
+            .ok()
+            .and_then(|entry| entry["at"].as_u64())
+            .is_some_and(|at| at < before)
+    });
+    if expired.is_empty() {
+        return Ok(0);
+    }
+    if kept.is_empty() {
+        shred(path)?;
+        return Ok(expired.len());
+    }
+
+    let kept: String = kept.iter().map(|line| format!("{line}\n")).collect();
+    overwrite(path, kept.as_bytes(), contents.len())?;
+
+    Ok(expired.len())
+}
+
+/// Overwrites `path` with zeros before deleting it, so what it held can't be
+/// read back from the disk it was on. SSDs and copy-on-write file systems may
+/// keep the old blocks around regardless.
+pub fn shred(path: &Path) -> anyhow::Result<()> {
+    let len = std::fs::metadata(path)?.len() as usize;
+    overwrite(path, &[], len)?;
+    std::fs::remove_file(path).with_context(|| format!("Failed to delete {}", path.display()))
+}
+
+/// Replaces the `len` bytes of `path` with `contents`, padded with zeros,
+/// and then cuts it down to `contents`.
+fn overwrite(path: &Path, contents: &[u8], len: usize) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(contents)?;
+    file.write_all(&vec![0; len.saturating_sub(contents.len())])?;
+    file.sync_all()?;
+    file.set_len(contents.len() as u64)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// When `path` was last written to, as a Unix timestamp.
+fn modified(path: &Path) -> anyhow::Result<u64> {
+    let modified = std::fs::metadata(path)?.modified()?;
+
+    Ok(modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs())
+}
@@ -0,0 +1,339 @@
+//! Deterministic lead scoring. The model only extracts facts about each lead;
+//! points are added up locally from weighted criteria, so the same lead always
+//! gets the same score.
+
+use std::{collections::BTreeMap, convert::Infallible, path::Path, sync::Arc};
+
+use anyhow::Context;
+use rig::{
+    completion::ToolDefinition,
+    tool::{Tool, ToolSet},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::{config::ToolsConfig, tools::is_allowed};
+
+/// Weighted criteria loaded from a YAML file, e.g.
+///
+/// ```yaml
+/// name: B2B SaaS
+/// fields:
+///   company_size: { type: number, description: Number of employees }
+///   free_email: { type: boolean, description: Whether the email is from a free provider }
+/// criteria:
+///   - { field: company_size, gte: 50, points: 3 }
+///   - { field: free_email, equals: true, points: -2 }
+/// threshold: 2
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rubric {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Facts the model extracts for every lead, keyed by field name.
+    pub fields: BTreeMap<String, Field>,
+    pub criteria: Vec<Criterion>,
+    /// Minimum score of a qualified lead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    String,
+    Number,
+    Boolean,
+}
+
+/// Awards `points` to leads whose `field` meets every condition given.
+///
+/// String comparisons ignore case. A field the model couldn't extract never
+/// meets a condition.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Criterion {
+    pub field: String,
+    pub points: f64,
+    /// Shown when explaining a score, instead of the conditions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_of: Option<Vec<Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gte: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lte: Option<f64>,
+}
+
+/// The score of one lead and the criteria that contributed to it.
+#[derive(Debug, Serialize)]
+pub struct Score {
+    pub id: String,
+    pub score: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qualified: Option<bool>,
+    pub reasons: Vec<String>,
+}
+
+impl Rubric {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rubric {}", path.display()))?;
+        let rubric: Self = serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse rubric {}", path.display()))?;
+
+        for criterion in &rubric.criteria {
+            anyhow::ensure!(
+                rubric.fields.contains_key(&criterion.field),
+                "Rubric criterion refers to the undefined field `{}`",
+                criterion.field
+            );
+            anyhow::ensure!(
+                !criterion.conditions().is_empty(),
+                "Rubric criterion on `{}` has no condition",
+                criterion.field
+            );
+        }
+
+        Ok(rubric)
+    }
+
+    pub fn score(&self, id: String, fields: &Map<String, Value>) -> Score {
+        let mut score = 0.0;
+        let mut reasons = Vec::new();
+
+        for criterion in &self.criteria {
+            if criterion.matches(fields.get(&criterion.field)) {
+                score += criterion.points;
+                reasons.push(format!("{} ({:+})", criterion.describe(), criterion.points));
+            }
+        }
+
+        Score {
+            id,
+            score,
+            qualified: self.threshold.map(|threshold| score >= threshold),
+            reasons,
+        }
+    }
+
+    /// Added to the preamble, so the model uses the scoring tool instead of
+    /// judging leads itself.
+    pub fn instructions(&self) -> String {
+        let name = self.name.as_deref().unwrap_or("configured");
+        let mut instructions = format!(
+            "\nQualify leads with the {name} scoring rubric. For every lead, extract the \
+             fields below from its row and pass them to the `{}` tool, which returns the \
+             lead's score. Never score leads yourself, and leave a field out when the row \
+             doesn't tell. Write each lead's score",
+            ScoreLeads::NAME
+        );
+        if self.threshold.is_some() {
+            instructions.push_str(" and whether it is qualified");
+        }
+        instructions.push_str(" to the results.\nFields:\n");
+
+        for (name, field) in &self.fields {
+            instructions.push_str(&format!("- {name}: {}\n", field.description));
+        }
+
+        instructions
+    }
+}
+
+impl Criterion {
+    fn conditions(&self) -> Vec<String> {
+        let mut conditions = Vec::new();
+
+        if let Some(expected) = &self.equals {
+            conditions.push(format!("= {expected}"));
+        }
+        if let Some(options) = &self.one_of {
+            conditions.push(format!("in {}", Value::from(options.clone())));
+        }
+        if let Some(needle) = &self.contains {
+            conditions.push(format!("contains {needle:?}"));
+        }
+        for (op, bound) in [
+            (">", self.gt),
+            (">=", self.gte),
+            ("<", self.lt),
+            ("<=", self.lte),
+        ] {
+            if let Some(bound) = bound {
+                conditions.push(format!("{op} {bound}"));
+            }
+        }
+
+        conditions
+    }
+
+    fn describe(&self) -> String {
+        match &self.label {
+            Some(label) => label.clone(),
+            None => format!("{} {}", self.field, self.conditions().join(" and ")),
+        }
+    }
+
+    fn matches(&self, value: Option<&Value>) -> bool {
+        let Some(value) = value.filter(|value| !value.is_null()) else {
+            return false;
+        };
+
+        let number = as_number(value);
+        let bounds_hold = [
+            (self.gt, number.zip(self.gt).is_some_and(|(n, b)| n > b)),
+            (self.gte, number.zip(self.gte).is_some_and(|(n, b)| n >= b)),
+            (self.lt, number.zip(self.lt).is_some_and(|(n, b)| n < b)),
+            (self.lte, number.zip(self.lte).is_some_and(|(n, b)| n <= b)),
+        ]
+        .into_iter()
+        .all(|(bound, holds)| bound.is_none() || holds);
+
+        bounds_hold
+            && self
+                .equals
+                .as_ref()
+                .is_none_or(|expected| values_equal(value, expected))
+            && self
+                .one_of
+                .as_ref()
+                .is_none_or(|options| options.iter().any(|option| values_equal(value, option)))
+            && self.contains.as_ref().is_none_or(|needle| {
+                value
+                    .as_str()
+                    .is_some_and(|text| text.to_lowercase().contains(&needle.to_lowercase()))
+            })
+    }
+}
+
+/// Adds the scoring tool unless `filter` hides it, returning the instructions
+/// to append to the preamble.
+pub async fn add_tool(
+    rubric: Rubric,
+    filter: &ToolsConfig,
+    toolset: &mut ToolSet,
+    definitions: &mut Vec<ToolDefinition>,
+) -> Option<String> {
+    if !is_allowed(filter, ScoreLeads::NAME) {
+        return None;
+    }
+
+    let instructions = rubric.instructions();
+    let tool = ScoreLeads(Arc::new(rubric));
+    definitions.push(tool.definition(String::new()).await);
+    toolset.add_tool(tool);
+
+    Some(instructions)
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn values_equal(value: &Value, expected: &Value) -> bool {
+    match (value, expected) {
+        (Value::String(a), Value::String(b)) => a.eq_ignore_ascii_case(b),
+        (value, Value::Number(_)) => as_number(value) == as_number(expected),
+        (Value::String(text), Value::Bool(expected)) => text.parse() == Ok(*expected),
+        (value, expected) => value == expected,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScoreLeadsArgs {
+    leads: Vec<Lead>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Lead {
+    id: String,
+    #[serde(default)]
+    fields: Map<String, Value>,
+}
+
+/// Scores the leads whose fields the model extracted.
+pub struct ScoreLeads(pub Arc<Rubric>);
+
+impl Tool for ScoreLeads {
+    const NAME: &'static str = "rubric__score_leads";
+
+    type Error = Infallible;
+    type Args = ScoreLeadsArgs;
+    type Output = Vec<Score>;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        let fields: Map<String, Value> = self
+            .0
+            .fields
+            .iter()
+            .map(|(name, field)| {
+                let kind = match field.kind {
+                    FieldKind::String => "string",
+                    FieldKind::Number => "number",
+                    FieldKind::Boolean => "boolean",
+                };
+                let schema = json!({ "type": kind, "description": field.description });
+
+                (name.clone(), schema)
+            })
+            .collect();
+
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Score leads with the qualification rubric, given the fields \
+                          extracted from their rows."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "leads": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "id": {
+                                    "type": "string",
+                                    "description": "Identifies the lead, e.g. its row number"
+                                },
+                                "fields": { "type": "object", "properties": fields }
+                            },
+                            "required": ["id", "fields"]
+                        }
+                    }
+                },
+                "required": ["leads"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Vec<Score>, Infallible> {
+        Ok(args
+            .leads
+            .into_iter()
+            .map(|lead| self.0.score(lead.id, &lead.fields))
+            .collect())
+    }
+}
@@ -0,0 +1,149 @@
+//! Qualifying the rows added to sheets since the last run, on cron schedules,
+//! for forms that keep collecting responses.
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use croner::Cron;
+use rig::streaming::StreamingCompletionModel;
+
+use crate::{
+    chat::Agent,
+    cli::{QualifyArgs, ScheduleArgs},
+    config::ScheduleConfig,
+    output::OutputFormat,
+    provider::Model,
+    qualify::{self, Pipeline, Resources},
+    store,
+};
+
+/// Parses the cron expression of a schedule, in the five-field format (or six,
+/// with seconds first).
+pub fn parse(cron: &str) -> anyhow::Result<Cron> {
+    cron.parse()
+        .with_context(|| format!("Invalid cron expression `{cron}`"))
+}
+
+/// Waits for each schedule to come up and qualifies the rows added since its
+/// sheet was last qualified, until the process is stopped. With `--once`, every
+/// schedule runs right away, once.
+///
+/// A run that fails is reported and retried at the next time it's due.
+pub async fn run(
+    agent: &Agent<Model>,
+    resources: &Resources,
+    schedules: &[ScheduleConfig],
+    args: &ScheduleArgs,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(!schedules.is_empty(), "No [[schedule]] is configured");
+    let crons = schedules
+        .iter()
+        .map(|schedule| parse(&schedule.cron))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if args.once {
+        for schedule in schedules {
+            qualify_new_rows(agent, resources, schedule, output).await;
+        }
+        return Ok(());
+    }
+
+    loop {
+        let now = Local::now();
+        let next = crons
+            .iter()
+            .map(|cron| cron.find_next_occurrence(&now, false))
+            .collect::<Result<Vec<DateTime<Local>>, _>>()?;
+        let Some(due) = next.iter().min().copied() else {
+            return Ok(());
+        };
+        eprintln!("Next run at {}", due.format("%Y-%m-%d %H:%M:%S"));
+
+        tokio::time::sleep((due - now).to_std().unwrap_or_default()).await;
+        for (schedule, _) in schedules.iter().zip(&next).filter(|(_, at)| **at == due) {
+            qualify_new_rows(agent, resources, schedule, output).await;
+        }
+    }
+}
+
+async fn qualify_new_rows(
+    agent: &Agent<Model>,
+    resources: &Resources,
+    schedule: &ScheduleConfig,
+    output: OutputFormat,
+) {
+    if let Err(e) = try_qualify_new_rows(agent, resources, schedule, output).await {
+        eprintln!("Failed to qualify `{}`: {e:#}", schedule.sheet);
+    }
+}
+
+async fn try_qualify_new_rows(
+    agent: &Agent<Model>,
+    resources: &Resources,
+    schedule: &ScheduleConfig,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let args = QualifyArgs {
+        spreadsheet: schedule.spreadsheet.clone(),
+        sheet: schedule.sheet.clone(),
+        criteria: schedule.criteria.clone(),
+        chunk_size: None,
+        overlap: None,
+        concurrency: None,
+        from_row: None,
+        incremental: false,
+        resume_run: None,
+        results_sheet: schedule.results_sheet.clone(),
+        cleaned_sheet: None,
+        named_range: schedule.named_range.clone(),
+        summary: false,
+        export_csv: None,
+        report: None,
+        push_to: schedule.push_to,
+        force: false,
+    };
+
+    qualify_since_last_run(agent, resources, args, None, output).await
+}
+
+/// Qualifies the rows of `args.sheet` added since it was last qualified this
+/// way, or all of them if it never was, and records the last row with cells
+/// in it. `row`, a row known to be new, is where qualifying starts if the
+/// sheet never was qualified, and at the latest if it was.
+/// Scheduled runs and form submissions share the record, so they qualify
+/// each row once between them.
+pub async fn qualify_since_last_run<M: StreamingCompletionModel>(
+    agent: &Agent<M>,
+    resources: &Resources,
+    mut args: QualifyArgs,
+    row: Option<u64>,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let key = format!("{}/{}", args.spreadsheet, args.sheet);
+    // Row 1 holds the headers.
+    let first_row = match (store::open()?.last_row(&key)?, row) {
+        (Some(last_row), Some(row)) => (last_row + 1).min(row),
+        (Some(last_row), None) => last_row + 1,
+        (None, row) => row.unwrap_or(2),
+    };
+    eprintln!("Qualifying `{}` from row {first_row}", args.sheet);
+
+    args.from_row = Some(first_row);
+    let pipeline = Pipeline {
+        agent,
+        resources,
+        output,
+        progress: None,
+        bar: None,
+    };
+
+    // Rows whose chunk failed are in the results with their error, so they're
+    // not qualified again either. A dry run writes no results.
+    if let Some(row) = qualify::run(pipeline, &args).await?.last_row
+        && !resources.dry_run
+    {
+        store::open()?.set_last_row(&key, row)?;
+    }
+
+    Ok(())
+}
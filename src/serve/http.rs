@@ -0,0 +1,159 @@
+//! An HTTP API, so other services can drive the agent without a terminal.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+
+use super::ApiError;
+use crate::{
+    chat::{Agent, Turn},
+    cli::QualifyArgs,
+    error::Error,
+    jobs::{Job, Jobs},
+    provider::Model,
+    qualify::{self, Resources},
+};
+
+/// Everything the handlers share.
+pub struct Server {
+    pub agent: Arc<Agent<Model>>,
+    pub resources: Arc<Resources>,
+    pub jobs: Arc<Jobs>,
+    /// Bearer token requests must carry, if any.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChatRequest {
+    prompt: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatResponse {
+    #[serde(flatten)]
+    turn: Turn,
+    /// Why the prompt didn't complete, if it didn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Server {
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        super::authorize(self.token.as_deref(), headers)
+    }
+}
+
+/// Serves the API on `listen` until the process is stopped.
+pub async fn run(server: Server, listen: SocketAddr) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/v1/chat", post(chat))
+        .route("/v1/qualify", post(start_qualify))
+        .route("/v1/jobs", get(list_jobs))
+        .route("/v1/jobs/{id}", get(get_job).delete(cancel_job))
+        .with_state(Arc::new(server));
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    eprintln!("Listening on http://{listen}");
+
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+/// Answers a prompt with a fresh history, so requests don't see each other.
+async fn chat(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    Json(request): Json<ChatRequest>,
+) -> Result<Json<ChatResponse>, ApiError> {
+    server.authorize(&headers)?;
+
+    let mut history = Vec::new();
+    let transcript = server
+        .agent
+        .transcript(&format!("http-{}", uuid::Uuid::new_v4()));
+    let res = server
+        .agent
+        .call_until_response(
+            request.prompt.as_str().into(),
+            &mut history,
+            transcript.as_ref(),
+            None,
+        )
+        .await;
+
+    match res {
+        Ok(turn) => Ok(Json(ChatResponse { turn, error: None })),
+        Err(Error::LimitExceeded(limit)) => {
+            let error = format!("Stopped after the agent {}", limit.reason);
+            Ok(Json(ChatResponse {
+                turn: limit.abandon(&mut history),
+                error: Some(error),
+            }))
+        }
+        Err(e) => Err(ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Starts qualifying a sheet in the background and answers right away with
+/// the job, to be polled at `/v1/jobs/{id}`.
+async fn start_qualify(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    Json(args): Json<QualifyArgs>,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    server.authorize(&headers)?;
+
+    let id = qualify::start(
+        &server.jobs,
+        server.agent.clone(),
+        server.resources.clone(),
+        args,
+    );
+
+    Ok((StatusCode::ACCEPTED, Json(job(&server, &id)?)))
+}
+
+async fn list_jobs(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Job>>, ApiError> {
+    server.authorize(&headers)?;
+
+    Ok(Json(server.jobs.list()))
+}
+
+async fn get_job(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Job>, ApiError> {
+    server.authorize(&headers)?;
+
+    Ok(Json(job(&server, &id)?))
+}
+
+async fn cancel_job(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Job>, ApiError> {
+    server.authorize(&headers)?;
+
+    server.jobs.cancel(&id).map(Json).ok_or_else(|| no_job(&id))
+}
+
+fn job(server: &Server, id: &str) -> Result<Job, ApiError> {
+    server.jobs.get(id).ok_or_else(|| no_job(id))
+}
+
+fn no_job(id: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("There's no job {id}"))
+}
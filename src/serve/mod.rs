@@ -0,0 +1,42 @@
+//! Running the agent behind a front-end other than the terminal.
+
+pub mod http;
+pub mod slack;
+pub mod webhook;
+
+use axum::{
+    Json,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+/// A failed request, answered with `{"error": ...}`.
+pub struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Refuses requests that don't carry `token` as a bearer token, if there is
+/// one to carry.
+fn authorize(token: Option<&str>, headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(token) = token else {
+        return Ok(());
+    };
+
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if bearer != Some(token) {
+        return Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong bearer token".to_string(),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,294 @@
+//! A Slack bot: mentions are answered in their thread by the same tool loop
+//! as the terminal, over a Socket Mode connection so no public URL is needed.
+
+use std::collections::HashMap;
+
+use futures::{SinkExt, StreamExt};
+use rig::message::Message;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite;
+
+use crate::{
+    chat::Agent,
+    config::{BackoffConfig, SlackConfig},
+    error::Error,
+    provider::Model,
+};
+
+const API_URL: &str = "https://slack.com/api";
+
+/// Added to the preamble, so replies point to where the results went.
+pub const INSTRUCTIONS: &str = "\nYou're answering people who mention you in Slack. Keep replies \
+    short. When you write results to a sheet, end your reply with the name of the sheet and a \
+    link to its spreadsheet, https://docs.google.com/spreadsheets/d/<ID>.";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SlackError {
+    #[error("Set {0} to run the Slack bot")]
+    MissingEnv(String),
+    #[error("Request to Slack failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Slack returned an error: {0}")]
+    Api(String),
+    #[error("The Socket Mode connection failed: {0}")]
+    Socket(#[from] tungstenite::Error),
+}
+
+/// A message mentioning the bot.
+struct Mention {
+    channel: String,
+    /// The thread to reply in, which starts at the mention unless it's in one.
+    thread_ts: String,
+    text: String,
+}
+
+impl Mention {
+    fn from_event(event: &Value) -> Option<Self> {
+        // Only mentions by people; the bot's own messages would loop.
+        if event["type"] != "app_mention" || !event["bot_id"].is_null() {
+            return None;
+        }
+
+        Some(Self {
+            channel: event["channel"].as_str()?.to_string(),
+            thread_ts: event["thread_ts"]
+                .as_str()
+                .or(event["ts"].as_str())?
+                .to_string(),
+            text: plain_text(event["text"].as_str()?),
+        })
+    }
+}
+
+/// What Socket Mode sends over the connection.
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    kind: String,
+    /// Set on messages that have to be acknowledged.
+    envelope_id: Option<String>,
+    #[serde(default)]
+    payload: Value,
+}
+
+#[derive(Clone)]
+struct SlackClient {
+    http: reqwest::Client,
+    /// App-level token with the `connections:write` scope, for Socket Mode.
+    app_token: String,
+    bot_token: String,
+}
+
+impl SlackClient {
+    fn new(config: &SlackConfig) -> Result<Self, SlackError> {
+        let env =
+            |name: &String| std::env::var(name).map_err(|_| SlackError::MissingEnv(name.clone()));
+
+        Ok(Self {
+            http: reqwest::Client::new(),
+            app_token: env(&config.app_token_env)?,
+            bot_token: env(&config.bot_token_env)?,
+        })
+    }
+
+    async fn call(&self, method: &str, token: &str, body: Value) -> Result<Value, SlackError> {
+        let response: Value = self
+            .http
+            .post(format!("{API_URL}/{method}"))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // Slack reports errors with a 200 and `ok: false`.
+        if response["ok"] != true {
+            let error = response["error"].as_str().unwrap_or("unknown error");
+            return Err(SlackError::Api(format!("{method}: {error}")));
+        }
+
+        Ok(response)
+    }
+
+    /// The WebSocket URL of a new Socket Mode connection.
+    async fn open_connection(&self) -> Result<String, SlackError> {
+        let response = self
+            .call("apps.connections.open", &self.app_token, json!({}))
+            .await?;
+
+        response["url"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| SlackError::Api("apps.connections.open: no URL".to_string()))
+    }
+
+    async fn reply(&self, mention: &Mention, text: &str) -> Result<(), SlackError> {
+        self.call(
+            "chat.postMessage",
+            &self.bot_token,
+            json!({
+                "channel": mention.channel,
+                "thread_ts": mention.thread_ts,
+                "text": text,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Answers mentions of the bot until the connection to Slack can't be
+/// restored.
+///
+/// Mentions are answered one at a time, each thread with its own history, so
+/// follow-ups in a thread carry on the same conversation.
+pub async fn run(agent: &Agent<Model>, config: &SlackConfig) -> anyhow::Result<()> {
+    let client = SlackClient::new(config)?;
+    let (sender, mut mentions) = mpsc::unbounded_channel();
+    // Connections are read on their own task, so envelopes are acknowledged
+    // while the agent works.
+    let listener = tokio::spawn(listen(client.clone(), config.reconnect.clone(), sender));
+    eprintln!("Listening for mentions on Slack");
+
+    let mut threads: HashMap<(String, String), Vec<Message>> = HashMap::new();
+    while let Some(mention) = mentions.recv().await {
+        eprintln!("> {}", mention.text);
+        let history = threads
+            .entry((mention.channel.clone(), mention.thread_ts.clone()))
+            .or_default();
+
+        let transcript =
+            agent.transcript(&format!("slack-{}-{}", mention.channel, mention.thread_ts));
+        let answer = match agent
+            .call_until_response(
+                mention.text.as_str().into(),
+                history,
+                transcript.as_ref(),
+                None,
+            )
+            .await
+        {
+            Ok(turn) => turn.answer,
+            Err(Error::LimitExceeded(limit)) => limit.abandon(history).answer,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                format!("Sorry, that didn't work: {e}")
+            }
+        };
+
+        if let Err(e) = client.reply(&mention, &answer).await {
+            eprintln!("Failed to reply on Slack: {e}");
+        }
+    }
+
+    // Mentions only stop coming when the listener gives up.
+    Err(listener.await?.into())
+}
+
+/// Keeps a Socket Mode connection open, reconnecting when Slack asks to or the
+/// connection drops, and returns the error that made it give up.
+async fn listen(
+    client: SlackClient,
+    backoff: BackoffConfig,
+    mentions: mpsc::UnboundedSender<Mention>,
+) -> SlackError {
+    let mut failures = 0;
+
+    loop {
+        let error = match connect(&client, &mentions).await {
+            Ok(()) => {
+                failures = 0;
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        failures += 1;
+        if failures >= backoff.max_attempts {
+            return error;
+        }
+
+        let delay = backoff.delay(failures - 1);
+        eprintln!(
+            "Lost the connection to Slack, reconnecting in {:.1}s: {error}",
+            delay.as_secs_f64()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Reads one connection until it closes, acknowledging every envelope and
+/// passing on the mentions.
+async fn connect(
+    client: &SlackClient,
+    mentions: &mpsc::UnboundedSender<Mention>,
+) -> Result<(), SlackError> {
+    let url = client.open_connection().await?;
+    let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str()).await?;
+
+    while let Some(message) = socket.next().await {
+        let text = match message? {
+            tungstenite::Message::Text(text) => text,
+            tungstenite::Message::Close(_) => break,
+            _ => continue,
+        };
+        let Ok(envelope) = serde_json::from_str::<Envelope>(&text) else {
+            continue;
+        };
+
+        // Unacknowledged envelopes are sent again.
+        if let Some(id) = &envelope.envelope_id {
+            let ack = json!({ "envelope_id": id }).to_string();
+            socket.send(tungstenite::Message::text(ack)).await?;
+        }
+
+        match envelope.kind.as_str() {
+            "disconnect" => break,
+            "events_api" => {
+                if let Some(mention) = Mention::from_event(&envelope.payload["event"]) {
+                    let _ = mentions.send(mention);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// `text` without Slack's markup: user mentions are dropped, and links and
+/// channels are replaced by their URL and name.
+fn plain_text(text: &str) -> String {
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        plain.push_str(&rest[..start]);
+        let (target, label) = rest[start + 1..start + end]
+            .split_once('|')
+            .unwrap_or((&rest[start + 1..start + end], ""));
+
+        if target.starts_with('#') && !label.is_empty() {
+            plain.push_str(&format!("#{label}"));
+        } else if !target.starts_with('@') && !target.starts_with('!') {
+            plain.push_str(target);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    plain.push_str(rest);
+
+    plain
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,123 @@
+//! An endpoint for the `onFormSubmit` trigger of an Apps Script to post form
+//! responses to as they come in, so each is qualified and its verdict
+//! appended to the results sheet within seconds, rather than at the next
+//! scheduled run.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use rig::streaming::StreamingCompletionModel;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::sync::Mutex;
+
+use super::ApiError;
+use crate::{
+    chat::Agent, cli::QualifyArgs, crm::CrmTarget, output::OutputFormat, provider::Model,
+    qualify::Resources, schedule,
+};
+
+/// Everything the handler shares.
+pub struct Server {
+    pub agent: Arc<Agent<Model>>,
+    pub resources: Arc<Resources>,
+    /// Bearer token requests must carry, if any.
+    pub token: Option<String>,
+    /// Held while a submission is qualified, so the next one starts after the
+    /// rows it read.
+    pub running: Mutex<()>,
+}
+
+/// A form response, as posted by the trigger.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Submission {
+    spreadsheet: String,
+    sheet: String,
+    /// The row the response went to, `e.range.getRow()` in the trigger. Rows
+    /// above it are left alone the first time the sheet is posted about.
+    row: Option<u64>,
+    criteria: Option<String>,
+    results_sheet: Option<String>,
+    named_range: Option<String>,
+    push_to: Option<CrmTarget>,
+}
+
+/// Serves the endpoint on `listen` until the process is stopped.
+pub async fn run(server: Server, listen: SocketAddr) -> anyhow::Result<()> {
+    if server.token.is_none() {
+        eprintln!("No token is set, so anyone who can reach the endpoint can post to it");
+    }
+    let app = Router::new()
+        .route("/v1/submissions", post(submit))
+        .with_state(Arc::new(server));
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    eprintln!("Listening for form submissions on http://{listen}/v1/submissions");
+
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+/// Qualifies the rows of the sheet added since it last was, in the
+/// background, and answers right away, since triggers can't wait long.
+///
+/// Submissions that come in together are qualified one after the other, and
+/// the first to run takes in the rows of the others, which the ledger of
+/// incremental runs then skips.
+async fn submit(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    Json(submission): Json<Submission>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    super::authorize(server.token.as_deref(), &headers)?;
+
+    tokio::spawn(async move {
+        let _running = server.running.lock().await;
+        let sheet = submission.sheet.clone();
+        if let Err(e) = qualify(&server.agent, &server.resources, submission).await {
+            eprintln!("Failed to qualify the submission to `{sheet}`: {e:#}");
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "status": "accepted" }))))
+}
+
+/// Qualifies the rows of the sheet `submission` went to that are new since it
+/// last was, taking in its own row even if the last run recorded reading it.
+pub async fn qualify<M: StreamingCompletionModel>(
+    agent: &Agent<M>,
+    resources: &Resources,
+    submission: Submission,
+) -> anyhow::Result<()> {
+    // Row 1 holds the headers.
+    let row = submission.row.map(|row| row.max(2));
+    let args = QualifyArgs {
+        spreadsheet: submission.spreadsheet,
+        sheet: submission.sheet,
+        criteria: submission.criteria,
+        chunk_size: None,
+        overlap: None,
+        concurrency: None,
+        from_row: None,
+        // Rows read again because of `row` were qualified already.
+        incremental: true,
+        resume_run: None,
+        results_sheet: submission.results_sheet,
+        cleaned_sheet: None,
+        named_range: submission.named_range,
+        summary: false,
+        export_csv: None,
+        report: None,
+        push_to: submission.push_to,
+        force: false,
+    };
+
+    schedule::qualify_since_last_run(agent, resources, args, row, OutputFormat::Text).await
+}
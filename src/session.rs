@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use rig::message::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::{retention, store, usage::Usage};
+
+/// A conversation saved in the database, so it can be resumed with `--resume <id>`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    /// Unix timestamp, in seconds.
+    pub created_at: u64,
+    /// Unix timestamp, in seconds.
+    pub updated_at: u64,
+    /// Full history, including tool calls and their results.
+    pub chat_history: Vec<Message>,
+    /// Tokens used so far, keyed by model name.
+    #[serde(default)]
+    pub usage: BTreeMap<String, Usage>,
+    /// The branch `chat_history` is the history of, see [`Session::fork`].
+    #[serde(default = "main_branch")]
+    pub branch: String,
+    /// The histories of the other branches, keyed by name.
+    #[serde(default)]
+    pub branches: BTreeMap<String, Vec<Message>>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let now = unix_now();
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            created_at: now,
+            updated_at: now,
+            chat_history: Vec::new(),
+            usage: BTreeMap::new(),
+            branch: main_branch(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Loads a saved session. Sessions saved as JSON files by older versions
+    /// are moved into the database the first time they're loaded.
+    pub fn load(id: &str) -> anyhow::Result<Self> {
+        let mut store = store::open()?;
+        if let Some(session) = store.load_session(id)? {
+            return Ok(session);
+        }
+
+        let path = legacy_path(id)?;
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("There's no saved session {id}"))?;
+        let session: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse session file {}", path.display()))?;
+        store.save_session(&session)?;
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+
+        Ok(session)
+    }
+
+    pub fn record_usage(&mut self, model: &str, usage: Usage) {
+        *self.usage.entry(model.to_string()).or_default() += usage;
+    }
+
+    /// Goes on with the conversation in a new branch `name`, keeping the
+    /// history so far under the branch it's on, to switch back to later.
+    pub fn fork(&mut self, name: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "Branch names are made of letters, digits, `-` and `_`"
+        );
+        anyhow::ensure!(
+            name != self.branch && !self.branches.contains_key(name),
+            "There's already a branch `{name}`"
+        );
+
+        self.branches
+            .insert(self.branch.clone(), self.chat_history.clone());
+        self.branch = name.to_string();
+
+        Ok(())
+    }
+
+    /// Switches to the branch `name`, keeping the one it was on.
+    pub fn switch(&mut self, name: &str) -> anyhow::Result<()> {
+        let history = self
+            .branches
+            .remove(name)
+            .with_context(|| format!("There's no branch `{name}`, see /branches"))?;
+        let previous = std::mem::replace(&mut self.chat_history, history);
+        self.branches.insert(
+            std::mem::replace(&mut self.branch, name.to_string()),
+            previous,
+        );
+
+        Ok(())
+    }
+
+    /// How many messages the branch `name` starts with that are the same as
+    /// the current one's, i.e. where the two diverge.
+    pub fn shared(&self, name: &str) -> Option<usize> {
+        let history = self.branches.get(name)?;
+
+        Some(
+            history
+                .iter()
+                .zip(&self.chat_history)
+                .take_while(|(theirs, ours)| theirs == ours)
+                .count(),
+        )
+    }
+
+    /// Saves the session, unless nothing is kept on disk, see
+    /// [`retention::persists`].
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        self.updated_at = unix_now();
+        if !retention::persists() {
+            return Ok(());
+        }
+
+        store::open()?.save_session(self)
+    }
+}
+
+/// `~/.local/share/gsheets-agent/sessions` (or the platform equivalent), where
+/// sessions used to be saved.
+pub fn sessions_dir() -> anyhow::Result<PathBuf> {
+    dirs::data_dir()
+        .map(|dir| dir.join("gsheets-agent").join("sessions"))
+        .context("Could not determine the data directory to look for old sessions in")
+}
+
+fn legacy_path(id: &str) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+        "Invalid session id `{id}`"
+    );
+
+    Ok(sessions_dir()?.join(format!("{id}.json")))
+}
+
+/// The branch of a session that was never forked.
+pub fn main_branch() -> String {
+    "main".to_string()
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
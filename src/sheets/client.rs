@@ -0,0 +1,226 @@
+use reqwest::{Method, StatusCode, Url, header::RETRY_AFTER};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+
+use super::{Range, named};
+use crate::auth::{AuthError, GoogleAuth};
+
+const BASE_URL: &str = "https://sheets.googleapis.com/v4/spreadsheets";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SheetsError {
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error("Request to the Google Sheets API failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("The Google Sheets API returned {status}: {message}")]
+    Api {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+    #[error("The Google Sheets request was aborted: {0}")]
+    Aborted(#[from] tokio::task::JoinError),
+    #[error("A sheet called `{0}` already exists")]
+    SheetExists(String),
+    #[error("{0}")]
+    InvalidRange(String),
+    #[error("Google Sheets can only be reached with `credentials` under [auth]")]
+    NoCredentials,
+}
+
+/// Client for the parts of the Google Sheets REST API the agent uses.
+#[derive(Clone)]
+pub struct SheetsClient {
+    http: reqwest::Client,
+    auth: GoogleAuth,
+}
+
+impl SheetsClient {
+    pub fn new(auth: GoogleAuth) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            auth,
+        }
+    }
+
+    /// Titles and sizes of the sheets in a spreadsheet, and its named ranges.
+    pub async fn list_sheets(&self, spreadsheet_id: &str) -> Result<Value, SheetsError> {
+        let mut url = url(&[spreadsheet_id]);
+        url.query_pairs_mut()
+            .append_pair("fields", "properties.title,sheets.properties,namedRanges");
+
+        self.send(Method::GET, url, None).await
+    }
+
+    /// Values in `range`, in A1 notation such as `Leads!A1:F100`.
+    pub async fn read_range(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+    ) -> Result<Value, SheetsError> {
+        self.send(Method::GET, url(&[spreadsheet_id, "values", range]), None)
+            .await
+    }
+
+    /// Overwrites the cells of `range`, starting at its top-left corner.
+    pub async fn write_range(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        values: Vec<Vec<Value>>,
+    ) -> Result<Value, SheetsError> {
+        let mut url = url(&[spreadsheet_id, "values", range]);
+        url.query_pairs_mut()
+            .append_pair("valueInputOption", "USER_ENTERED");
+
+        self.send(Method::PUT, url, Some(json!({ "values": values })))
+            .await
+    }
+
+    /// Adds rows after the last row of the table found in `range`.
+    pub async fn append_rows(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        values: Vec<Vec<Value>>,
+    ) -> Result<Value, SheetsError> {
+        let mut url = url(&[spreadsheet_id, "values", &format!("{range}:append")]);
+        url.query_pairs_mut()
+            .append_pair("valueInputOption", "USER_ENTERED")
+            .append_pair("insertDataOption", "INSERT_ROWS");
+
+        self.send(Method::POST, url, Some(json!({ "values": values })))
+            .await
+    }
+
+    /// Adds a sheet called `title` to the spreadsheet.
+    pub async fn create_sheet(
+        &self,
+        spreadsheet_id: &str,
+        title: &str,
+    ) -> Result<Value, SheetsError> {
+        let res = self
+            .batch_update(
+                spreadsheet_id,
+                vec![json!({ "addSheet": { "properties": { "title": title } } })],
+            )
+            .await;
+
+        match res {
+            Err(SheetsError::Api { status, message })
+                if status == StatusCode::BAD_REQUEST && message.contains("already exists") =>
+            {
+                Err(SheetsError::SheetExists(title.to_string()))
+            }
+            res => res,
+        }
+    }
+
+    /// Points the named range `name` at `range`, in A1 notation, adding it if
+    /// the spreadsheet doesn't have one called that yet.
+    pub async fn set_named_range(
+        &self,
+        spreadsheet_id: &str,
+        name: &str,
+        range: &str,
+    ) -> Result<Value, SheetsError> {
+        let spreadsheet = self.list_sheets(spreadsheet_id).await?;
+        let sheets: Vec<&Value> = spreadsheet["sheets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|sheet| &sheet["properties"])
+            .collect();
+        let parsed = Range::parse(range, |title| {
+            sheets.iter().any(|sheet| sheet["title"] == title)
+        })
+        .map_err(SheetsError::InvalidRange)?;
+        let sheet = match &parsed.sheet {
+            Some(title) => sheets
+                .iter()
+                .find(|sheet| sheet["title"] == title.as_str())
+                .ok_or_else(|| SheetsError::InvalidRange(format!("There's no sheet `{title}`")))?,
+            None => sheets.first().ok_or_else(|| {
+                SheetsError::InvalidRange("The spreadsheet has no sheets".to_string())
+            })?,
+        };
+        let grid = named::grid(&parsed, sheet["sheetId"].as_u64().unwrap_or(0));
+
+        let request = match named::list(&spreadsheet)
+            .into_iter()
+            .find(|named| named.name == name)
+        {
+            Some(named) => json!({
+                "updateNamedRange": {
+                    "namedRange": { "namedRangeId": named.id, "name": name, "range": grid },
+                    "fields": "range",
+                }
+            }),
+            None => json!({ "addNamedRange": { "namedRange": { "name": name, "range": grid } } }),
+        };
+
+        self.batch_update(spreadsheet_id, vec![request]).await
+    }
+
+    /// Applies `requests` to the spreadsheet in one go, as in `addSheet` or
+    /// `repeatCell`, all or none of them.
+    pub async fn batch_update(
+        &self,
+        spreadsheet_id: &str,
+        requests: Vec<Value>,
+    ) -> Result<Value, SheetsError> {
+        self.send(
+            Method::POST,
+            url(&[&format!("{spreadsheet_id}:batchUpdate")]),
+            Some(json!({ "requests": requests })),
+        )
+        .await
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<Value>,
+    ) -> Result<T, SheetsError> {
+        let token = self.auth.token().await?;
+
+        let mut request = self.http.request(method, url).bearer_auth(token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            let body: Value = response.json().await.unwrap_or_default();
+            let mut message = body["error"]["message"]
+                .as_str()
+                .unwrap_or("no error message")
+                .to_string();
+            // Read by the tools, which wait this long before trying again.
+            if let Some(secs) = retry_after {
+                message.push_str(&format!(" (retry after {secs}s)"));
+            }
+
+            return Err(SheetsError::Api { status, message });
+        }
+
+        Ok(response.json().await?)
+    }
+}
+
+/// `BASE_URL` with `segments` appended, each percent-encoded.
+fn url(segments: &[&str]) -> Url {
+    let mut url = Url::parse(BASE_URL).expect("BASE_URL is valid");
+    url.path_segments_mut()
+        .expect("BASE_URL can have a path")
+        .extend(segments);
+
+    url
+}
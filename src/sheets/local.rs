@@ -0,0 +1,353 @@
+//! Spreadsheets kept in memory, so files like CSV exports and Excel workbooks
+//! can be qualified with the same tools and pipeline as live Google Sheets.
+
+use std::{path::Path, sync::Mutex};
+
+use anyhow::Context;
+use calamine::{Data, Reader};
+use serde_json::{Value, json};
+
+use super::{
+    SheetsError,
+    range::{Range, column_name},
+};
+
+/// The spreadsheet ID under which the local workbook is reached.
+pub const LOCAL_SPREADSHEET: &str = "local";
+
+/// Sheets imported from files, changed in memory only.
+#[derive(Debug, Default)]
+pub struct Workbook {
+    sheets: Mutex<Vec<Sheet>>,
+}
+
+#[derive(Debug)]
+struct Sheet {
+    title: String,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Workbook {
+    /// Adds the rows of a CSV file as a sheet named after the file, e.g.
+    /// `leads` for `leads.csv`. Every cell is read as text.
+    pub fn import_csv(&self, path: &Path) -> anyhow::Result<String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let rows = reader
+            .records()
+            .map(|record| {
+                record.map(|record| record.iter().map(|cell| json!(cell)).collect::<Vec<_>>())
+            })
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        self.add_sheet(sheet_title(path)?, rows)
+    }
+
+    /// Adds every worksheet of an Excel (or OpenDocument) file as a sheet with
+    /// the worksheet's name, returning the names. Cells are read as the text
+    /// they show, with dates in ISO 8601.
+    pub fn import_xlsx(&self, path: &Path) -> anyhow::Result<Vec<String>> {
+        let mut workbook = calamine::open_workbook_auto(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let mut titles = Vec::new();
+        for (title, range) in workbook.worksheets() {
+            // The range starts at the first used cell, which needn't be A1.
+            let (first_row, first_column) = range.start().unwrap_or_default();
+            let mut rows = vec![Vec::new(); first_row as usize];
+            rows.extend(range.rows().map(|cells| {
+                let padding = std::iter::repeat_n(json!(""), first_column as usize);
+                padding.chain(cells.iter().map(excel_cell)).collect()
+            }));
+
+            titles.push(self.add_sheet(title, rows)?);
+        }
+
+        Ok(titles)
+    }
+
+    /// Adds a sheet called `title`, returning the title.
+    pub fn add_sheet(&self, title: String, rows: Vec<Vec<Value>>) -> anyhow::Result<String> {
+        let mut sheets = self.sheets.lock().expect("workbook lock poisoned");
+        anyhow::ensure!(
+            !sheets.iter().any(|sheet| sheet.title == title),
+            "Two imported files would both be the `{title}` sheet"
+        );
+        sheets.push(Sheet {
+            title: title.clone(),
+            rows,
+        });
+
+        Ok(title)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sheets
+            .lock()
+            .expect("workbook lock poisoned")
+            .is_empty()
+    }
+
+    pub fn titles(&self) -> Vec<String> {
+        let sheets = self.sheets.lock().expect("workbook lock poisoned");
+        sheets.iter().map(|sheet| sheet.title.clone()).collect()
+    }
+
+    /// Matches the Sheets API's `spreadsheets.get` response.
+    pub fn list_sheets(&self) -> Value {
+        let sheets = self.sheets.lock().expect("workbook lock poisoned");
+        let sheets: Vec<Value> = sheets
+            .iter()
+            .enumerate()
+            .map(|(index, sheet)| {
+                json!({
+                    "properties": {
+                        "sheetId": index,
+                        "title": sheet.title,
+                        "index": index,
+                        "gridProperties": {
+                            "rowCount": sheet.rows.len(),
+                            "columnCount": sheet.rows.iter().map(Vec::len).max().unwrap_or(0),
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        json!({ "properties": { "title": LOCAL_SPREADSHEET }, "sheets": sheets })
+    }
+
+    /// Matches the Sheets API's `values.get` response, which leaves out
+    /// trailing empty rows and cells.
+    pub fn read_range(&self, range: &str) -> Result<Value, SheetsError> {
+        let sheets = self.sheets.lock().expect("workbook lock poisoned");
+        let (sheet, range) = find(&sheets, range)?;
+
+        let last_row = range
+            .last_row
+            .unwrap_or(usize::MAX)
+            .min(sheet.rows.len().saturating_sub(1));
+        let mut values: Vec<Vec<Value>> = sheet
+            .rows
+            .get(range.first_row..=last_row)
+            .unwrap_or_default()
+            .iter()
+            .map(|row| {
+                let last_column = range
+                    .last_column
+                    .unwrap_or(usize::MAX)
+                    .min(row.len().saturating_sub(1));
+                let mut cells = row
+                    .get(range.first_column..=last_column)
+                    .unwrap_or_default()
+                    .to_vec();
+                while cells.last().is_some_and(is_empty) {
+                    cells.pop();
+                }
+                cells
+            })
+            .collect();
+        while values.last().is_some_and(Vec::is_empty) {
+            values.pop();
+        }
+
+        let mut response = json!({ "range": a1(&sheet.title, &range), "majorDimension": "ROWS" });
+        if !values.is_empty() {
+            response["values"] = json!(values);
+        }
+
+        Ok(response)
+    }
+
+    /// Overwrites the cells of `range`, starting at its top-left corner.
+    pub fn write_range(&self, range: &str, values: Vec<Vec<Value>>) -> Result<Value, SheetsError> {
+        let mut sheets = self.sheets.lock().expect("workbook lock poisoned");
+        let (sheet, range) = find_mut(&mut sheets, range)?;
+
+        Ok(write(sheet, range.first_row, range.first_column, values))
+    }
+
+    /// Adds rows after the last non-empty row of the sheet.
+    pub fn append_rows(&self, range: &str, values: Vec<Vec<Value>>) -> Result<Value, SheetsError> {
+        let mut sheets = self.sheets.lock().expect("workbook lock poisoned");
+        let (sheet, range) = find_mut(&mut sheets, range)?;
+
+        let first_row = sheet
+            .rows
+            .iter()
+            .rposition(|row| !row.iter().all(is_empty))
+            .map_or(0, |last| last + 1);
+
+        Ok(json!({ "updates": write(sheet, first_row, range.first_column, values) }))
+    }
+
+    pub fn create_sheet(&self, title: &str) -> Result<Value, SheetsError> {
+        let mut sheets = self.sheets.lock().expect("workbook lock poisoned");
+        if sheets.iter().any(|sheet| sheet.title == title) {
+            return Err(SheetsError::SheetExists(title.to_string()));
+        }
+        sheets.push(Sheet {
+            title: title.to_string(),
+            rows: Vec::new(),
+        });
+
+        Ok(json!({ "replies": [{ "addSheet": { "properties": { "title": title } } }] }))
+    }
+}
+
+/// Writes `rows` to a CSV file at `path`.
+pub fn export_csv(path: &Path, rows: &[Vec<Value>]) -> anyhow::Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+
+    for row in rows {
+        writer.write_record(row.iter().map(cell_text))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// How a cell reads as text, e.g. in a CSV file.
+pub fn cell_text(cell: &Value) -> String {
+    match cell {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        cell => cell.to_string(),
+    }
+}
+
+fn excel_cell(cell: &Data) -> Value {
+    match cell {
+        Data::DateTime(datetime) => match datetime.as_datetime() {
+            Some(datetime) if datetime.time() == chrono::NaiveTime::MIN => {
+                json!(datetime.date().to_string())
+            }
+            Some(datetime) => json!(datetime.to_string()),
+            None => json!(datetime.to_string()),
+        },
+        Data::Empty => json!(""),
+        cell => json!(cell.to_string()),
+    }
+}
+
+fn sheet_title(path: &Path) -> anyhow::Result<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(String::from)
+        .with_context(|| format!("{} has no usable file name", path.display()))
+}
+
+fn find<'a>(sheets: &'a [Sheet], range: &str) -> Result<(&'a Sheet, Range), SheetsError> {
+    let range = parse(sheets, range)?;
+    let sheet = match &range.sheet {
+        Some(title) => sheets.iter().find(|sheet| &sheet.title == title),
+        None => sheets.first(),
+    };
+
+    match sheet {
+        Some(sheet) => Ok((sheet, range)),
+        None => Err(no_sheet(&range)),
+    }
+}
+
+fn find_mut<'a>(
+    sheets: &'a mut [Sheet],
+    range: &str,
+) -> Result<(&'a mut Sheet, Range), SheetsError> {
+    let range = parse(sheets, range)?;
+    let sheet = match &range.sheet {
+        Some(title) => sheets.iter_mut().find(|sheet| &sheet.title == title),
+        None => sheets.first_mut(),
+    };
+
+    match sheet {
+        Some(sheet) => Ok((sheet, range)),
+        None => Err(no_sheet(&range)),
+    }
+}
+
+fn parse(sheets: &[Sheet], range: &str) -> Result<Range, SheetsError> {
+    Range::parse(range, |name| sheets.iter().any(|sheet| sheet.title == name))
+        .map_err(SheetsError::InvalidRange)
+}
+
+fn no_sheet(range: &Range) -> SheetsError {
+    SheetsError::InvalidRange(match &range.sheet {
+        Some(title) => format!("The local spreadsheet has no sheet called `{title}`"),
+        None => "The local spreadsheet has no sheets".to_string(),
+    })
+}
+
+fn write(
+    sheet: &mut Sheet,
+    first_row: usize,
+    first_column: usize,
+    values: Vec<Vec<Value>>,
+) -> Value {
+    let rows = values.len();
+    let columns = values.iter().map(Vec::len).max().unwrap_or(0);
+    let cells = values.iter().map(Vec::len).sum::<usize>();
+
+    for (offset, cells) in values.into_iter().enumerate() {
+        let row_index = first_row + offset;
+        if sheet.rows.len() <= row_index {
+            sheet.rows.resize(row_index + 1, Vec::new());
+        }
+
+        let row = &mut sheet.rows[row_index];
+        if row.len() < first_column + cells.len() {
+            row.resize(first_column + cells.len(), json!(""));
+        }
+        for (column, cell) in cells.into_iter().enumerate() {
+            row[first_column + column] = cell;
+        }
+    }
+
+    let written = Range {
+        sheet: None,
+        first_row,
+        last_row: Some(first_row + rows.saturating_sub(1)),
+        first_column,
+        last_column: Some(first_column + columns.saturating_sub(1)),
+    };
+
+    json!({
+        "updatedRange": a1(&sheet.title, &written),
+        "updatedRows": rows,
+        "updatedColumns": columns,
+        "updatedCells": cells,
+    })
+}
+
+/// `range` on the sheet `title` in A1 notation.
+fn a1(title: &str, range: &Range) -> String {
+    let cell = |column: usize, row: Option<usize>| match row {
+        Some(row) => format!("{}{}", column_name(column), row + 1),
+        None => column_name(column),
+    };
+    let start = cell(range.first_column, Some(range.first_row));
+
+    let title = super::quote_sheet(title);
+    match range.last_column {
+        Some(last_column) => format!("{title}!{start}:{}", cell(last_column, range.last_row)),
+        None => format!("{title}!{start}"),
+    }
+}
+
+fn is_empty(cell: &Value) -> bool {
+    match cell {
+        Value::Null => true,
+        Value::String(text) => text.is_empty(),
+        _ => false,
+    }
+}
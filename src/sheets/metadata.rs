@@ -0,0 +1,107 @@
+//! What the model is told at the start of a session about the spreadsheets it
+//! will likely work on: their sheets, how many rows those hold and their
+//! headers, and their named ranges, so it doesn't guess at the names of tabs
+//! or call tools to find out.
+
+use futures::future::join_all;
+
+use super::{Spreadsheets, cell_text, named, quote_sheet, rows};
+
+/// Sheets described per spreadsheet, so a workbook with many tabs doesn't
+/// crowd the preamble.
+const MAX_SHEETS: usize = 20;
+/// Headers listed per sheet.
+const MAX_HEADERS: usize = 30;
+
+/// The sheets of each spreadsheet in `spreadsheets`, given as what the user
+/// calls it and its ID, as added to the preamble. Spreadsheets that can't be
+/// read are left out.
+pub async fn describe(sheets: &Spreadsheets, spreadsheets: &[(String, String)]) -> String {
+    let mut context = String::new();
+
+    for (name, id) in spreadsheets {
+        let listed = match sheets.list_sheets(id).await {
+            Ok(listed) => listed,
+            Err(e) => {
+                tracing::warn!("Failed to read the sheets of `{name}` to describe them: {e}");
+                continue;
+            }
+        };
+        let titles: Vec<&str> = listed["sheets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|sheet| sheet["properties"]["title"].as_str())
+            .collect();
+
+        context.push_str(&format!("\n- `{name}`, ID `{id}`:"));
+        let described = join_all(
+            titles
+                .iter()
+                .take(MAX_SHEETS)
+                .map(|title| describe_sheet(sheets, id, title)),
+        )
+        .await;
+        for sheet in described {
+            context.push_str(&format!("\n  - {sheet}"));
+        }
+        if titles.len() > MAX_SHEETS {
+            context.push_str(&format!(
+                "\n  - and {} more sheets",
+                titles.len() - MAX_SHEETS
+            ));
+        }
+        let named: Vec<String> = named::list(&listed)
+            .into_iter()
+            .map(|named| format!("`{}` ({})", named.name, named.range))
+            .collect();
+        if !named.is_empty() {
+            context.push_str(&format!(
+                "\n  - named ranges, which ranges can be given as: {}",
+                named.join(", ")
+            ));
+        }
+    }
+
+    if context.is_empty() {
+        return context;
+    }
+    format!(
+        "\nThe spreadsheets below held these sheets when the session started; use the sheet \
+         names as they're written here, quoted in ranges if they have spaces:{context}"
+    )
+}
+
+/// `sheet` in a line, e.g. `` `Leads`: 120 rows below the headers Name, Company ``.
+/// The rows are those with a value in the first column.
+async fn describe_sheet(sheets: &Spreadsheets, id: &str, sheet: &str) -> String {
+    let headers = sheets
+        .read_range(id, &format!("{}!1:1", quote_sheet(sheet)))
+        .await
+        .map(rows)
+        .unwrap_or_default();
+    let headers: Vec<String> = headers
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+        .iter()
+        .map(cell_text)
+        .filter(|header| !header.is_empty())
+        .collect();
+    if headers.is_empty() {
+        return format!("`{sheet}`: empty, or without headers");
+    }
+
+    let count = sheets
+        .read_range(id, &format!("{}!A2:A", quote_sheet(sheet)))
+        .await
+        .map(|values| rows(values).len())
+        .unwrap_or_default();
+    let mut line = format!("`{sheet}`: {count} rows below the headers ");
+    line.push_str(&headers[..headers.len().min(MAX_HEADERS)].join(", "));
+    if headers.len() > MAX_HEADERS {
+        line.push_str(&format!(" and {} more", headers.len() - MAX_HEADERS));
+    }
+
+    line
+}
@@ -0,0 +1,124 @@
+//! A built-in client for the Google Sheets API, so the agent can work without a
+//! separate MCP server, and a local workbook for imported files.
+
+mod client;
+mod local;
+mod metadata;
+mod named;
+mod pages;
+mod range;
+mod spreadsheets;
+mod tools;
+mod url;
+
+pub use client::{SheetsClient, SheetsError};
+pub use local::{LOCAL_SPREADSHEET, Workbook, cell_text, export_csv};
+pub use metadata::describe;
+pub use pages::{NumberedRow, PageReader, quote_sheet, rows};
+pub use range::{Range, column_name};
+pub use spreadsheets::Spreadsheets;
+pub use tools::{AppendRows, CreateNamedRange, CreateSheet, WriteRange};
+pub use url::{link_context, spreadsheet_id};
+
+use rig::{
+    completion::ToolDefinition,
+    tool::{Tool, ToolSet},
+};
+
+use anyhow::Context;
+
+use crate::{
+    cli::ExportArgs,
+    config::{SheetsConfig, ToolsConfig},
+    tools::is_allowed,
+};
+
+/// Adds the Sheets tools `filter` allows to `toolset`, if the client is enabled
+/// or files were imported.
+pub async fn add_tools(
+    config: &SheetsConfig,
+    spreadsheets: &Spreadsheets,
+    filter: &ToolsConfig,
+    toolset: &mut ToolSet,
+    definitions: &mut Vec<ToolDefinition>,
+) -> anyhow::Result<()> {
+    if !config.enabled && spreadsheets.local().is_empty() {
+        return Ok(());
+    }
+    anyhow::ensure!(
+        !config.enabled || spreadsheets.has_google(),
+        "The built-in Sheets client needs `credentials` under [auth]"
+    );
+    let client = spreadsheets.clone();
+
+    add(
+        tools::ListSheets(client.clone()),
+        filter,
+        toolset,
+        definitions,
+    )
+    .await;
+    add(
+        tools::ReadRange(client.clone()),
+        filter,
+        toolset,
+        definitions,
+    )
+    .await;
+    add(
+        tools::WriteRange(client.clone()),
+        filter,
+        toolset,
+        definitions,
+    )
+    .await;
+    add(
+        tools::AppendRows(client.clone()),
+        filter,
+        toolset,
+        definitions,
+    )
+    .await;
+    add(
+        tools::CreateSheet(client.clone()),
+        filter,
+        toolset,
+        definitions,
+    )
+    .await;
+    add(
+        tools::CreateNamedRange(client),
+        filter,
+        toolset,
+        definitions,
+    )
+    .await;
+
+    Ok(())
+}
+
+async fn add(
+    tool: impl Tool + 'static,
+    filter: &ToolsConfig,
+    toolset: &mut ToolSet,
+    definitions: &mut Vec<ToolDefinition>,
+) {
+    if is_allowed(filter, &tool.name()) {
+        definitions.push(tool.definition(String::new()).await);
+        toolset.add_tool(tool);
+    }
+}
+
+/// Saves the sheet named in `args` as a CSV file.
+pub async fn export(spreadsheets: &Spreadsheets, args: &ExportArgs) -> anyhow::Result<()> {
+    let values = spreadsheets
+        .read_range(&args.spreadsheet, &quote_sheet(&args.sheet))
+        .await
+        .with_context(|| format!("Failed to read `{}`", args.sheet))?;
+    let rows = rows(values);
+
+    export_csv(&args.to, &rows)?;
+    eprintln!("Exported {} rows to {}", rows.len(), args.to.display());
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Named ranges, which give dashboards and formulas a stable reference to a
+//! region of a spreadsheet: listed in A1 notation for the model, and set from
+//! it.
+
+use serde_json::{Value, json};
+
+use super::{Range, column_name, quote_sheet};
+
+/// A named range of a spreadsheet.
+#[derive(Debug, Clone)]
+pub struct NamedRange {
+    pub id: String,
+    pub name: String,
+    /// In A1 notation, e.g. `'Leads results'!A:H`.
+    pub range: String,
+}
+
+/// The named ranges of a `spreadsheets.get` response with `namedRanges` and
+/// the sheets' properties in it.
+pub fn list(spreadsheet: &Value) -> Vec<NamedRange> {
+    let sheets = spreadsheet["sheets"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let sheet = |id: u64| {
+        sheets
+            .iter()
+            .map(|sheet| &sheet["properties"])
+            .find(|properties| properties["sheetId"].as_u64().unwrap_or(0) == id)
+    };
+
+    spreadsheet["namedRanges"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|named| {
+            let grid = &named["range"];
+            // The ID is left out for the first sheet.
+            let properties = sheet(grid["sheetId"].as_u64().unwrap_or(0))?;
+            let columns = properties["gridProperties"]["columnCount"]
+                .as_u64()
+                .unwrap_or(26);
+
+            Some(NamedRange {
+                id: named["namedRangeId"].as_str()?.to_string(),
+                name: named["name"].as_str()?.to_string(),
+                range: a1(properties["title"].as_str()?, grid, columns),
+            })
+        })
+        .collect()
+}
+
+/// `GridRange` `grid` on `sheet` in A1 notation, its columns ending at the
+/// sheet's last, `columns`, if it doesn't say. Ranges without an end row run
+/// to the bottom of the sheet, as whole columns do.
+fn a1(sheet: &str, grid: &Value, columns: u64) -> String {
+    let index = |key: &str| grid[key].as_u64();
+    let first_column = index("startColumnIndex").unwrap_or(0);
+    let end_column = index("endColumnIndex")
+        .unwrap_or(columns)
+        .max(first_column + 1);
+    let (first_row, end_row) = match (index("startRowIndex"), index("endRowIndex")) {
+        (None | Some(0), None) => (String::new(), String::new()),
+        (start, end) => (
+            (start.unwrap_or(0) + 1).to_string(),
+            end.map_or_else(String::new, |end| end.to_string()),
+        ),
+    };
+
+    format!(
+        "{}!{}{first_row}:{}{end_row}",
+        quote_sheet(sheet),
+        column_name(first_column as usize),
+        column_name(end_column as usize - 1),
+    )
+}
+
+/// `range` as a `GridRange` on the sheet `sheet_id`, whose indexes are
+/// 0-based with exclusive ends. Bounds `range` doesn't have are left out, so
+/// it runs to the edge of the sheet.
+pub fn grid(range: &Range, sheet_id: u64) -> Value {
+    let mut grid = json!({ "sheetId": sheet_id });
+    if range.first_row > 0 || range.last_row.is_some() {
+        grid["startRowIndex"] = json!(range.first_row);
+    }
+    if let Some(last_row) = range.last_row {
+        grid["endRowIndex"] = json!(last_row + 1);
+    }
+    grid["startColumnIndex"] = json!(range.first_column);
+    if let Some(last_column) = range.last_column {
+        grid["endColumnIndex"] = json!(last_column + 1);
+    }
+
+    grid
+}
@@ -0,0 +1,126 @@
+//! Reading a sheet a page of rows at a time, so large sheets never have to be
+//! held in a single response or prompt.
+
+use serde_json::Value;
+
+use super::{SheetsError, Spreadsheets};
+
+/// A row's number, counting from 1, and its cells.
+pub type NumberedRow<'a> = (u64, &'a [Value]);
+
+/// Consecutive rows of a sheet.
+#[derive(Debug)]
+pub struct Page {
+    /// Row number of the first row in `rows`, counting from 1.
+    pub first_row: u64,
+    pub rows: Vec<Vec<Value>>,
+    /// Leading rows that were also at the end of the previous page.
+    pub overlap: usize,
+}
+
+impl Page {
+    /// The rows with their row numbers, split into the overlap and the rest.
+    pub fn numbered(&self) -> (Vec<NumberedRow<'_>>, Vec<NumberedRow<'_>>) {
+        let mut rows: Vec<_> = (self.first_row..)
+            .zip(self.rows.iter().map(Vec::as_slice))
+            .collect();
+        let new_rows = rows.split_off(self.overlap.min(rows.len()));
+
+        (rows, new_rows)
+    }
+
+    /// Number of the last row with cells in it. Pages are padded with blank
+    /// rows to a full page, so later rows may follow this one.
+    pub fn last_row(&self) -> u64 {
+        let filled = self.rows.iter().rposition(|row| !row.is_empty());
+        self.first_row + filled.unwrap_or(0) as u64
+    }
+}
+
+/// Reads `page_rows` new rows per request, each page starting with the last
+/// `overlap` rows of the one before.
+///
+/// Reading ends at the first page without values, so a run of blank rows at
+/// least a page long hides the rows after it.
+pub struct PageReader<'a> {
+    spreadsheets: &'a Spreadsheets,
+    spreadsheet_id: &'a str,
+    sheet: String,
+    page_rows: u64,
+    overlap: usize,
+    next_row: u64,
+    previous: Vec<Vec<Value>>,
+}
+
+impl<'a> PageReader<'a> {
+    /// Starts reading `sheet` at `first_row`. `overlap` must be below `page_rows`.
+    pub fn new(
+        spreadsheets: &'a Spreadsheets,
+        spreadsheet_id: &'a str,
+        sheet: &str,
+        first_row: u64,
+        page_rows: u32,
+        overlap: u32,
+    ) -> Self {
+        Self {
+            spreadsheets,
+            spreadsheet_id,
+            sheet: quote_sheet(sheet),
+            page_rows: page_rows.into(),
+            overlap: overlap as usize,
+            next_row: first_row,
+            previous: Vec::new(),
+        }
+    }
+
+    pub async fn next_page(&mut self) -> Result<Option<Page>, SheetsError> {
+        let first_row = self.next_row;
+        let last_row = first_row + self.page_rows - 1;
+        let range = format!("{}!{first_row}:{last_row}", self.sheet);
+
+        let mut rows = rows(
+            self.spreadsheets
+                .read_range(self.spreadsheet_id, &range)
+                .await?,
+        );
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        self.next_row = last_row + 1;
+        // Trailing blank rows are left out of the response.
+        rows.resize(self.page_rows as usize, Vec::new());
+
+        let overlap = self.overlap.min(self.previous.len());
+        let mut page_rows = self.previous.split_off(self.previous.len() - overlap);
+        page_rows.extend(rows);
+        self.previous = page_rows.clone();
+
+        Ok(Some(Page {
+            first_row: first_row - overlap as u64,
+            rows: page_rows,
+            overlap,
+        }))
+    }
+}
+
+/// `name` as the range of a whole sheet, quoted so it may contain spaces.
+pub fn quote_sheet(name: &str) -> String {
+    format!("'{}'", name.replace('\'', "''"))
+}
+
+/// The rows of a `values.get` response.
+pub fn rows(response: Value) -> Vec<Vec<Value>> {
+    match response {
+        Value::Object(mut response) => match response.remove("values") {
+            Some(Value::Array(rows)) => rows
+                .into_iter()
+                .map(|row| match row {
+                    Value::Array(cells) => cells,
+                    _ => Vec::new(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,147 @@
+//! A1 notation, for ranges of the local workbook.
+
+/// A rectangle of cells on one sheet, 0-based and inclusive. Missing bounds
+/// extend to the edge of the sheet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    /// `None` when the range didn't name a sheet, e.g. `A1:B2`.
+    pub sheet: Option<String>,
+    pub first_row: usize,
+    pub last_row: Option<usize>,
+    pub first_column: usize,
+    pub last_column: Option<usize>,
+}
+
+impl Range {
+    /// Parses ranges such as `Leads`, `'Q1 leads'!A2:F`, `Leads!2:501` or `A1`.
+    ///
+    /// Text without a `!` is a sheet name if `is_sheet` says so, like in the
+    /// Sheets API, and otherwise cells on the first sheet.
+    pub fn parse(range: &str, is_sheet: impl Fn(&str) -> bool) -> Result<Self, String> {
+        let (sheet, cells) = split_sheet(range)?;
+
+        match sheet {
+            None if is_sheet(cells) => Ok(Self::whole(Some(cells.to_string()))),
+            None => parse_cells(cells),
+            Some(sheet) if cells.is_empty() => Ok(Self::whole(Some(sheet))),
+            Some(sheet) => Ok(Self {
+                sheet: Some(sheet),
+                ..parse_cells(cells)?
+            }),
+        }
+    }
+
+    fn whole(sheet: Option<String>) -> Self {
+        Self {
+            sheet,
+            first_row: 0,
+            last_row: None,
+            first_column: 0,
+            last_column: None,
+        }
+    }
+}
+
+/// The (unquoted) sheet name before a `!`, and the cells after it.
+fn split_sheet(range: &str) -> Result<(Option<String>, &str), String> {
+    let range = range.trim();
+
+    if let Some(quoted) = range.strip_prefix('\'') {
+        // `''` is an escaped quote inside the name.
+        let mut name = String::new();
+        let mut chars = quoted.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            if c != '\'' {
+                name.push(c);
+            } else if chars.peek().is_some_and(|&(_, next)| next == '\'') {
+                name.push('\'');
+                chars.next();
+            } else {
+                let rest = &quoted[index + 1..];
+                let cells = match rest.strip_prefix('!') {
+                    Some(cells) => cells,
+                    None if rest.is_empty() => "",
+                    None => return Err(format!("Expected `!` after the sheet name in `{range}`")),
+                };
+                return Ok((Some(name), cells));
+            }
+        }
+
+        return Err(format!("The sheet name in `{range}` isn't closed with `'`"));
+    }
+
+    Ok(match range.split_once('!') {
+        Some((sheet, cells)) => (Some(sheet.to_string()), cells),
+        None => (None, range),
+    })
+}
+
+fn parse_cells(cells: &str) -> Result<Range, String> {
+    let (start, end) = match cells.split_once(':') {
+        Some((start, end)) => (parse_cell(start)?, Some(parse_cell(end)?)),
+        None => (parse_cell(cells)?, None),
+    };
+
+    let (start_column, start_row) = start;
+    let (last_column, last_row) = match end {
+        Some(end) => end,
+        // A single cell, or a whole row or column.
+        None => start,
+    };
+
+    Ok(Range {
+        sheet: None,
+        first_row: start_row.unwrap_or(0),
+        last_row,
+        first_column: start_column.unwrap_or(0),
+        last_column,
+    })
+}
+
+/// The 0-based column and row of a reference like `B12`, `B` or `12`.
+fn parse_cell(cell: &str) -> Result<(Option<usize>, Option<usize>), String> {
+    let cell = cell.trim().replace('$', "");
+    let digits = cell
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(cell.len());
+    let (letters, number) = cell.split_at(digits);
+
+    let invalid = || format!("`{cell}` isn't a cell in A1 notation");
+    if (letters.is_empty() && number.is_empty())
+        || letters.len() > 3
+        || !letters.chars().all(|c| c.is_ascii_alphabetic())
+    {
+        return Err(invalid());
+    }
+
+    let column = (!letters.is_empty()).then(|| {
+        letters.chars().fold(0, |column, c| {
+            column * 26 + (c.to_ascii_uppercase() as usize - 'A' as usize + 1)
+        }) - 1
+    });
+    let row = match number {
+        "" => None,
+        number => match number.parse::<usize>() {
+            Ok(row) if row > 0 => Some(row - 1),
+            _ => return Err(invalid()),
+        },
+    };
+
+    Ok((column, row))
+}
+
+/// The letters of the 0-based `column`, e.g. `AA` for 26.
+pub fn column_name(column: usize) -> String {
+    let mut name = Vec::new();
+    let mut column = column + 1;
+
+    while column > 0 {
+        let rem = (column - 1) % 26;
+        name.push(b'A' + rem as u8);
+        column = (column - 1) / 26;
+    }
+    name.reverse();
+
+    String::from_utf8(name).expect("column names are ASCII")
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use serde_json::{Value, json};
+
+use super::{LOCAL_SPREADSHEET, SheetsClient, SheetsError, Workbook};
+
+/// Sends each request to the local workbook or to Google Sheets, depending on
+/// the spreadsheet ID, so callers work the same with either.
+#[derive(Clone)]
+pub struct Spreadsheets {
+    google: Option<SheetsClient>,
+    local: Arc<Workbook>,
+}
+
+impl Spreadsheets {
+    pub fn new(google: Option<SheetsClient>, local: Arc<Workbook>) -> Self {
+        Self { google, local }
+    }
+
+    pub fn has_google(&self) -> bool {
+        self.google.is_some()
+    }
+
+    pub fn local(&self) -> &Workbook {
+        &self.local
+    }
+
+    pub async fn list_sheets(&self, spreadsheet_id: &str) -> Result<Value, SheetsError> {
+        match self.route(spreadsheet_id)? {
+            None => Ok(self.local.list_sheets()),
+            Some(google) => google.list_sheets(spreadsheet_id).await,
+        }
+    }
+
+    pub async fn read_range(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+    ) -> Result<Value, SheetsError> {
+        match self.route(spreadsheet_id)? {
+            None => self.local.read_range(range),
+            Some(google) => google.read_range(spreadsheet_id, range).await,
+        }
+    }
+
+    pub async fn write_range(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        values: Vec<Vec<Value>>,
+    ) -> Result<Value, SheetsError> {
+        match self.route(spreadsheet_id)? {
+            None => self.local.write_range(range, values),
+            Some(google) => google.write_range(spreadsheet_id, range, values).await,
+        }
+    }
+
+    pub async fn append_rows(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        values: Vec<Vec<Value>>,
+    ) -> Result<Value, SheetsError> {
+        match self.route(spreadsheet_id)? {
+            None => self.local.append_rows(range, values),
+            Some(google) => google.append_rows(spreadsheet_id, range, values).await,
+        }
+    }
+
+    pub async fn create_sheet(
+        &self,
+        spreadsheet_id: &str,
+        title: &str,
+    ) -> Result<Value, SheetsError> {
+        match self.route(spreadsheet_id)? {
+            None => self.local.create_sheet(title),
+            Some(google) => google.create_sheet(spreadsheet_id, title).await,
+        }
+    }
+
+    /// Points the named range `name` at `range`. Imported files have no named
+    /// ranges, since they're saved as values only.
+    pub async fn set_named_range(
+        &self,
+        spreadsheet_id: &str,
+        name: &str,
+        range: &str,
+    ) -> Result<Value, SheetsError> {
+        match self.route(spreadsheet_id)? {
+            None => Err(SheetsError::InvalidRange(
+                "Imported files can't have named ranges".to_string(),
+            )),
+            Some(google) => google.set_named_range(spreadsheet_id, name, range).await,
+        }
+    }
+
+    /// Applies the formatting `requests` of a batch update. The local workbook
+    /// only holds values, so it ignores them.
+    pub async fn format(
+        &self,
+        spreadsheet_id: &str,
+        requests: Vec<Value>,
+    ) -> Result<Value, SheetsError> {
+        match self.route(spreadsheet_id)? {
+            None => Ok(json!({ "replies": [] })),
+            Some(google) => google.batch_update(spreadsheet_id, requests).await,
+        }
+    }
+
+    /// The Google client for `spreadsheet_id`, or `None` for the local workbook.
+    fn route(&self, spreadsheet_id: &str) -> Result<Option<&SheetsClient>, SheetsError> {
+        if spreadsheet_id == LOCAL_SPREADSHEET {
+            return Ok(None);
+        }
+
+        self.google
+            .as_ref()
+            .map(Some)
+            .ok_or(SheetsError::NoCredentials)
+    }
+}
@@ -0,0 +1,278 @@
+//! The Sheets API exposed to the model as rig tools.
+
+use std::future::Future;
+
+use rig::{completion::ToolDefinition, tool::Tool};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use super::{SheetsError, Spreadsheets, named};
+
+#[derive(Debug, Deserialize)]
+pub struct SpreadsheetArgs {
+    spreadsheet_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RangeArgs {
+    spreadsheet_id: String,
+    range: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValuesArgs {
+    spreadsheet_id: String,
+    range: String,
+    values: Vec<Vec<Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSheetArgs {
+    spreadsheet_id: String,
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NamedRangeArgs {
+    spreadsheet_id: String,
+    name: String,
+    range: String,
+}
+
+pub struct ListSheets(pub Spreadsheets);
+pub struct ReadRange(pub Spreadsheets);
+pub struct WriteRange(pub Spreadsheets);
+pub struct AppendRows(pub Spreadsheets);
+pub struct CreateSheet(pub Spreadsheets);
+pub struct CreateNamedRange(pub Spreadsheets);
+
+impl Tool for ListSheets {
+    const NAME: &'static str = "sheets__list_sheets";
+
+    type Error = SheetsError;
+    type Args = SpreadsheetArgs;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        definition(
+            Self::NAME,
+            "List the sheets of a spreadsheet with their sizes, and its named ranges.",
+            json!({}),
+        )
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Value, SheetsError>> + Send + Sync {
+        let client = self.0.clone();
+        on_task(async move {
+            let mut listed = client.list_sheets(&args.spreadsheet_id).await?;
+            // Named ranges come as grid indexes on sheet IDs, which models
+            // don't turn into A1 notation reliably.
+            let named: Vec<Value> = named::list(&listed)
+                .into_iter()
+                .map(|named| json!({ "name": named.name, "range": named.range }))
+                .collect();
+            if let Some(listed) = listed.as_object_mut() {
+                listed.remove("namedRanges");
+                if !named.is_empty() {
+                    listed.insert("namedRanges".to_string(), json!(named));
+                }
+            }
+
+            Ok(listed)
+        })
+    }
+}
+
+impl Tool for ReadRange {
+    const NAME: &'static str = "sheets__read_range";
+
+    type Error = SheetsError;
+    type Args = RangeArgs;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        definition(
+            Self::NAME,
+            "Read the values of a range.",
+            json!({ "range": range_schema() }),
+        )
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Value, SheetsError>> + Send + Sync {
+        let client = self.0.clone();
+        on_task(async move { client.read_range(&args.spreadsheet_id, &args.range).await })
+    }
+}
+
+impl Tool for WriteRange {
+    const NAME: &'static str = "sheets__write_range";
+
+    type Error = SheetsError;
+    type Args = ValuesArgs;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        definition(
+            Self::NAME,
+            "Overwrite the cells of a range, starting at its top-left cell.",
+            json!({ "range": range_schema(), "values": values_schema() }),
+        )
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Value, SheetsError>> + Send + Sync {
+        let client = self.0.clone();
+        on_task(async move {
+            client
+                .write_range(&args.spreadsheet_id, &args.range, args.values)
+                .await
+        })
+    }
+}
+
+impl Tool for AppendRows {
+    const NAME: &'static str = "sheets__append_rows";
+
+    type Error = SheetsError;
+    type Args = ValuesArgs;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        definition(
+            Self::NAME,
+            "Append rows after the last row of the table in a range.",
+            json!({ "range": range_schema(), "values": values_schema() }),
+        )
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Value, SheetsError>> + Send + Sync {
+        let client = self.0.clone();
+        on_task(async move {
+            client
+                .append_rows(&args.spreadsheet_id, &args.range, args.values)
+                .await
+        })
+    }
+}
+
+impl Tool for CreateSheet {
+    const NAME: &'static str = "sheets__create_sheet";
+
+    type Error = SheetsError;
+    type Args = CreateSheetArgs;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        definition(
+            Self::NAME,
+            "Add a new, empty sheet to a spreadsheet.",
+            json!({ "title": { "type": "string", "description": "Title of the new sheet" } }),
+        )
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Value, SheetsError>> + Send + Sync {
+        let client = self.0.clone();
+        on_task(async move { client.create_sheet(&args.spreadsheet_id, &args.title).await })
+    }
+}
+
+impl Tool for CreateNamedRange {
+    const NAME: &'static str = "sheets__create_named_range";
+
+    type Error = SheetsError;
+    type Args = NamedRangeArgs;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        definition(
+            Self::NAME,
+            "Give a range a name, so formulas and dashboards can refer to it by that name; \
+             a named range that already has the name is moved to the range.",
+            json!({
+                "name": {
+                    "type": "string",
+                    "description": "Name of the range: letters, digits and underscores, not starting with a digit"
+                },
+                "range": range_schema(),
+            }),
+        )
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Value, SheetsError>> + Send + Sync {
+        let client = self.0.clone();
+        on_task(async move {
+            client
+                .set_named_range(&args.spreadsheet_id, &args.name, &args.range)
+                .await
+        })
+    }
+}
+
+fn range_schema() -> Value {
+    json!({
+        "type": "string",
+        "description": "Range in A1 notation, e.g. `Leads!A1:F100`, or just a sheet name"
+    })
+}
+
+fn values_schema() -> Value {
+    json!({
+        "type": "array",
+        "description": "Rows of cell values; formulas start with `=`",
+        "items": { "type": "array", "items": {} }
+    })
+}
+
+/// A tool taking `spreadsheet_id` and `properties`, all of them required.
+fn definition(name: &str, description: &str, properties: Value) -> ToolDefinition {
+    let mut properties = match properties {
+        Value::Object(properties) => properties,
+        _ => unreachable!("tool properties are always an object"),
+    };
+    properties.insert(
+        "spreadsheet_id".to_string(),
+        json!({
+            "type": "string",
+            "description": "ID of the spreadsheet, as found in its URL, or `local` for imported files"
+        }),
+    );
+    let required: Vec<&String> = properties.keys().collect();
+
+    ToolDefinition {
+        name: name.to_string(),
+        description: description.to_string(),
+        parameters: json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        }),
+    }
+}
+
+/// Runs `future` on its own task.
+///
+/// Tool futures have to be `Sync`, which the HTTP client's aren't, but the
+/// handle of a spawned task is.
+fn on_task<T: Send + 'static>(
+    future: impl Future<Output = Result<T, SheetsError>> + Send + 'static,
+) -> impl Future<Output = Result<T, SheetsError>> + Send + Sync {
+    let handle = tokio::spawn(future);
+    async move { handle.await? }
+}
@@ -0,0 +1,158 @@
+/// Where the path of a spreadsheet starts in its URL.
+const URL_PREFIX: &str = "docs.google.com/spreadsheets/";
+
+/// A link to a spreadsheet, as pasted from the browser.
+#[derive(Debug, PartialEq)]
+pub struct Link<'a> {
+    pub url: &'a str,
+    pub spreadsheet_id: &'a str,
+    /// The `sheetId` of the sheet the link opens, from its `gid` parameter.
+    pub gid: Option<&'a str>,
+}
+
+impl<'a> Link<'a> {
+    /// `url` as a link to a spreadsheet, or `None` if it isn't one.
+    pub fn parse(url: &'a str) -> Option<Self> {
+        let (_, rest) = url.split_once(URL_PREFIX)?;
+        // Links opened while signed in to several accounts name the account,
+        // as in `/spreadsheets/u/1/d/<id>`.
+        let rest = match rest.strip_prefix("u/") {
+            Some(rest) => {
+                let (account, rest) = rest.split_once('/')?;
+                if account.is_empty() || !account.chars().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                rest
+            }
+            None => rest,
+        };
+        let rest = rest.strip_prefix("d/")?;
+        let spreadsheet_id = rest.split(['/', '?', '#']).next()?;
+        if spreadsheet_id.is_empty() {
+            return None;
+        }
+        let gid = rest
+            .split(['?', '#', '&'])
+            .find_map(|param| param.strip_prefix("gid="))
+            .map(|gid| gid.trim_end_matches(|c: char| !c.is_ascii_digit()))
+            .filter(|gid| !gid.is_empty() && gid.chars().all(|c| c.is_ascii_digit()));
+
+        Some(Self {
+            url,
+            spreadsheet_id,
+            gid,
+        })
+    }
+}
+
+/// The ID of the spreadsheet `input` links to, e.g. `1AbC...` for
+/// `https://docs.google.com/spreadsheets/d/1AbC.../edit#gid=0`, or `input`
+/// itself if it isn't a link.
+pub fn spreadsheet_id(input: &str) -> &str {
+    let input = input.trim();
+
+    Link::parse(input).map_or(input, |link| link.spreadsheet_id)
+}
+
+/// The links to spreadsheets in `text`, without repeats, e.g. in a prompt.
+pub fn links(text: &str) -> Vec<Link<'_>> {
+    let mut links: Vec<Link<'_>> = Vec::new();
+    for word in text.split_whitespace() {
+        // The URL of a Markdown link, `[text](url)`.
+        let word = word.rsplit_once("](").map_or(word, |(_, url)| url);
+        // Links are often quoted, bracketed or end a sentence.
+        let word = word.trim_matches(|c: char| "<>()[]{}\"'`,.;:!".contains(c));
+        if let Some(link) = Link::parse(word)
+            && !links
+                .iter()
+                .any(|other| other.spreadsheet_id == link.spreadsheet_id && other.gid == link.gid)
+        {
+            links.push(link);
+        }
+    }
+
+    links
+}
+
+/// What the model is told about the spreadsheet links in `text`, so it
+/// doesn't have to pick the IDs out of them itself, or `None` if there are
+/// none.
+pub fn link_context(text: &str) -> Option<String> {
+    let links = links(text);
+    if links.is_empty() {
+        return None;
+    }
+
+    let mut context = "The spreadsheet links above, parsed:".to_string();
+    for link in links {
+        context.push_str(&format!(
+            "\n- {}: the spreadsheet ID is `{}`",
+            link.url, link.spreadsheet_id
+        ));
+        if let Some(gid) = link.gid {
+            context.push_str(&format!(
+                ", and it opens the sheet whose `sheetId` is {gid}; \
+                 list the sheets to find its name"
+            ));
+        }
+    }
+
+    Some(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "1AbCdEfGhIjKlMnOpQrStUvWxYz0123456789";
+
+    fn link(url: &str) -> Option<(&str, Option<&str>)> {
+        Link::parse(url).map(|link| (link.spreadsheet_id, link.gid))
+    }
+
+    #[test]
+    fn parses_the_gid_wherever_it_is() {
+        let base = format!("https://docs.google.com/spreadsheets/d/{ID}");
+        for url in [
+            format!("{base}/edit#gid=42"),
+            format!("{base}/edit?gid=42"),
+            format!("{base}/edit?usp=sharing&gid=42"),
+            format!("{base}/edit?gid=42#gid=42"),
+        ] {
+            assert_eq!(link(&url), Some((ID, Some("42"))), "{url}");
+        }
+        assert_eq!(link(&format!("{base}/edit")), Some((ID, None)));
+    }
+
+    #[test]
+    fn parses_links_that_name_the_account() {
+        let url = format!("https://docs.google.com/spreadsheets/u/1/d/{ID}/edit#gid=0");
+        assert_eq!(link(&url), Some((ID, Some("0"))));
+        assert_eq!(
+            link(&format!("https://docs.google.com/spreadsheets/u/x/d/{ID}")),
+            None
+        );
+        assert_eq!(link("https://docs.google.com/spreadsheets/u/0/"), None);
+    }
+
+    #[test]
+    fn finds_links_in_brackets_and_punctuation() {
+        let url = format!("https://docs.google.com/spreadsheets/d/{ID}/edit#gid=7");
+        for text in [
+            format!("Qualify [the leads]({url}), please"),
+            format!("Qualify <{url}>."),
+            format!("Qualify the leads in \"{url}\"!"),
+            format!("Qualify the leads ({url})"),
+        ] {
+            let links = links(&text);
+            assert_eq!(links.len(), 1, "{text}");
+            assert_eq!(links[0].url, url, "{text}");
+            assert_eq!((links[0].spreadsheet_id, links[0].gid), (ID, Some("7")));
+        }
+    }
+
+    #[test]
+    fn takes_ids_as_they_are() {
+        assert_eq!(spreadsheet_id(&format!(" {ID} ")), ID);
+    }
+}
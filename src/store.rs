@@ -0,0 +1,661 @@
+//! A SQLite database holding everything the agent keeps between runs:
+//! sessions, background jobs, qualification runs with their usage and metrics,
+//! and the ledgers and schedule state of incremental runs, and the
+//! spreadsheets of the workspace.
+
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+
+use crate::{
+    jobs::{Job, Status},
+    metrics::Metrics,
+    qualify::Columns,
+    session::{self, Session},
+    usage::Usage,
+    workspace::Spreadsheet,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    chat_history TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS branches (
+    session_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    chat_history TEXT, -- NULL for the branch the session is on, whose history it holds
+    PRIMARY KEY (session_id, name)
+);
+CREATE TABLE IF NOT EXISTS usage (
+    owner_kind TEXT NOT NULL, -- 'session' or 'run'
+    owner_id TEXT NOT NULL,
+    model TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    PRIMARY KEY (owner_kind, owner_id, model)
+);
+CREATE TABLE IF NOT EXISTS metrics (
+    owner_kind TEXT NOT NULL, -- 'session' or 'run'
+    owner_id TEXT NOT NULL,
+    model_calls INTEGER NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    tool_calls TEXT NOT NULL, -- a JSON object of call counts keyed by tool
+    tool_errors INTEGER NOT NULL,
+    retries INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    PRIMARY KEY (owner_kind, owner_id)
+);
+CREATE TABLE IF NOT EXISTS runs (
+    id TEXT PRIMARY KEY,
+    spreadsheet TEXT NOT NULL,
+    sheet TEXT NOT NULL,
+    results_sheet TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER,
+    leads INTEGER NOT NULL DEFAULT 0,
+    qualified INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    duplicates INTEGER NOT NULL DEFAULT 0,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS runs_by_spreadsheet ON runs (spreadsheet, started_at);
+CREATE TABLE IF NOT EXISTS jobs (
+    id TEXT PRIMARY KEY,
+    description TEXT NOT NULL,
+    status TEXT NOT NULL,
+    progress INTEGER NOT NULL,
+    error TEXT,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS ledger (
+    key TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    PRIMARY KEY (key, hash)
+);
+CREATE TABLE IF NOT EXISTS schedule_state (
+    key TEXT PRIMARY KEY,
+    last_row INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS column_mappings (
+    spreadsheet TEXT NOT NULL,
+    sheet TEXT NOT NULL,
+    columns TEXT NOT NULL, -- a JSON object of headers keyed by what their column holds
+    PRIMARY KEY (spreadsheet, sheet)
+);
+CREATE TABLE IF NOT EXISTS workspace (
+    alias TEXT PRIMARY KEY,
+    spreadsheet_id TEXT NOT NULL,
+    title TEXT,
+    sheets TEXT NOT NULL -- a JSON array of sheet names
+);
+";
+
+/// A qualification run, as listed by `gsheets-agent runs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    pub id: String,
+    pub spreadsheet: String,
+    pub sheet: String,
+    pub results_sheet: String,
+    /// Unix timestamp, in seconds.
+    pub started_at: u64,
+    /// Unix timestamp, in seconds; missing while the run goes on.
+    pub finished_at: Option<u64>,
+    pub leads: usize,
+    pub qualified: usize,
+    pub failed: usize,
+    pub duplicates: usize,
+    /// Why the run stopped, if it didn't finish.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Tokens used, keyed by model name.
+    pub usage: BTreeMap<String, Usage>,
+    /// What the run did, once it's over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Metrics>,
+}
+
+pub struct Store(Connection);
+
+/// Opens the database, creating it if needed.
+///
+/// Connections are cheap, so callers open one for each thing they store
+/// rather than holding on to it.
+pub fn open() -> anyhow::Result<Store> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let connection = Connection::open(&path)
+        .with_context(|| format!("Failed to open the database {}", path.display()))?;
+    // Background jobs write from other threads.
+    connection.busy_timeout(Duration::from_secs(5))?;
+    connection
+        .execute_batch(SCHEMA)
+        .context("Failed to set up the database")?;
+
+    Ok(Store(connection))
+}
+
+/// `~/.local/share/gsheets-agent/gsheets-agent.db` (or the platform equivalent).
+pub fn path() -> anyhow::Result<PathBuf> {
+    // Tests keep to a new database of their own, rather than the user's.
+    if cfg!(test) {
+        static PATH: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+        let path = PATH.get_or_init(|| {
+            let name = format!("gsheets-agent-test-{}.db", uuid::Uuid::new_v4());
+            std::env::temp_dir().join(name)
+        });
+        return Ok(path.clone());
+    }
+    dirs::data_dir()
+        .map(|dir| dir.join("gsheets-agent").join("gsheets-agent.db"))
+        .context("Could not determine the data directory to store the database in")
+}
+
+impl Store {
+    pub fn load_session(&self, id: &str) -> anyhow::Result<Option<Session>> {
+        let row = self
+            .0
+            .query_row(
+                "SELECT created_at, updated_at, chat_history FROM sessions WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok((
+                        row.get::<_, u64>(0)?,
+                        row.get::<_, u64>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((created_at, updated_at, chat_history)) = row else {
+            return Ok(None);
+        };
+
+        let mut branch = session::main_branch();
+        let mut branches = BTreeMap::new();
+        let mut statement = self
+            .0
+            .prepare("SELECT name, chat_history FROM branches WHERE session_id = ?1")?;
+        let rows = statement.query_map([id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?;
+        for row in rows {
+            match row? {
+                (name, None) => branch = name,
+                (name, Some(history)) => {
+                    let history = serde_json::from_str(&history).with_context(|| {
+                        format!("Failed to parse the branch `{name}` of session {id}")
+                    })?;
+                    branches.insert(name, history);
+                }
+            }
+        }
+
+        Ok(Some(Session {
+            id: id.to_string(),
+            created_at,
+            updated_at,
+            chat_history: serde_json::from_str(&chat_history)
+                .with_context(|| format!("Failed to parse the history of session {id}"))?,
+            usage: self.usage("session", id)?,
+            branch,
+            branches,
+        }))
+    }
+
+    pub fn save_session(&mut self, session: &Session) -> anyhow::Result<()> {
+        let transaction = self.0.transaction()?;
+        transaction.execute(
+            "INSERT INTO sessions (id, created_at, updated_at, chat_history)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (id) DO UPDATE SET
+                 updated_at = excluded.updated_at, chat_history = excluded.chat_history",
+            params![
+                session.id,
+                session.created_at,
+                session.updated_at,
+                serde_json::to_string(&session.chat_history)?
+            ],
+        )?;
+        transaction.execute("DELETE FROM branches WHERE session_id = ?1", [&session.id])?;
+        if !session.branches.is_empty() {
+            let mut statement = transaction.prepare(
+                "INSERT INTO branches (session_id, name, chat_history) VALUES (?1, ?2, ?3)",
+            )?;
+            statement.execute(params![session.id, session.branch, None::<String>])?;
+            for (name, history) in &session.branches {
+                statement.execute(params![session.id, name, serde_json::to_string(history)?])?;
+            }
+        }
+        save_usage(&transaction, "session", &session.id, &session.usage)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Inserts `run`, or updates it once it has finished.
+    pub fn save_run(&mut self, run: &Run) -> anyhow::Result<()> {
+        let transaction = self.0.transaction()?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO runs (id, spreadsheet, sheet, results_sheet, started_at,
+                 finished_at, leads, qualified, failed, duplicates, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                run.id,
+                run.spreadsheet,
+                run.sheet,
+                run.results_sheet,
+                run.started_at,
+                run.finished_at,
+                run.leads,
+                run.qualified,
+                run.failed,
+                run.duplicates,
+                run.error
+            ],
+        )?;
+        save_usage(&transaction, "run", &run.id, &run.usage)?;
+        if let Some(metrics) = &run.metrics {
+            save_metrics(&transaction, "run", &run.id, metrics)?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// The latest `limit` runs, newest first, optionally only those against
+    /// `spreadsheet`.
+    pub fn runs(&self, spreadsheet: Option<&str>, limit: usize) -> anyhow::Result<Vec<Run>> {
+        let mut statement = self.0.prepare(
+            "SELECT id, spreadsheet, sheet, results_sheet, started_at, finished_at, leads,
+                 qualified, failed, duplicates, error
+             FROM runs WHERE ?1 IS NULL OR spreadsheet = ?1
+             ORDER BY started_at DESC LIMIT ?2",
+        )?;
+        let runs = statement
+            .query_map(params![spreadsheet, limit], |row| {
+                Ok(Run {
+                    id: row.get(0)?,
+                    spreadsheet: row.get(1)?,
+                    sheet: row.get(2)?,
+                    results_sheet: row.get(3)?,
+                    started_at: row.get(4)?,
+                    finished_at: row.get(5)?,
+                    leads: row.get(6)?,
+                    qualified: row.get(7)?,
+                    failed: row.get(8)?,
+                    duplicates: row.get(9)?,
+                    error: row.get(10)?,
+                    usage: BTreeMap::new(),
+                    metrics: None,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        runs.into_iter()
+            .map(|run| {
+                Ok(Run {
+                    usage: self.usage("run", &run.id)?,
+                    metrics: self.metrics("run", &run.id)?,
+                    ..run
+                })
+            })
+            .collect()
+    }
+
+    pub fn save_job(&self, job: &Job, updated_at: u64) -> anyhow::Result<()> {
+        self.0.execute(
+            "INSERT OR REPLACE INTO jobs (id, description, status, progress, error, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                job.id,
+                job.description,
+                job.status.to_string(),
+                job.progress,
+                job.error,
+                updated_at
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn load_job(&self, id: &str) -> anyhow::Result<Option<Job>> {
+        let job = self
+            .0
+            .query_row(
+                "SELECT description, status, progress, error FROM jobs WHERE id = ?1",
+                [id],
+                |row| {
+                    let status: String = row.get(1)?;
+                    Ok(Job {
+                        id: id.to_string(),
+                        description: row.get(0)?,
+                        status: match status.as_str() {
+                            "succeeded" => Status::Succeeded,
+                            "cancelled" => Status::Cancelled,
+                            "running" => Status::Running,
+                            _ => Status::Failed,
+                        },
+                        progress: row.get(2)?,
+                        error: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(job)
+    }
+
+    pub fn ledger(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        let mut statement = self.0.prepare("SELECT hash FROM ledger WHERE key = ?1")?;
+        let hashes = statement
+            .query_map([key], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(hashes)
+    }
+
+    pub fn add_to_ledger<'a>(
+        &mut self,
+        key: &str,
+        hashes: impl IntoIterator<Item = &'a String>,
+    ) -> anyhow::Result<()> {
+        let transaction = self.0.transaction()?;
+        for hash in hashes {
+            transaction.execute(
+                "INSERT OR IGNORE INTO ledger (key, hash) VALUES (?1, ?2)",
+                params![key, hash],
+            )?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn last_row(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        let row = self
+            .0
+            .query_row(
+                "SELECT last_row FROM schedule_state WHERE key = ?1",
+                [key],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(row)
+    }
+
+    pub fn set_last_row(&self, key: &str, row: u64) -> anyhow::Result<()> {
+        self.0.execute(
+            "INSERT OR REPLACE INTO schedule_state (key, last_row) VALUES (?1, ?2)",
+            params![key, row],
+        )?;
+
+        Ok(())
+    }
+
+    /// The columns `gsheets-agent columns` saved for `sheet`.
+    pub fn column_mapping(
+        &self,
+        spreadsheet: &str,
+        sheet: &str,
+    ) -> anyhow::Result<Option<Columns>> {
+        let columns: Option<String> = self
+            .0
+            .query_row(
+                "SELECT columns FROM column_mappings WHERE spreadsheet = ?1 AND sheet = ?2",
+                [spreadsheet, sheet],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        columns
+            .map(|columns| {
+                serde_json::from_str(&columns)
+                    .with_context(|| format!("Failed to parse the column mapping of `{sheet}`"))
+            })
+            .transpose()
+    }
+
+    pub fn save_column_mapping(
+        &self,
+        spreadsheet: &str,
+        sheet: &str,
+        columns: &Columns,
+    ) -> anyhow::Result<()> {
+        self.0.execute(
+            "INSERT OR REPLACE INTO column_mappings (spreadsheet, sheet, columns)
+             VALUES (?1, ?2, ?3)",
+            params![spreadsheet, sheet, serde_json::to_string(columns)?],
+        )?;
+
+        Ok(())
+    }
+
+    /// The spreadsheets of the workspace, by alias.
+    pub fn workspace(&self) -> anyhow::Result<Vec<Spreadsheet>> {
+        let mut statement = self
+            .0
+            .prepare("SELECT alias, spreadsheet_id, title, sheets FROM workspace ORDER BY alias")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (alias, id, title, sheets) = row?;
+            Ok(Spreadsheet {
+                sheets: serde_json::from_str(&sheets)
+                    .with_context(|| format!("Failed to parse the sheets of `{alias}`"))?,
+                alias,
+                id,
+                title,
+            })
+        })
+        .collect()
+    }
+
+    /// Adds `spreadsheet` to the workspace, replacing one with the same alias.
+    pub fn save_to_workspace(&self, spreadsheet: &Spreadsheet) -> anyhow::Result<()> {
+        self.0.execute(
+            "INSERT INTO workspace (alias, spreadsheet_id, title, sheets) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (alias) DO UPDATE SET
+                 spreadsheet_id = excluded.spreadsheet_id,
+                 title = excluded.title,
+                 sheets = excluded.sheets",
+            params![
+                spreadsheet.alias,
+                spreadsheet.id,
+                spreadsheet.title,
+                serde_json::to_string(&spreadsheet.sheets)?
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Removes the spreadsheet `alias` from the workspace, returning whether
+    /// there was one.
+    pub fn remove_from_workspace(&self, alias: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .0
+            .execute("DELETE FROM workspace WHERE alias = ?1", [alias])?
+            > 0)
+    }
+
+    /// Deletes the sessions last saved before `before`, a Unix timestamp,
+    /// with their branches and usage, overwriting what they held. Returns how many there were.
+    pub fn purge_sessions(&mut self, before: u64) -> anyhow::Result<usize> {
+        self.0.pragma_update(None, "secure_delete", true)?;
+        let transaction = self.0.transaction()?;
+        for table in ["usage", "metrics"] {
+            transaction.execute(
+                &format!(
+                    "DELETE FROM {table} WHERE owner_kind = 'session'
+                         AND owner_id IN (SELECT id FROM sessions WHERE updated_at < ?1)"
+                ),
+                [before],
+            )?;
+        }
+        transaction.execute(
+            "DELETE FROM branches
+             WHERE session_id IN (SELECT id FROM sessions WHERE updated_at < ?1)",
+            [before],
+        )?;
+        let purged = transaction.execute("DELETE FROM sessions WHERE updated_at < ?1", [before])?;
+        transaction.commit()?;
+
+        Ok(purged)
+    }
+
+    /// Deletes the background jobs last updated before `before`, a Unix
+    /// timestamp, overwriting what they held. Returns how many there were.
+    pub fn purge_jobs(&self, before: u64) -> anyhow::Result<usize> {
+        self.0.pragma_update(None, "secure_delete", true)?;
+
+        Ok(self
+            .0
+            .execute("DELETE FROM jobs WHERE updated_at < ?1", [before])?)
+    }
+
+    /// Adds `metrics` to those of the session `id`, which batch runs go on
+    /// with.
+    pub fn add_session_metrics(&mut self, id: &str, metrics: Metrics) -> anyhow::Result<()> {
+        let transaction = self.0.transaction()?;
+        let mut total = Store::metrics_in(&transaction, "session", id)?.unwrap_or_default();
+        total += metrics;
+        save_metrics(&transaction, "session", id, &total)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn metrics(&self, kind: &str, id: &str) -> anyhow::Result<Option<Metrics>> {
+        Store::metrics_in(&self.0, kind, id)
+    }
+
+    fn metrics_in(
+        connection: &Connection,
+        kind: &str,
+        id: &str,
+    ) -> anyhow::Result<Option<Metrics>> {
+        let row = connection
+            .query_row(
+                "SELECT model_calls, prompt_tokens, completion_tokens, tool_calls, tool_errors,
+                     retries, duration_ms
+                 FROM metrics WHERE owner_kind = ?1 AND owner_id = ?2",
+                params![kind, id],
+                |row| {
+                    Ok((
+                        Metrics {
+                            model_calls: row.get(0)?,
+                            usage: Usage {
+                                prompt_tokens: row.get(1)?,
+                                completion_tokens: row.get(2)?,
+                            },
+                            tool_calls: BTreeMap::new(),
+                            tool_errors: row.get(4)?,
+                            retries: row.get(5)?,
+                            duration_ms: row.get(6)?,
+                        },
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        row.map(|(metrics, tool_calls)| {
+            Ok(Metrics {
+                tool_calls: serde_json::from_str(&tool_calls)?,
+                ..metrics
+            })
+        })
+        .transpose()
+    }
+
+    fn usage(&self, kind: &str, id: &str) -> anyhow::Result<BTreeMap<String, Usage>> {
+        let mut statement = self.0.prepare(
+            "SELECT model, prompt_tokens, completion_tokens FROM usage
+             WHERE owner_kind = ?1 AND owner_id = ?2",
+        )?;
+        let usage = statement
+            .query_map(params![kind, id], |row| {
+                Ok((
+                    row.get(0)?,
+                    Usage {
+                        prompt_tokens: row.get(1)?,
+                        completion_tokens: row.get(2)?,
+                    },
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(usage)
+    }
+}
+
+fn save_usage(
+    connection: &Connection,
+    kind: &str,
+    id: &str,
+    usage: &BTreeMap<String, Usage>,
+) -> anyhow::Result<()> {
+    for (model, usage) in usage {
+        connection.execute(
+            "INSERT OR REPLACE INTO usage
+                 (owner_kind, owner_id, model, prompt_tokens, completion_tokens)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                kind,
+                id,
+                model,
+                usage.prompt_tokens,
+                usage.completion_tokens
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn save_metrics(
+    connection: &Connection,
+    kind: &str,
+    id: &str,
+    metrics: &Metrics,
+) -> anyhow::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO metrics
+             (owner_kind, owner_id, model_calls, prompt_tokens, completion_tokens, tool_calls,
+              tool_errors, retries, duration_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            kind,
+            id,
+            metrics.model_calls,
+            metrics.usage.prompt_tokens,
+            metrics.usage.completion_tokens,
+            serde_json::to_string(&metrics.tool_calls)?,
+            metrics.tool_errors,
+            metrics.retries,
+            metrics.duration_ms
+        ],
+    )?;
+
+    Ok(())
+}
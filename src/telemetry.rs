@@ -0,0 +1,145 @@
+//! Export of traces and metrics to an OpenTelemetry collector over OTLP/HTTP.
+//!
+//! The spans of [`crate::logging`] become traces. Metrics are recorded whether
+//! or not they're exported, and cost nothing when they aren't.
+
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use opentelemetry::{
+    KeyValue, global,
+    metrics::{Counter, Histogram},
+    trace::TracerProvider,
+};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, metrics::SdkMeterProvider, trace::SdkTracerProvider};
+use tracing::Subscriber;
+use tracing_subscriber::{Layer, registry::LookupSpan};
+
+use crate::{config::TelemetryConfig, usage::Usage};
+
+/// Flushes what hasn't been exported yet when dropped.
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            eprintln!("Failed to export the last traces: {e}");
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            eprintln!("Failed to export the last metrics: {e}");
+        }
+    }
+}
+
+/// Starts exporting to `config.endpoint`, returning the layer that turns spans
+/// into traces. Returns `None` when no endpoint is configured.
+pub fn init<S>(
+    config: &TelemetryConfig,
+) -> anyhow::Result<Option<(impl Layer<S> + use<S>, Telemetry)>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Some(endpoint) = &config.endpoint else {
+        return Ok(None);
+    };
+    let endpoint = endpoint.trim_end_matches('/');
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .build();
+
+    let spans = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{endpoint}/v1/traces"))
+        .build()
+        .context("Failed to set up the OTLP trace exporter")?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(spans)
+        .with_resource(resource.clone())
+        .build();
+
+    let metrics = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{endpoint}/v1/metrics"))
+        .build()
+        .context("Failed to set up the OTLP metric exporter")?;
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metrics)
+        .with_resource(resource)
+        .build();
+    global::set_meter_provider(meter_provider.clone());
+
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("gsheets-agent"));
+
+    Ok(Some((
+        layer,
+        Telemetry {
+            tracer_provider,
+            meter_provider,
+        },
+    )))
+}
+
+struct Metrics {
+    model_duration: Histogram<f64>,
+    tool_duration: Histogram<f64>,
+    tokens: Counter<u64>,
+}
+
+/// Created on first use, which is after [`init`] has set the meter provider.
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let meter = global::meter("gsheets-agent");
+
+    Metrics {
+        model_duration: meter
+            .f64_histogram("gsheets_agent.model.duration")
+            .with_unit("ms")
+            .with_description("Latency of completions, including retries")
+            .build(),
+        tool_duration: meter
+            .f64_histogram("gsheets_agent.tool.duration")
+            .with_unit("ms")
+            .with_description("Latency of tool calls")
+            .build(),
+        tokens: meter
+            .u64_counter("gsheets_agent.tokens")
+            .with_description("Estimated tokens used by completions")
+            .build(),
+    }
+});
+
+/// Records a completion of `model`, with the tokens it used if it succeeded.
+pub fn record_model_call(model: &str, duration_ms: u64, usage: Option<Usage>) {
+    let metrics = &*METRICS;
+    let model = KeyValue::new("model", model.to_string());
+
+    metrics.model_duration.record(
+        duration_ms as f64,
+        &[model.clone(), outcome(usage.is_some())],
+    );
+    if let Some(usage) = usage {
+        for (kind, tokens) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+        ] {
+            metrics
+                .tokens
+                .add(tokens as u64, &[model.clone(), KeyValue::new("kind", kind)]);
+        }
+    }
+}
+
+pub fn record_tool_call(tool: &str, duration_ms: u64, succeeded: bool) {
+    METRICS.tool_duration.record(
+        duration_ms as f64,
+        &[KeyValue::new("tool", tool.to_string()), outcome(succeeded)],
+    );
+}
+
+/// Error rates are the share of measurements with the `error` outcome.
+fn outcome(succeeded: bool) -> KeyValue {
+    KeyValue::new("outcome", if succeeded { "ok" } else { "error" })
+}
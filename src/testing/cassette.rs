@@ -0,0 +1,203 @@
+//! A completion model that replays recorded completions, so tests of the tool
+//! loop are deterministic and run offline.
+//!
+//! A cassette is a JSON file in `src/testing/cassettes` listing, for every
+//! completion in order, the prompt it was given and what the model answered.
+//! Replaying fails as soon as a prompt differs from the recorded one. With
+//! `CASSETTE_RECORD=1` set, completions come from the default model instead
+//! and the cassette is rewritten with them.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt;
+use rig::{
+    OneOrMany,
+    completion::{self, CompletionError, CompletionRequest, CompletionResponse},
+    message::{AssistantContent, Message, ToolResultContent, UserContent},
+    streaming::{StreamingChoice, StreamingCompletionModel, StreamingResult},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{config::ModelConfig, provider::Model};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    /// Text of the request's prompt, or of the tool results it carries.
+    prompt: String,
+    response: Vec<Choice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Choice {
+    Text(String),
+    ToolCall { name: String, arguments: Value },
+}
+
+#[derive(Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    /// The model recorded from; replaying if `None`.
+    recording: Option<Model>,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    interactions: Vec<Interaction>,
+    /// Index of the next interaction to replay.
+    next: usize,
+}
+
+impl Cassette {
+    /// Loads `src/testing/cassettes/{name}.json`, or starts recording it over.
+    pub fn load(name: &str) -> Self {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/testing/cassettes")
+            .join(format!("{name}.json"));
+
+        let (recording, interactions) = if std::env::var_os("CASSETTE_RECORD").is_some() {
+            (
+                Some(Model::from_config(&ModelConfig::default())),
+                Vec::new(),
+            )
+        } else {
+            let contents = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+            let interactions = serde_json::from_str(&contents)
+                .unwrap_or_else(|e| panic!("Failed to parse {}: {e}", path.display()));
+            (None, interactions)
+        };
+
+        Self {
+            path,
+            recording,
+            state: Arc::new(Mutex::new(State {
+                interactions,
+                next: 0,
+            })),
+        }
+    }
+
+    /// Whether every recorded completion was replayed.
+    pub fn finished(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.next == state.interactions.len()
+    }
+
+    async fn respond(&self, request: CompletionRequest) -> Result<Vec<Choice>, CompletionError> {
+        let prompt = prompt_text(&request.prompt);
+
+        let Some(model) = &self.recording else {
+            let mut state = self.state.lock().unwrap();
+            let Some(interaction) = state.interactions.get(state.next).cloned() else {
+                return Err(CompletionError::ProviderError(format!(
+                    "{} has no completion left for the prompt {prompt:?}",
+                    self.path.display()
+                )));
+            };
+            if interaction.prompt != prompt {
+                return Err(CompletionError::ProviderError(format!(
+                    "{} expected the prompt {:?} for completion {}, got {prompt:?}",
+                    self.path.display(),
+                    interaction.prompt,
+                    state.next + 1
+                )));
+            }
+            state.next += 1;
+
+            return Ok(interaction.response);
+        };
+
+        let mut stream = model.stream(request).await?;
+        let mut response = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            match chunk? {
+                StreamingChoice::Message(text) => match response.last_mut() {
+                    Some(Choice::Text(last)) => last.push_str(&text),
+                    _ => response.push(Choice::Text(text)),
+                },
+                StreamingChoice::ToolCall(name, _, arguments) => {
+                    response.push(Choice::ToolCall { name, arguments });
+                }
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.interactions.push(Interaction {
+            prompt,
+            response: response.clone(),
+        });
+        state.next += 1;
+        let json = serde_json::to_string_pretty(&state.interactions).unwrap();
+        std::fs::write(&self.path, json + "\n")
+            .unwrap_or_else(|e| panic!("Failed to write {}: {e}", self.path.display()));
+
+        Ok(response)
+    }
+}
+
+impl completion::CompletionModel for Cassette {
+    type Response = ();
+
+    async fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse<()>, CompletionError> {
+        let choices = self.respond(request).await?;
+        let contents = choices.into_iter().map(|choice| match choice {
+            Choice::Text(text) => AssistantContent::text(text),
+            Choice::ToolCall { name, arguments } => {
+                AssistantContent::tool_call(String::new(), name, arguments)
+            }
+        });
+
+        Ok(CompletionResponse {
+            choice: OneOrMany::many(contents.collect::<Vec<_>>())
+                .map_err(|_| CompletionError::ResponseError("The response is empty".into()))?,
+            raw_response: (),
+        })
+    }
+}
+
+impl StreamingCompletionModel for Cassette {
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamingResult, CompletionError> {
+        let chunks = self.respond(request).await?.into_iter().map(|choice| {
+            Ok(match choice {
+                Choice::Text(text) => StreamingChoice::Message(text),
+                // Providers may leave out ids, which the tool loop makes up.
+                Choice::ToolCall { name, arguments } => {
+                    StreamingChoice::ToolCall(name, String::new(), arguments)
+                }
+            })
+        });
+
+        Ok(Box::pin(futures::stream::iter(chunks)))
+    }
+}
+
+fn prompt_text(prompt: &Message) -> String {
+    let Message::User { content } = prompt else {
+        return String::new();
+    };
+
+    content
+        .iter()
+        .flat_map(|content| match content {
+            UserContent::Text(text) => vec![text.text.clone()],
+            UserContent::ToolResult(result) => result
+                .content
+                .iter()
+                .filter_map(|content| match content {
+                    ToolResultContent::Text(text) => Some(text.text.clone()),
+                    ToolResultContent::Image(_) => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
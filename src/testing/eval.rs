@@ -0,0 +1,57 @@
+//! Regression tests of qualification quality, run with `cargo test --features
+//! eval`: the agent judges a bundled set of anonymized leads whose verdicts are
+//! known, and fails if it agrees with them less often than it should.
+//!
+//! The answers are replayed from the `golden_leads` cassette; with
+//! `CASSETTE_RECORD=1`, the live default model judges the leads instead and
+//! its answers are recorded, which is how a change to the prompts is checked.
+
+use serde_json::Value;
+
+use crate::{
+    config::{GuardConfig, QualifyConfig},
+    qualify::{
+        Columns,
+        eval::{evaluate, labeled_leads},
+    },
+};
+
+const CRITERIA: &str = "B2B companies with at least 50 employees, where the contact is a \
+                        manager or above and has a business need";
+/// Lowest share of the leads the agent must give the labeled verdict.
+const MIN_ACCURACY: f64 = 0.85;
+
+#[tokio::test]
+async fn judges_the_golden_leads_like_people_do() {
+    let mut csv = csv::Reader::from_path(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testing/golden_leads.csv"),
+    )
+    .unwrap();
+    let headers: Vec<String> = csv.headers().unwrap().iter().map(String::from).collect();
+    let rows: Vec<Vec<Value>> = csv
+        .records()
+        .map(|record| record.unwrap().iter().map(Value::from).collect())
+        .collect();
+    let (leads, labels) = labeled_leads(&headers, &rows, "Qualified");
+    assert_eq!(leads.len(), rows.len());
+
+    let agent = super::agent("golden_leads", GuardConfig::default()).await;
+    let metrics = evaluate(
+        &agent,
+        &QualifyConfig::default(),
+        CRITERIA,
+        &leads,
+        &labels,
+        &Columns::default(),
+    )
+    .await;
+
+    println!("{}", serde_json::to_string_pretty(&metrics).unwrap());
+    assert_eq!(metrics.judged, leads.len(), "some leads weren't judged");
+    assert!(
+        metrics.accuracy >= MIN_ACCURACY,
+        "the accuracy fell to {:.2}, below {MIN_ACCURACY}",
+        metrics.accuracy
+    );
+    assert!(agent.model.finished());
+}
@@ -0,0 +1,235 @@
+//! An in-process MCP server with a fake Google Sheets tool surface.
+//!
+//! Tool handlers are plain functions, so the spreadsheets live in statics.
+//! Tests share one server and keep out of each other's way by using
+//! spreadsheet IDs of their own.
+
+use std::{
+    collections::BTreeMap,
+    net::{TcpListener, TcpStream},
+    pin::Pin,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::Duration,
+};
+
+use mcp_core::{
+    server::Server,
+    transport::ServerSseTransport,
+    types::{CallToolRequest, CallToolResponse, ServerCapabilities, Tool, ToolResponseContent},
+};
+use serde_json::{Value, json};
+
+type Rows = Vec<Vec<String>>;
+/// What tool handlers return, as [`mcp_core::tools::ToolHandlerFn`] requires.
+type Pending = Pin<Box<dyn Future<Output = CallToolResponse> + Send>>;
+
+/// Sheets keyed by spreadsheet ID, then by title.
+static SPREADSHEETS: LazyLock<Mutex<BTreeMap<String, BTreeMap<String, Rows>>>> =
+    LazyLock::new(Mutex::default);
+/// Names of the tools called on each spreadsheet, in order.
+static CALLS: LazyLock<Mutex<BTreeMap<String, Vec<String>>>> = LazyLock::new(Mutex::default);
+
+static SERVER: OnceLock<String> = OnceLock::new();
+
+/// SSE endpoint of the server, started on first use on a thread of its own so
+/// it outlives the runtime of any one test.
+pub fn url() -> &'static str {
+    SERVER.get_or_init(|| {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("no free port for the mock MCP server")
+            .port();
+
+        std::thread::spawn(move || {
+            let protocol = Server::builder("mock-sheets".to_string(), "1.0".to_string())
+                .capabilities(ServerCapabilities {
+                    tools: Some(json!({ "listChanged": false })),
+                    ..Default::default()
+                })
+                .register_tool(
+                    tool("list_sheets", "List the sheets of a spreadsheet", &[]),
+                    list_sheets,
+                )
+                .register_tool(
+                    tool("read_range", "Read the rows of a sheet", &["range"]),
+                    read_range,
+                )
+                .register_tool(
+                    tool(
+                        "write_range",
+                        "Replace the rows of a sheet",
+                        &["range", "values"],
+                    ),
+                    write_range,
+                )
+                .build();
+            let transport = ServerSseTransport::new("127.0.0.1".to_string(), port, protocol);
+
+            tokio::runtime::Runtime::new()
+                .expect("failed to start the mock MCP server's runtime")
+                .block_on(Server::start(transport))
+                .expect("the mock MCP server stopped");
+        });
+
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        format!("http://127.0.0.1:{port}/sse")
+    })
+}
+
+pub fn insert(spreadsheet: &str, sheet: &str, rows: &[&[&str]]) {
+    let rows = rows
+        .iter()
+        .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+        .collect();
+
+    SPREADSHEETS
+        .lock()
+        .unwrap()
+        .entry(spreadsheet.to_string())
+        .or_default()
+        .insert(sheet.to_string(), rows);
+}
+
+pub fn rows(spreadsheet: &str, sheet: &str) -> Option<Rows> {
+    SPREADSHEETS
+        .lock()
+        .unwrap()
+        .get(spreadsheet)?
+        .get(sheet)
+        .cloned()
+}
+
+/// The tools called on `spreadsheet` so far.
+pub fn calls(spreadsheet: &str) -> Vec<String> {
+    CALLS
+        .lock()
+        .unwrap()
+        .get(spreadsheet)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// A tool taking a `spreadsheet_id` and the string arguments in `params`, but
+/// `values`, which is a list of rows.
+fn tool(name: &str, description: &str, params: &[&str]) -> Tool {
+    let mut properties = json!({ "spreadsheet_id": { "type": "string" } });
+    for param in params {
+        properties[*param] = match *param {
+            "values" => json!({
+                "type": "array",
+                "items": { "type": "array", "items": { "type": "string" } }
+            }),
+            _ => json!({ "type": "string" }),
+        };
+    }
+    let mut required = vec!["spreadsheet_id"];
+    required.extend(params);
+
+    Tool {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        input_schema: json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        }),
+    }
+}
+
+/// Runs `handler` on the spreadsheet the request names, recording the call.
+fn handle(
+    request: &CallToolRequest,
+    handler: impl FnOnce(&mut BTreeMap<String, Rows>, &BTreeMap<String, Value>) -> Result<Value, String>,
+) -> CallToolResponse {
+    let args: BTreeMap<String, Value> = request
+        .arguments
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let Some(spreadsheet) = args.get("spreadsheet_id").and_then(Value::as_str) else {
+        return response(Err("`spreadsheet_id` is required".to_string()));
+    };
+
+    CALLS
+        .lock()
+        .unwrap()
+        .entry(spreadsheet.to_string())
+        .or_default()
+        .push(request.name.clone());
+
+    let mut spreadsheets = SPREADSHEETS.lock().unwrap();
+    let res = match spreadsheets.get_mut(spreadsheet) {
+        Some(sheets) => handler(sheets, &args),
+        None => Err(format!("There's no spreadsheet `{spreadsheet}`")),
+    };
+
+    response(res)
+}
+
+fn response(res: Result<Value, String>) -> CallToolResponse {
+    let (text, is_error) = match res {
+        Ok(value) => (value.to_string(), None),
+        Err(e) => (e, Some(true)),
+    };
+
+    CallToolResponse {
+        content: vec![ToolResponseContent::Text { text }],
+        is_error,
+        meta: None,
+    }
+}
+
+/// The title of the sheet in a range like `Leads!A1:C3`.
+fn sheet(args: &BTreeMap<String, Value>) -> Result<&str, String> {
+    let range = args
+        .get("range")
+        .and_then(Value::as_str)
+        .ok_or("`range` is required")?;
+
+    Ok(range.split('!').next().unwrap_or(range))
+}
+
+fn list_sheets(request: CallToolRequest) -> Pending {
+    Box::pin(async move {
+        handle(&request, |sheets, _| {
+            Ok(json!({ "sheets": sheets.keys().collect::<Vec<_>>() }))
+        })
+    })
+}
+
+fn read_range(request: CallToolRequest) -> Pending {
+    Box::pin(async move {
+        handle(&request, |sheets, args| {
+            let sheet = sheet(args)?;
+            let rows = sheets
+                .get(sheet)
+                .ok_or_else(|| format!("There's no sheet `{sheet}`"))?;
+
+            Ok(json!({ "values": rows }))
+        })
+    })
+}
+
+fn write_range(request: CallToolRequest) -> Pending {
+    Box::pin(async move {
+        handle(&request, |sheets, args| {
+            let sheet = sheet(args)?.to_string();
+            let rows: Rows = args
+                .get("values")
+                .cloned()
+                .and_then(|values| serde_json::from_value(values).ok())
+                .ok_or("`values` must be a list of rows of strings")?;
+            let updated = rows.len();
+            sheets.insert(sheet, rows);
+
+            Ok(json!({ "updatedRows": updated }))
+        })
+    })
+}
@@ -0,0 +1,157 @@
+//! Harness for tests of the tool loop that need neither a live MCP server nor
+//! a model provider: the agent talks to [`mock_mcp`] through the real MCP
+//! client, and its completions are replayed from a [`Cassette`].
+
+mod cassette;
+#[cfg(feature = "eval")]
+mod eval;
+mod mock_mcp;
+mod qualify;
+mod tool_loop;
+
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use rig::{completion::ToolDefinition, tool::ToolSet};
+
+pub use cassette::Cassette;
+
+use crate::{
+    audit::AuditLog,
+    chat::Agent,
+    config::{
+        AuditConfig, CrmConfig, GuardConfig, HistoryConfig, LimitsConfig, ModelConfig,
+        NotifyConfig, QualifyConfig, ServerConfig, SheetsConfig, ToolsConfig,
+    },
+    mcp::{McpServers, McpTools},
+    profile::Profiles,
+    qualify::Resources,
+    sheets::{Spreadsheets, Workbook},
+    tools::{Toolbox, WriteGuard},
+    workspace::Workspace,
+};
+
+/// An agent with the mock server's tools, as `gsheets__<tool>`, replaying the
+/// cassette `name`. There's no user to confirm changes, as in batch mode.
+pub async fn agent(name: &str, guard: GuardConfig) -> Agent<Cassette> {
+    agent_with_tools(name, guard, ToolsConfig::default()).await
+}
+
+/// Like [`agent`], with the tools configured by `tools`.
+pub async fn agent_with_tools(
+    name: &str,
+    guard: GuardConfig,
+    tools: ToolsConfig,
+) -> Agent<Cassette> {
+    agent_with_guard(name, WriteGuard::new(guard, false), tools).await
+}
+
+/// Like [`agent_with_tools`], with the calls policed by `guard`.
+pub async fn agent_with_guard(
+    name: &str,
+    guard: WriteGuard,
+    tools: ToolsConfig,
+) -> Agent<Cassette> {
+    build(name, guard, tools, ToolSet::default(), Vec::new(), None).await
+}
+
+/// Like [`agent`], logging what it writes to the audit log at `path`.
+pub async fn agent_with_audit(name: &str, path: &Path) -> Agent<Cassette> {
+    let config = AuditConfig {
+        enabled: true,
+        path: Some(path.to_path_buf()),
+    };
+    let audit = AuditLog::from_config(&config).unwrap();
+    let guard = WriteGuard::new(GuardConfig::default(), false);
+
+    build(
+        name,
+        guard,
+        ToolsConfig::default(),
+        ToolSet::default(),
+        Vec::new(),
+        audit,
+    )
+    .await
+}
+
+/// Like [`agent`], with the built-in Sheets tools on `sheets` too, as when
+/// files are imported.
+pub async fn agent_with_sheets(
+    name: &str,
+    guard: GuardConfig,
+    sheets: &Spreadsheets,
+) -> Agent<Cassette> {
+    let tools = ToolsConfig::default();
+    let (mut toolset, mut definitions) = (ToolSet::default(), Vec::new());
+    crate::sheets::add_tools(
+        &SheetsConfig::default(),
+        sheets,
+        &tools,
+        &mut toolset,
+        &mut definitions,
+    )
+    .await
+    .unwrap();
+    let guard = WriteGuard::new(guard, false);
+
+    build(name, guard, tools, toolset, definitions, None).await
+}
+
+async fn build(
+    name: &str,
+    guard: WriteGuard,
+    tools: ToolsConfig,
+    toolset: ToolSet,
+    definitions: Vec<ToolDefinition>,
+    audit: Option<AuditLog>,
+) -> Agent<Cassette> {
+    let server = ServerConfig {
+        sse_url: mock_mcp::url().to_string(),
+        ..ServerConfig::default()
+    };
+    let servers = McpServers::connect(&BTreeMap::from([("gsheets".to_string(), server)]), None)
+        .await
+        .expect("failed to connect to the mock MCP server");
+
+    Agent {
+        model: Cassette::load(name),
+        model_config: ModelConfig::default(),
+        fallbacks: Vec::new(),
+        history_config: HistoryConfig::default(),
+        limits: LimitsConfig::default(),
+        preamble: "You are a test agent.".to_string(),
+        instructions: String::new(),
+        profile: None,
+        profiles: Profiles::default(),
+        tools: Toolbox::new(
+            McpTools::new(servers, &tools).await,
+            toolset,
+            definitions,
+            guard,
+            &tools,
+            audit,
+        ),
+        workspace: Workspace::default(),
+        echo: None,
+        transcripts: None,
+        privacy: None,
+        injection: None,
+        limiter: None,
+        activity: None,
+    }
+}
+
+/// What `qualify` runs share, with only the local workbook to read and write,
+/// which starts out empty.
+pub async fn resources(config: QualifyConfig) -> Resources {
+    Resources {
+        sheets: Spreadsheets::new(None, Arc::new(Workbook::default())),
+        config,
+        crm: CrmConfig::default(),
+        notify: NotifyConfig::default(),
+        dry_run: false,
+        web: None,
+        pricing: BTreeMap::new(),
+        mcp: McpServers::connect(&BTreeMap::new(), None).await.unwrap(),
+    }
+}
@@ -0,0 +1,509 @@
+//! The `qualify` pipeline, run against the local workbook with recorded
+//! judgments.
+
+use serde_json::{Value, json};
+
+use super::{agent, agent_with_audit, agent_with_guard, agent_with_sheets, resources};
+use crate::{
+    cli::QualifyArgs,
+    config::{GuardConfig, QualifyConfig, ToolsConfig},
+    output::OutputFormat,
+    qualify::{self, Pipeline, Resources},
+    schedule,
+    serve::webhook::{self, Submission},
+    sheets::{LOCAL_SPREADSHEET, rows},
+    store,
+    tools::{Policy, WriteGuard},
+};
+
+const CRITERIA: &str = "Companies that build computing machines";
+
+/// The arguments of `qualify --sheet {sheet}` on the local workbook.
+fn args(sheet: &str) -> QualifyArgs {
+    QualifyArgs {
+        spreadsheet: LOCAL_SPREADSHEET.to_string(),
+        sheet: sheet.to_string(),
+        criteria: Some(CRITERIA.to_string()),
+        chunk_size: None,
+        overlap: None,
+        concurrency: None,
+        from_row: None,
+        incremental: false,
+        resume_run: None,
+        results_sheet: None,
+        cleaned_sheet: None,
+        named_range: None,
+        summary: false,
+        export_csv: None,
+        report: None,
+        push_to: None,
+        force: false,
+    }
+}
+
+/// Adds `sheet` to the local workbook with a lead per row below the headers.
+fn insert(resources: &Resources, sheet: &str, leads: &[[&str; 2]]) {
+    let mut rows = vec![vec![json!("Name"), json!("Company")]];
+    rows.extend(leads.iter().map(|lead| lead.map(Value::from).to_vec()));
+    resources
+        .sheets
+        .local()
+        .add_sheet(sheet.to_string(), rows)
+        .unwrap();
+}
+
+/// Adds `lead` below the last row of `sheet`.
+async fn add_lead(resources: &Resources, sheet: &str, lead: [&str; 2]) {
+    resources
+        .sheets
+        .append_rows(
+            LOCAL_SPREADSHEET,
+            &format!("'{sheet}'!A1"),
+            vec![lead.map(Value::from).to_vec()],
+        )
+        .await
+        .unwrap();
+}
+
+/// The row and verdict of each result of `sheet`, in the order they were
+/// written.
+async fn verdicts(resources: &Resources, sheet: &str) -> Vec<(Value, Value)> {
+    let results = resources
+        .sheets
+        .read_range(LOCAL_SPREADSHEET, &format!("'{sheet} results'!A2:C"))
+        .await
+        .unwrap();
+
+    rows(results)
+        .into_iter()
+        .map(|row| (row[0].clone(), row[2].clone()))
+        .collect()
+}
+
+/// A form response to `sheet` in `row`, as the Apps Script trigger posts it.
+fn submission(sheet: &str, row: u64) -> Submission {
+    serde_json::from_value(json!({
+        "spreadsheet": LOCAL_SPREADSHEET,
+        "sheet": sheet,
+        "row": row,
+        "criteria": CRITERIA,
+    }))
+    .unwrap()
+}
+
+fn config() -> QualifyConfig {
+    QualifyConfig {
+        lock_minutes: 0,
+        format_results: false,
+        ..QualifyConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn qualifies_the_rows_added_since_the_last_run() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Signups",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let agent = agent(
+        "qualifies_the_rows_added_since_the_last_run",
+        GuardConfig::default(),
+    )
+    .await;
+
+    schedule::qualify_since_last_run(
+        &agent,
+        &resources,
+        args("Signups"),
+        None,
+        OutputFormat::Json,
+    )
+    .await
+    .unwrap();
+    // The page padded to the chunk size ends further down, but the run
+    // stopped at the last lead.
+    let key = format!("{LOCAL_SPREADSHEET}/Signups");
+    assert_eq!(store::open().unwrap().last_row(&key).unwrap(), Some(2));
+
+    add_lead(&resources, "Signups", ["Grace Hopper", "Remington Rand"]).await;
+    schedule::qualify_since_last_run(
+        &agent,
+        &resources,
+        args("Signups"),
+        None,
+        OutputFormat::Json,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        verdicts(&resources, "Signups").await,
+        [(json!(2), json!("yes")), (json!(3), json!("yes"))]
+    );
+    assert_eq!(store::open().unwrap().last_row(&key).unwrap(), Some(3));
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn qualifies_each_form_submission_once() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Responses",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let agent = agent(
+        "qualifies_each_form_submission_once",
+        GuardConfig::default(),
+    )
+    .await;
+
+    webhook::qualify(&agent, &resources, submission("Responses", 2))
+        .await
+        .unwrap();
+    // Two responses come in together, and the first submission's run takes
+    // in both.
+    add_lead(&resources, "Responses", ["Grace Hopper", "Remington Rand"]).await;
+    add_lead(&resources, "Responses", ["Konrad Zuse", "Zuse KG"]).await;
+    for row in [3, 4] {
+        webhook::qualify(&agent, &resources, submission("Responses", row))
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        verdicts(&resources, "Responses").await,
+        [
+            (json!(2), json!("yes")),
+            (json!(3), json!("yes")),
+            (json!(4), json!("yes"))
+        ]
+    );
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn undoes_the_results_of_the_latest_run() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Leads",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let agent = agent_with_sheets(
+        "undoes_the_results_of_the_latest_run",
+        GuardConfig::default(),
+        &resources.sheets,
+    )
+    .await;
+    let run = async |args: QualifyArgs| {
+        let pipeline = Pipeline {
+            agent: &agent,
+            resources: &resources,
+            output: OutputFormat::Json,
+            progress: None,
+            bar: None,
+        };
+        qualify::run(pipeline, &args).await.unwrap();
+    };
+
+    run(args("Leads")).await;
+    add_lead(&resources, "Leads", ["Grace Hopper", "Remington Rand"]).await;
+    run(QualifyArgs {
+        from_row: Some(3),
+        ..args("Leads")
+    })
+    .await;
+    assert_eq!(verdicts(&resources, "Leads").await.len(), 2);
+
+    let restored = agent.tools.undo(true).await.unwrap();
+
+    assert_eq!(restored, ["'Leads results'!A3:G3"]);
+    assert_eq!(
+        verdicts(&resources, "Leads").await,
+        [(json!(2), json!("yes"))]
+    );
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn refuses_to_qualify_in_read_only_mode() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Closed",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let guard = GuardConfig {
+        refuse_writes: true,
+        ..GuardConfig::default()
+    };
+    let agent = agent_with_guard(
+        "refuses_to_qualify_in_read_only_mode",
+        WriteGuard::new(guard, false),
+        ToolsConfig::default(),
+    )
+    .await;
+    let pipeline = Pipeline {
+        agent: &agent,
+        resources: &resources,
+        output: OutputFormat::Json,
+        progress: None,
+        bar: None,
+    };
+
+    let e = qualify::run(pipeline, &args("Closed")).await.err().unwrap();
+
+    assert!(e.to_string().contains("this session is read-only"), "{e}");
+    assert!(
+        !resources
+            .sheets
+            .local()
+            .titles()
+            .contains(&"Closed results".into())
+    );
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn previews_the_results_in_a_dry_run() {
+    let mut resources = resources(config()).await;
+    resources.dry_run = true;
+    insert(
+        &resources,
+        "Drafts",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let agent = agent("previews_the_results_in_a_dry_run", GuardConfig::default()).await;
+
+    schedule::qualify_since_last_run(&agent, &resources, args("Drafts"), None, OutputFormat::Json)
+        .await
+        .unwrap();
+
+    assert_eq!(resources.sheets.local().titles(), ["Drafts"]);
+    let key = format!("{LOCAL_SPREADSHEET}/Drafts");
+    assert_eq!(store::open().unwrap().last_row(&key).unwrap(), None);
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn refuses_to_qualify_what_the_role_may_not_write() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Watched",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let policy: Policy = serde_yaml::from_str("roles: { analyst: { allow: [read] } }").unwrap();
+    let guard = WriteGuard::new(GuardConfig::default(), false)
+        .with_role(Some(policy.role("analyst").unwrap()));
+    let agent = agent_with_guard(
+        "refuses_to_qualify_what_the_role_may_not_write",
+        guard,
+        ToolsConfig::default(),
+    )
+    .await;
+    let pipeline = Pipeline {
+        agent: &agent,
+        resources: &resources,
+        output: OutputFormat::Json,
+        progress: None,
+        bar: None,
+    };
+
+    let e = qualify::run(pipeline, &args("Watched"))
+        .await
+        .err()
+        .unwrap();
+
+    assert!(
+        e.to_string()
+            .starts_with("`sheets__write_range` is a write operation, and the `analyst` role"),
+        "{e}"
+    );
+    assert_eq!(resources.sheets.local().titles(), ["Watched"]);
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn audits_the_results_it_writes() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Audited",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let agent = agent_with_audit("audits_the_results_it_writes", &path).await;
+    let pipeline = Pipeline {
+        agent: &agent,
+        resources: &resources,
+        output: OutputFormat::Json,
+        progress: None,
+        bar: None,
+    };
+
+    qualify::run(pipeline, &args("Audited")).await.unwrap();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let entries: Vec<Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let writes: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry["tool"].is_string())
+        .collect();
+    assert_eq!(writes.len(), 1, "{entries:?}");
+    assert_eq!(writes[0]["tool"], "sheets__write_range");
+    assert_eq!(writes[0]["range"], "'Audited results'!A1:G2");
+    assert_eq!(writes[0]["outcome"], "applied");
+    assert!(writes[0]["old_values"].is_string() && writes[0]["new_values"].is_string());
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn writes_the_results_of_failed_leads_once() {
+    let resources = resources(QualifyConfig {
+        max_reprompts: 0,
+        ..config()
+    })
+    .await;
+    insert(
+        &resources,
+        "Retried",
+        &[["Ada Lovelace", "Analytical Engines"]],
+    );
+    let agent = agent(
+        "writes_the_results_of_failed_leads_once",
+        GuardConfig::default(),
+    )
+    .await;
+    let run = async || {
+        let pipeline = Pipeline {
+            agent: &agent,
+            resources: &resources,
+            output: OutputFormat::Json,
+            progress: None,
+            bar: None,
+        };
+        let args = QualifyArgs {
+            incremental: true,
+            ..args("Retried")
+        };
+        qualify::run(pipeline, &args).await.unwrap();
+    };
+
+    // The model can't judge the lead the first time, and does the second.
+    run().await;
+    run().await;
+
+    assert_eq!(
+        verdicts(&resources, "Retried").await,
+        [(json!(2), json!("yes"))]
+    );
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn leaves_no_results_of_earlier_runs_behind() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Shortlist",
+        &[
+            ["Ada Lovelace", "Analytical Engines"],
+            ["Grace Hopper", "Remington Rand"],
+        ],
+    );
+    let agent = agent(
+        "leaves_no_results_of_earlier_runs_behind",
+        GuardConfig::default(),
+    )
+    .await;
+    let run = async || {
+        let pipeline = Pipeline {
+            agent: &agent,
+            resources: &resources,
+            output: OutputFormat::Json,
+            progress: None,
+            bar: None,
+        };
+        qualify::run(pipeline, &args("Shortlist")).await.unwrap();
+    };
+
+    run().await;
+    // The second lead drops out before the sheet is qualified again.
+    resources
+        .sheets
+        .write_range(
+            LOCAL_SPREADSHEET,
+            "'Shortlist'!A3",
+            vec![vec![json!(""); 2]],
+        )
+        .await
+        .unwrap();
+    run().await;
+
+    assert_eq!(
+        verdicts(&resources, "Shortlist").await,
+        [(json!(2), json!("yes"))]
+    );
+    assert!(agent.model.finished());
+}
+
+#[tokio::test]
+async fn undoes_every_chunk_of_the_latest_run() {
+    let resources = resources(config()).await;
+    insert(
+        &resources,
+        "Chunked",
+        &[
+            ["Ada Lovelace", "Analytical Engines"],
+            ["Grace Hopper", "Remington Rand"],
+        ],
+    );
+    resources
+        .sheets
+        .local()
+        .add_sheet("Notes".to_string(), vec![vec![json!("Note")]])
+        .unwrap();
+    let guard = GuardConfig {
+        confirm: false,
+        ..GuardConfig::default()
+    };
+    let agent = agent_with_sheets(
+        "undoes_every_chunk_of_the_latest_run",
+        guard,
+        &resources.sheets,
+    )
+    .await;
+    let pipeline = Pipeline {
+        agent: &agent,
+        resources: &resources,
+        output: OutputFormat::Json,
+        progress: None,
+        bar: None,
+    };
+    let args = QualifyArgs {
+        chunk_size: Some(1),
+        overlap: Some(0),
+        ..args("Chunked")
+    };
+
+    // The model notes something down while judging the first chunk.
+    qualify::run(pipeline, &args).await.unwrap();
+    let restored = agent.tools.undo(true).await.unwrap();
+
+    assert_eq!(restored, ["'Chunked results'!A1:G3", "Notes!A2:A2"]);
+    let notes = resources
+        .sheets
+        .read_range(LOCAL_SPREADSHEET, "Notes")
+        .await
+        .unwrap();
+    assert_eq!(rows(notes), [[json!("Note")]]);
+    assert!(agent.model.finished());
+}
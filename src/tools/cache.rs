@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::config::ToolsConfig;
+
+/// The tool, spreadsheet and range a read is cached under.
+type Key = (String, String, String);
+
+/// Serves repeated reads of the same range from memory, since models often
+/// read a range again a few calls after they last read it.
+pub struct ReadCache {
+    ttl: Duration,
+    reads: Mutex<HashMap<Key, (Instant, String)>>,
+}
+
+impl ReadCache {
+    /// The cache for `config`, or `None` when reads aren't cached.
+    pub fn from_config(config: &ToolsConfig) -> Option<Self> {
+        (config.cache_ttl_secs > 0).then(|| Self {
+            ttl: Duration::from_secs(config.cache_ttl_secs),
+            reads: Mutex::default(),
+        })
+    }
+
+    /// What `tool` returned for the same range, if that was recently enough.
+    pub fn get(&self, tool: &str, args: &Value) -> Option<String> {
+        let key = key(tool, args)?;
+        let mut reads = self.reads.lock().expect("the cache lock isn't poisoned");
+
+        match reads.get(&key) {
+            Some((read_at, result)) if read_at.elapsed() < self.ttl => Some(result.clone()),
+            Some(_) => {
+                reads.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Remembers what `tool` returned, if it read a range.
+    pub fn insert(&self, tool: &str, args: &Value, result: &str) {
+        if let Some(key) = key(tool, args) {
+            self.reads
+                .lock()
+                .expect("the cache lock isn't poisoned")
+                .insert(key, (Instant::now(), result.to_string()));
+        }
+    }
+
+    /// Forgets every read, since after a write any of them may be out of date.
+    pub fn clear(&self) {
+        self.reads
+            .lock()
+            .expect("the cache lock isn't poisoned")
+            .clear();
+    }
+}
+
+/// The key of a call to `tool`, or `None` if it doesn't read a range of a
+/// spreadsheet.
+fn key(tool: &str, args: &Value) -> Option<Key> {
+    let spreadsheet = args.get("spreadsheet_id")?.as_str()?;
+    let range = args.get("range")?.as_str()?;
+
+    Some((tool.to_string(), spreadsheet.to_string(), range.to_string()))
+}
+
+/// Answers a call made again within the same run of the agent with what it
+/// returned the first time, for tools that don't change anything and so
+/// return the same for the same arguments until something does.
+#[derive(Default)]
+pub struct TurnMemo {
+    results: Mutex<HashMap<(String, String), String>>,
+}
+
+impl TurnMemo {
+    /// The memo for `config`, or `None` when every call goes to the tool.
+    pub fn from_config(config: &ToolsConfig) -> Option<Self> {
+        config.memoize_calls.then(Self::default)
+    }
+
+    /// What `tool` returned for the same `args` earlier in the run.
+    pub fn get(&self, tool: &str, args: &Value) -> Option<String> {
+        self.results
+            .lock()
+            .expect("the memo lock isn't poisoned")
+            .get(&(tool.to_string(), args.to_string()))
+            .cloned()
+    }
+
+    pub fn insert(&self, tool: &str, args: &Value, result: &str) {
+        self.results
+            .lock()
+            .expect("the memo lock isn't poisoned")
+            .insert((tool.to_string(), args.to_string()), result.to_string());
+    }
+
+    /// Forgets every result, at the start of a run or after a change.
+    pub fn clear(&self) {
+        self.results
+            .lock()
+            .expect("the memo lock isn't poisoned")
+            .clear();
+    }
+}
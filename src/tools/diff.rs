@@ -0,0 +1,119 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::sheets::{Range, cell_text, column_name};
+
+/// Most changed cells shown in a diff; the rest are only counted.
+const MAX_SHOWN: usize = 50;
+
+/// The cells a write would change, compared cell by cell with what the range
+/// holds now, like `git diff` for a spreadsheet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    /// The range written, as the model gave it.
+    pub range: String,
+    pub changes: Vec<CellChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellChange {
+    /// The cell in A1 notation, e.g. `B3`.
+    pub cell: String,
+    /// `None` if the cell is empty.
+    pub old: Option<String>,
+    /// `None` if the cell is cleared.
+    pub new: Option<String>,
+}
+
+impl Diff {
+    /// Compares the `current` values of `range` with the `proposed` ones.
+    ///
+    /// Like the Sheets API, cells left out of `proposed` or given as `null`
+    /// keep their values, and only an empty string clears a cell.
+    pub fn new(range: &str, current: &[Vec<Value>], proposed: &[Vec<Value>]) -> Self {
+        let (first_row, first_column) = start(range);
+
+        let changes = proposed
+            .iter()
+            .enumerate()
+            .flat_map(|(row, cells)| {
+                cells.iter().enumerate().filter_map(move |(column, new)| {
+                    if new.is_null() {
+                        return None;
+                    }
+                    let old = current
+                        .get(row)
+                        .and_then(|cells| cells.get(column))
+                        .map(cell_text)
+                        .filter(|old| !old.is_empty());
+                    let new = Some(cell_text(new)).filter(|new| !new.is_empty());
+
+                    (old != new).then(|| CellChange {
+                        cell: format!(
+                            "{}{}",
+                            column_name(first_column + column),
+                            first_row + row + 1
+                        ),
+                        old,
+                        new,
+                    })
+                })
+            })
+            .collect();
+
+        Self {
+            range: range.to_string(),
+            changes,
+        }
+    }
+
+    /// The cells `rows` would fill when appended after the `current` rows of
+    /// `range`, all of them new.
+    pub fn appended(range: &str, current: &[Vec<Value>], rows: &[Vec<Value>]) -> Self {
+        let proposed: Vec<Vec<Value>> = std::iter::repeat_n(Vec::new(), current.len())
+            .chain(rows.iter().cloned())
+            .collect();
+
+        Self::new(range, &[], &proposed)
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.changes.len() {
+            0 => return write!(f, "{}: no cells change", self.range),
+            1 => write!(f, "{}: 1 cell changes", self.range)?,
+            changes => write!(f, "{}: {changes} cells change", self.range)?,
+        }
+
+        let shown = &self.changes[..self.changes.len().min(MAX_SHOWN)];
+        let width = shown
+            .iter()
+            .map(|change| change.cell.len() + 1)
+            .max()
+            .unwrap_or(0);
+        for change in shown {
+            let mut label = format!("{}:", change.cell);
+            if let Some(old) = &change.old {
+                write!(f, "\n  {label:<width$} - {old}")?;
+                label.clear();
+            }
+            let new = change.new.as_deref().unwrap_or("(cleared)");
+            write!(f, "\n  {label:<width$} + {new}")?;
+        }
+        if self.changes.len() > shown.len() {
+            write!(f, "\n  … and {} more", self.changes.len() - shown.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The 0-based row and column of the first cell of `range`, e.g. `(1, 0)`
+/// for `Leads!A2:F`, or of the sheet when it's just a sheet name.
+fn start(range: &str) -> (usize, usize) {
+    let is_sheet = |text: &str| Range::parse(text, |_| false).is_err();
+
+    Range::parse(range, is_sheet).map_or((0, 0), |range| (range.first_row, range.first_column))
+}
@@ -0,0 +1,160 @@
+use std::io::{Write, stdin};
+
+use crate::{
+    config::GuardConfig,
+    error::Error,
+    tools::{Diff, Operation, Role, pattern},
+};
+
+/// Asks the user before the agent runs tools that modify spreadsheets, or keeps
+/// them from running at all in dry-run and read-only mode.
+pub struct WriteGuard {
+    config: GuardConfig,
+    /// Whether there is a user at the terminal to ask for confirmation.
+    interactive: bool,
+    /// What the user may do, if the policy says.
+    role: Option<Role>,
+}
+
+impl WriteGuard {
+    pub fn new(config: GuardConfig, interactive: bool) -> Self {
+        Self {
+            config,
+            interactive,
+            role: None,
+        }
+    }
+
+    /// Refuses the tools `role` may not run.
+    pub fn with_role(mut self, role: Option<Role>) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Whether `tool` is classified as modifying data.
+    ///
+    /// `read_only` patterns take precedence over `mutating` ones, so they can be
+    /// used to carve out exceptions.
+    pub fn is_mutating(&self, tool: &str) -> bool {
+        !pattern::matches_any(&self.config.read_only, tool)
+            && pattern::matches_any(&self.config.mutating, tool)
+    }
+
+    /// Whether mutating tools should be simulated instead of run.
+    pub fn dry_run(&self) -> bool {
+        self.config.dry_run
+    }
+
+    /// Whether [`WriteGuard::check`] prompts on the terminal before `tool` runs.
+    pub fn asks(&self, tool: &str) -> bool {
+        self.interactive
+            && self.config.confirm
+            && !self.config.dry_run
+            && !self.config.refuse_writes
+            && self.is_mutating(tool)
+    }
+
+    /// Refuses `tool` if it's mutating and the session is read-only, however
+    /// the model was told to behave.
+    ///
+    /// The error explains the refusal to the model.
+    pub fn check_read_only(&self, tool: &str) -> Result<(), Error> {
+        if !self.config.refuse_writes || !self.is_mutating(tool) {
+            return Ok(());
+        }
+
+        Err(read_only(tool))
+    }
+
+    /// Refuses writes made without a tool call, like those of `qualify`, as
+    /// the built-in `tools` making them would be refused: all of them in a
+    /// read-only session, and those whose operation the user's role may not
+    /// run.
+    pub fn check_writes(&self, tools: &[&str]) -> Result<(), Error> {
+        for tool in tools {
+            if self.config.refuse_writes {
+                return Err(read_only(tool));
+            }
+            if let Some(role) = &self.role {
+                role.check(tool, Operation::of(tool, true))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refuses `tool` unless the user's role may run it.
+    pub fn check_role(&self, tool: &str) -> Result<(), Error> {
+        match &self.role {
+            Some(role) => role.check(tool, Operation::of(tool, self.is_mutating(tool))),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether mutating tools keep what they overwrite, for `/undo`.
+    pub fn undo(&self) -> bool {
+        self.config.undo
+    }
+
+    /// Whether the cells a call to `tool` would change are shown first, when
+    /// asking for confirmation or instead of running it in a dry run.
+    pub fn shows_diff(&self, tool: &str) -> bool {
+        self.config.diff
+            && (self.asks(tool)
+                || self.config.dry_run && !self.config.refuse_writes && self.is_mutating(tool))
+    }
+
+    /// Checks whether the call may run, prompting on the terminal for mutating
+    /// tools with the cells they change, if `diff` has them.
+    ///
+    /// The error explains the refusal to the model.
+    pub fn check(
+        &self,
+        tool: &str,
+        args: &serde_json::Value,
+        diff: Option<&Diff>,
+    ) -> Result<(), Error> {
+        if !self.config.confirm || !self.is_mutating(tool) {
+            return Ok(());
+        }
+
+        if !self.interactive {
+            return Err(Error::Tool(format!(
+                "`{tool}` modifies data and needs the user's confirmation, which can't be given \
+                 in batch mode. Tell the user to rerun with --yes to allow it."
+            )));
+        }
+
+        match diff {
+            Some(diff) => eprintln!("The agent wants to run `{tool}`, which changes {diff}"),
+            None => {
+                let args = serde_json::to_string_pretty(args).unwrap_or_else(|_| args.to_string());
+                eprintln!("The agent wants to run `{tool}` with:\n{args}");
+            }
+        }
+        eprint!("Apply this change? [y/N] ");
+        let _ = std::io::stderr().flush();
+
+        let mut answer = String::new();
+        let approved = stdin().read_line(&mut answer).is_ok()
+            && matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes");
+
+        if !approved {
+            return Err(Error::Tool(format!(
+                "The user declined to run `{tool}`. Don't retry it unless they ask you to."
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// The error explaining to the model that `tool` wasn't run in a read-only
+/// session.
+fn read_only(tool: &str) -> Error {
+    Error::Tool(format!(
+        "`{tool}` modifies data, and this session is read-only, so it wasn't run. Don't retry it \
+         or look for another way to change the spreadsheet; tell the user what you would have \
+         changed instead, and that rerunning without --read-only allows changes."
+    ))
+}
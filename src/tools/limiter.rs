@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Spaces out calls to each key with a token bucket, so bursts of calls stay
+/// within per-minute quotas: those of the Sheets API for each spreadsheet, or
+/// of a model provider for each model.
+pub struct RateLimiter {
+    /// Calls that may be made at once after a quiet minute.
+    capacity: f64,
+    /// Calls regained per second.
+    rate: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    /// Negative while calls are waiting for their turn.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// The limiter allowing `requests_per_minute` calls to each key, or `None`
+    /// when calls aren't limited.
+    pub fn per_minute(requests_per_minute: u32) -> Option<Self> {
+        (requests_per_minute > 0).then(|| Self {
+            capacity: f64::from(requests_per_minute),
+            rate: f64::from(requests_per_minute) / 60.0,
+            buckets: Mutex::default(),
+        })
+    }
+
+    /// Waits until a call to `key` is within its quota.
+    pub async fn acquire(&self, key: &str) {
+        let wait = {
+            let mut buckets = self
+                .buckets
+                .lock()
+                .expect("the limiter lock isn't poisoned");
+            let now = Instant::now();
+            let bucket = buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+                tokens: self.capacity,
+                updated: now,
+            });
+
+            let regained = now.duration_since(bucket.updated).as_secs_f64() * self.rate;
+            bucket.tokens = (bucket.tokens + regained).min(self.capacity) - 1.0;
+            bucket.updated = now;
+
+            (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / self.rate))
+        };
+
+        if let Some(wait) = wait {
+            tracing::debug!(
+                key,
+                "Waiting {:.1}s to stay within the quota",
+                wait.as_secs_f64()
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Substrings of tool errors that mean the server's quota is used up.
+const RATE_LIMITED_MARKERS: &[&str] = &[
+    "Too Many Requests",
+    "RESOURCE_EXHAUSTED",
+    "Quota exceeded",
+    "rate limit",
+];
+
+/// Whether the tool error `message` says to slow down.
+pub fn is_rate_limited(message: &str) -> bool {
+    RATE_LIMITED_MARKERS
+        .iter()
+        .any(|marker| message.contains(marker))
+}
+
+/// How long the tool error `message` says to wait, as in "retry after 30s".
+pub fn retry_after(message: &str) -> Option<Duration> {
+    let (_, rest) = message.split_once("retry after ")?;
+    let secs = rest.split(|c: char| !c.is_ascii_digit()).next()?;
+
+    secs.parse().ok().map(Duration::from_secs)
+}
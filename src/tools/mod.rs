@@ -0,0 +1,645 @@
+mod cache;
+mod diff;
+mod guard;
+mod limiter;
+mod pager;
+mod pattern;
+mod policy;
+mod repair;
+mod schema;
+mod undo;
+
+pub use diff::Diff;
+pub use guard::WriteGuard;
+pub use limiter::RateLimiter;
+pub use policy::{Operation, Policy, Role};
+
+use std::time::Duration;
+
+use rig::{
+    completion::ToolDefinition,
+    message::ToolCall,
+    tool::{Tool, ToolSet, ToolSetError},
+};
+use serde_json::{Value, json};
+
+use crate::{
+    config::{BackoffConfig, ToolsConfig},
+    error::Error,
+    mcp::{McpTools, NAMESPACE_SEPARATOR},
+    metrics::Metrics,
+    sheets::{self, LOCAL_SPREADSHEET, Spreadsheets, WriteRange, quote_sheet},
+};
+
+use cache::{ReadCache, TurnMemo};
+use pager::{FETCH_MORE, Pager};
+use undo::{Snapshot, UndoLog};
+
+use crate::audit::{self, AuditLog, Origin, Outcome};
+
+/// Whether `tool` may be advertised to the model under `config`.
+pub fn is_allowed(config: &ToolsConfig, tool: &str) -> bool {
+    (config.allow.is_empty() || pattern::matches_any(&config.allow, tool))
+        && !pattern::matches_any(&config.deny, tool)
+}
+
+/// The tools advertised to the model, together with the policies applied
+/// whenever the model calls one of them.
+pub struct Toolbox {
+    mcp: McpTools,
+    /// The built-in tools.
+    toolset: ToolSet,
+    definitions: Vec<ToolDefinition>,
+    guard: WriteGuard,
+    /// Cuts oversized results short; `None` if they're sent in full.
+    pager: Option<Pager>,
+    /// `None` if every read goes to the tool.
+    cache: Option<ReadCache>,
+    /// `None` if calls repeated within a run go to the tool again.
+    memo: Option<TurnMemo>,
+    undo: UndoLog,
+    audit: Option<AuditLog>,
+    /// `None` if calls aren't spaced out.
+    limiter: Option<RateLimiter>,
+    retry: BackoffConfig,
+    /// `None` if calls may take as long as they take.
+    timeout: Option<Duration>,
+    /// Whether common mistakes in arguments are fixed before they're used.
+    repairs: bool,
+    /// Whether arguments are checked against the tool's input schema first.
+    validates: bool,
+}
+
+/// A range a mutating tool is about to write `values` to, and what it holds now.
+struct Target {
+    tool: String,
+    spreadsheet_id: Value,
+    range: String,
+    current: Vec<Vec<Value>>,
+    values: Vec<Vec<Value>>,
+}
+
+impl Target {
+    fn appends(&self) -> bool {
+        self.tool.ends_with("append_rows")
+    }
+
+    fn diff(&self) -> Diff {
+        match self.appends() {
+            true => Diff::appended(&self.range, &self.current, &self.values),
+            false => Diff::new(&self.range, &self.current, &self.values),
+        }
+    }
+
+    /// The cells written, and what they hold now.
+    fn covered(&self) -> Option<(String, Vec<Vec<Value>>)> {
+        undo::covered(&self.range, &self.current, &self.values, self.appends())
+    }
+
+    /// The snapshot to undo the write with, if its server has a tool to write
+    /// the old values back with.
+    fn snapshot(&self, tools: &Toolbox) -> Option<Snapshot> {
+        let (namespace, _) = self.tool.split_once(NAMESPACE_SEPARATOR)?;
+        let writer = format!("{namespace}{NAMESPACE_SEPARATOR}write_range");
+        if !tools.contains(&writer) {
+            return None;
+        }
+        let (range, values) = self.covered()?;
+
+        Some(Snapshot {
+            writer,
+            spreadsheet_id: self.spreadsheet_id.clone(),
+            range,
+            values,
+        })
+    }
+}
+
+impl Toolbox {
+    pub fn new(
+        mcp: McpTools,
+        toolset: ToolSet,
+        mut definitions: Vec<ToolDefinition>,
+        guard: WriteGuard,
+        config: &ToolsConfig,
+        audit: Option<AuditLog>,
+    ) -> Self {
+        let pager = Pager::from_config(config);
+        if let Some(pager) = &pager {
+            definitions.push(pager.definition());
+        }
+
+        Self {
+            mcp,
+            toolset,
+            definitions,
+            guard,
+            pager,
+            cache: ReadCache::from_config(config),
+            memo: TurnMemo::from_config(config),
+            undo: UndoLog::default(),
+            audit,
+            limiter: RateLimiter::per_minute(config.requests_per_minute),
+            retry: config.retry.clone(),
+            timeout: (config.timeout_secs > 0).then(|| Duration::from_secs(config.timeout_secs)),
+            repairs: config.repair_arguments,
+            validates: config.validate_arguments,
+        }
+    }
+
+    /// The tools of the MCP servers, followed by the built-in ones.
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        let mut definitions = self.mcp.definitions();
+        definitions.extend(self.definitions.iter().cloned());
+
+        definitions
+    }
+
+    /// Picks up tools the MCP servers added, removed or changed, if it's
+    /// time to check.
+    pub async fn refresh(&self) {
+        self.mcp.refresh().await;
+    }
+
+    /// `args` with the mistakes models often make fixed where that's safe:
+    /// arguments sent as JSON with trailing commas, numbers and booleans sent
+    /// as strings, and ranges without a sheet when the spreadsheet only has
+    /// one. A range without a sheet in a spreadsheet with several is sent
+    /// back to the model, as there's no telling which sheet it meant.
+    async fn repair(&self, tool: &str, args: &Value) -> Result<Value, Error> {
+        let mut repaired = repair::decode(args).unwrap_or_else(|| args.clone());
+        if let Some(parameters) = self.parameters(tool) {
+            repaired = repair::coerce(&parameters, &repaired);
+        }
+
+        if let Some(range) = repaired.get("range").and_then(Value::as_str)
+            && repair::lacks_sheet(range)
+            && let Some(spreadsheet_id) = repaired.get("spreadsheet_id").cloned()
+            && let Some((namespace, _)) = tool.split_once(NAMESPACE_SEPARATOR)
+        {
+            let lister = format!("{namespace}{NAMESPACE_SEPARATOR}list_sheets");
+            let listed = match self.contains(&lister) {
+                true => self
+                    .send(&lister, &json!({ "spreadsheet_id": spreadsheet_id }))
+                    .await
+                    .ok()
+                    .and_then(|listed| serde_json::from_str(&listed).ok()),
+                false => None,
+            };
+            let titles = listed.as_ref().map(repair::titles).unwrap_or_default();
+            match titles.as_slice() {
+                // A sheet may well be called `Q1`.
+                titles if titles.iter().any(|title| title == range) => {}
+                [sheet] => {
+                    repaired["range"] = Value::String(repair::on_sheet(sheet, range));
+                }
+                [] => {}
+                titles => {
+                    return Err(Error::Tool(format!(
+                        "`{tool}` wasn't called, because the range `{range}` doesn't say which \
+                         sheet it's on, and the spreadsheet has several: {}. Call it again with \
+                         the sheet in the range, e.g. `{}`.",
+                        titles.join(", "),
+                        repair::on_sheet(&titles[0], range)
+                    )));
+                }
+            }
+        }
+
+        if repaired != *args {
+            tracing::debug!(tool, %args, %repaired, "Repaired the arguments of a tool call");
+        }
+
+        Ok(repaired)
+    }
+
+    /// Refuses `args` unless they match the input schema of `tool`, saying
+    /// what's wrong with them so the model can fix its call.
+    fn validate(&self, tool: &str, args: &Value) -> Result<(), Error> {
+        let Some(parameters) = self.parameters(tool) else {
+            return Ok(());
+        };
+
+        let problems = schema::validate(&parameters, args);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        tracing::debug!(
+            tool,
+            ?problems,
+            "Refused a call whose arguments don't match the schema"
+        );
+
+        Err(Error::Tool(format!(
+            "`{tool}` wasn't called, because its arguments don't match its input schema:\n- {}\n\
+             Fix them and call it again.",
+            problems.join("\n- ")
+        )))
+    }
+
+    /// The input schema of `tool`.
+    fn parameters(&self, tool: &str) -> Option<Value> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.name == tool)
+            .cloned()
+            .or_else(|| self.mcp.definition(tool))
+            .map(|definition| definition.parameters)
+    }
+
+    fn contains(&self, tool: &str) -> bool {
+        self.toolset.contains(tool) || self.mcp.contains(tool)
+    }
+
+    /// Whether calling `tool` changes data, so it's subject to dry runs and
+    /// confirmation.
+    pub fn is_mutating(&self, tool: &str) -> bool {
+        self.guard.is_mutating(tool)
+    }
+
+    /// Whether calling `tool` asks the user for confirmation first.
+    pub fn asks(&self, tool: &str) -> bool {
+        self.guard.asks(tool)
+    }
+
+    /// Executes `tool_call`, returning the result or the error to report back
+    /// to the model. Mutating tools are refused outright in read-only mode,
+    /// as are tools the user's role may not run and arguments that don't
+    /// match the tool's input schema. Reads are served from the cache while
+    /// it's fresh, calls repeated within a run from what they returned the
+    /// first time, and results too long for the pager are cut short. Calls to
+    /// mutating tools go in the audit log, with their `origin`.
+    pub async fn call(&self, tool_call: &ToolCall, origin: Origin<'_>) -> Result<String, Error> {
+        let name = &tool_call.function.name;
+        let args = &tool_call.function.arguments;
+
+        if let Some(pager) = &self.pager
+            && name == FETCH_MORE
+        {
+            return pager.fetch_more(args);
+        }
+
+        let mutating = self.guard.is_mutating(name);
+        if let Err(e) = self.guard.check_read_only(name) {
+            eprintln!("[read-only] Refused `{name}`, which modifies data");
+            self.audit(name, args, None, origin, Outcome::Refused, Some(&e));
+            return Err(e);
+        }
+        if let Err(e) = self.guard.check_role(name) {
+            eprintln!("[policy] Refused `{name}`: {e}");
+            self.audit(name, args, None, origin, Outcome::Refused, Some(&e));
+            return Err(e);
+        }
+        let repaired = match self.repairs {
+            true => Some(self.repair(name, args).await?),
+            false => None,
+        };
+        let args = repaired.as_ref().unwrap_or(args);
+        if self.validates {
+            self.validate(name, args)?;
+        }
+
+        let shows_diff = self.guard.shows_diff(name);
+        let keeps_undo = mutating && self.guard.undo() && !self.guard.dry_run();
+        let mut target = match shows_diff || mutating && self.audit.is_some() {
+            true => self.target(name, args).await,
+            false => None,
+        };
+        let diff = target.as_ref().filter(|_| shows_diff).map(Target::diff);
+
+        if self.guard.dry_run() && mutating {
+            match &diff {
+                Some(diff) => eprintln!("[dry run] Skipped `{name}`, which would change {diff}"),
+                None => eprintln!("[dry run] Skipped `{name}` with {args}"),
+            }
+            self.audit(
+                name,
+                args,
+                target.as_ref(),
+                origin,
+                Outcome::Simulated,
+                None,
+            );
+            return Ok(format!(
+                "Success (simulated): `{name}` was not actually run because the agent is in \
+                 dry-run mode. Continue as if it had succeeded."
+            ));
+        }
+
+        if let Err(e) = self.guard.check(name, args, diff.as_ref()) {
+            self.audit(
+                name,
+                args,
+                target.as_ref(),
+                origin,
+                Outcome::Refused,
+                Some(&e),
+            );
+            return Err(e);
+        }
+
+        if keeps_undo {
+            if target.is_none() {
+                target = self.target(name, args).await;
+            }
+            if let Some(snapshot) = target.as_ref().and_then(|t| t.snapshot(self)) {
+                self.undo.push(snapshot);
+            }
+        }
+        if let Some(cache) = &self.cache
+            && !mutating
+            && let Some(result) = cache.get(name, args)
+        {
+            tracing::debug!(tool = %name, "Served the read from the cache");
+            return Ok(self.page(result));
+        }
+        if let Some(memo) = &self.memo
+            && !mutating
+            && let Some(result) = memo.get(name, args)
+        {
+            tracing::debug!(tool = %name, "Answered a repeated call from earlier in the run");
+            return Ok(self.page(result));
+        }
+
+        let res = self.send(name, args).await;
+
+        if mutating {
+            let outcome = match &res {
+                Ok(_) => Outcome::Applied,
+                Err(_) => Outcome::Failed,
+            };
+            self.audit(
+                name,
+                args,
+                target.as_ref(),
+                origin,
+                outcome,
+                res.as_ref().err(),
+            );
+        }
+        if let Some(cache) = &self.cache {
+            // Even a failed write may have changed part of the range.
+            if mutating {
+                cache.clear();
+            } else if let Ok(result) = &res {
+                cache.insert(name, args, result);
+            }
+        }
+        if let Some(memo) = &self.memo {
+            if mutating {
+                memo.clear();
+            } else if let Ok(result) = &res {
+                memo.insert(name, args, result);
+            }
+        }
+
+        res.map(|result| self.page(result))
+    }
+
+    /// Records the metrics of a run in the audit log, if there is one; see
+    /// [`AuditLog::record_metrics`].
+    pub fn audit_metrics(&self, owner: &str, id: &str, metrics: &Metrics) {
+        if let Some(log) = &self.audit {
+            log.record_metrics(owner, id, metrics);
+        }
+    }
+
+    /// Logs a call to the mutating `tool`, if there's an audit log.
+    fn audit(
+        &self,
+        tool: &str,
+        args: &Value,
+        target: Option<&Target>,
+        origin: Origin<'_>,
+        outcome: Outcome,
+        error: Option<&Error>,
+    ) {
+        let Some(log) = &self.audit else {
+            return;
+        };
+
+        let covered = target.and_then(Target::covered);
+        let new_values = args
+            .get("values")
+            .and_then(|values| serde_json::from_value::<Vec<Vec<Value>>>(values.clone()).ok());
+        log.record(&audit::Entry {
+            tool,
+            spreadsheet: args.get("spreadsheet_id"),
+            range: match &covered {
+                Some((range, _)) => Some(range),
+                None => args.get("range").and_then(Value::as_str),
+            },
+            old_values: covered.as_ref().map(|(_, values)| audit::hash(values)),
+            new_values: new_values.as_deref().map(audit::hash),
+            prompt: origin.prompt,
+            model: origin.model,
+            outcome,
+            error: error.map(Error::to_string),
+        });
+    }
+
+    /// Starts a new run of the agent, whose changes `/undo run` undoes together,
+    /// forgetting the calls of the last one.
+    pub fn begin_run(&self) {
+        self.undo.begin_run();
+        if let Some(memo) = &self.memo {
+            memo.clear();
+        }
+    }
+
+    /// Refuses writes made without a tool call, like those of `qualify`, as
+    /// the built-in `tools` making them would be refused, with the same error.
+    pub fn check_writes(&self, tools: &[&str]) -> Result<(), Error> {
+        self.guard.check_writes(tools)
+    }
+
+    /// Reads what `sheet` holds where `rows` are about to be written from A1,
+    /// or appended below its rows with `append`, for writes made without a
+    /// tool, like those of `qualify`: the cells covered and their values are
+    /// kept for `/undo`, which writes them back with the built-in
+    /// `write_range` tool, and returned for the audit log. Nothing is read
+    /// when neither needs them.
+    pub async fn before_write(
+        &self,
+        sheets: &Spreadsheets,
+        spreadsheet_id: &str,
+        sheet: &str,
+        rows: &[Vec<Value>],
+        append: bool,
+    ) -> Option<(String, Vec<Vec<Value>>)> {
+        let keeps_undo = self.guard.undo() && !self.guard.dry_run();
+        if !keeps_undo && self.audit.is_none() {
+            return None;
+        }
+        let current = match sheets.read_range(spreadsheet_id, &quote_sheet(sheet)).await {
+            Ok(current) => sheets::rows(current),
+            Err(e) => {
+                tracing::warn!("Failed to read `{sheet}` before writing to it: {e}");
+                return None;
+            }
+        };
+        let range = format!("{}!A1", quote_sheet(sheet));
+        let (range, values) = undo::covered(&range, &current, rows, append)?;
+        if keeps_undo {
+            self.undo.push(Snapshot {
+                writer: WriteRange::NAME.to_string(),
+                spreadsheet_id: json!(spreadsheet_id),
+                range: range.clone(),
+                values: values.clone(),
+            });
+        }
+
+        Some((range, values))
+    }
+
+    /// Whether changes go in the audit log.
+    pub fn audits(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Adds `entry` to the audit log, if there is one, for changes made
+    /// without a tool call.
+    pub fn record(&self, entry: &audit::Entry<'_>) {
+        if let Some(log) = &self.audit {
+            log.record(entry);
+        }
+    }
+
+    /// Writes back what the cells held before the latest change, or before
+    /// every change of the latest run if `run`, returning the ranges restored.
+    pub async fn undo(&self, run: bool) -> Result<Vec<String>, Error> {
+        let snapshots = self.undo.take(run);
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+        if let Some(memo) = &self.memo {
+            memo.clear();
+        }
+
+        // Undoing is a change too, made by the user rather than a model.
+        let origin = Origin {
+            prompt: if run { "/undo run" } else { "/undo" },
+            model: "",
+        };
+        let mut restored = Vec::with_capacity(snapshots.len());
+        for snapshot in snapshots {
+            let args = json!({
+                "spreadsheet_id": snapshot.spreadsheet_id,
+                "range": snapshot.range,
+                "values": snapshot.values,
+            });
+            let res = self
+                .send(&snapshot.writer, &args)
+                .await
+                .map_err(|e| Error::Tool(format!("Failed to restore {}: {e}", snapshot.range)));
+            let outcome = match &res {
+                Ok(_) => Outcome::Applied,
+                Err(_) => Outcome::Failed,
+            };
+            self.audit(
+                &snapshot.writer,
+                &args,
+                None,
+                origin,
+                outcome,
+                res.as_ref().err(),
+            );
+            res?;
+            restored.push(snapshot.range);
+        }
+
+        Ok(restored)
+    }
+
+    /// The range a call to `tool` with `args` writes to, if it writes values
+    /// to a range and its server can read the range first.
+    async fn target(&self, tool: &str, args: &Value) -> Option<Target> {
+        let range = args.get("range")?.as_str()?;
+        let values = serde_json::from_value(args.get("values")?.clone()).ok()?;
+        let spreadsheet_id = args.get("spreadsheet_id")?.clone();
+
+        let (namespace, _) = tool.split_once(NAMESPACE_SEPARATOR)?;
+        let reader = format!("{namespace}{NAMESPACE_SEPARATOR}read_range");
+        if !self.contains(&reader) {
+            return None;
+        }
+        let read = json!({ "spreadsheet_id": spreadsheet_id, "range": range });
+        let current = self.send(&reader, &read).await.ok()?;
+
+        Some(Target {
+            tool: tool.to_string(),
+            spreadsheet_id,
+            range: range.to_string(),
+            current: sheets::rows(serde_json::from_str(&current).ok()?),
+            values,
+        })
+    }
+
+    /// Calls `tool` within the quota of its spreadsheet, retrying with backoff
+    /// when the server says the quota is used up all the same.
+    async fn send(&self, tool: &str, args: &Value) -> Result<String, Error> {
+        let spreadsheet = args
+            .get("spreadsheet_id")
+            .and_then(Value::as_str)
+            .filter(|id| *id != LOCAL_SPREADSHEET);
+        let mut attempt = 1;
+
+        loop {
+            if let (Some(limiter), Some(spreadsheet)) = (&self.limiter, spreadsheet) {
+                limiter.acquire(spreadsheet).await;
+            }
+
+            let call = self.call_tool(tool, args.to_string());
+            let res = match self.timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, call).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        return Err(Error::Tool(format!(
+                            "`{tool}` didn't finish within {}s and was given up on. It may \
+                             still have made its changes, so check before calling it again.",
+                            timeout.as_secs()
+                        )));
+                    }
+                },
+                None => call.await,
+            };
+            match res {
+                Err(e)
+                    if attempt < self.retry.max_attempts
+                        && limiter::is_rate_limited(&e.to_string()) =>
+                {
+                    let delay = limiter::retry_after(&e.to_string()).unwrap_or_else(|| {
+                        self.retry
+                            .delay(attempt - 1)
+                            .mul_f64(rand::random_range(0.5..=1.0))
+                    });
+                    tracing::warn!(
+                        error = %e,
+                        "`{tool}` was rate limited, retrying in {:.1}s (attempt {}/{})",
+                        delay.as_secs_f64(),
+                        attempt + 1,
+                        self.retry.max_attempts
+                    );
+
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res.map_err(|e| Error::Tool(e.to_string())),
+            }
+        }
+    }
+
+    async fn call_tool(&self, tool: &str, args: String) -> Result<String, ToolSetError> {
+        match self.toolset.contains(tool) {
+            true => self.toolset.call(tool, args).await,
+            false => self.mcp.call(tool, args).await,
+        }
+    }
+
+    fn page(&self, result: String) -> String {
+        match &self.pager {
+            Some(pager) => pager.page(result),
+            None => result,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use rig::completion::ToolDefinition;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{config::ToolsConfig, error::Error, tools::is_allowed};
+
+/// The synthetic tool the model pages through cut-short results with.
+pub const FETCH_MORE: &str = "results__fetch_more";
+
+/// Cut-short results kept for `fetch_more`; older ones are forgotten.
+const KEPT_RESULTS: usize = 16;
+
+/// Keeps oversized tool results out of the conversation: only their first
+/// page is sent to the model, which can read on with [`FETCH_MORE`].
+pub struct Pager {
+    /// Longest page, in bytes.
+    page_bytes: usize,
+    results: Mutex<Results>,
+}
+
+#[derive(Default)]
+struct Results {
+    next_id: u64,
+    kept: VecDeque<(String, String)>,
+}
+
+#[derive(Deserialize)]
+struct FetchMoreArgs {
+    result_id: String,
+    offset: usize,
+}
+
+impl Pager {
+    /// The pager for `config`, or `None` when results are never cut short or
+    /// `fetch_more` isn't allowed, since a cut-short result couldn't be read on.
+    pub fn from_config(config: &ToolsConfig) -> Option<Self> {
+        if config.max_result_tokens == 0 || !is_allowed(config, FETCH_MORE) {
+            return None;
+        }
+
+        Some(Self {
+            page_bytes: config.max_result_tokens * 4,
+            results: Mutex::default(),
+        })
+    }
+
+    pub fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: FETCH_MORE.to_string(),
+            description: "Read more of a tool result that was cut short, a page at a time."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "result_id": {
+                        "type": "string",
+                        "description": "The ID given where the result was cut short"
+                    },
+                    "offset": {
+                        "type": "integer",
+                        "description": "The byte to read from, as given where the result was cut short"
+                    }
+                },
+                "required": ["result_id", "offset"]
+            }),
+        }
+    }
+
+    /// `result` if it fits on a page, or else its first page, keeping the
+    /// rest for `fetch_more`.
+    pub fn page(&self, result: String) -> String {
+        if result.len() <= self.page_bytes {
+            return result;
+        }
+
+        let mut results = self
+            .results
+            .lock()
+            .expect("the results lock isn't poisoned");
+        results.next_id += 1;
+        let id = format!("r{}", results.next_id);
+        let page = self.cut(&id, &result, 0);
+        if results.kept.len() == KEPT_RESULTS {
+            results.kept.pop_front();
+        }
+        results.kept.push_back((id, result));
+
+        page
+    }
+
+    /// Answers a call to [`FETCH_MORE`] with the next page of a kept result.
+    pub fn fetch_more(&self, args: &serde_json::Value) -> Result<String, Error> {
+        let args: FetchMoreArgs = serde_json::from_value(args.clone())
+            .map_err(|e| Error::Tool(format!("Invalid arguments for `{FETCH_MORE}`: {e}")))?;
+
+        let results = self
+            .results
+            .lock()
+            .expect("the results lock isn't poisoned");
+        let Some((id, result)) = results.kept.iter().find(|(id, _)| *id == args.result_id) else {
+            return Err(Error::Tool(format!(
+                "There's no result `{}` anymore. Call the original tool again.",
+                args.result_id
+            )));
+        };
+        if args.offset >= result.len() {
+            return Err(Error::Tool(format!(
+                "Result `{id}` is only {} bytes long, so there's nothing more to read.",
+                result.len()
+            )));
+        }
+
+        Ok(self.cut(id, result, args.offset))
+    }
+
+    /// The page of `result` from `offset`, with a note on how to read on if
+    /// there's more.
+    fn cut(&self, id: &str, result: &str, offset: usize) -> String {
+        let start = result.floor_char_boundary(offset);
+        let mut end = result.floor_char_boundary(start + self.page_bytes);
+        if end == start {
+            end = result.ceil_char_boundary(start + 1);
+        }
+
+        let mut page = result[start..end].to_string();
+        if end < result.len() {
+            page.push_str(&format!(
+                "\n\n[Cut short: this is bytes {start}-{end} of {}. Call `{FETCH_MORE}` with \
+                 {{\"result_id\": \"{id}\", \"offset\": {end}}} to read on, or narrow the \
+                 request instead.]",
+                result.len()
+            ));
+        }
+
+        page
+    }
+}
@@ -0,0 +1,124 @@
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{error::Error, tools::pattern};
+
+/// What each role may do, loaded from a YAML file, e.g.
+///
+/// ```yaml
+/// roles:
+///   analyst: { allow: [read] }
+///   ops: { allow: [read, append] }
+///   admin: { allow: [all], deny: ["*share*"] }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    pub roles: BTreeMap<String, Role>,
+}
+
+/// The operations one role may run tools for.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Role {
+    #[serde(skip)]
+    pub name: String,
+    pub allow: Vec<Operation>,
+    /// Globs over namespaced tool names refused even if their operation is
+    /// allowed.
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// What a tool does to a spreadsheet, going by its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Changes nothing.
+    Read,
+    /// Adds rows or cells, leaving the ones there alone.
+    Append,
+    /// Deletes or clears data.
+    Delete,
+    /// Any other change, e.g. overwriting or formatting cells.
+    Write,
+    /// Every operation above.
+    All,
+}
+
+impl Operation {
+    /// The operation of `tool`, which the guard says is `mutating` or not.
+    pub fn of(tool: &str, mutating: bool) -> Self {
+        let is = |verbs: &[&str]| verbs.iter().any(|verb| tool.contains(verb));
+        match () {
+            () if !mutating => Self::Read,
+            () if is(&["delete", "clear", "remove"]) => Self::Delete,
+            () if is(&["append", "insert"]) => Self::Append,
+            () => Self::Write,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read",
+            Self::Append => "append",
+            Self::Delete => "delete",
+            Self::Write => "write",
+            Self::All => "all",
+        })
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read policy {}", path.display()))?;
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse policy {}", path.display()))
+    }
+
+    /// The role called `name`.
+    pub fn role(mut self, name: &str) -> anyhow::Result<Role> {
+        match self.roles.remove(name) {
+            Some(role) => Ok(Role {
+                name: name.to_string(),
+                ..role
+            }),
+            None => anyhow::bail!(
+                "There's no role `{name}` in the policy; it has {}",
+                self.roles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+impl Role {
+    /// Refuses `tool` unless this role may run it.
+    ///
+    /// The error explains the refusal to the model.
+    pub fn check(&self, tool: &str, operation: Operation) -> Result<(), Error> {
+        if pattern::matches_any(&self.deny, tool) {
+            return Err(Error::Tool(format!(
+                "The `{}` role may not run `{tool}`, so it wasn't run. Don't retry it; tell the \
+                 user someone with another role has to.",
+                self.name
+            )));
+        }
+        if !self.allow.contains(&Operation::All) && !self.allow.contains(&operation) {
+            let allowed: Vec<String> = self.allow.iter().map(Operation::to_string).collect();
+            return Err(Error::Tool(format!(
+                "`{tool}` is a {operation} operation, and the `{}` role may only {}, so it wasn't \
+                 run. Don't retry it or look for another way to {operation}; tell the user \
+                 someone with another role has to.",
+                self.name,
+                allowed.join(" or ")
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::sheets::quote_sheet;
+
+/// Cells in A1 notation without a sheet, e.g. `A1:F200`, `B:B` or `2:501`.
+static CELLS: LazyLock<Regex> = LazyLock::new(|| {
+    let bound = r"(?:\$?[A-Za-z]{1,3}\$?\d*|\$?\d+)";
+    Regex::new(&format!("^{bound}(?::{bound})?$")).expect("the pattern is valid")
+});
+
+/// Arguments the model sent as a JSON string the provider couldn't decode,
+/// decoded once the trailing commas in it are dropped.
+pub fn decode(args: &Value) -> Option<Value> {
+    let Value::String(encoded) = args else {
+        return None;
+    };
+
+    match serde_json::from_str(&without_trailing_commas(encoded)) {
+        Ok(value @ Value::Object(_)) => Some(value),
+        _ => None,
+    }
+}
+
+/// `json` without commas right before a closing `}` or `]`, leaving strings
+/// alone.
+fn without_trailing_commas(json: &str) -> String {
+    let mut fixed = String::with_capacity(json.len());
+    let (mut in_string, mut escaped) = (false, false);
+    let mut chars = json.chars();
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            let rest: String = chars
+                .clone()
+                .skip_while(|c| c.is_whitespace())
+                .take(1)
+                .collect();
+            if rest == "}" || rest == "]" {
+                continue;
+            }
+        }
+        fixed.push(c);
+    }
+
+    fixed
+}
+
+/// `args` with the strings the `schema` wants as numbers or booleans, e.g.
+/// `"42"` for an integer, turned into them, and the numbers and booleans it
+/// wants as strings turned into strings. Leaves values it can't convert
+/// losslessly for validation to report.
+pub fn coerce(schema: &Value, args: &Value) -> Value {
+    let kinds: Vec<&str> = match schema.get("type") {
+        Some(Value::String(kind)) => vec![kind.as_str()],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let wants = |kind: &str| kinds.contains(&kind);
+
+    match args {
+        Value::String(text) if !wants("string") => {
+            let text = text.trim();
+            if wants("integer")
+                && let Ok(n) = text.parse::<i64>()
+            {
+                return Value::from(n);
+            }
+            if wants("number")
+                && let Ok(n) = text.parse::<f64>()
+                && let Some(n) = serde_json::Number::from_f64(n)
+            {
+                return Value::Number(n);
+            }
+            match (wants("boolean"), text) {
+                (true, "true") => Value::Bool(true),
+                (true, "false") => Value::Bool(false),
+                _ => args.clone(),
+            }
+        }
+        Value::Number(_) | Value::Bool(_) if wants("string") && kinds.len() == 1 => {
+            Value::String(args.to_string())
+        }
+        Value::Object(map) => {
+            let properties = schema.get("properties");
+            Value::Object(
+                map.iter()
+                    .map(|(key, value)| {
+                        let value = match properties.and_then(|p| p.get(key)) {
+                            Some(schema) => coerce(schema, value),
+                            None => value.clone(),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect(),
+            )
+        }
+        Value::Array(items) => match schema.get("items") {
+            Some(item) => items.iter().map(|value| coerce(item, value)).collect(),
+            None => args.clone(),
+        },
+        _ => args.clone(),
+    }
+}
+
+/// Whether `range` is only cells, without the sheet they're on.
+pub fn lacks_sheet(range: &str) -> bool {
+    CELLS.is_match(range.trim())
+}
+
+/// `cells` on `sheet`, with the sheet's name quoted only if it has to be.
+pub fn on_sheet(sheet: &str, cells: &str) -> String {
+    let plain = sheet.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !sheet.is_empty()
+        && !lacks_sheet(sheet);
+    match plain {
+        true => format!("{sheet}!{cells}"),
+        false => format!("{}!{cells}", quote_sheet(sheet)),
+    }
+}
+
+/// The titles of the sheets in what a `list_sheets` tool returned, whether
+/// it's the Sheets API's `spreadsheets.get` response or a list of names.
+pub fn titles(listed: &Value) -> Vec<String> {
+    let sheets = match listed {
+        Value::Array(sheets) => sheets,
+        listed => match listed.get("sheets").and_then(Value::as_array) {
+            Some(sheets) => sheets,
+            None => return Vec::new(),
+        },
+    };
+
+    sheets
+        .iter()
+        .filter_map(|sheet| {
+            sheet
+                .as_str()
+                .or_else(|| sheet["properties"]["title"].as_str())
+                .or_else(|| sheet["title"].as_str())
+        })
+        .map(str::to_string)
+        .collect()
+}
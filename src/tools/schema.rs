@@ -0,0 +1,153 @@
+use serde_json::Value;
+
+/// What's wrong with `args` as the input of a tool with the JSON `schema`,
+/// one problem per line, or nothing if they match it.
+///
+/// Only the keywords tool schemas use are checked: `type`, `enum`,
+/// `required`, `properties`, `additionalProperties`, `items`, `minimum`,
+/// `maximum`, `minItems` and `maxItems`. Others are ignored, so a schema
+/// using them is enforced by the server alone.
+pub fn validate(schema: &Value, args: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    check(schema, args, "arguments", &mut problems);
+    problems
+}
+
+fn check(schema: &Value, value: &Value, path: &str, problems: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(kind) => vec![kind.as_str()],
+            Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|kind| is(kind, value)) {
+            problems.push(format!(
+                "`{path}` must be {}, not {}",
+                types.join(" or "),
+                describe(value)
+            ));
+            // Its contents can't match either, and would only add noise.
+            return;
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum")
+        && !allowed.contains(value)
+    {
+        let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+        problems.push(format!(
+            "`{path}` must be one of {}, not {value}",
+            allowed.join(", ")
+        ));
+    }
+
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64)
+            && number < minimum
+        {
+            problems.push(format!("`{path}` must be at least {minimum}, not {value}"));
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64)
+            && number > maximum
+        {
+            problems.push(format!("`{path}` must be at most {maximum}, not {value}"));
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for required in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !map.contains_key(required) {
+                    problems.push(format!("`{path}.{required}` is required but missing"));
+                }
+            }
+            for (key, value) in map {
+                let property = format!("{path}.{key}");
+                match properties.and_then(|properties| properties.get(key)) {
+                    Some(schema) => check(schema, value, &property, problems),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            let known: Vec<String> = properties
+                                .into_iter()
+                                .flat_map(|properties| properties.keys())
+                                .map(|key| format!("`{key}`"))
+                                .collect();
+                            problems.push(format!(
+                                "`{property}` isn't an argument of this tool, which takes {}",
+                                known.join(", ")
+                            ));
+                        }
+                        Some(schema @ Value::Object(_)) => {
+                            check(schema, value, &property, problems)
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+                && (items.len() as u64) < min
+            {
+                problems.push(format!(
+                    "`{path}` must have at least {min} items, not {}",
+                    items.len()
+                ));
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+                && items.len() as u64 > max
+            {
+                problems.push(format!(
+                    "`{path}` must have at most {max} items, not {}",
+                    items.len()
+                ));
+            }
+            if let Some(item) = schema.get("items") {
+                for (i, value) in items.iter().enumerate() {
+                    check(item, value, &format!("{path}[{i}]"), problems);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether `value` is of the JSON Schema type `kind`.
+fn is(kind: &str, value: &Value) -> bool {
+    match kind {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        // A type this doesn't know is left to the server.
+        _ => true,
+    }
+}
+
+/// `value` as a problem mentions it, e.g. `the string "12"`.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => format!("the boolean {value}"),
+        Value::Number(_) => format!("the number {value}"),
+        Value::String(_) => format!("the string {value}"),
+        Value::Array(_) => "an array".to_string(),
+        Value::Object(_) => "an object".to_string(),
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+use serde_json::Value;
+
+use crate::sheets::{Range, column_name, quote_sheet};
+
+/// Changes kept for undoing; older ones can't be undone anymore.
+const KEPT_CHANGES: usize = 200;
+
+/// What a range held before a mutating tool wrote to it, so `/undo` can
+/// write it back.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// The tool that writes the values back, e.g. `gsheets__write_range`.
+    pub writer: String,
+    pub spreadsheet_id: Value,
+    /// Exactly the cells written, e.g. `Leads!A2:C4`.
+    pub range: String,
+    /// The cells' values before, with `""` for the ones that were empty.
+    pub values: Vec<Vec<Value>>,
+}
+
+/// The cells a write of `proposed` to `range` covers, e.g. `Leads!A2:C4`, and
+/// their values now, given the `current` values of `range`. Appended rows
+/// cover the cells after the `current` ones.
+pub fn covered(
+    range: &str,
+    current: &[Vec<Value>],
+    proposed: &[Vec<Value>],
+    append: bool,
+) -> Option<(String, Vec<Vec<Value>>)> {
+    let is_sheet = |text: &str| Range::parse(text, |_| false).is_err();
+    let parsed = Range::parse(range, is_sheet).ok()?;
+
+    let columns = proposed.iter().map(Vec::len).max().filter(|n| *n > 0)?;
+    let (first_row, values) = if append {
+        let cleared = vec![vec![Value::from(""); columns]; proposed.len()];
+        (parsed.first_row + current.len(), cleared)
+    } else {
+        let before = (0..proposed.len())
+            .map(|row| {
+                (0..columns)
+                    .map(|column| {
+                        current
+                            .get(row)
+                            .and_then(|cells| cells.get(column))
+                            .cloned()
+                            .unwrap_or_else(|| Value::from(""))
+                    })
+                    .collect()
+            })
+            .collect();
+        (parsed.first_row, before)
+    };
+
+    let sheet = match &parsed.sheet {
+        Some(sheet) if sheet.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+            format!("{sheet}!")
+        }
+        Some(sheet) => format!("{}!", quote_sheet(sheet)),
+        None => String::new(),
+    };
+    let range = format!(
+        "{sheet}{}{}:{}{}",
+        column_name(parsed.first_column),
+        first_row + 1,
+        column_name(parsed.first_column + columns - 1),
+        first_row + proposed.len(),
+    );
+
+    Some((range, values))
+}
+
+/// The snapshots of the changes made, newest last, each with the run it was
+/// made in, so the changes of a whole run can be undone together.
+#[derive(Default)]
+pub struct UndoLog {
+    run: AtomicU64,
+    changes: Mutex<Vec<(u64, Snapshot)>>,
+}
+
+impl UndoLog {
+    /// Starts a new run: the changes made from now on are undone together.
+    pub fn begin_run(&self) {
+        self.run.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn push(&self, snapshot: Snapshot) {
+        let mut changes = self.changes.lock().expect("the undo lock isn't poisoned");
+        if changes.len() == KEPT_CHANGES {
+            changes.remove(0);
+        }
+        changes.push((self.run.load(Ordering::Relaxed), snapshot));
+    }
+
+    /// Takes the snapshot of the latest change, or of every change in the
+    /// latest run with changes if `run`, newest first.
+    pub fn take(&self, run: bool) -> Vec<Snapshot> {
+        let mut changes = self.changes.lock().expect("the undo lock isn't poisoned");
+        let Some(&(latest, _)) = changes.last() else {
+            return Vec::new();
+        };
+        let keep = match run {
+            true => changes.partition_point(|(run, _)| *run < latest),
+            false => changes.len() - 1,
+        };
+
+        changes
+            .drain(keep..)
+            .rev()
+            .map(|(_, snapshot)| snapshot)
+            .collect()
+    }
+}
@@ -0,0 +1,173 @@
+//! Audit logs of what the agent was asked and what it did, written as JSON
+//! Lines with one file per session. Personal data is masked before anything
+//! reaches the disk.
+
+use std::{fs::OpenOptions, io::Write, path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::{config::TranscriptConfig, retention, session};
+
+pub(crate) const EMAIL: &str = r"(?i)[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}";
+/// Digits in the groups phone numbers are written in, which leaves dates and
+/// most IDs alone.
+pub(crate) const PHONE: &str =
+    r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{2,4}\)|\b\d{2,4})[\s.-]?\d{3,4}[\s.-]?\d{3,4}\b";
+/// Street addresses written with the number first, as in `12 Baker Street`,
+/// or after the street, as in `Keizersgracht 123`.
+pub(crate) const ADDRESS: &str = r"\b\d{1,5}[a-zA-Z]?\s+(?:[A-Z][\w'.-]*\s+){1,4}(?:Street|St|Avenue|Ave|Road|Rd|Boulevard|Blvd|Lane|Ln|Drive|Dr|Court|Ct|Way|Place|Pl|Square|Sq|Terrace|Parkway|Pkwy)\b\.?|\b[A-Z][\w'-]*(?:straat|laan|weg|gracht|plein|kade|singel|straße|strasse|gasse|allee)\s+\d{1,5}[a-zA-Z]?\b";
+
+/// Where transcripts are written, and what is masked in them.
+#[derive(Clone)]
+pub struct Transcripts {
+    dir: PathBuf,
+    redactor: Arc<Redactor>,
+}
+
+impl Transcripts {
+    /// Returns `None` unless transcripts are enabled.
+    pub fn from_config(config: &TranscriptConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled || !retention::persists() {
+            return Ok(None);
+        }
+
+        let dir = dir(config)?;
+        let mut rules = Vec::new();
+        for mask in &config.mask {
+            rules.push((Regex::new(mask.pattern())?, format!("[{}]", mask.name())));
+        }
+        for rule in &config.redact {
+            let regex = Regex::new(&rule.pattern).with_context(|| {
+                format!(
+                    "Invalid pattern `{}` in [[transcript.redact]]",
+                    rule.pattern
+                )
+            })?;
+            let replacement = rule.replacement.as_deref().unwrap_or("[redacted]");
+            rules.push((regex, replacement.to_string()));
+        }
+
+        Ok(Some(Self {
+            dir,
+            redactor: Arc::new(Redactor { rules }),
+        }))
+    }
+
+    /// The transcript of the session `id`, appended to if it exists.
+    pub fn open(&self, id: &str) -> Transcript {
+        let name: String = id
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+
+        Transcript {
+            path: self.dir.join(format!("{name}.jsonl")),
+            redactor: self.redactor.clone(),
+        }
+    }
+}
+
+/// Where transcripts are written: `[transcript] dir`, or
+/// `~/.local/share/gsheets-agent/transcripts`.
+pub fn dir(config: &TranscriptConfig) -> anyhow::Result<PathBuf> {
+    match &config.dir {
+        Some(dir) => Ok(dir.clone()),
+        None => dirs::data_dir()
+            .map(|dir| dir.join("gsheets-agent").join("transcripts"))
+            .context("Could not determine the data directory to store transcripts in"),
+    }
+}
+
+/// What happened in a session, in order.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry<'a> {
+    Prompt {
+        text: &'a str,
+    },
+    /// Text the model answered with, possibly alongside a tool call.
+    Completion {
+        model: &'a str,
+        text: &'a str,
+    },
+    ToolCall {
+        id: &'a str,
+        tool: &'a str,
+        arguments: &'a Value,
+    },
+    ToolResult {
+        id: &'a str,
+        result: &'a str,
+        /// Whether `result` is the error the call failed with.
+        error: bool,
+    },
+}
+
+pub struct Transcript {
+    path: PathBuf,
+    redactor: Arc<Redactor>,
+}
+
+impl Transcript {
+    /// Appends `entry`, masked. Failing to do so doesn't interrupt the session.
+    pub fn record(&self, entry: &Entry<'_>) {
+        let mut line = json!({ "at": session::unix_now() });
+        if let (Value::Object(line), Ok(Value::Object(entry))) =
+            (&mut line, serde_json::to_value(entry))
+        {
+            line.extend(entry);
+        }
+        self.redactor.redact(&mut line);
+
+        if let Err(e) = self.append(&line) {
+            tracing::warn!(
+                "Failed to write to the transcript {}: {e:#}",
+                self.path.display()
+            );
+        }
+    }
+
+    fn append(&self, line: &Value) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        // One write per line, so concurrent writers don't interleave.
+        file.write_all(format!("{line}\n").as_bytes())?;
+        Ok(())
+    }
+}
+
+struct Redactor {
+    rules: Vec<(Regex, String)>,
+}
+
+impl Redactor {
+    /// Masks every string in `value`, down to those nested in tool arguments.
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => {
+                for (regex, replacement) in &self.rules {
+                    if let std::borrow::Cow::Owned(masked) =
+                        regex.replace_all(text, replacement.as_str())
+                    {
+                        *text = masked;
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact(value)),
+            Value::Object(fields) => fields.values_mut().for_each(|value| self.redact(value)),
+            Value::Null | Value::Bool(_) | Value::Number(_) => {}
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! `--tui`: the chat in a full-screen terminal UI, next to panes showing the
+//! tool calls the agent makes and the cells it last read or wrote.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use ratatui::{
+    DefaultTerminal, Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, List, ListItem, Paragraph, Row, Table, Wrap},
+};
+use rig::message::Message;
+use serde_json::Value;
+use tokio::{sync::mpsc::UnboundedReceiver, task::JoinHandle};
+
+use crate::{
+    chat::{Activity, Agent, Turn},
+    commands,
+    config::PriceConfig,
+    error::Error,
+    provider::Model,
+    session::Session,
+    usage,
+};
+
+/// Widest a column of the sheet preview gets, in characters.
+const MAX_COLUMN_WIDTH: u16 = 24;
+
+/// A turn running in the background, handing the history back when done.
+type Pending = JoinHandle<(Result<Turn, Error>, Vec<Message>)>;
+
+/// Runs the chat until the user quits, saving `session` after every turn.
+pub async fn run(
+    agent: Arc<Agent<Model>>,
+    mut session: Session,
+    mut activity: UnboundedReceiver<Activity>,
+    pricing: &BTreeMap<String, PriceConfig>,
+) -> anyhow::Result<()> {
+    let mut terminal = ratatui::try_init().context("Failed to set up the terminal")?;
+    let res = event_loop(&mut terminal, &agent, &mut session, &mut activity).await;
+    ratatui::restore();
+
+    eprintln!("Session: {} (continue it later with --resume)", session.id);
+    eprintln!("{}", usage::summary(&session.usage, pricing));
+    res
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    agent: &Arc<Agent<Model>>,
+    session: &mut Session,
+    activity: &mut UnboundedReceiver<Activity>,
+) -> anyhow::Result<()> {
+    let mut app = App::default();
+    app.notice(format!(
+        "Session {}. Changes to sheets are only made with --yes, as there's no asking for \
+         confirmation here.",
+        session.id
+    ));
+
+    let mut events = EventStream::new();
+    // Keeps the elapsed time moving while the agent works.
+    let mut tick = tokio::time::interval(Duration::from_millis(250));
+    let mut pending: Option<Pending> = None;
+
+    loop {
+        terminal
+            .draw(|frame| app.draw(frame))
+            .context("Failed to draw the terminal UI")?;
+
+        tokio::select! {
+            Some(activity) = activity.recv() => app.on_activity(activity),
+            joined = async { pending.as_mut().expect("a turn is running").await },
+                if pending.is_some() =>
+            {
+                pending = None;
+                app.started = None;
+                let (res, history) = joined.context("The turn was interrupted")?;
+                session.chat_history = history;
+
+                match res {
+                    Ok(turn) => {
+                        session.record_usage(&turn.model, turn.usage);
+                        app.finish(turn.answer);
+                    }
+                    Err(e) => app.notice(format!("Error: {e}")),
+                }
+                if let Err(e) = session.save() {
+                    app.notice(format!("Failed to save the session: {e:#}"));
+                }
+            }
+            event = events.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let Event::Key(key) = event.context("Failed to read from the terminal")? else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+
+                match (key.code, key.modifiers) {
+                    (KeyCode::Char('c' | 'd'), KeyModifiers::CONTROL) | (KeyCode::Esc, _) => break,
+                    (KeyCode::Enter, _) if pending.is_none() => {
+                        let prompt = std::mem::take(&mut app.input);
+                        match prompt.trim() {
+                            "" => {}
+                            "quit" => break,
+                            _ if commands::parse(&prompt).is_some() => {
+                                app.notice("Commands aren't available in the TUI.".to_string());
+                            }
+                            _ => {
+                                app.say(Speaker::User, prompt.clone());
+                                app.started = Some(Instant::now());
+                                pending = Some(start_turn(agent, session, prompt));
+                            }
+                        }
+                    }
+                    (KeyCode::Char(c), _) => app.input.push(c),
+                    (KeyCode::Backspace, _) => {
+                        app.input.pop();
+                    }
+                    (KeyCode::PageUp, _) => app.scroll_back = app.scroll_back.saturating_add(5),
+                    (KeyCode::PageDown, _) => app.scroll_back = app.scroll_back.saturating_sub(5),
+                    _ => {}
+                }
+            }
+            _ = tick.tick(), if pending.is_some() => {}
+        }
+    }
+
+    // The session still holds the history from before the unfinished turn.
+    if let Some(pending) = pending {
+        pending.abort();
+    }
+    Ok(())
+}
+
+/// Runs `prompt` on a copy of the history, so quitting halfway leaves the
+/// session as it was.
+fn start_turn(agent: &Arc<Agent<Model>>, session: &Session, prompt: String) -> Pending {
+    let agent = agent.clone();
+    let mut history = session.chat_history.clone();
+    let transcript = agent.transcript(&session.id);
+
+    tokio::spawn(async move {
+        let res = match agent
+            .call_until_response(
+                prompt.as_str().into(),
+                &mut history,
+                transcript.as_ref(),
+                None,
+            )
+            .await
+        {
+            // There's no asking whether to go on, as the REPL does.
+            Err(Error::LimitExceeded(limit)) => Ok(limit.abandon(&mut history)),
+            res => res,
+        };
+        (res, history)
+    })
+}
+
+#[derive(Default)]
+struct App {
+    said: Vec<Said>,
+    /// Whether the last of `said` is an answer still streaming in.
+    streaming: bool,
+    tools: Vec<ToolActivity>,
+    preview: Option<Preview>,
+    input: String,
+    /// When the running turn started.
+    started: Option<Instant>,
+    /// Lines scrolled up from the end of the chat.
+    scroll_back: u16,
+}
+
+struct Said {
+    speaker: Speaker,
+    text: String,
+}
+
+#[derive(PartialEq, Eq)]
+enum Speaker {
+    User,
+    Agent,
+    Notice,
+}
+
+struct ToolActivity {
+    id: String,
+    name: String,
+    /// The range the call is about, or its arguments.
+    target: String,
+    status: Status,
+}
+
+enum Status {
+    Running,
+    Done { duration_ms: u64 },
+    Failed { duration_ms: u64, error: String },
+}
+
+/// Cells the agent read or wrote.
+struct Preview {
+    title: String,
+    rows: Vec<Vec<String>>,
+}
+
+impl App {
+    fn say(&mut self, speaker: Speaker, text: String) {
+        self.said.push(Said { speaker, text });
+        self.streaming = false;
+        self.scroll_back = 0;
+    }
+
+    fn notice(&mut self, text: String) {
+        self.say(Speaker::Notice, text);
+    }
+
+    fn on_activity(&mut self, activity: Activity) {
+        match activity {
+            Activity::Text(chunk) => match self.said.last_mut() {
+                Some(said) if self.streaming => said.text.push_str(&chunk),
+                _ => {
+                    self.say(Speaker::Agent, chunk);
+                    self.streaming = true;
+                }
+            },
+            Activity::ToolCall {
+                id,
+                name,
+                arguments,
+            } => {
+                // Text after the call is a new answer.
+                self.streaming = false;
+                self.tools.push(ToolActivity {
+                    id,
+                    name,
+                    target: target(&arguments),
+                    status: Status::Running,
+                });
+            }
+            Activity::ToolResult {
+                id,
+                name,
+                arguments,
+                result,
+                duration_ms,
+            } => {
+                if let Some(preview) = preview(&arguments, result.as_deref().ok()) {
+                    self.preview = Some(preview);
+                }
+                if let Some(tool) = self
+                    .tools
+                    .iter_mut()
+                    .rev()
+                    .find(|tool| tool.id == id && tool.name == name)
+                {
+                    tool.status = match result {
+                        Ok(_) => Status::Done { duration_ms },
+                        Err(error) => Status::Failed { duration_ms, error },
+                    };
+                }
+            }
+        }
+    }
+
+    /// Shows `answer`, unless it was streamed in already.
+    fn finish(&mut self, answer: String) {
+        let streamed = self
+            .said
+            .last()
+            .is_some_and(|said| said.speaker == Speaker::Agent && said.text == answer);
+        if !streamed {
+            self.say(Speaker::Agent, answer);
+        }
+        self.streaming = false;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, input] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+        let [chat, side] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main);
+        let [tools, sheet] =
+            Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(side);
+
+        self.draw_chat(frame, chat);
+        self.draw_tools(frame, tools);
+        self.draw_preview(frame, sheet);
+        self.draw_input(frame, input);
+    }
+
+    fn draw_chat(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = Vec::new();
+        for said in &self.said {
+            let header = match said.speaker {
+                Speaker::User => Span::from("You").green().bold(),
+                Speaker::Agent => Span::from("Agent").cyan().bold(),
+                Speaker::Notice => Span::from("Note").yellow().bold(),
+            };
+            lines.push(Line::from(header));
+            lines.extend(said.text.lines().map(|line| Line::from(line.to_string())));
+            lines.push(Line::default());
+        }
+
+        let title = match self.started {
+            Some(started) => format!(" Chat · working, {}s ", started.elapsed().as_secs()),
+            None => " Chat ".to_string(),
+        };
+        let paragraph = Paragraph::new(Text::from(lines)).wrap(Wrap { trim: false });
+
+        // Keeps the latest message in view, unless scrolled back.
+        let height = area.height.saturating_sub(2);
+        let total =
+            u16::try_from(paragraph.line_count(area.width.saturating_sub(2))).unwrap_or(u16::MAX);
+        let bottom = total.saturating_sub(height);
+        let scroll = bottom.saturating_sub(self.scroll_back);
+
+        frame.render_widget(
+            paragraph
+                .scroll((scroll, 0))
+                .block(Block::bordered().title(title)),
+            area,
+        );
+    }
+
+    fn draw_tools(&self, frame: &mut Frame, area: Rect) {
+        let visible = usize::from(area.height.saturating_sub(2));
+        let items = self
+            .tools
+            .iter()
+            .skip(self.tools.len().saturating_sub(visible))
+            .map(|tool| {
+                let status = match &tool.status {
+                    Status::Running => Span::from("… ").yellow(),
+                    Status::Done { .. } => Span::from("✓ ").green(),
+                    Status::Failed { .. } => Span::from("✗ ").red(),
+                };
+                let detail = match &tool.status {
+                    Status::Running => String::new(),
+                    Status::Done { duration_ms } => format!(" {duration_ms} ms"),
+                    Status::Failed { duration_ms, error } => {
+                        format!(" {duration_ms} ms: {}", error.lines().next().unwrap_or(""))
+                    }
+                };
+
+                ListItem::new(Line::from(vec![
+                    status,
+                    Span::from(tool.name.clone()).bold(),
+                    Span::from(format!(" {}", tool.target)).dark_gray(),
+                    Span::from(detail),
+                ]))
+            });
+
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" Tool calls ")),
+            area,
+        );
+    }
+
+    fn draw_preview(&self, frame: &mut Frame, area: Rect) {
+        let Some(preview) = &self.preview else {
+            frame.render_widget(
+                Paragraph::new("Nothing read or written yet.")
+                    .dark_gray()
+                    .block(Block::bordered().title(" Sheet ")),
+                area,
+            );
+            return;
+        };
+
+        let columns = preview.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![3; columns];
+        for row in &preview.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                let len = u16::try_from(cell.chars().count()).unwrap_or(u16::MAX);
+                *width = (*width).max(len.min(MAX_COLUMN_WIDTH));
+            }
+        }
+
+        let mut rows = preview.rows.iter().map(|row| Row::new(row.clone()));
+        let header = rows.next().map(|row| row.style(Style::new().bold()));
+        let mut table = Table::new(rows, widths.into_iter().map(Constraint::Length))
+            .block(Block::bordered().title(format!(" {} ", preview.title)));
+        if let Some(header) = header {
+            table = table.header(header);
+        }
+
+        frame.render_widget(table, area);
+    }
+
+    fn draw_input(&self, frame: &mut Frame, area: Rect) {
+        let title = if self.started.is_some() {
+            " Waiting for the agent… (Esc to quit) "
+        } else {
+            " Message (Enter to send, PageUp/PageDown to scroll, Esc to quit) "
+        };
+        frame.render_widget(
+            Paragraph::new(self.input.as_str()).block(Block::bordered().title(title)),
+            area,
+        );
+
+        let typed = u16::try_from(self.input.chars().count()).unwrap_or(u16::MAX);
+        let x = area
+            .x
+            .saturating_add(1)
+            .saturating_add(typed)
+            .min(area.right().saturating_sub(2));
+        frame.set_cursor_position((x, area.y + 1));
+    }
+}
+
+/// The range a tool call is about, or its arguments if it has none.
+fn target(arguments: &Value) -> String {
+    match arguments.get("range").and_then(Value::as_str) {
+        Some(range) => range.to_string(),
+        None if arguments.as_object().is_some_and(|args| args.is_empty()) => String::new(),
+        None => arguments.to_string(),
+    }
+}
+
+/// The cells a successful call wrote, from its arguments, or read, from its
+/// result.
+fn preview(arguments: &Value, result: Option<&str>) -> Option<Preview> {
+    let result = result?;
+    let range = arguments
+        .get("range")
+        .and_then(Value::as_str)
+        .unwrap_or("Range");
+
+    let (verb, values) = match arguments.get("values") {
+        Some(values) => ("written", values.clone()),
+        None => (
+            "read",
+            serde_json::from_str::<Value>(result)
+                .ok()?
+                .get("values")?
+                .clone(),
+        ),
+    };
+    let rows: Vec<Vec<String>> = values
+        .as_array()?
+        .iter()
+        .map(|row| {
+            row.as_array()
+                .map(|cells| cells.iter().map(cell_text).collect())
+                .unwrap_or_default()
+        })
+        .collect();
+
+    (!rows.is_empty()).then(|| Preview {
+        title: format!("{range} ({verb})"),
+        rows,
+    })
+}
+
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
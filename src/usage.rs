@@ -0,0 +1,112 @@
+//! Token accounting and the cost estimates derived from it.
+
+use std::{collections::BTreeMap, ops::AddAssign};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PriceConfig;
+
+/// List prices in USD per million tokens, matched by model name prefix.
+///
+/// More specific prefixes come first, since the first match wins.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("o3-mini", 1.10, 4.40),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("gemini-2.5-flash-lite", 0.10, 0.40),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.5-pro", 1.25, 10.00),
+    ("gemini-2.0-flash-lite", 0.075, 0.30),
+    ("gemini-2.0-flash", 0.10, 0.40),
+];
+
+/// Tokens used by model calls.
+///
+/// These are estimates: streamed responses don't report what the provider billed.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Prices for `model`, from the `[pricing]` config or else the built-in list.
+pub fn price(model: &str, pricing: &BTreeMap<String, PriceConfig>) -> Option<PriceConfig> {
+    pricing.get(model).cloned().or_else(|| {
+        PRICES
+            .iter()
+            .find(|(prefix, _, _)| model.starts_with(prefix))
+            .map(|&(_, input, output)| PriceConfig { input, output })
+    })
+}
+
+/// Estimated cost of `usage` in USD.
+pub fn cost(usage: Usage, price: &PriceConfig) -> f64 {
+    (usage.prompt_tokens as f64 * price.input + usage.completion_tokens as f64 * price.output)
+        / 1_000_000.0
+}
+
+/// Estimated cost of `usage` in USD over every model, and whether some of them
+/// have no known price, so the cost leaves them out.
+pub fn total_cost(
+    usage: &BTreeMap<String, Usage>,
+    pricing: &BTreeMap<String, PriceConfig>,
+) -> (f64, bool) {
+    usage.iter().fold(
+        (0.0, false),
+        |(total, unpriced), (model, usage)| match price(model, pricing) {
+            Some(price) => (total + cost(*usage, &price), unpriced),
+            None => (total, true),
+        },
+    )
+}
+
+/// A per-model breakdown of `usage` with its total cost, for printing.
+pub fn summary(usage: &BTreeMap<String, Usage>, pricing: &BTreeMap<String, PriceConfig>) -> String {
+    let mut summary = String::from("Estimated usage for this session:");
+    let mut total = 0.0;
+    let mut unpriced = false;
+
+    for (model, usage) in usage {
+        summary.push_str(&format!(
+            "\n  {model}: {} prompt + {} completion tokens",
+            usage.prompt_tokens, usage.completion_tokens
+        ));
+
+        match price(model, pricing) {
+            Some(price) => {
+                let cost = cost(*usage, &price);
+                total += cost;
+                summary.push_str(&format!(", ${cost:.4}"));
+            }
+            None => {
+                unpriced = true;
+                summary.push_str(" (no price known; add it under [pricing])");
+            }
+        }
+    }
+
+    if usage.is_empty() {
+        summary.push_str(" none yet");
+    } else {
+        summary.push_str(&format!("\n  Total: ${total:.4}"));
+        if unpriced {
+            summary.push_str(" plus models without a known price");
+        }
+    }
+
+    summary
+}
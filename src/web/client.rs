@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use serde_json::Value;
+
+use crate::config::{SearchProvider, WebConfig};
+
+const BRAVE_URL: &str = "https://api.search.brave.com/res/v1/web/search";
+/// Summaries are cut off here, so they don't crowd out the leads in a prompt.
+const MAX_SUMMARY_CHARS: usize = 600;
+/// Email domains that say nothing about the lead's company.
+const FREE_EMAIL_DOMAINS: &[&str] = &[
+    "aol.com",
+    "gmail.com",
+    "gmx.com",
+    "gmx.de",
+    "googlemail.com",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "yahoo.com",
+    "yandex.com",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebError {
+    #[error("Looking up {domain} failed: {source}")]
+    Http {
+        domain: String,
+        source: reqwest::Error,
+    },
+    #[error("The search API returned {status}: {message}")]
+    Api {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+    #[error("`{0}` isn't a domain name")]
+    InvalidDomain(String),
+    #[error("Nothing was found about {0}")]
+    NotFound(String),
+    #[error("The lookup was aborted: {0}")]
+    Aborted(#[from] tokio::task::JoinError),
+}
+
+/// Summarizes what the web says about a company, given its domain.
+#[derive(Clone)]
+pub struct WebClient {
+    http: reqwest::Client,
+    provider: SearchProvider,
+    api_key: Option<String>,
+    max_results: usize,
+}
+
+impl WebClient {
+    pub fn new(config: &WebConfig) -> anyhow::Result<Self> {
+        let api_key = match config.provider {
+            SearchProvider::Homepage => None,
+            SearchProvider::Brave => Some(std::env::var(&config.api_key_env).map_err(|_| {
+                anyhow::anyhow!(
+                    "Set {} to the Brave Search API key, or use the `homepage` provider under [web]",
+                    config.api_key_env
+                )
+            })?),
+        };
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .user_agent(concat!("gsheets-agent/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        Ok(Self {
+            http,
+            provider: config.provider,
+            api_key,
+            max_results: config.max_results,
+        })
+    }
+
+    /// A few sentences about the company at `domain`, e.g. `example.com`.
+    pub async fn lookup(&self, domain: &str) -> Result<String, WebError> {
+        let domain = domain.trim().trim_end_matches('/').to_lowercase();
+        if domain.is_empty() || domain.contains(['/', ' ', '@']) || !domain.contains('.') {
+            return Err(WebError::InvalidDomain(domain));
+        }
+
+        let summary = match self.provider {
+            SearchProvider::Homepage => self.homepage(&domain).await?,
+            SearchProvider::Brave => self.brave(&domain).await?,
+        };
+        if summary.trim().is_empty() {
+            return Err(WebError::NotFound(domain));
+        }
+
+        Ok(truncate(&summary, MAX_SUMMARY_CHARS))
+    }
+
+    /// The title and description of the company's homepage.
+    async fn homepage(&self, domain: &str) -> Result<String, WebError> {
+        let http_error = |source| WebError::Http {
+            domain: domain.to_string(),
+            source,
+        };
+
+        let response = self
+            .http
+            .get(format!("https://{domain}"))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(http_error)?;
+        let html = response.text().await.map_err(http_error)?;
+
+        let parts: Vec<String> = [
+            tag_text(&html, "title"),
+            meta_content(&html, "description"),
+            meta_content(&html, "og:description"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        Ok(parts.join(" — "))
+    }
+
+    /// The top search results about the domain.
+    async fn brave(&self, domain: &str) -> Result<String, WebError> {
+        let http_error = |source| WebError::Http {
+            domain: domain.to_string(),
+            source,
+        };
+
+        let mut url = Url::parse(BRAVE_URL).expect("BRAVE_URL is valid");
+        url.query_pairs_mut()
+            .append_pair("q", &format!("{domain} company"))
+            .append_pair("count", &self.max_results.to_string());
+
+        let response = self
+            .http
+            .get(url)
+            .header(
+                "X-Subscription-Token",
+                self.api_key.as_deref().unwrap_or_default(),
+            )
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .map_err(http_error)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(WebError::Api { status, message });
+        }
+
+        let body: Value = response.json().await.map_err(http_error)?;
+        let results = body["web"]["results"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+
+        let lines: Vec<String> = results
+            .iter()
+            .take(self.max_results)
+            .map(|result| {
+                format!(
+                    "{}: {}",
+                    result["title"].as_str().unwrap_or_default(),
+                    strip_tags(result["description"].as_str().unwrap_or_default())
+                )
+            })
+            .collect();
+
+        Ok(lines.join("\n"))
+    }
+}
+
+/// The domain of the company behind `email` or a website URL, unless it's a
+/// free email provider.
+pub fn company_domain(email_or_website: &str) -> Option<String> {
+    let text = email_or_website.trim().to_lowercase();
+    let domain = match text.rsplit_once('@') {
+        Some((_, domain)) => domain,
+        None => {
+            let without_scheme = text
+                .split_once("://")
+                .map_or(text.as_str(), |(_, rest)| rest);
+            without_scheme.split(['/', '?', '#']).next()?
+        }
+    };
+    let domain = domain.strip_prefix("www.").unwrap_or(domain);
+
+    (domain.contains('.') && !FREE_EMAIL_DOMAINS.contains(&domain)).then(|| domain.to_string())
+}
+
+/// The text inside the first `<tag>` of `html`.
+fn tag_text(html: &str, tag: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find(&format!("<{tag}"))?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find(&format!("</{tag}"))?;
+
+    clean(&html[start..end])
+}
+
+/// The `content` of the `<meta>` tag whose `name` or `property` is `name`.
+fn meta_content(html: &str, name: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+
+    lower.match_indices("<meta").find_map(|(start, _)| {
+        let end = start + lower[start..].find('>')?;
+        let tag = &lower[start..end];
+        let names = [format!("name=\"{name}\""), format!("property=\"{name}\"")];
+        if !names.iter().any(|attribute| tag.contains(attribute)) {
+            return None;
+        }
+
+        let content = tag.find("content=\"")? + "content=\"".len();
+        let content_end = content + tag[content..].find('"')?;
+        // ASCII lowercasing keeps the offsets valid in the original, with its case.
+        clean(html.get(start + content..start + content_end)?)
+    })
+}
+
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// `text` with common entities decoded and whitespace collapsed, if it's not empty.
+fn clean(text: &str) -> Option<String> {
+    let text = text
+        .replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    (!text.is_empty()).then_some(text)
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
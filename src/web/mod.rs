@@ -0,0 +1,41 @@
+//! Looking up the companies behind leads on the web, for firmographics that
+//! aren't in the form data.
+
+mod client;
+mod tools;
+
+pub use client::{WebClient, WebError, company_domain};
+
+use rig::{
+    completion::ToolDefinition,
+    tool::{Tool, ToolSet},
+};
+
+use crate::{
+    config::{ToolsConfig, WebConfig},
+    tools::is_allowed,
+};
+
+/// The client for the configured lookups, or `None` when they're disabled.
+pub fn client(config: &WebConfig) -> anyhow::Result<Option<WebClient>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    WebClient::new(config).map(Some)
+}
+
+/// Adds the lookup tool to `toolset`, unless `filter` hides it.
+pub async fn add_tool(
+    client: &WebClient,
+    filter: &ToolsConfig,
+    toolset: &mut ToolSet,
+    definitions: &mut Vec<ToolDefinition>,
+) {
+    let tool = tools::LookupCompany(client.clone());
+
+    if is_allowed(filter, &tool.name()) {
+        definitions.push(tool.definition(String::new()).await);
+        toolset.add_tool(tool);
+    }
+}
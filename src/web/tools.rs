@@ -0,0 +1,53 @@
+//! Company lookups exposed to the model as a rig tool.
+
+use std::future::Future;
+
+use rig::{completion::ToolDefinition, tool::Tool};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{WebClient, WebError};
+
+#[derive(Debug, Deserialize)]
+pub struct LookupArgs {
+    domain: String,
+}
+
+pub struct LookupCompany(pub WebClient);
+
+impl Tool for LookupCompany {
+    const NAME: &'static str = "web__lookup_company";
+
+    type Error = WebError;
+    type Args = LookupArgs;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Look up a company on the web by its domain, for firmographics like its \
+                          industry and size that the lead didn't provide."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "domain": {
+                        "type": "string",
+                        "description": "The company's domain, e.g. from the lead's work email"
+                    }
+                },
+                "required": ["domain"]
+            }),
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<String, WebError>> + Send + Sync {
+        // Tool futures have to be `Sync`, which the HTTP client's aren't.
+        let client = self.0.clone();
+        let handle = tokio::spawn(async move { client.lookup(&args.domain).await });
+        async move { handle.await? }
+    }
+}
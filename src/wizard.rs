@@ -0,0 +1,235 @@
+//! `init-criteria`: asks what makes a good lead and writes the answers as a
+//! scoring rubric, so they don't have to be explained to the model again in
+//! every session.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde_json::Value;
+
+use crate::{
+    cli::InitCriteriaArgs,
+    repl::Repl,
+    rubric::{Criterion, Field, FieldKind, Rubric},
+};
+
+/// Points for each kind of answer; the rubric file can be edited to weigh
+/// them differently.
+const INDUSTRY_POINTS: f64 = 2.0;
+const SIZE_POINTS: f64 = 3.0;
+const BUDGET_POINTS: f64 = 2.0;
+const DISQUALIFIER_POINTS: f64 = -10.0;
+
+pub fn run(args: &InitCriteriaArgs) -> anyhow::Result<()> {
+    let mut repl = Repl::new()?;
+
+    if args.to.exists()
+        && !repl.confirm(&format!(
+            "{} already exists. Overwrite it?",
+            args.to.display()
+        ))
+    {
+        return Ok(());
+    }
+
+    println!(
+        "Answer a few questions about the leads you're after; leave an answer empty to skip it."
+    );
+    println!("Separate several answers with commas.");
+
+    let Some(rubric) = interview(&mut repl)? else {
+        eprintln!("Stopped without writing a rubric");
+        return Ok(());
+    };
+    anyhow::ensure!(
+        !rubric.criteria.is_empty(),
+        "Every question was skipped, so there's nothing to score leads by"
+    );
+
+    let yaml = serde_yaml::to_string(&rubric).context("Failed to serialize the rubric")?;
+    std::fs::write(&args.to, yaml)
+        .with_context(|| format!("Failed to write {}", args.to.display()))?;
+    eprintln!(
+        "Wrote {}. Use it with --rubric {0}, or set `rubric` in the config.",
+        args.to.display()
+    );
+
+    Ok(())
+}
+
+/// Asks every question, returning `None` if the user gives up on the way.
+fn interview(repl: &mut Repl) -> anyhow::Result<Option<Rubric>> {
+    let mut rubric = Rubric {
+        name: None,
+        fields: BTreeMap::new(),
+        criteria: Vec::new(),
+        threshold: None,
+    };
+
+    let Some(name) = repl.ask("What should the rubric be called, e.g. \"B2B SaaS\"?")? else {
+        return Ok(None);
+    };
+    rubric.name = Some(name).filter(|name| !name.is_empty());
+
+    let Some(industries) = repl.ask("Which industries are you targeting?")? else {
+        return Ok(None);
+    };
+    let industries = list(&industries);
+    if !industries.is_empty() {
+        rubric.fields.insert(
+            "industry".to_string(),
+            field(
+                FieldKind::String,
+                "Industry the company is in, in a word or two",
+            ),
+        );
+        rubric.criteria.push(Criterion {
+            field: "industry".to_string(),
+            points: INDUSTRY_POINTS,
+            label: Some("Target industry".to_string()),
+            one_of: Some(industries.into_iter().map(Value::from).collect()),
+            ..Criterion::default()
+        });
+    }
+
+    let Some(min_size) = ask_number(repl, "What's the smallest company size, in employees?")?
+    else {
+        return Ok(None);
+    };
+    let Some(max_size) = ask_number(repl, "And the largest?")? else {
+        return Ok(None);
+    };
+    if min_size.is_some() || max_size.is_some() {
+        rubric.fields.insert(
+            "company_size".to_string(),
+            field(FieldKind::Number, "Number of employees"),
+        );
+        rubric.criteria.push(Criterion {
+            field: "company_size".to_string(),
+            points: SIZE_POINTS,
+            label: Some("Company size in range".to_string()),
+            gte: min_size,
+            lte: max_size,
+            ..Criterion::default()
+        });
+    }
+
+    let Some(signals) =
+        repl.ask("What shows a lead has budget, e.g. \"asked for pricing, enterprise plan\"?")?
+    else {
+        return Ok(None);
+    };
+    let signals = list(&signals);
+    if !signals.is_empty() {
+        rubric.fields.insert(
+            "budget_signal".to_string(),
+            field(
+                FieldKind::Boolean,
+                &format!(
+                    "Whether the lead shows any of these signs of budget: {}",
+                    signals.join(", ")
+                ),
+            ),
+        );
+        rubric.criteria.push(Criterion {
+            field: "budget_signal".to_string(),
+            points: BUDGET_POINTS,
+            label: Some("Shows budget".to_string()),
+            equals: Some(Value::Bool(true)),
+            ..Criterion::default()
+        });
+    }
+
+    let Some(disqualifiers) =
+        repl.ask("What rules a lead out, e.g. \"student, competitor, free email address\"?")?
+    else {
+        return Ok(None);
+    };
+    for disqualifier in list(&disqualifiers) {
+        let name = field_name(&disqualifier);
+        if rubric.fields.contains_key(&name) {
+            continue;
+        }
+        rubric.fields.insert(
+            name.clone(),
+            field(
+                FieldKind::Boolean,
+                &format!("Whether this applies to the lead: {disqualifier}"),
+            ),
+        );
+        rubric.criteria.push(Criterion {
+            field: name,
+            points: DISQUALIFIER_POINTS,
+            label: Some(format!("Ruled out: {disqualifier}")),
+            equals: Some(Value::Bool(true)),
+            ..Criterion::default()
+        });
+    }
+
+    let positive: f64 = rubric
+        .criteria
+        .iter()
+        .map(|criterion| criterion.points)
+        .filter(|points| *points > 0.0)
+        .sum();
+    let Some(threshold) = ask_number(
+        repl,
+        &format!("What's the lowest score of a qualified lead, out of {positive}? [{positive}]"),
+    )?
+    else {
+        return Ok(None);
+    };
+    rubric.threshold = Some(threshold.unwrap_or(positive));
+
+    Ok(Some(rubric))
+}
+
+/// Asks until the answer is a number or empty, returning `None` if the user
+/// gives up and `Some(None)` for an empty answer.
+fn ask_number(repl: &mut Repl, question: &str) -> anyhow::Result<Option<Option<f64>>> {
+    loop {
+        let Some(answer) = repl.ask(question)? else {
+            return Ok(None);
+        };
+        if answer.is_empty() {
+            return Ok(Some(None));
+        }
+        match answer.replace([',', '_'], "").parse() {
+            Ok(number) => return Ok(Some(Some(number))),
+            Err(_) => println!("Please enter a number, or nothing to skip this."),
+        }
+    }
+}
+
+fn field(kind: FieldKind, description: &str) -> Field {
+    Field {
+        kind,
+        description: description.to_string(),
+    }
+}
+
+/// The comma-separated answers in `answer`.
+fn list(answer: &str) -> Vec<String> {
+    answer
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// A field name for `text`, e.g. `free_email_address` for "Free email address".
+fn field_name(text: &str) -> String {
+    let name = text
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+
+    if name.is_empty() {
+        "disqualified".to_string()
+    } else {
+        name
+    }
+}
@@ -0,0 +1,136 @@
+//! Spreadsheets registered under short aliases, so the user can refer to them
+//! by name instead of pasting their IDs, and the model knows what's in them.
+
+use std::sync::RwLock;
+
+use crate::{
+    sheets::{SheetsError, Spreadsheets},
+    store,
+};
+
+/// A spreadsheet of the workspace.
+#[derive(Debug, Clone)]
+pub struct Spreadsheet {
+    pub alias: String,
+    pub id: String,
+    /// `None` if the spreadsheet couldn't be read when it was added.
+    pub title: Option<String>,
+    pub sheets: Vec<String>,
+}
+
+impl Spreadsheet {
+    /// The spreadsheet `id`, with the title and sheet names read from it.
+    pub async fn read(
+        alias: &str,
+        id: &str,
+        spreadsheets: &Spreadsheets,
+    ) -> Result<Self, SheetsError> {
+        let listed = spreadsheets.list_sheets(id).await?;
+        let sheets = listed["sheets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|sheet| sheet["properties"]["title"].as_str())
+            .map(str::to_string)
+            .collect();
+
+        Ok(Self {
+            alias: alias.to_string(),
+            id: id.to_string(),
+            title: listed["properties"]["title"].as_str().map(str::to_string),
+            sheets,
+        })
+    }
+}
+
+/// The spreadsheets the user has added with `/workspace add`, kept in the
+/// database.
+#[derive(Default)]
+pub struct Workspace {
+    spreadsheets: RwLock<Vec<Spreadsheet>>,
+}
+
+impl Workspace {
+    /// The workspace saved in the database.
+    pub fn load() -> anyhow::Result<Self> {
+        Ok(Self {
+            spreadsheets: RwLock::new(store::open()?.workspace()?),
+        })
+    }
+
+    /// The spreadsheets, by alias.
+    pub fn list(&self) -> Vec<Spreadsheet> {
+        self.spreadsheets
+            .read()
+            .expect("the workspace lock isn't poisoned")
+            .clone()
+    }
+
+    /// Adds `spreadsheet`, replacing the one with the same alias, if any.
+    pub fn add(&self, spreadsheet: Spreadsheet) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !spreadsheet.alias.is_empty()
+                && spreadsheet
+                    .alias
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_'),
+            "Invalid alias `{}`: use letters, digits, `-` and `_`",
+            spreadsheet.alias
+        );
+        store::open()?.save_to_workspace(&spreadsheet)?;
+
+        let mut spreadsheets = self
+            .spreadsheets
+            .write()
+            .expect("the workspace lock isn't poisoned");
+        spreadsheets.retain(|other| other.alias != spreadsheet.alias);
+        spreadsheets.push(spreadsheet);
+        spreadsheets.sort_by(|a, b| a.alias.cmp(&b.alias));
+
+        Ok(())
+    }
+
+    /// Removes the spreadsheet `alias`, returning whether there was one.
+    pub fn remove(&self, alias: &str) -> anyhow::Result<bool> {
+        let removed = store::open()?.remove_from_workspace(alias)?;
+        self.spreadsheets
+            .write()
+            .expect("the workspace lock isn't poisoned")
+            .retain(|spreadsheet| spreadsheet.alias != alias);
+
+        Ok(removed)
+    }
+
+    /// What the model is told about the workspace, added to the preamble, or
+    /// nothing if it's empty.
+    pub fn context(&self) -> String {
+        let spreadsheets = self
+            .spreadsheets
+            .read()
+            .expect("the workspace lock isn't poisoned");
+        if spreadsheets.is_empty() {
+            return String::new();
+        }
+
+        let mut context = "\nThe user may refer to these spreadsheets by their alias; \
+                           use their IDs with the tools:"
+            .to_string();
+        for spreadsheet in spreadsheets.iter() {
+            context.push_str(&format!(
+                "\n- `{}`: ID `{}`",
+                spreadsheet.alias, spreadsheet.id
+            ));
+            if let Some(title) = &spreadsheet.title {
+                context.push_str(&format!(", titled \"{title}\""));
+            }
+            if !spreadsheet.sheets.is_empty() {
+                context.push_str(&format!(
+                    ", with the sheets {}",
+                    spreadsheet.sheets.join(", ")
+                ));
+            }
+        }
+
+        context
+    }
+}